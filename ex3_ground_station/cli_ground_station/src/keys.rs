@@ -0,0 +1,136 @@
+use common::component_ids::ComponentIds;
+use common::key_store::{random_key, rotate_key_body, KeyStore, KEY_SLOTS};
+use common::link_crypto::KEY_SIZE;
+use common::message_structure::*;
+use common::msg_id::RESERVED_MSG_ID;
+use common::opcodes;
+
+use crate::link::UhfLink;
+use crate::tracker::CmdTracker;
+
+fn help() {
+    println!("Usage: KEY <subcommand>, where <subcommand> is:");
+    println!("  INIT                 create a new key store, to be copied to the satellite before launch");
+    println!("  SHOW                 list the populated key slots and the active slot");
+    println!("  GEN <slot>           generate a new key and install it in <slot> on the ground and the satellite");
+    println!("  LOAD <slot> <hex>    load a key into <slot> on the ground only");
+    println!("  SELECT <slot>        switch the ground and the satellite to the key in <slot>");
+}
+
+pub fn is_key_cmd(input: &str) -> bool {
+    input.split(' ').next().is_some_and(|cmd| cmd.eq_ignore_ascii_case("KEY"))
+}
+
+fn parse_slot(token: Option<&&str>) -> Option<u8> {
+    match token.and_then(|t| t.parse::<u8>().ok()) {
+        Some(slot) if (slot as usize) < KEY_SLOTS => Some(slot),
+        _ => {
+            println!("Expected a key slot from 0 to {}", KEY_SLOTS - 1);
+            None
+        }
+    }
+}
+
+fn key_mgmt_msg(opcode: opcodes::COMS, body: Vec<u8>) -> Msg {
+    Msg::new(MsgType::Cmd, RESERVED_MSG_ID, ComponentIds::COMS as u8, ComponentIds::GS as u8, opcode as u8, body)
+}
+
+/// Uplink a key management cmd sealed with the master key. Returns true if the satellite acked it
+fn uplink_with_master(uhf_iface: &mut UhfLink, store: &mut KeyStore, tracker: &mut CmdTracker, mut msg: Msg, input: &str) -> bool {
+    let msg_id = tracker.track(&mut msg, input);
+    let sealed = match store.seal_with_master(msg) {
+        Ok(sealed) => sealed,
+        Err(e) => {
+            eprintln!("Sealing command failed: {}", e);
+            return false;
+        }
+    };
+    match crate::uplink(uhf_iface, sealed, tracker) {
+        Some(ack) => {
+            tracker.on_ack(msg_id, &ack);
+            ack.ack_code() == Some(Ok(AckCode::Success))
+        }
+        None => false,
+    }
+}
+
+/// Run a KEY cmd entered by the operator
+pub fn run_cmd(input: &str, uhf_iface: &mut UhfLink, key_store: &mut Option<KeyStore>, tracker: &mut CmdTracker) {
+    let tokens: Vec<&str> = input.split(' ').filter(|t| !t.is_empty()).collect();
+    let subcmd = tokens.get(1).map(|t| t.to_uppercase()).unwrap_or_default();
+
+    if subcmd == "INIT" {
+        let path = KeyStore::default_path();
+        if key_store.is_some() || path.exists() {
+            println!("Key store {:?} already exists", path);
+            return;
+        }
+        match KeyStore::generate(&path) {
+            Ok(store) => {
+                println!("Created key store {:?}, copy it to the satellite as its key store", path);
+                *key_store = Some(store);
+            }
+            Err(e) => eprintln!("Creating key store failed: {}", e),
+        }
+        return;
+    }
+
+    let Some(store) = key_store.as_mut() else {
+        println!("No key store loaded, create one with KEY INIT");
+        return;
+    };
+
+    match subcmd.as_str() {
+        "SHOW" => {
+            for slot in 0..KEY_SLOTS as u8 {
+                if store.slot(slot).is_some() {
+                    let active = if slot == store.active_slot() { " (active)" } else { "" };
+                    println!("slot {}{}", slot, active);
+                }
+            }
+        }
+        "GEN" => {
+            let Some(slot) = parse_slot(tokens.get(2)) else { return };
+            if slot == store.active_slot() {
+                println!("Can't replace the active key, select another slot first");
+                return;
+            }
+            let key = random_key();
+            let msg = key_mgmt_msg(opcodes::COMS::RotateKey, rotate_key_body(slot, &key));
+            if uplink_with_master(uhf_iface, store, tracker, msg, input) {
+                match store.set_slot(slot, key) {
+                    Ok(()) => println!("Installed new key in slot {}", slot),
+                    Err(e) => eprintln!("Satellite has the new key, but saving it failed: {}", e),
+                }
+            }
+        }
+        "LOAD" => {
+            let Some(slot) = parse_slot(tokens.get(2)) else { return };
+            let mut key = [0u8; KEY_SIZE];
+            if hex::decode_to_slice(tokens.get(3).unwrap_or(&""), &mut key).is_err() {
+                println!("Expected the key as {} hex digits", KEY_SIZE * 2);
+                return;
+            }
+            match store.set_slot(slot, key) {
+                Ok(()) => println!("Loaded key into slot {}", slot),
+                Err(e) => eprintln!("Loading key failed: {}", e),
+            }
+        }
+        "SELECT" => {
+            let Some(slot) = parse_slot(tokens.get(2)) else { return };
+            if store.slot(slot).is_none() {
+                println!("Key slot {} is empty", slot);
+                return;
+            }
+            let msg = key_mgmt_msg(opcodes::COMS::SelectKey, vec![slot]);
+            // Only switch once the satellite has, or following cmds would fail to authenticate
+            if uplink_with_master(uhf_iface, store, tracker, msg, input) {
+                match store.select(slot) {
+                    Ok(()) => println!("Switched to key slot {}", slot),
+                    Err(e) => eprintln!("Satellite switched keys, but saving the switch failed: {}", e),
+                }
+            }
+        }
+        _ => help(),
+    }
+}
@@ -0,0 +1,83 @@
+/*
+The GS's end of the UHF link to the satellite.
+
+The link is a TCP stream, so a read can bring down several frames at once, or stop partway through
+one. The bytes after the last whole frame are kept here until the rest of the frame arrives.
+*/
+
+use std::io::{Error as IoError, ErrorKind};
+use std::net::TcpStream;
+
+use common::constants::BULK_PACKET_SIZE_BYTES;
+use common::message_structure::*;
+use interface::{tcp::TcpInterface, Interface};
+
+pub struct UhfLink {
+    iface: TcpInterface,
+    /// Bytes read that don't make a whole frame yet
+    received: Vec<u8>,
+}
+
+impl UhfLink {
+    pub fn new(iface: TcpInterface) -> UhfLink {
+        UhfLink { iface, received: vec![] }
+    }
+
+    /// The socket, to poll it or set its read timeout
+    pub fn stream(&self) -> &TcpStream {
+        &self.iface.stream
+    }
+
+    pub fn send(&mut self, frame: &[u8]) -> Result<usize, IoError> {
+        self.iface.send(frame)
+    }
+
+    /// Next frame that has already been read in whole, without reading the link
+    pub fn buffered(&mut self) -> Option<Result<Msg, DecodeError>> {
+        next_link_msg(&mut self.received)
+    }
+
+    /// Next frame sent down, reading the link once if a whole one hasn't been read yet.
+    /// Returns None if that read didn't finish a frame.
+    pub fn read_msg(&mut self) -> Result<Option<Msg>, IoError> {
+        if let Some(frame) = self.buffered() {
+            return Ok(Some(frame?));
+        }
+        let mut buf = [0u8; BULK_PACKET_SIZE_BYTES + MAX_CRC_SIZE];
+        let len = self.iface.read(&mut buf)?;
+        if len == 0 {
+            return Err(IoError::new(ErrorKind::UnexpectedEof, "satellite connection ended"));
+        }
+        self.received.extend_from_slice(&buf[..len]);
+        Ok(self.buffered().transpose()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::constants::LINK_CRC;
+    use std::io::Write;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_frames_are_reframed() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut link = UhfLink::new(TcpInterface::new_client("127.0.0.1".to_string(), port).unwrap());
+        let (mut satellite, _) = listener.accept().unwrap();
+        let frame = |msg_id| serialize_msg(&Msg::new(MsgType::Ack, msg_id, 0, 2, 0, vec![]).with_crc(LINK_CRC)).unwrap();
+        let third = frame(3);
+
+        satellite.write_all(&[frame(1), frame(2), third[..HEADER_SIZE].to_vec()].concat()).unwrap();
+        assert_eq!(link.read_msg().unwrap().unwrap().header.msg_id, 1);
+        assert_eq!(link.buffered().unwrap().unwrap().header.msg_id, 2);
+        assert!(link.buffered().is_none());
+
+        satellite.write_all(&third[HEADER_SIZE..]).unwrap();
+        assert_eq!(link.read_msg().unwrap().unwrap().header.msg_id, 3);
+
+        drop(satellite);
+        assert_eq!(link.read_msg().unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    }
+}
@@ -13,21 +13,30 @@ TODO
 */
 mod bulk;
 mod eps;
+mod keys;
+mod link;
+mod schedule;
+mod sequence;
 mod shell;
+mod time;
+mod tracker;
 
-use common::{ports, ComponentIds};
+use common::{config, constants::LINK_CRC, registry, ComponentIds};
 use common::message_structure::*;
+use common::msg_id::RESERVED_MSG_ID;
+use common::key_store::KeyStore;
 use interface::{tcp::*, Interface};
+use link::UhfLink;
+use tracker::CmdTracker;
 
 use std::str::from_utf8;
 
 use std::io::Write;
 use std::process;
 use std::os::fd::{AsFd};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
-use strum::IntoEnumIterator;
 
 const STDIN_PFD: usize = 0;
 const UHF_PFD: usize = 1;
@@ -39,7 +48,7 @@ const ACK_TIMEOUT: u64 = 10; // seconds a receiver (GS or SC) will wait before t
 //TODO - get file if one already this time the 'program is run' - then properly append JSON data (right now it just appends json data entirely)
 //TODO - get the current users name
 //TODO - Store the associated build msg with the operator entered string (if the msg is built successfully)
-/// Store string entered by operator in a JSON file, with other metadata like timestamp, operator name, TBD ...
+// Store string entered by operator in a JSON file, with other metadata like timestamp, operator name, TBD ...
 // fn store_operator_entered_string(operator_str: String) {
 //     // Write the operator entered string to a file using JSON, with a time stamp
 //     let utc: DateTime<Utc> = Utc::now();
@@ -66,13 +75,22 @@ fn help() {
     for x in ComponentIds::iter() {
         println!("  {}", x);
     }
+    println!("<payload> help lists the opcodes of a payload");
+    println!("Scheduler ScheduleCmd <time> <payload> <opcode> <args> runs a cmd at <time>, which is");
+    println!("  +<seconds> from now, a Unix time or an RFC 3339 time");
+    println!("Scheduler ScheduleOnEvent <event>[>N | <N][+seconds] <payload> <opcode> <args> runs a cmd");
+    println!("  on an event, e.g. Boot+60, GsPassStart or BatteryVoltage>7400");
+    println!("KEY <subcommand>, see KEY help");
+    println!("SEQ <subcommand> checks and uploads cmd sequences, see SEQ help");
+    println!("TIME SYNC sets the satellite's clock to this machine's, allowing for the round trip");
+    println!("STATUS lists the state of recently sent commands");
     println!("quit/exit");
     println!("help/?");
 }
 
 /// Build a message from operator input string, where values are delimited by spaces.
 /// 1st value is the destination component string - converted to equivalent component id.
-/// 2nd value is opcode num - converted from ascii to a byte.
+/// 2nd value is the opcode name or number, which must be one of the payload's opcodes in the registry.
 /// Remaining values are the data - converted from ascii into bytes.
 fn build_msg_from_operator_input(operator_str: String) -> Option<Msg> {
     //Parse input string by spaces
@@ -89,37 +107,54 @@ fn build_msg_from_operator_input(operator_str: String) -> Option<Msg> {
         return None;
     }
 
-    let cmd = input_tokens[0].to_uppercase();
-    let payload = match ComponentIds::iter().find(|x| cmd == format!("{x}")) {
-        Some(p) => p,
-        None => {
-            let p = ComponentIds::BulkMsgDispatcher;
-            if input_tokens[0] == format!("{p}") {
-                p
-            }
-            else {
-                println!("Unknown payload: {}", input_tokens[0]);
-                return None;
-            }
+    let payload = match registry::component(input_tokens[0]).map(|c| ComponentIds::try_from(c.id)) {
+        Some(Ok(p)) => p,
+        _ => {
+            println!("Unknown payload: {}", input_tokens[0]);
+            return None;
         }
     };
 
     let mut opcode = 0;
-    let msg_type = MsgType::Cmd as u8;
+    let msg_type = MsgType::Cmd;
 
     let msg_body = match payload {
         ComponentIds::BulkMsgDispatcher => bulk::parse_cmd(&input_tokens[1..]),
+        // Times can be relative or dates, events are named, and the cmd to schedule is typed after its
+        // time or trigger like any other cmd
+        ComponentIds::Scheduler if schedule::parsed_here(input_tokens[1]).is_some() => {
+            let op = schedule::parsed_here(input_tokens[1]).unwrap();
+            opcode = op as u8;
+            schedule::parse_cmd(op, &input_tokens[2..])
+        },
 //             ComponentIds::EPS => eps::parse_cmd(&input_tokens[1..]), Why?
         ComponentIds::SHELL => shell::parse_cmd(&input_tokens[1..]),
-        _ => {
-            opcode = input_tokens[1].parse::<u8>().unwrap();
-            Some(input_tokens[2..].join(" ").as_bytes().to_vec())
+        _ => match payload.info().opcode(input_tokens[1]) {
+            Some(op) => {
+                opcode = op.value;
+                match op.encode(&input_tokens[2..]) {
+                    Ok(body) => Some(body),
+                    Err(e) => {
+                        println!("Bad arguments for {} {}: {}", payload, op.name, e);
+                        println!("{}", payload.info().usage());
+                        None
+                    }
+                }
+            },
+            None => {
+                if input_tokens[1] != "help" {
+                    println!("Unknown opcode for {}: {}", payload, input_tokens[1]);
+                }
+                println!("{}", payload.info().usage());
+                None
+            }
         }
     };
 
     match msg_body {
         Some(b) => {
-            let msg = Msg::new(msg_type, 0, payload as u8, ComponentIds::GS as u8, opcode, b);
+            // The msg_id is allocated when the msg is tracked
+            let msg = Msg::new(msg_type, RESERVED_MSG_ID, payload as u8, ComponentIds::GS as u8, opcode, b);
             // println!("Built msg: {:?}", msg);
             Some(msg)
         },
@@ -127,15 +162,22 @@ fn build_msg_from_operator_input(operator_str: String) -> Option<Msg> {
     }
 }
 
-/// Takes mutable reference to the awaiting ack flag, derefs it and sets the value
-fn handle_response(msg: &Msg) {
-    //TODO - handle if the Ack is OK or ERR , OR not an ACK at all
-    if msg.header.op_code == AckCode::Failed as u8 {
-        match std::str::from_utf8(&msg.msg_body) {
-            Ok(s) => println!("Command failed: {}", s),
-            Err(e) => println!("Command failed, respnse corrupt: {}", e),
-        }
-        return;
+/// Handle a msg downlinked from the satellite. Acks and Reports update the state of the cmd they are for
+fn handle_response(msg: &Msg, tracker: &mut CmdTracker) {
+    match msg.header.msg_type {
+        MsgType::Ack => return tracker.on_ack(msg.header.msg_id, msg),
+        // The table a ListTasks downlinks is printed, rather than shown as the cmd's state
+        MsgType::Report if msg.header.source_id == ComponentIds::Scheduler as u8
+                           && tracker.input(msg.header.msg_id).is_some_and(schedule::is_list) => {
+            return tracker.on_report(&schedule::handle_list_report(msg));
+        },
+        MsgType::Report => return tracker.on_report(msg),
+        _ => (),
+    }
+
+    match tracker.on_response(msg) {
+        Some(input) => println!("[{}] response to {}:", msg.header.msg_id, input),
+        None => println!("[{}] unsolicited msg:", msg.header.msg_id),
     }
 
     if let Ok(payload) = ComponentIds::try_from(msg.header.source_id) {
@@ -144,13 +186,90 @@ fn handle_response(msg: &Msg) {
             ComponentIds::EPS => eps::handle_response(msg),
             ComponentIds::SHELL => shell::handle_response(msg),
             _ => {
-                println!("response from {:?}: {:?}", payload, msg);
+                println!("Received Message: {:?}, body {:?} = {:?}",
+                         msg.header, msg.msg_body, String::from_utf8_lossy(&msg.msg_body));
             },
         }
     };
 }
 
-fn send_cmd(uhf_iface: &mut TcpInterface) {
+/// Read one frame sent down by the satellite
+fn read_downlink(uhf_iface: &mut UhfLink) -> Option<Msg> {
+    match uhf_iface.read_msg() {
+        Ok(msg) => msg,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+            println!("satellite connection ended");
+            None
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+            println!("Received garbled frame: {}", e);
+            None
+        },
+        Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => None,
+        Err(e) => {
+            println!("read from satellite failed: {}", e);
+            None
+        },
+    }
+}
+
+/// A frame that came down in the same read as an earlier one, which poll won't wake for
+fn buffered_downlink(uhf_iface: &mut UhfLink) -> Option<Msg> {
+    match uhf_iface.buffered()? {
+        Ok(msg) => Some(msg),
+        Err(e) => {
+            println!("Received garbled frame: {}", e);
+            None
+        },
+    }
+}
+
+/// Handle a frame the satellite sent down on its own, rather than in response to an uplink
+fn handle_downlink(uhf_iface: &mut UhfLink, msg: Msg, tracker: &mut CmdTracker) {
+    if msg.header.msg_type == MsgType::Bulk {
+        let msg_id = msg.header.msg_id;
+        tracker.on_bulk_start(msg_id);
+        let result = bulk::process_download(uhf_iface, &msg);
+        tracker.on_bulk_done(msg_id, result);
+    } else {
+        handle_response(&msg, tracker);
+    }
+}
+
+/// Uplink a sealed msg to the satellite, and wait for the Ack for it. Anything else that comes down
+/// in the meantime, like Reports on earlier cmds, is handled as it arrives.
+fn uplink(uhf_iface: &mut UhfLink, msg: Msg, tracker: &mut CmdTracker) -> Option<Msg> {
+    let msg_id = msg.header.msg_id;
+    let frame = serialize_msg(&msg.with_crc(LINK_CRC)).unwrap();
+    match uhf_iface.send(&frame) {
+        Ok(len) => println!("Sent {} bytes to Coms handler", len),
+        Err(e) => {
+            eprintln!("Send to Satellite failed: {}", e);
+            return None;
+        }
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(ACK_TIMEOUT);
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()).filter(|d| !d.is_zero()) {
+        let _ = uhf_iface.stream().set_read_timeout(Some(remaining));
+        let Some(response) = read_downlink(uhf_iface) else {
+            continue;
+        };
+        // A frame too damaged to read its msg_id is NACKed with the reserved id
+        let for_this_cmd = response.header.msg_id == msg_id
+            || (response.header.msg_id == RESERVED_MSG_ID && response.ack_code() == Some(Ok(AckCode::Corrupted)));
+        if response.header.msg_type == MsgType::Ack && for_this_cmd {
+            let _ = uhf_iface.stream().set_read_timeout(None);
+            return Some(response);
+        }
+        handle_downlink(uhf_iface, response, tracker);
+    }
+    let _ = uhf_iface.stream().set_read_timeout(None);
+    tracker.on_timeout(msg_id);
+    None
+}
+
+fn send_cmd(uhf_iface: &mut UhfLink, key_store: &mut Option<KeyStore>, tracker: &mut CmdTracker) {
     let mut input = String::new();
     let stdin = std::io::stdin();
     match stdin.read_line(&mut input) {
@@ -162,37 +281,41 @@ fn send_cmd(uhf_iface: &mut TcpInterface) {
     }
 
     let input = input.trim().to_string();
-    match build_msg_from_operator_input(input) {
-        Some(mstruct) => {
-            let msg = serialize_msg(&mstruct).unwrap();
-            match uhf_iface.send(&msg) {
-                Ok(len) => println!("Sent {} bytes to Coms handler", len),
-                Err(e) => {
-                    eprintln!("Send to Satellite failed: {}", e);
-                    return;
-                }
-            }
-        },
-        None => return, // No message to send
-    };
-
-    let mut buf = [0u8; 128];
-
-    let _ = uhf_iface.stream.set_read_timeout(Some(Duration::from_secs(ACK_TIMEOUT)));
+    if keys::is_key_cmd(&input) {
+        keys::run_cmd(&input, uhf_iface, key_store, tracker);
+        return;
+    }
+    if sequence::is_seq_cmd(&input) {
+        sequence::run_cmd(&input, uhf_iface, key_store, tracker);
+        return;
+    }
+    if time::is_time_sync_cmd(&input) {
+        time::sync(uhf_iface, key_store, tracker);
+        return;
+    }
+    if input.eq_ignore_ascii_case("STATUS") {
+        tracker.print_status();
+        return;
+    }
 
-    match uhf_iface.read(&mut buf) {
-        Ok(len) => {
-            if len == 0 {
-                println!("satellite connection ended");
-            }
-            else {
-                match deserialize_msg(&buf) {
-                    Ok(response) => handle_response(&response),
-                    Err(e) => println!("Response garbled: {}", e),
-                };
-            }
-        },
-        Err(e) => println!("read from satellite failed: {}", e),
+    let Some(mut mstruct) = build_msg_from_operator_input(input.clone()) else {
+        return; // No message to send
+    };
+    // Every uplinked command has to be sealed with the link key shared with the satellite
+    let Some(store) = key_store.as_mut() else {
+        eprintln!("No key store loaded, create one with KEY INIT");
+        return;
+    };
+    let msg_id = tracker.track(&mut mstruct, &input);
+    let sealed = match store.seal(mstruct) {
+        Ok(msg) => msg,
+        Err(e) => {
+            eprintln!("Sealing command failed: {}", e);
+            return;
+        }
+    };
+    if let Some(ack) = uplink(uhf_iface, sealed, tracker) {
+        tracker.on_ack(msg_id, &ack);
     }
 }
 
@@ -222,13 +345,23 @@ fn beacon_listen(beacon_iface: &mut TcpInterface) {
 }
 
 fn main() {
-    let ipaddr = std::env::args().nth(1).unwrap_or("localhost".to_string());
+    let gs_config = &config::get().ground_station;
+    let ipaddr = std::env::args().nth(1).unwrap_or(gs_config.uhf_host.clone());
+
+    let mut key_store = match KeyStore::load_default() {
+        Ok(store) => Some(store),
+        Err(e) => {
+            eprintln!("Can't load key store {:?}: {e}", KeyStore::default_path());
+            eprintln!("Commands can't be sent until one is created with KEY INIT");
+            None
+        }
+    };
 
     eprintln!("Connecting to UHF channel via TCP at {ipaddr}...");
     // Create tcp client listening to simulated uhf server.
     let mut uhf_iface =
-        match TcpInterface::new_client(ipaddr.to_string(), ports::SIM_ESAT_UHF_PORT) {
-            Ok(ti) => ti,
+        match TcpInterface::new_client(ipaddr.to_string(), gs_config.uhf_port) {
+            Ok(ti) => UhfLink::new(ti),
             Err(e) => {
                 eprintln!("Can't connect to satellite: {e}");
                 process::exit(1);
@@ -238,7 +371,7 @@ fn main() {
     eprintln!("Connecting to beacon broadcast channel via TCP at {ipaddr}...");
     // Create tcp client listening to simulated uhf beacon server.
     let mut beacon_iface =
-        match TcpInterface::new_client(ipaddr.to_string(), ports::SIM_ESAT_BEACON_PORT) {
+        match TcpInterface::new_client(ipaddr.to_string(), gs_config.beacon_port) {
             Ok(ti) => ti,
             Err(e) => {
                 eprintln!("Can't connect to beacon port: {e}");
@@ -248,11 +381,12 @@ fn main() {
 
     let stdin_stream = std::io::stdin();
     let stdin_pfd = PollFd::new(stdin_stream.as_fd(), PollFlags::POLLIN);
-    let uhf_stream = uhf_iface.stream.try_clone().unwrap();
+    let uhf_stream = uhf_iface.stream().try_clone().unwrap();
     let uhf_pfd = PollFd::new(uhf_stream.as_fd(), PollFlags::POLLIN);
     let beacon_stream = beacon_iface.stream.try_clone().unwrap();
     let beacon_pfd = PollFd::new(beacon_stream.as_fd(), PollFlags::POLLIN);
     let mut fds = [stdin_pfd, beacon_pfd, uhf_pfd];
+    let mut tracker = CmdTracker::new();
 
     loop {
         print!("ex3> ");
@@ -280,7 +414,7 @@ fn main() {
         for flag in stdin_events {
             match flag {
                 PollFlags::POLLIN => {
-                    send_cmd(&mut uhf_iface);
+                    send_cmd(&mut uhf_iface, &mut key_store, &mut tracker);
                 },
                 PollFlags::POLLHUP => {
                     eprintln!("Lost stdin connection");
@@ -318,7 +452,13 @@ fn main() {
         let uhf_events = fds[UHF_PFD].revents().expect("Unexpected UHF event");
         for flag in uhf_events {
             match flag {
-                PollFlags::POLLIN => bulk::process_download(&mut uhf_iface),
+                PollFlags::POLLIN => {
+                    let mut downlinked = read_downlink(&mut uhf_iface);
+                    while let Some(msg) = downlinked {
+                        handle_downlink(&mut uhf_iface, msg, &mut tracker);
+                        downlinked = buffered_downlink(&mut uhf_iface);
+                    }
+                },
                 PollFlags::POLLHUP => {
                     eprintln!("Lost UHF connection");
                     beacon_iface.close();
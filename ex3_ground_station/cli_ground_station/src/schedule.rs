@@ -0,0 +1,304 @@
+/*
+Scheduler cmds that take a time or an event, and the table of tasks the Scheduler downlinks:
+
+    Scheduler ScheduleCmd <time> <payload> <opcode> <args>         run a cmd at <time>
+    Scheduler ScheduleOnEvent <trigger> <payload> <opcode> <args>  run a cmd on <trigger>
+    Scheduler SetTaskTime <task id> <time>                         move a task to <time>
+    Scheduler PublishEvent <event> [value]                         raise an event onboard
+    Scheduler ListTasks [first]                                    print the schedule from the first-th task
+
+<time> is +<seconds> from now, a Unix time in seconds, or an RFC 3339 UTC time like
+2024-06-01T12:00:00Z. <trigger> is an event (see common::events) by name, optionally followed by >N or
+<N for values above or below N, and +<seconds> to run that long after the event, e.g. Boot+60,
+GsPassStart or BatteryVoltage>7400. The cmd to schedule is typed as it would be to send it straight
+away. The other Scheduler opcodes (CancelTask, ShiftTask, ClearTasks) take plain args, like those of any
+payload.
+*/
+
+use chrono::{DateTime, Utc};
+use common::component_ids::ComponentIds;
+use common::events::{EventKind, EVENT_KINDS};
+use common::message_structure::{serialize_msg, Msg, ResultCode};
+use common::schedule::{encode_time, Condition, EventTrigger, TaskTable};
+use common::{opcodes, registry};
+
+fn usage() {
+    let time = "<+seconds | unix time | RFC 3339 time>";
+    println!("Usage: {} ScheduleCmd {} <payload> <opcode> <args>", ComponentIds::Scheduler, time);
+    println!("       {} ScheduleOnEvent <event>[>N | <N][+seconds] <payload> <opcode> <args>", ComponentIds::Scheduler);
+    println!("       {} SetTaskTime <task id> {}", ComponentIds::Scheduler, time);
+    println!("       {} PublishEvent <event> [value]", ComponentIds::Scheduler);
+    let events: Vec<String> = EVENT_KINDS.iter().map(|e| e.to_string()).collect();
+    println!("Events: {}", events.join(", "));
+}
+
+/// The Scheduler opcode `name_or_value` names, if it is one of those whose args are parsed here
+pub fn parsed_here(name_or_value: &str) -> Option<opcodes::Scheduler> {
+    let info = ComponentIds::Scheduler.info().opcode(name_or_value)?;
+    match opcodes::Scheduler::from(info.value) {
+        op @ (opcodes::Scheduler::ScheduleCmd | opcodes::Scheduler::SetTaskTime
+              | opcodes::Scheduler::ScheduleOnEvent | opcodes::Scheduler::PublishEvent) => Some(op),
+        _ => None,
+    }
+}
+
+/// Whether the operator's `input` was a ListTasks
+pub fn is_list(input: &str) -> bool {
+    let mut tokens = input.split(' ');
+    tokens.next().and_then(registry::component).is_some_and(|c| c.id == ComponentIds::Scheduler as u8)
+        && tokens.next().and_then(|op| ComponentIds::Scheduler.info().opcode(op))
+            .is_some_and(|op| op.value == opcodes::Scheduler::ListTasks as u8)
+}
+
+/// Parse a time to run a cmd at, into ms since the Unix epoch
+fn parse_time(token: &str, now: DateTime<Utc>) -> Result<u64, String> {
+    let time = if let Some(delay) = token.strip_prefix('+') {
+        let delay: u32 = delay.parse().map_err(|_| format!("bad delay '{}', expected whole seconds", delay))?;
+        now + chrono::Duration::seconds(delay as i64)
+    } else if let Ok(secs) = token.parse::<i64>() {
+        DateTime::from_timestamp(secs, 0).ok_or_else(|| format!("bad Unix time {}", secs))?
+    } else {
+        token.parse::<DateTime<Utc>>().map_err(|e| format!("bad time '{}': {}", token, e))?
+    };
+    if time <= now {
+        return Err(format!("{} is in the past", time));
+    }
+    Ok(time.timestamp_millis() as u64)
+}
+
+/// Parse a trigger like BatteryVoltage>7400+60
+fn parse_trigger(token: &str) -> Result<EventTrigger, String> {
+    let (rest, delay) = match token.split_once('+') {
+        Some((rest, delay)) => (rest, delay.parse().map_err(|_| format!("bad delay '{}', expected whole seconds", delay))?),
+        None => (token, 0),
+    };
+    let threshold = |value: &str| value.parse::<i32>().map_err(|_| format!("bad threshold '{}'", value));
+    let (event, condition) = if let Some((event, value)) = rest.split_once('>') {
+        (event, Condition::Above(threshold(value)?))
+    } else if let Some((event, value)) = rest.split_once('<') {
+        (event, Condition::Below(threshold(value)?))
+    } else {
+        (rest, Condition::Any)
+    };
+    let event = event.parse::<EventKind>().map_err(|e| e.to_string())?;
+    Ok(EventTrigger { event, condition, delay })
+}
+
+/// Build the body of `opcode`, one of those `parsed_here`, from the args after it
+pub fn parse_cmd(opcode: opcodes::Scheduler, input: &[&str]) -> Option<Vec<u8>> {
+    match opcode {
+        opcodes::Scheduler::ScheduleOnEvent => return parse_on_event(input),
+        opcodes::Scheduler::PublishEvent => return parse_publish(input),
+        _ => (),
+    }
+    let (time_token, min_args) = match opcode {
+        opcodes::Scheduler::SetTaskTime => (input.get(1), 2),
+        _ => (input.first(), 3),
+    };
+    let time = match time_token.filter(|_| input.len() >= min_args).map(|t| parse_time(t, Utc::now())) {
+        Some(Ok(time)) => time,
+        Some(Err(e)) => {
+            println!("{}", e);
+            usage();
+            return None;
+        }
+        None => {
+            usage();
+            return None;
+        }
+    };
+    if opcode == opcodes::Scheduler::SetTaskTime {
+        let secs = (time / 1000).to_string();
+        let info = opcode.info()?;
+        return match info.encode(&[input[0], &secs]) {
+            Ok(body) => Some(body),
+            Err(e) => {
+                println!("Bad arguments for {} {}: {}", ComponentIds::Scheduler, info.name, e);
+                None
+            }
+        };
+    }
+    let cmd = crate::build_msg_from_operator_input(input[1..].join(" "))?;
+    let mut body = encode_time(time).to_vec();
+    body.extend(serialize_msg(&cmd).ok()?);
+    Some(body)
+}
+
+fn parse_on_event(input: &[&str]) -> Option<Vec<u8>> {
+    if input.len() < 3 {
+        usage();
+        return None;
+    }
+    let trigger = match parse_trigger(input[0]) {
+        Ok(trigger) => trigger,
+        Err(e) => {
+            println!("{}", e);
+            usage();
+            return None;
+        }
+    };
+    let cmd = crate::build_msg_from_operator_input(input[1..].join(" "))?;
+    let mut body = trigger.to_bytes().to_vec();
+    body.extend(serialize_msg(&cmd).ok()?);
+    Some(body)
+}
+
+/// The event can be given by name, unlike in the registry's args
+fn parse_publish(input: &[&str]) -> Option<Vec<u8>> {
+    let event = match input.first().map(|event| event.parse::<EventKind>()) {
+        Some(Ok(event)) => (event as u8).to_string(),
+        Some(Err(e)) => {
+            println!("{}", e);
+            usage();
+            return None;
+        }
+        None => {
+            usage();
+            return None;
+        }
+    };
+    let mut args = vec![event.as_str()];
+    args.extend(&input[1..]);
+    let info = opcodes::Scheduler::PublishEvent.info()?;
+    match info.encode(&args) {
+        Ok(body) => Some(body),
+        Err(e) => {
+            println!("Bad arguments for {} {}: {}", ComponentIds::Scheduler, info.name, e);
+            None
+        }
+    }
+}
+
+/// "IRIS FetchImage" for a cmd to IRIS with opcode 2
+fn describe_cmd(dest: u8, opcode: u8) -> String {
+    match registry::component_by_id(dest) {
+        Some(c) => match c.opcode(&opcode.to_string()) {
+            Some(op) => format!("{} {}", c.name, op.name),
+            None => format!("{} {}", c.name, opcode),
+        },
+        None => format!("{} {}", dest, opcode),
+    }
+}
+
+/// Print the task table in the Report on a ListTasks, returning the Report with a summary of the table
+/// in place of it
+pub fn handle_list_report(report: &Msg) -> Msg {
+    let mut summary = report.clone();
+    // A failed ListTasks has the reason in its Report
+    if report.result_code() != Some(Ok(ResultCode::Success)) {
+        return summary;
+    }
+    let table = match TaskTable::from_bytes(&report.msg_body) {
+        Ok(table) => table,
+        Err(e) => {
+            summary.msg_body = format!("garbled task table - {}", e).into_bytes();
+            return summary;
+        }
+    };
+    if table.rows.is_empty() {
+        println!("No tasks from {} of {}", table.first, table.total);
+    } else {
+        let first = table.first as usize;
+        println!("Tasks {}-{} of {}:", first + 1, first + table.rows.len(), table.total);
+        println!("{:>5}  {:<25}  {:<9}  {:<24}  {:>5}", "id", "time (UTC)", "state", "cmd", "msg");
+        for row in &table.rows {
+            let time = match row.trigger {
+                Some(trigger) => format!("on {}", trigger),
+                None => DateTime::from_timestamp_millis(row.time as i64)
+                    .map_or(format!("{} ms", row.time), |t| t.format("%Y-%m-%d %H:%M:%S%.3f").to_string()),
+            };
+            println!("{:>5}  {:<25}  {:<9}  {:<24}  {:>5}", row.id, time, row.state.to_string(),
+                     describe_cmd(row.dest, row.opcode), row.msg_id);
+        }
+        let next = first + table.rows.len();
+        if next < table.total as usize {
+            println!("More with: {} ListTasks {}", ComponentIds::Scheduler, next);
+        }
+    }
+    summary.msg_body = format!("listed {} of {} tasks", table.rows.len(), table.total).into_bytes();
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_time() {
+        let now = DateTime::from_timestamp(1_717_200_000, 0).unwrap();
+        assert_eq!(parse_time("+90", now), Ok(1_717_200_090_000));
+        assert_eq!(parse_time("1717300000", now), Ok(1_717_300_000_000));
+        assert_eq!(parse_time("2024-06-01T12:00:00Z", now), Ok(1_717_243_200_000));
+        assert!(parse_time("+0", now).is_err());
+        assert!(parse_time("1717100000", now).is_err());
+        assert!(parse_time("+1.5", now).is_err());
+        assert!(parse_time("noon", now).is_err());
+    }
+
+    #[test]
+    fn test_parse_cmd() {
+        let schedule = opcodes::Scheduler::ScheduleCmd;
+        let body = parse_cmd(schedule, &["+60", "IRIS", "FetchImage", "2"]).unwrap();
+        let cmd = common::message_structure::deserialize_msg(&body[common::schedule::TIME_SIZE..]).unwrap();
+        assert_eq!(cmd.header.dest_id, ComponentIds::IRIS as u8);
+        assert_eq!(cmd.header.op_code, 2);
+        assert_eq!(cmd.msg_body, [2]);
+        assert!(parse_cmd(schedule, &["+60", "IRIS", "FetchImage", "0"]).is_none());
+        assert!(parse_cmd(schedule, &["+60", "IRIS"]).is_none());
+
+        let set_time = opcodes::Scheduler::SetTaskTime;
+        let body = parse_cmd(set_time, &["7", "2100-01-01T00:00:00Z"]).unwrap();
+        assert_eq!(body, [7, 0, 0x00, 0x57, 0x86, 0xf4]);
+        assert!(parse_cmd(set_time, &["7"]).is_none());
+        assert!(parse_cmd(set_time, &["x", "+60"]).is_none());
+        assert_eq!(parsed_here("settasktime"), Some(set_time));
+        assert_eq!(parsed_here("0"), Some(schedule));
+        assert_eq!(parsed_here("ListTasks"), None);
+    }
+
+    #[test]
+    fn test_parse_trigger() {
+        let trigger = |event, condition, delay| Ok(EventTrigger { event, condition, delay });
+        assert_eq!(parse_trigger("Boot+60"), trigger(EventKind::Boot, Condition::Any, 60));
+        assert_eq!(parse_trigger("gspassstart"), trigger(EventKind::GsPassStart, Condition::Any, 0));
+        assert_eq!(parse_trigger("BatteryVoltage>7400"), trigger(EventKind::BatteryVoltage, Condition::Above(7400), 0));
+        assert_eq!(parse_trigger("BatteryVoltage<-5+30"), trigger(EventKind::BatteryVoltage, Condition::Below(-5), 30));
+        assert!(parse_trigger("Eclipse").is_err());
+        assert!(parse_trigger("Boot+soon").is_err());
+        assert!(parse_trigger("BatteryVoltage>high").is_err());
+
+        let on_event = opcodes::Scheduler::ScheduleOnEvent;
+        let body = parse_cmd(on_event, &["GsPassStart+10", "EPS", "GetHK"]).unwrap();
+        assert_eq!(EventTrigger::from_bytes(&body).unwrap(), parse_trigger("GsPassStart+10").unwrap());
+        let cmd = common::message_structure::deserialize_msg(&body[common::schedule::TRIGGER_SIZE..]).unwrap();
+        assert_eq!(cmd.header.dest_id, ComponentIds::EPS as u8);
+        assert!(parse_cmd(on_event, &["GsPassStart", "EPS"]).is_none());
+
+        let publish = opcodes::Scheduler::PublishEvent;
+        assert_eq!(parse_cmd(publish, &["BatteryVoltage", "7400"]).unwrap(), [3, 0xe8, 0x1c, 0, 0]);
+        assert_eq!(parse_cmd(publish, &["GsPassEnd"]).unwrap(), [2]);
+        assert!(parse_cmd(publish, &["Eclipse"]).is_none());
+        assert_eq!(parsed_here("PublishEvent"), Some(publish));
+    }
+
+    #[test]
+    fn test_list_report() {
+        assert!(is_list("Scheduler ListTasks 8"));
+        assert!(is_list("scheduler 1"));
+        assert!(!is_list("Scheduler CancelTask 1"));
+        assert!(!is_list("IRIS 1"));
+
+        let list = Msg::new(common::message_structure::MsgType::Cmd, 5, ComponentIds::Scheduler as u8,
+                            ComponentIds::GS as u8, opcodes::Scheduler::ListTasks as u8, vec![]);
+        let table = TaskTable { total: 1, first: 0, rows: vec![common::schedule::TaskRow {
+            id: 3, time: 1_717_243_200_000, state: common::schedule::MessageState::Waiting,
+            dest: ComponentIds::IRIS as u8, opcode: 2, msg_id: 5, trigger: None,
+        }] };
+        let summary = handle_list_report(&list.report(Ok(table.to_bytes())));
+        assert_eq!(summary.msg_body, b"listed 1 of 1 tasks");
+        assert_eq!(describe_cmd(ComponentIds::IRIS as u8, 2), "IRIS FetchImage");
+        assert_eq!(describe_cmd(200, 2), "200 2");
+        let failed = list.report(Err(std::io::Error::other("oops")));
+        assert_eq!(handle_list_report(&failed).msg_body, b"oops");
+    }
+}
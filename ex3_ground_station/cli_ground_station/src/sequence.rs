@@ -0,0 +1,152 @@
+use common::component_ids::ComponentIds;
+use common::constants::DOWNLINK_MSG_BODY_SIZE;
+use common::key_store::KeyStore;
+use common::link_crypto::ENVELOPE_OVERHEAD;
+use common::message_structure::*;
+use common::msg_id::RESERVED_MSG_ID;
+use common::opcodes;
+use common::sequence::{check_name, Sequence};
+
+use crate::link::UhfLink;
+use crate::tracker::CmdTracker;
+
+/// Most bytes of a sequence sent in one UploadChunk, which has to fit in one frame once it is sealed
+const CHUNK_SIZE: usize = DOWNLINK_MSG_BODY_SIZE - ENVELOPE_OVERHEAD - 2;
+
+fn help() {
+    println!("Usage: SEQ <subcommand>, where <subcommand> is:");
+    println!("  CHECK <file>           compile the sequence in <file> and list its steps");
+    println!("  UPLOAD <name> <file>   compile the sequence in <file> and upload it as <name>");
+    println!("Stored sequences are run with Sequencer StartSequence <name>");
+}
+
+pub fn is_seq_cmd(input: &str) -> bool {
+    input.split(' ').next().is_some_and(|cmd| cmd.eq_ignore_ascii_case("SEQ"))
+}
+
+fn compile(path: Option<&&str>) -> Option<Sequence> {
+    let Some(path) = path else {
+        help();
+        return None;
+    };
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => {
+            eprintln!("Cannot read {}: {}", path, e);
+            return None;
+        }
+    };
+    match Sequence::parse(&text) {
+        Ok(sequence) => Some(sequence),
+        Err(e) => {
+            println!("{}: {}", path, e);
+            None
+        }
+    }
+}
+
+fn sequencer_msg(opcode: opcodes::Sequencer, body: Vec<u8>) -> Msg {
+    Msg::new(MsgType::Cmd, RESERVED_MSG_ID, ComponentIds::Sequencer as u8, ComponentIds::GS as u8, opcode as u8, body)
+}
+
+/// The msgs that upload `sequence` as `name`
+fn upload_msgs(name: &str, sequence: &Sequence) -> Result<Vec<Msg>, String> {
+    check_name(name).map_err(|e| e.to_string())?;
+    let bytes = sequence.to_bytes().map_err(|e| e.to_string())?;
+    let begin = opcodes::Sequencer::BeginUpload.info().unwrap()
+        .encode(&[&bytes.len().to_string(), name]).map_err(|e| e.to_string())?;
+    let mut msgs = vec![sequencer_msg(opcodes::Sequencer::BeginUpload, begin)];
+    for (i, chunk) in bytes.chunks(CHUNK_SIZE).enumerate() {
+        let mut body = ((i * CHUNK_SIZE) as u16).to_le_bytes().to_vec();
+        body.extend(chunk);
+        msgs.push(sequencer_msg(opcodes::Sequencer::UploadChunk, body));
+    }
+    msgs.push(sequencer_msg(opcodes::Sequencer::EndUpload, crc32(&bytes).to_le_bytes().to_vec()));
+    Ok(msgs)
+}
+
+/// Run a SEQ cmd entered by the operator
+pub fn run_cmd(input: &str, uhf_iface: &mut UhfLink, key_store: &mut Option<KeyStore>, tracker: &mut CmdTracker) {
+    let tokens: Vec<&str> = input.split(' ').filter(|t| !t.is_empty()).collect();
+    match tokens.get(1).map(|t| t.to_uppercase()).unwrap_or_default().as_str() {
+        "CHECK" => {
+            let Some(sequence) = compile(tokens.get(2)) else { return };
+            for (i, step) in sequence.steps.iter().enumerate() {
+                println!("{:3} {}", i, step);
+            }
+            match sequence.to_bytes() {
+                Ok(bytes) => println!("{} steps in {} bytes", sequence.steps.len(), bytes.len()),
+                Err(e) => println!("{}", e),
+            }
+        }
+        "UPLOAD" => {
+            let Some(name) = tokens.get(2) else {
+                help();
+                return;
+            };
+            let Some(sequence) = compile(tokens.get(3)) else { return };
+            let msgs = match upload_msgs(name, &sequence) {
+                Ok(msgs) => msgs,
+                Err(e) => {
+                    println!("{}", e);
+                    return;
+                }
+            };
+            let Some(store) = key_store.as_mut() else {
+                eprintln!("No key store loaded, create one with KEY INIT");
+                return;
+            };
+            let count = msgs.len();
+            for (i, mut msg) in msgs.into_iter().enumerate() {
+                let msg_id = tracker.track(&mut msg, &format!("{} ({}/{})", input, i + 1, count));
+                let sealed = match store.seal(msg) {
+                    Ok(sealed) => sealed,
+                    Err(e) => {
+                        eprintln!("Sealing command failed: {}", e);
+                        return;
+                    }
+                };
+                let Some(ack) = crate::uplink(uhf_iface, sealed, tracker) else {
+                    println!("Upload of {} stopped, run SEQ UPLOAD again", name);
+                    return;
+                };
+                tracker.on_ack(msg_id, &ack);
+                if ack.ack_code() != Some(Ok(AckCode::Success)) {
+                    println!("Upload of {} stopped, run SEQ UPLOAD again", name);
+                    return;
+                }
+            }
+            println!("Uploaded {}, see STATUS for whether the satellite stored it", name);
+        }
+        _ => help(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::constants::{LINK_CRC, UHF_MAX_MESSAGE_SIZE_BYTES};
+
+    #[test]
+    fn test_upload_msgs() {
+        let sequence = Sequence::parse(&"IRIS ToggleSensor on\nwait 5\n".repeat(20)).unwrap();
+        let bytes = sequence.to_bytes().unwrap();
+        let msgs = upload_msgs("power_cycle", &sequence).unwrap();
+        assert_eq!(msgs.len(), 2 + bytes.len().div_ceil(CHUNK_SIZE));
+
+        let args = opcodes::Sequencer::BeginUpload.decode(&msgs[0].msg_body).unwrap();
+        assert_eq!(args[0].int::<usize>(), Some(bytes.len()));
+        assert_eq!(args[1].as_str(), Some("power_cycle"));
+        let mut uploaded: Vec<u8> = vec![];
+        for chunk in &msgs[1..msgs.len() - 1] {
+            assert_eq!(u16::from_le_bytes([chunk.msg_body[0], chunk.msg_body[1]]) as usize, uploaded.len());
+            assert!(HEADER_SIZE + chunk.msg_body.len() + ENVELOPE_OVERHEAD + LINK_CRC.size() <= UHF_MAX_MESSAGE_SIZE_BYTES);
+            uploaded.extend(&chunk.msg_body[2..]);
+        }
+        assert_eq!(uploaded, bytes);
+        let args = opcodes::Sequencer::EndUpload.decode(&msgs[msgs.len() - 1].msg_body).unwrap();
+        assert_eq!(args[0].int::<u32>(), Some(crc32(&bytes)));
+
+        assert!(upload_msgs("no/such", &sequence).is_err());
+    }
+}
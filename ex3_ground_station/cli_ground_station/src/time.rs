@@ -0,0 +1,78 @@
+use common::component_ids::ComponentIds;
+use common::key_store::KeyStore;
+use common::message_structure::*;
+use common::msg_id::RESERVED_MSG_ID;
+use common::opcodes;
+use std::time::Instant;
+
+use crate::link::UhfLink;
+use crate::tracker::CmdTracker;
+
+/// TIME SYNC, as other Time cmds go to the time service as they are
+pub fn is_time_sync_cmd(input: &str) -> bool {
+    let tokens: Vec<&str> = input.split(' ').filter(|t| !t.is_empty()).collect();
+    tokens.len() == 2 && tokens[0].eq_ignore_ascii_case("TIME") && tokens[1].eq_ignore_ascii_case("SYNC")
+}
+
+fn time_msg(opcode: opcodes::Time, args: &[&str]) -> Msg {
+    let body = opcode.info().unwrap().encode(args).unwrap();
+    Msg::new(MsgType::Cmd, RESERVED_MSG_ID, ComponentIds::Time as u8, ComponentIds::GS as u8, opcode as u8, body)
+}
+
+/// The SyncTime for a GS clock reading `utc_ms` and a round trip of `rtt_ms`
+fn sync_msg(utc_ms: i64, rtt_ms: u32) -> Msg {
+    time_msg(opcodes::Time::SyncTime, &[&utc_ms.to_string(), &rtt_ms.to_string()])
+}
+
+/// Seal and uplink `msg`, returning whether the satellite took it
+fn send(msg: Msg, input: &str, uhf_iface: &mut UhfLink, store: &mut KeyStore, tracker: &mut CmdTracker) -> bool {
+    let mut msg = msg;
+    let msg_id = tracker.track(&mut msg, input);
+    let sealed = match store.seal(msg) {
+        Ok(sealed) => sealed,
+        Err(e) => {
+            eprintln!("Sealing command failed: {}", e);
+            return false;
+        }
+    };
+    let Some(ack) = crate::uplink(uhf_iface, sealed, tracker) else { return false };
+    tracker.on_ack(msg_id, &ack);
+    ack.ack_code() == Some(Ok(AckCode::Success))
+}
+
+/// Sync the satellite's clock to this machine's: measure the round trip with a GetTime, then send a
+/// SyncTime with the UTC and the round trip
+pub fn sync(uhf_iface: &mut UhfLink, key_store: &mut Option<KeyStore>, tracker: &mut CmdTracker) {
+    let Some(store) = key_store.as_mut() else {
+        eprintln!("No key store loaded, create one with KEY INIT");
+        return;
+    };
+    // The Ack on the GetTime comes from the coms_handler, so it times the whole link there and back
+    let sent_at = Instant::now();
+    if !send(time_msg(opcodes::Time::GetTime, &[]), "TIME SYNC (round trip)", uhf_iface, store, tracker) {
+        println!("Cannot measure the round trip, run TIME SYNC again");
+        return;
+    }
+    let rtt_ms = sent_at.elapsed().as_millis().min(u32::MAX as u128) as u32;
+    let sync = sync_msg(chrono::Utc::now().timestamp_millis(), rtt_ms);
+    if !send(sync, "TIME SYNC", uhf_iface, store, tracker) {
+        println!("Sync not taken, run TIME SYNC again");
+        return;
+    }
+    println!("Synced with a round trip of {} ms, see STATUS for how far the clock moved", rtt_ms);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sync_msg() {
+        let msg = sync_msg(1_717_243_200_123, 850);
+        assert_eq!((msg.header.dest_id, msg.header.op_code), (ComponentIds::Time as u8, opcodes::Time::SyncTime as u8));
+        let args = opcodes::Time::SyncTime.decode(&msg.msg_body).unwrap();
+        assert_eq!((args[0].int::<i64>(), args[1].int::<u32>()), (Some(1_717_243_200_123), Some(850)));
+        assert!(is_time_sync_cmd("time  SYNC"));
+        assert!(!is_time_sync_cmd("Time GetTime"));
+    }
+}
@@ -0,0 +1,267 @@
+/*
+Keeps track of the state of each cmd the operator sends, and correlates whatever the satellite sends
+down with the cmd that caused it.
+
+A cmd goes through two stages on the satellite. The coms_handler Acks it once it has been accepted
+from the link, then the handler it was destined for sends a Report once it has executed it. Handlers
+may also send responses (e.g. shell output), and a bulk transfer comes down as a series of Bulk msgs.
+All of these carry the msg_id of the cmd, which is how they are matched up here. Msgs with an id from
+the spacecraft range were not caused by any cmd of ours.
+*/
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::Instant;
+
+use common::message_structure::*;
+use common::msg_id::{MsgIdAllocator, MsgOrigin};
+
+/// Number of cmds remembered for STATUS
+const HISTORY_LEN: usize = 50;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CmdState {
+    /// Uplinked, waiting for the Ack
+    Sent,
+    /// No Ack arrived in time - the cmd may or may not have been received
+    NoAck,
+    /// Acked by the coms_handler, waiting for the Report
+    Accepted,
+    /// The data asked for is being downlinked
+    Downlinking,
+    /// NACKed by the coms_handler, it was never executed
+    Rejected(AckCode, String),
+    /// The handler reported on executing it
+    Completed(ResultCode, String),
+}
+
+impl fmt::Display for CmdState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CmdState::Sent => write!(f, "sent"),
+            CmdState::NoAck => write!(f, "no ack"),
+            CmdState::Accepted => write!(f, "accepted, awaiting report"),
+            CmdState::Downlinking => write!(f, "downlinking"),
+            CmdState::Rejected(code, reason) => write!(f, "rejected ({}) {}", code, reason),
+            CmdState::Completed(ResultCode::Success, context) => write!(f, "completed {}", context),
+            CmdState::Completed(code, context) => write!(f, "failed ({}) {}", code, context),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TrackedCmd {
+    pub msg_id: u16,
+    /// What the operator typed
+    pub input: String,
+    pub state: CmdState,
+    /// Number of responses received for it, not counting the Ack and Report
+    pub responses: usize,
+    sent_at: Instant,
+}
+
+#[derive(Debug)]
+pub struct CmdTracker {
+    msg_ids: MsgIdAllocator,
+    cmds: VecDeque<TrackedCmd>,
+}
+
+impl CmdTracker {
+    pub fn new() -> Self {
+        CmdTracker { msg_ids: MsgIdAllocator::ground(), cmds: VecDeque::new() }
+    }
+
+    /// Give `msg` a fresh msg_id and start tracking it. Must be done before the msg is sealed.
+    pub fn track(&mut self, msg: &mut Msg, input: &str) -> u16 {
+        let msg_id = self.msg_ids.next_id();
+        msg.header.msg_id = msg_id;
+        if self.cmds.len() == HISTORY_LEN {
+            self.cmds.pop_front();
+        }
+        self.cmds.push_back(TrackedCmd {
+            msg_id,
+            input: input.to_string(),
+            state: CmdState::Sent,
+            responses: 0,
+            sent_at: Instant::now(),
+        });
+        msg_id
+    }
+
+    #[cfg(test)]
+    fn get(&self, msg_id: u16) -> Option<&TrackedCmd> {
+        self.cmds.iter().rev().find(|c| c.msg_id == msg_id)
+    }
+
+    /// The cmd a msg with `msg_id` was sent for, if it is one of ours and still remembered
+    fn get_mut(&mut self, msg_id: u16) -> Option<&mut TrackedCmd> {
+        if MsgOrigin::of(msg_id) != Some(MsgOrigin::Ground) {
+            return None;
+        }
+        self.cmds.iter_mut().rev().find(|c| c.msg_id == msg_id)
+    }
+
+    /// What the operator typed for the cmd with `msg_id`, if it is still remembered
+    pub fn input(&mut self, msg_id: u16) -> Option<&str> {
+        self.get_mut(msg_id).map(|cmd| cmd.input.as_str())
+    }
+
+    fn set_state(&mut self, msg_id: u16, state: CmdState) {
+        match self.get_mut(msg_id) {
+            Some(cmd) => {
+                cmd.state = state;
+                println!("[{}] {}: {}", cmd.msg_id, cmd.input, cmd.state);
+            }
+            None => println!("[{}] untracked cmd: {}", msg_id, state),
+        }
+    }
+
+    pub fn on_timeout(&mut self, msg_id: u16) {
+        self.set_state(msg_id, CmdState::NoAck);
+    }
+
+    /// Update a cmd's state from the Ack the coms_handler sent for it
+    pub fn on_ack(&mut self, msg_id: u16, ack: &Msg) {
+        let reason = String::from_utf8_lossy(&ack.msg_body).to_string();
+        match ack.ack_code() {
+            Some(Ok(AckCode::Success)) => self.set_state(msg_id, CmdState::Accepted),
+            Some(Ok(AckCode::Corrupted)) => {
+                self.set_state(msg_id, CmdState::Rejected(AckCode::Corrupted, "corrupted on uplink, resend it".to_string()))
+            }
+            Some(Ok(code)) => self.set_state(msg_id, CmdState::Rejected(code, reason)),
+            Some(Err(e)) => self.set_state(msg_id, CmdState::Rejected(AckCode::Failed, format!("garbled ack - {}", e))),
+            None => (),
+        }
+    }
+
+    /// Update a cmd's state from the Report of the handler that executed it
+    pub fn on_report(&mut self, report: &Msg) {
+        let context = String::from_utf8_lossy(&report.msg_body).to_string();
+        let code = match report.result_code() {
+            Some(Ok(code)) => code,
+            Some(Err(e)) => {
+                println!("[{}] garbled report - {}", report.header.msg_id, e);
+                return;
+            }
+            None => return,
+        };
+        self.set_state(report.header.msg_id, CmdState::Completed(code, context));
+    }
+
+    /// Match a response from a handler to its cmd. Returns what the operator typed for the cmd,
+    /// or None if the response isn't for one of our cmds.
+    pub fn on_response(&mut self, response: &Msg) -> Option<String> {
+        let cmd = self.get_mut(response.header.msg_id)?;
+        cmd.responses += 1;
+        Some(cmd.input.clone())
+    }
+
+    /// A bulk transfer asked for by a cmd has started coming down
+    pub fn on_bulk_start(&mut self, msg_id: u16) {
+        self.set_state(msg_id, CmdState::Downlinking);
+    }
+
+    /// A bulk transfer has finished, with the file it was saved to or the reason it failed
+    pub fn on_bulk_done(&mut self, msg_id: u16, result: Result<String, String>) {
+        let state = match result {
+            Ok(path) => CmdState::Completed(ResultCode::Success, format!("saved to {}", path)),
+            Err(e) => CmdState::Completed(ResultCode::Failed, e),
+        };
+        self.set_state(msg_id, state);
+    }
+
+    /// Print the state of recently sent cmds
+    pub fn print_status(&self) {
+        if self.cmds.is_empty() {
+            println!("No commands sent yet");
+        }
+        for cmd in &self.cmds {
+            print!("[{}] {:>4}s ago  {}: {}", cmd.msg_id, cmd.sent_at.elapsed().as_secs(), cmd.input, cmd.state);
+            match cmd.responses {
+                0 => println!(),
+                n => println!(" ({} responses)", n),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::ComponentIds;
+
+    fn cmd() -> Msg {
+        Msg::new(MsgType::Cmd, 0, ComponentIds::EPS as u8, ComponentIds::GS as u8, 3, vec![])
+    }
+
+    #[test]
+    fn test_two_stage_tracking() {
+        let mut tracker = CmdTracker::new();
+        let mut msg = cmd();
+        let id = tracker.track(&mut msg, "EPS GetHK");
+        assert_eq!(msg.header.msg_id, id);
+        assert_eq!(tracker.get(id).unwrap().state, CmdState::Sent);
+
+        let ack = Msg::new_ack(id, ComponentIds::GS as u8, ComponentIds::COMS as u8, AckCode::Success, vec![]);
+        tracker.on_ack(id, &ack);
+        assert_eq!(tracker.get(id).unwrap().state, CmdState::Accepted);
+
+        tracker.on_report(&msg.report(Ok(b"20C".to_vec())));
+        assert_eq!(tracker.get(id).unwrap().state, CmdState::Completed(ResultCode::Success, "20C".to_string()));
+    }
+
+    #[test]
+    fn test_rejected_and_failed() {
+        let mut tracker = CmdTracker::new();
+        let mut first = cmd();
+        let mut second = cmd();
+        let first_id = tracker.track(&mut first, "EPS GetHK");
+        let second_id = tracker.track(&mut second, "GPS 1");
+        assert_ne!(first_id, second_id);
+
+        let nack = Msg::new_ack(first_id, ComponentIds::GS as u8, ComponentIds::COMS as u8, AckCode::AuthFailed, b"bad tag".to_vec());
+        tracker.on_ack(first_id, &nack);
+        assert_eq!(tracker.get(first_id).unwrap().state, CmdState::Rejected(AckCode::AuthFailed, "bad tag".to_string()));
+
+        let unsupported = std::io::Error::new(std::io::ErrorKind::Unsupported, "not yet");
+        tracker.on_report(&second.report(Err(unsupported)));
+        assert_eq!(tracker.get(second_id).unwrap().state, CmdState::Completed(ResultCode::NotImplemented, "not yet".to_string()));
+    }
+
+    #[test]
+    fn test_ids_skip_zero_and_history_is_bounded() {
+        let mut tracker = CmdTracker::new();
+        tracker.msg_ids = MsgIdAllocator::ground().starting_at(0x7FFF);
+        assert_eq!(tracker.track(&mut cmd(), "a"), 0x7FFF);
+        assert_eq!(tracker.track(&mut cmd(), "b"), 1);
+        for _ in 0..HISTORY_LEN {
+            tracker.track(&mut cmd(), "c");
+        }
+        assert_eq!(tracker.cmds.len(), HISTORY_LEN);
+        assert!(tracker.get(0x7FFF).is_none());
+    }
+
+    #[test]
+    fn test_correlates_responses_and_bulk_transfers() {
+        let mut tracker = CmdTracker::new();
+        let mut shell = Msg::new(MsgType::Cmd, 0, ComponentIds::SHELL as u8, ComponentIds::GS as u8, 0, b"ls".to_vec());
+        let mut bulk = Msg::new(MsgType::Cmd, 0, ComponentIds::BulkMsgDispatcher as u8, ComponentIds::GS as u8, 0, vec![]);
+        let shell_id = tracker.track(&mut shell, "SHELL ls");
+        let bulk_id = tracker.track(&mut bulk, "BulkMsgDispatcher dfgm");
+
+        let output = Msg::new(MsgType::Cmd, shell_id, ComponentIds::GS as u8, ComponentIds::SHELL as u8, 0, b"a b".to_vec());
+        assert_eq!(tracker.on_response(&output).as_deref(), Some("SHELL ls"));
+        assert_eq!(tracker.on_response(&output).as_deref(), Some("SHELL ls"));
+        assert_eq!(tracker.get(shell_id).unwrap().responses, 2);
+
+        // Msgs the spacecraft sent on its own don't belong to any cmd
+        let unsolicited = Msg::new(MsgType::Cmd, 0x8123, ComponentIds::GS as u8, ComponentIds::EPS as u8, 0, vec![]);
+        assert_eq!(tracker.on_response(&unsolicited), None);
+
+        tracker.on_bulk_start(bulk_id);
+        assert_eq!(tracker.get(bulk_id).unwrap().state, CmdState::Downlinking);
+        tracker.on_bulk_done(bulk_id, Ok("dfgm/data".to_string()));
+        assert_eq!(tracker.get(bulk_id).unwrap().state,
+                   CmdState::Completed(ResultCode::Success, "saved to dfgm/data".to_string()));
+    }
+}
@@ -3,9 +3,15 @@ use reqwasm::http::Request;
 use serde_json::json;
 use wasm_bindgen_futures::spawn_local;
 use web_sys::{console, HtmlInputElement};
-use yew::{function_component, html, use_state, Callback, Html, InputEvent, SubmitEvent, TargetCast};
+use yew::{function_component, html, use_effect_with, use_state, Callback, Html, InputEvent, SubmitEvent, TargetCast};
 
 use crate::types::command::Command;
+use crate::types::dictionary::ComponentInfo;
+
+async fn fetch_dictionary() -> Result<Vec<ComponentInfo>, Box<dyn std::error::Error>> {
+    let response = Request::get("http://127.0.0.1:8000/api/dictionary").send().await?;
+    Ok(response.json::<Vec<ComponentInfo>>().await?)
+}
 
 async fn send_command(command: Command) -> Result<(), Box<dyn std::error::Error>> {
     let params = json!({
@@ -33,6 +39,26 @@ async fn send_command(command: Command) -> Result<(), Box<dyn std::error::Error>
 #[function_component(CommandForm)]
 pub fn command_form() -> Html {
     let command = use_state(Command::default);
+    let dictionary = use_state(Vec::<ComponentInfo>::new);
+
+    {
+        let dictionary = dictionary.clone();
+        use_effect_with((), move |_| {
+            spawn_local(async move {
+                match fetch_dictionary().await {
+                    Ok(components) => dictionary.set(components),
+                    Err(e) => console::error_1(&e.to_string().into()),
+                }
+            });
+        });
+    }
+
+    // Suggest only the opcodes of the payload entered so far
+    let opcodes = dictionary
+        .iter()
+        .find(|c| c.name.eq_ignore_ascii_case(&command.payload))
+        .map(|c| c.opcodes.clone())
+        .unwrap_or_default();
 
     let on_change = {
         let command = command.clone();
@@ -75,6 +101,7 @@ pub fn command_form() -> Html {
                     name="payload" 
                     type="text" 
                     autocomplete="off"
+                    list="payloads"
                     value={command.payload.clone()} 
                     oninput={on_change.clone()} 
                     class="mt-1 block w-full px-3 py-2 border border-gray-300 rounded-md shadow-sm focus:outline-none focus:ring-indigo-500 focus:border-indigo-500 sm:text-sm"
@@ -86,11 +113,20 @@ pub fn command_form() -> Html {
                     name="cmd" 
                     type="text" 
                     autocomplete="off"
+                    list="opcodes"
                     value={command.cmd.clone()} 
                     oninput={on_change.clone()} 
                     class="mt-1 block w-full px-3 py-2 border border-gray-300 rounded-md shadow-sm focus:outline-none focus:ring-indigo-500 focus:border-indigo-500 sm:text-sm"
                 />
             </label>
+            <datalist id="payloads">
+                { for dictionary.iter().map(|c| html! { <option value={c.name.clone()} /> }) }
+            </datalist>
+            <datalist id="opcodes">
+                { for opcodes.iter().map(|op| html! {
+                    <option value={op.name.clone()}>{ format!("{} {}", op.description, op.args.iter().map(|a| a.hint()).collect::<Vec<_>>().join(" ")) }</option>
+                }) }
+            </datalist>
             <label class="block">
                 <span class="text-gray-700">{"Data:"}</span>
                 <input 
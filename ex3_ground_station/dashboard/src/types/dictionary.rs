@@ -0,0 +1,44 @@
+use serde::Deserialize;
+
+/// The schema of an opcode argument, see `common::cmd_args::ArgSpec`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ArgInfo {
+    pub name: String,
+    pub range: Option<(i64, i64)>,
+    pub units: String,
+    pub optional: bool,
+}
+
+impl ArgInfo {
+    /// Hint shown next to the opcode, e.g. `<speed -8000..=8000 rpm>`
+    pub fn hint(&self) -> String {
+        let mut hint = self.name.clone();
+        if let Some((min, max)) = self.range {
+            hint.push_str(&format!(" {}..={}", min, max));
+        }
+        if !self.units.is_empty() {
+            hint.push_str(&format!(" {}", self.units));
+        }
+        if self.optional {
+            format!("[{}]", hint)
+        } else {
+            format!("<{}>", hint)
+        }
+    }
+}
+
+/// An opcode as served by the ground station's `/api/dictionary`, which is generated from the shared registry
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct OpcodeInfo {
+    pub name: String,
+    pub value: u8,
+    pub description: String,
+    pub args: Vec<ArgInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ComponentInfo {
+    pub name: String,
+    pub id: u8,
+    pub opcodes: Vec<OpcodeInfo>,
+}
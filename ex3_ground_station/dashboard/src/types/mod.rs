@@ -1 +1,2 @@
-pub mod command;
\ No newline at end of file
+pub mod command;
+pub mod dictionary;
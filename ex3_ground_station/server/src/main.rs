@@ -5,6 +5,8 @@ use rocket_cors::{AllowedHeaders, AllowedOrigins, CorsOptions};
 use tokio::sync::Mutex;
 use once_cell::sync::Lazy;
 use dotenv::dotenv;
+use common::config;
+use common::registry::{ComponentInfo, COMPONENTS};
 
 #[macro_use]
 extern crate rocket;
@@ -18,9 +20,11 @@ use types::command::Command;
 use utils::file_ops::{read_commands, write_command};
 use obc_client::ObcClient;
 
-static OBC_CLIENT: Lazy<Mutex<ObcClient>> = Lazy::new(|| Mutex::new(
-    ObcClient::new("localhost".to_string(), 50000)
-));
+/// Connects to the address in the config (see common::config), which is checked to be <host>:<port>
+static OBC_CLIENT: Lazy<Mutex<ObcClient>> = Lazy::new(|| {
+    let (host, port) = config::get().ground_station.obc_server.rsplit_once(':').unwrap();
+    Mutex::new(ObcClient::new(host.to_string(), port.parse().unwrap()))
+});
 
 #[get("/api/cmd", format="json")]
 async fn get_cmds() -> Json<Vec<Command>> {
@@ -28,6 +32,12 @@ async fn get_cmds() -> Json<Vec<Command>> {
     Json(commands)
 }
 
+/// The component and opcode registry shared with the spacecraft, so the dashboard offers only valid commands
+#[get("/api/dictionary", format="json")]
+fn get_dictionary() -> Json<&'static [ComponentInfo]> {
+    Json(COMPONENTS)
+}
+
 #[post("/api/cmd", format = "json", data = "<input>")]
 async fn post_cmd(input: Json<Command>) -> Status {
     println!("Got a form! Payload: {}, Cmd: {}, Data: {}", input.payload, input.cmd, input.data);
@@ -71,7 +81,7 @@ async fn rocket() -> _ {
         .to_cors().expect("Error creating CORS options");
 
     rocket::build()
-        .mount("/", routes![get_cmds, post_cmd, options_cors])
+        .mount("/", routes![get_cmds, get_dictionary, post_cmd, options_cors])
         .mount("/", FileServer::from(relative!("static")))
         .attach(cors)
 }
@@ -2,6 +2,9 @@ use tokio::net::TcpStream;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use std::error::Error;
 
+use common::registry::{self, RegistryError};
+use common::ComponentIds;
+
 use crate::message::{self, Message};
 
 pub struct ObcClient {
@@ -52,17 +55,24 @@ impl ObcClient {
 }
 
 fn parse_command(input: [&str; 3]) -> Result<Message, Box<dyn Error>> {
+    let payload = registry::component(input[0])
+        .ok_or_else(|| RegistryError::UnknownComponentName(input[0].to_string()))?;
+    let opcode = payload
+        .opcode(input[1])
+        .ok_or_else(|| RegistryError::UnknownOpcode(input[1].to_string()))?;
+    let args: Vec<&str> = input[2].split(' ').collect();
+    let body = opcode.encode(&args)?;
+    if body.len() > message::MSG_OPDATA_LEN {
+        return Err("command data too long".into());
+    }
+    let mut opdata = [0; message::MSG_OPDATA_LEN];
+    opdata[..body.len()].copy_from_slice(&body);
+
     let cmd = message::Command {
-        payload: match message::Payload::from_str(input[0]) {
-            Ok(p) => p,
-            Err(e) => return Err(e.into()),
-        },
-        opcode: match input[1].parse::<u8>() {
-            Ok(op) => op,
-            Err(_) => return Err("opcode must be numerical".into()),
-        },
-        oplen: 0,
-        opdata: [0; message::MSG_OPDATA_LEN],
+        payload: ComponentIds::try_from(payload.id)?,
+        opcode: opcode.value,
+        oplen: body.len(),
+        opdata,
     };
 
     println!("serializing: {:?}", cmd);
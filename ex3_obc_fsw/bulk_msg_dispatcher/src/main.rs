@@ -2,6 +2,7 @@ use common::bulk_msg_slicing::*;
 use common::*;
 use interface::ipc::*;
 use message_structure::*;
+use msg_id::RESERVED_MSG_ID;
 use std::fs::{File, OpenOptions};
 use std::io::Error as IoError;
 use std::io::Read;
@@ -13,7 +14,7 @@ use logging::*;
 use log::{trace, warn};
 use interface::Interface;
 
-const INTERNAL_MSG_BODY_SIZE: usize = 4088; // 4KB - 8 (header) being passed internally
+const INTERNAL_MSG_BODY_SIZE: usize = constants::BULK_PACKET_SIZE_BYTES - HEADER_SIZE; // 4KB - header being passed internally
 fn main() -> Result<(), IoError> {
     // All connected handlers and other clients will have a socket for the server defined here
     // This pipeline is directly to the coms_handler to be directly downlinked sliced data packets
@@ -39,22 +40,27 @@ fn main() -> Result<(), IoError> {
     let mut messages = Vec::new();
     let mut num_of_4kb_msgs = 1;
     let mut num_bytes = 4098;
+    // Id of the cmd that asked for the transfer in progress. Every msg of the transfer carries it,
+    // so the GS can tell which cmd the downlinked data is for
+    let mut transfer_id = RESERVED_MSG_ID;
 
-    let log_path = "logs";
-    init_logger(log_path);
+    init_program_logger("bulk_msg_dispatcher");
+
+    let mut registration = Registration::new(ComponentIds::BulkMsgDispatcher, "BulkMsgDispatcher");
 
     loop {
+        registration.maintain();
         let mut servers = vec![&mut coms_interface, &mut cmd_disp_interface];
         thread::sleep(Duration::from_secs(1));
         let _ = poll_ipc_server_sockets(&mut servers);
 
         for server in servers.into_iter().flatten() {
-            if let Some(msg) = handle_client(server)? {
+            while let Some(msg) = handle_client(server)? {
                 // If the server is bulk msg server and msg type is ack then send bulk msgs
                 // to coms for downlink
                 println!("bulk got msg: {:?}", msg);
                 if server.socket_path.contains("gs_bulk") {
-                    if msg.header.msg_type == MsgType::Ack as u8 {
+                    if msg.header.msg_type == MsgType::Ack {
                         trace!("Got ACK from COMS handler, starting downlink sequence.");
                         // If the first byte of message body is 0, then then continue with downlink
                         // process, by sending messages to gs
@@ -77,24 +83,24 @@ fn main() -> Result<(), IoError> {
                                 thread::sleep(Duration::from_micros(1));
                             }
                             messages.clear();
-                            server.clear_buffer();
                         } else {
                             todo!();
                         }
                     }
                 } else if server.socket_path.contains("BulkMsgDispatcher") {
                     let path_bytes: Vec<u8> = msg.msg_body.clone();
-                    let path = get_path_from_bytes(path_bytes)?;
-                    match get_data_from_path(&path) {
+                    // Relative paths are in the payload data dir (see common::config)
+                    let path = config::get().data_dir(&get_path_from_bytes(path_bytes)?);
+                    transfer_id = msg.header.msg_id;
+                    match get_data_from_path(&path.to_string_lossy(), transfer_id) {
                         Ok(bulk_msg) => {
-                            trace!("Bytes expected at GS: {}", bulk_msg.msg_body.len() + HEADER_SIZE); // +8 for header
+                            trace!("Bytes expected at GS: {}", bulk_msg.msg_body.len() + HEADER_SIZE); // + header
                             messages = handle_large_msg(bulk_msg.clone(), INTERNAL_MSG_BODY_SIZE)?;
     
                             let first_msg = messages[0].clone();
                             num_of_4kb_msgs = u16::from_le_bytes([first_msg.msg_body[0], first_msg.msg_body[1]]) + 1;
                             num_bytes = bulk_msg.msg_body.len() as u64;
                             trace!("Num of 4k msgs: {}", num_of_4kb_msgs);
-                            server.clear_buffer();
                             trace!("Successfully loaded data for downlinking... waiting on ACK.");
                         }
                         Err(e) => {
@@ -108,7 +114,7 @@ fn main() -> Result<(), IoError> {
         if !messages.is_empty() {
             if let Some(ref mut gs_bulk_server) = coms_interface {
                 if let Some(_client_addr) = &gs_bulk_server.client_addr {
-                    send_num_msgs_and_bytes_to_gs(num_of_4kb_msgs, num_bytes, gs_bulk_server)?;
+                    send_num_msgs_and_bytes_to_gs(transfer_id, num_of_4kb_msgs, num_bytes, gs_bulk_server)?;
                     println!("Sending Ack to COM, num msg: {num_of_4kb_msgs},  num bytes: {num_bytes}");
                 } else {
                     warn!("No data file descriptor found in coms_interface.");
@@ -126,27 +132,28 @@ fn get_path_from_bytes(path_bytes: Vec<u8>) -> Result<String, IoError> {
 }
 
 /// In charge of getting the file path from a Msg sent to the Bulk dispatcher from a handler
-fn handle_client(server: &IpcServer) -> Result<Option<Msg>, IoError> {
-    if server.buffer != [0u8; IPC_BUFFER_SIZE] {
-        trace!(
-            "Server {} received data",
-            server.socket_path
-        );
-        //Build Msg from received bytes and get body which contains path
-        Ok(Some(deserialize_msg(&server.buffer)?))
-    } else {
-        Ok(None)
+fn handle_client(server: &mut IpcServer) -> Result<Option<Msg>, IoError> {
+    match server.recv() {
+        Some(frame) => {
+            trace!(
+                "Server {} received {} B",
+                server.socket_path, frame.len()
+            );
+            //Build Msg from received bytes and get body which contains path
+            Ok(Some(deserialize_msg(&frame.data)?))
+        }
+        None => Ok(None),
     }
 }
 
 /// This is the communication protocol that will execute each time the Bulk Msg Dispatcher wants
 /// to send a Bulk Msg to the coms handler for downlinking.
-fn send_num_msgs_and_bytes_to_gs(num_msgs: u16, num_bytes: u64, iface: &mut IpcServer) -> Result<(), IoError> {
+fn send_num_msgs_and_bytes_to_gs(msg_id: u16, num_msgs: u16, num_bytes: u64, iface: &mut IpcServer) -> Result<(), IoError> {
     // 1. Send Msg to coms handler indicating Bulk Msg and buffer size needed
     let mut num_msgs_bytes: Vec<u8> = num_msgs.to_le_bytes().to_vec();
     let mut num_bytes_bytes: Vec<u8> = num_bytes.to_le_bytes().to_vec();
     num_msgs_bytes.append(&mut num_bytes_bytes);
-    let num_msg: Msg = Msg::new(MsgType::Bulk as u8, 0,
+    let num_msg: Msg = Msg::new(MsgType::Bulk, msg_id,
                                 ComponentIds::GS as u8, ComponentIds::DFGM as u8,
                                 2, num_msgs_bytes);
     iface.send(&serialize_msg(&num_msg)?)?;
@@ -154,8 +161,8 @@ fn send_num_msgs_and_bytes_to_gs(num_msgs: u16, num_bytes: u64, iface: &mut IpcS
 }
 
 /// This function will take all current data that is stored in a provided path and
-/// append it to the body of a bulk Msg. This Msg will then be sliced.
-fn get_data_from_path(path: &str) -> Result<Msg, std::io::Error> {
+/// append it to the body of a bulk Msg with the given `msg_id`. This Msg will then be sliced.
+fn get_data_from_path(path: &str, msg_id: u16) -> Result<Msg, std::io::Error> {
     let dir_path = Path::new(path);
 
     // Get the first file in the directory
@@ -187,6 +194,6 @@ fn get_data_from_path(path: &str) -> Result<Msg, std::io::Error> {
     }
 
     // Create the Msg object
-    let bulk_msg: Msg = Msg::new(MsgType::Bulk as u8, 0, 7, src_id, 0, data);
+    let bulk_msg: Msg = Msg::new(MsgType::Bulk, msg_id, 7, src_id, 0, data);
     Ok(bulk_msg)
 }
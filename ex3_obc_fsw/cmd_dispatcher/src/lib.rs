@@ -0,0 +1,326 @@
+/*
+Routing for the cmd_dispatcher.
+
+Every msg sent to the cmd_dispatcher's socket is forwarded to the component its dest_id names. Msgs for
+the GS are handed to the coms_handler to be downlinked. When a msg can't be delivered - its dest_id
+isn't a component, or nothing is listening for that component - it is NACKed with AckCode::Failed and
+the reason, back to whichever component sent it (or to the GS via the coms_handler).
+
+By default a component is reached on the socket named after it. Components register the socket they
+actually listen on with a CmdDispatcher Register msg, and keep renewing it (see interface::ipc::Registration).
+A registration that isn't renewed within REGISTRATION_TTL expires, and the component goes back to its
+default socket. Clients are only created when a msg has to be sent, and are dropped when a send fails,
+so a handler that starts (or restarts) after the dispatcher is reached on the next msg for it.
+
+Msgs aren't sent straight away, they go through a queue for their destination (see queue.rs) so urgent
+msgs go first and rate limits are kept. A handler whose socket is full is left alone until it catches up,
+and msgs for it back up in its queue; once that is full any more are NACKed. The depth of each queue and
+its counters are downlinked with CmdDispatcher GetHK.
+
+Before a msg is queued it is checked against the routing table (see routing.rs), which can deny it or
+send it to a different component. Denied msgs are NACKed with the reason, like any undeliverable msg.
+*/
+
+use common::component_ids::ComponentIds;
+use common::message_structure::{deserialize_msg, serialize_msg, AckCode, Msg, MsgType};
+use common::opcodes;
+use interface::ipc::{IpcClient, REGISTRATION_TTL};
+use log::{info, trace, warn};
+use std::io::{Error as IoError, ErrorKind};
+use std::time::Instant;
+
+pub mod queue;
+use queue::{OutboundQueue, Priority, QueueStats, RateLimit, DEFAULT_RATE_LIMITS};
+
+pub mod routing;
+use routing::RoutingTable;
+
+pub use interface::ipc::CMD_DISPATCHER_SOCKET;
+
+/// Name of the coms_handler socket for msgs to be downlinked to the GS
+pub const GS_LINK_SOCKET: &str = "gs_non_bulk";
+
+/// Where msgs for one component are delivered
+struct Route {
+    socket_name: String,
+    client: Option<IpcClient>,
+    /// When the component last registered, for routes to a registered socket
+    registered_at: Option<Instant>,
+    queue: OutboundQueue,
+}
+
+impl Route {
+    /// Send as many queued msgs as the rate limit and the socket allow. Msgs that can't be delivered
+    /// are added to `failed` with the reason
+    fn flush(&mut self, component: ComponentIds, now: Instant, failed: &mut Vec<(Vec<u8>, String)>) {
+        while let Some((priority, bytes)) = self.queue.peek(now) {
+            if self.client.is_none() {
+                match IpcClient::new(self.socket_name.clone()) {
+                    Ok(client) => self.client = Some(client),
+                    Err(e) => {
+                        let bytes = self.queue.pop(priority, false).unwrap();
+                        failed.push((bytes, format!("no connection to {} - {}", component, e)));
+                        continue;
+                    }
+                }
+            }
+            match self.client.as_mut().unwrap().try_send(bytes) {
+                Ok(len) => {
+                    trace!("Sent {} B to {}", len, component);
+                    self.queue.pop(priority, true);
+                }
+                // The handler isn't keeping up, leave the rest queued until it has drained its socket
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    self.queue.blocked();
+                    break;
+                }
+                Err(e) => {
+                    // Reconnected on the next msg for this component
+                    self.client = None;
+                    let bytes = self.queue.pop(priority, false).unwrap();
+                    failed.push((bytes, format!("{} not reachable - {}", component, e)));
+                }
+            }
+        }
+    }
+}
+
+pub struct Dispatcher {
+    /// Indexed by component id, so the destination of a msg can be looked up directly
+    routes: Vec<Route>,
+    default_socket: Box<dyn Fn(ComponentIds) -> String>,
+    routing: RoutingTable,
+}
+
+/// The socket a component is reached on until it registers one
+pub fn default_socket(component: ComponentIds) -> String {
+    match component {
+        ComponentIds::GS => GS_LINK_SOCKET.to_string(),
+        c => format!("{c}"),
+    }
+}
+
+impl Dispatcher {
+    /// Dispatch to the components' default sockets
+    pub fn connect() -> Dispatcher {
+        Dispatcher::new(default_socket)
+    }
+
+    /// Dispatch to the sockets named by `default_socket` until components register their own
+    pub fn new(default_socket: impl Fn(ComponentIds) -> String + 'static) -> Dispatcher {
+        let routes = (0..ComponentIds::LAST).map(|id| Route {
+            socket_name: ComponentIds::try_from(id).map(&default_socket).unwrap_or_default(),
+            client: None,
+            registered_at: None,
+            queue: OutboundQueue::default(),
+        }).collect();
+        let mut dispatcher = Dispatcher { routes, default_socket: Box::new(default_socket), routing: RoutingTable::default() };
+        for &(component, limit) in DEFAULT_RATE_LIMITS {
+            dispatcher.set_rate_limit(component, Some(limit));
+        }
+        dispatcher
+    }
+
+    /// Replace the routing table, keeping whether dev mode is on
+    pub fn set_routing(&mut self, routing: RoutingTable) {
+        let dev_mode = self.routing.dev_mode;
+        self.routing = routing;
+        self.routing.dev_mode = dev_mode;
+    }
+
+    /// Enable the routing rules that only apply in dev mode
+    pub fn set_dev_mode(&mut self, dev_mode: bool) {
+        if dev_mode != self.routing.dev_mode {
+            info!("Dev mode {}", if dev_mode { "on" } else { "off" });
+        }
+        self.routing.dev_mode = dev_mode;
+    }
+
+    /// Limit the rate msgs are sent to `component`, or stop limiting it with None
+    pub fn set_rate_limit(&mut self, component: ComponentIds, limit: Option<RateLimit>) {
+        self.routes[component as usize].queue.set_rate_limit(limit);
+    }
+
+    /// Queue depths and counters for msgs to `component`
+    pub fn stats(&self, component: ComponentIds) -> QueueStats {
+        self.routes[component as usize].queue.stats()
+    }
+
+    /// Deliver msgs for `component` to `socket_name`, until the registration expires
+    pub fn register(&mut self, component: ComponentIds, socket_name: &str) {
+        let route = &mut self.routes[component as usize];
+        if route.socket_name != socket_name || route.registered_at.is_none() {
+            info!("{} registered on {}", component, socket_name);
+            route.socket_name = socket_name.to_string();
+            route.client = None;
+        }
+        route.registered_at = Some(Instant::now());
+    }
+
+    /// Whether `component` has a registration that hasn't expired
+    pub fn is_registered(&self, component: ComponentIds) -> bool {
+        self.routes[component as usize].registered_at.is_some_and(|t| t.elapsed() < REGISTRATION_TTL)
+    }
+
+    /// Forget registrations that haven't been renewed in time
+    pub fn expire_registrations(&mut self) {
+        for component in ComponentIds::iter() {
+            let default_socket = (self.default_socket)(component);
+            let route = &mut self.routes[component as usize];
+            if route.registered_at.is_some_and(|t| t.elapsed() >= REGISTRATION_TTL) {
+                warn!("Registration of {} on {} expired", component, route.socket_name);
+                route.socket_name = default_socket;
+                route.client = None;
+                route.registered_at = None;
+            }
+        }
+    }
+
+    fn enqueue(&mut self, dest: ComponentIds, msg: &Msg, bytes: Vec<u8>) -> Result<(), IoError> {
+        self.routes[dest as usize].queue.push(Priority::of(msg), bytes)
+            .map_err(|_| IoError::new(ErrorKind::WouldBlock, format!("queue for {} is full", dest)))
+    }
+
+    /// Queue a serialized msg for its destination, then send whatever can be sent. If the msg can't be
+    /// queued it is NACKed to its source, and the reason is returned. Msgs that fail to send are NACKed
+    /// when they fail.
+    pub fn dispatch(&mut self, bytes: &[u8]) -> Result<(), IoError> {
+        let msg = deserialize_msg(bytes)?;
+        let res = match ComponentIds::try_from(msg.header.dest_id) {
+            Ok(dest) => self.route(&msg, dest, bytes),
+            Err(_) => Err(IoError::new(ErrorKind::InvalidData, format!("invalid destination {}", msg.header.dest_id))),
+        };
+        if let Err(ref e) = res {
+            warn!("Dispatch of msg {} failed: {}", msg.header.msg_id, e);
+            self.nack(&msg, &e.to_string());
+        }
+        self.flush();
+        res
+    }
+
+    /// Look up where a msg goes in the routing table, then queue it there
+    fn route(&mut self, msg: &Msg, dest: ComponentIds, bytes: &[u8]) -> Result<(), IoError> {
+        let delivery = self.routing.route(msg.header.source_id, dest, msg.header.op_code)
+            .map_err(|reason| IoError::new(ErrorKind::PermissionDenied, reason))?;
+        if delivery.to != dest {
+            trace!("Msg {} for {} routed to {}", msg.header.msg_id, dest, delivery.to);
+        }
+        let mut msg = msg.clone();
+        let bytes = if delivery.dest == dest {
+            bytes.to_vec()
+        } else {
+            msg.header.dest_id = delivery.dest as u8;
+            serialize_msg(&msg)?
+        };
+        match delivery.to {
+            ComponentIds::CmdDispatcher => self.handle_msg(&msg),
+            to => self.enqueue(to, &msg, bytes),
+        }
+    }
+
+    /// Send queued msgs, highest priority first, as far as the rate limits and the destinations'
+    /// sockets allow. Msgs that can't be delivered are NACKed to their source.
+    pub fn flush(&mut self) {
+        let now = Instant::now();
+        let mut failed = Vec::new();
+        loop {
+            for component in ComponentIds::iter() {
+                self.routes[component as usize].flush(component, now, &mut failed);
+            }
+            if failed.is_empty() {
+                break;
+            }
+            // The NACKs are queued like any other msg, so go round again to send them
+            for (bytes, reason) in std::mem::take(&mut failed) {
+                warn!("Delivery failed: {}", reason);
+                if let Ok(msg) = deserialize_msg(&bytes) {
+                    self.nack(&msg, &reason);
+                }
+            }
+        }
+    }
+
+    /// Handle a msg for the dispatcher itself
+    fn handle_msg(&mut self, msg: &Msg) -> Result<(), IoError> {
+        let opcode = opcodes::CmdDispatcher::from(msg.header.op_code);
+        match opcode {
+            opcodes::CmdDispatcher::Register => {
+                let source = ComponentIds::try_from(msg.header.source_id)
+                    .map_err(|e| IoError::new(ErrorKind::InvalidData, e.to_string()))?;
+                // Msgs from the GS are routed through the coms_handler, they can't be redirected from the ground
+                if matches!(source, ComponentIds::GS | ComponentIds::CmdDispatcher) {
+                    return Err(IoError::new(ErrorKind::PermissionDenied, format!("{} can't register", source)));
+                }
+                let args = opcode.decode(&msg.msg_body)?;
+                match args[0].as_str() {
+                    Some(socket_name) if !socket_name.is_empty() => {
+                        self.register(source, socket_name);
+                        Ok(())
+                    }
+                    _ => Err(IoError::new(ErrorKind::InvalidInput, "no socket to register")),
+                }
+            }
+            opcodes::CmdDispatcher::GetHK => {
+                let source = ComponentIds::try_from(msg.header.source_id)
+                    .map_err(|e| IoError::new(ErrorKind::InvalidData, e.to_string()))?;
+                let report = msg.report(Ok(self.housekeeping().into_bytes()));
+                self.enqueue(source, &report, serialize_msg(&report)?)
+            }
+            opcodes::CmdDispatcher::Error => {
+                Err(IoError::new(ErrorKind::InvalidInput, format!("unknown dispatcher opcode {}", msg.header.op_code)))
+            }
+        }
+    }
+
+    /// Queue stats of every destination that has seen any traffic, e.g. "IRIS q0/3/12 tx4 drop2 blk1"
+    /// for 0 critical, 3 normal and 12 bulk msgs queued, 4 sent, 2 dropped and 1 time its socket was full
+    pub fn housekeeping(&self) -> String {
+        ComponentIds::iter()
+            .filter(|c| !self.stats(*c).is_idle())
+            .map(|c| format!("{} {}", c, self.stats(c)))
+            .collect::<Vec<String>>()
+            .join("; ")
+    }
+
+    /// Queue a NACK of `msg` for the component that sent it
+    fn nack(&mut self, msg: &Msg, reason: &str) {
+        // A NACK that can't be delivered is not NACKed in turn
+        if msg.header.msg_type == MsgType::Ack {
+            return;
+        }
+        let source = match ComponentIds::try_from(msg.header.source_id) {
+            Ok(ComponentIds::CmdDispatcher) | Err(_) => {
+                warn!("Can't NACK msg {} from invalid source {}", msg.header.msg_id, msg.header.source_id);
+                return;
+            }
+            Ok(source) => source,
+        };
+        let nack = Msg::new_ack(msg.header.msg_id, source as u8, ComponentIds::CmdDispatcher as u8,
+                                AckCode::Failed, format!("Error: {}", reason).into_bytes());
+        let res = serialize_msg(&nack).and_then(|bytes| self.enqueue(source, &nack, bytes));
+        if let Err(e) = res {
+            warn!("Could not NACK msg {}: {}", msg.header.msg_id, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stale_registration_expires() {
+        let mut dispatcher = Dispatcher::connect();
+        dispatcher.register(ComponentIds::IRIS, "iris_handler");
+        dispatcher.expire_registrations();
+        assert!(dispatcher.is_registered(ComponentIds::IRIS));
+        assert_eq!(dispatcher.routes[ComponentIds::IRIS as usize].socket_name, "iris_handler");
+
+        let renewed_at = Instant::now().checked_sub(REGISTRATION_TTL).unwrap();
+        dispatcher.routes[ComponentIds::IRIS as usize].registered_at = Some(renewed_at);
+        assert!(!dispatcher.is_registered(ComponentIds::IRIS));
+        dispatcher.expire_registrations();
+        assert_eq!(dispatcher.routes[ComponentIds::IRIS as usize].socket_name, "IRIS");
+        assert_eq!(dispatcher.routes[ComponentIds::GS as usize].socket_name, GS_LINK_SOCKET);
+    }
+}
@@ -1,52 +1,40 @@
-use nix::unistd::close;
-use nix::Error;
-use strum::IntoEnumIterator;
-use std::os::fd::AsRawFd;
+use cmd_dispatcher::routing::RoutingTable;
+use cmd_dispatcher::{Dispatcher, CMD_DISPATCHER_SOCKET};
+use common::logging::*;
+use interface::ipc::{poll_ipc_server_sockets, IpcServer};
+use std::path::Path;
 
-use interface::ipc::{poll_ipc_server_sockets, IpcClient, IpcServer, IPC_BUFFER_SIZE};
-use common::component_ids::ComponentIds;
-use common::message_structure::MsgHeader;
+const USAGE: &str = "Usage: cmd_dispatcher [--dev-mode] [--routes <file>]";
 
 fn main() {
-    let mut component_streams: Vec<Option<IpcClient>> =
-        ComponentIds::iter().enumerate().map(|(i,c)| {
-            println!("{i}");
-            match IpcClient::new(format!("{c}")) {
-                Ok(client) => {
-                    Some(client)
-                }
-                Err(e) => {
-                    eprintln!("msg dispatcher couldn't connect to {}: {}", c, e);
-                    None
-                },
-            }
-        }).collect();
-    
-    for x in 0..ComponentIds::LAST as usize {
-        let payload = match ComponentIds::try_from(x as u8) {
-            Ok(p) => {
-                eprintln!("x {} yields {}", x, p);
-                p
-            },
-            Err(()) => {
-                eprintln!("x {} didn't convert", x);
-                continue;
+    init_program_logger("cmd_dispatcher");
+
+    // Components are connected to when there is a msg for them, so they can be started in any order
+    let mut dispatcher = Dispatcher::connect();
+
+    // --dev-mode enables the routing rules for testing and debugging (e.g. the shell), --routes replaces
+    // the default routing rules with the ones in a file
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let res = match arg.as_str() {
+            "--dev-mode" => {
+                dispatcher.set_dev_mode(true);
+                Ok(())
             }
-        };
-        match component_streams.get(x) {
-            Some(element) => match element {
-                Some(_e) => {
-                    println!("{} connected", payload);
-                }
-                None => {
-                    println!("{} not connected!", payload);
-                }
+            "--routes" => match args.next() {
+                Some(path) => RoutingTable::load(Path::new(&path)).map(|table| dispatcher.set_routing(table))
+                    .map_err(|e| format!("Could not load routing table: {}", e)),
+                None => Err(USAGE.to_string()),
             },
-            None => println!("bad index {}", x),
+            _ => Err(USAGE.to_string()),
         };
+        if let Err(e) = res {
+            eprintln!("{}", e);
+            return;
+        }
     }
 
-    let mut cmd_server = match IpcServer::new("cmd_dispatcher".to_string()) {
+    let mut cmd_server = match IpcServer::new(CMD_DISPATCHER_SOCKET.to_string()) {
         Ok(s) => Some(s),
         Err(e) => {
             eprintln!("Server connection error: {}", e);
@@ -56,40 +44,17 @@ fn main() {
 
     loop {
         let mut servers = vec![&mut cmd_server];
-        let (_s,_bytes) = match poll_ipc_server_sockets(&mut servers) {
-            Ok((bytes, sock)) => (bytes,sock),
-            Err(e) => {
-                eprintln!("read error: {}", e);
-                let _ = close(cmd_server.as_ref().unwrap().fd.as_raw_fd());
-                continue; // try again
-            }
-        };
-        if cmd_server.as_ref().unwrap().buffer != [0u8; IPC_BUFFER_SIZE] {
-            let dest = cmd_server.as_ref().unwrap().buffer[MsgHeader::DEST_INDEX];
-            let res = match ComponentIds::try_from(dest) {
-                Ok(payload) => {
-                    match &mut component_streams[dest as usize] {
-                        Some(client) => {
-                            let _ = client.send(&cmd_server.as_ref().unwrap().buffer);
-                            Ok(())
-                        },
-                        None => {
-                            eprintln!("No payload: {payload}!");
-                            Err(Error::EPIPE)
-                        }
-                    }
-                },
-                Err(_) => {
-                    eprintln!("Invalid payload: {dest}");
-                    Err(Error::EINVAL)
-                }
-            };
-
-            if res.is_err() {
-                eprintln!("Dispatch failed: NACKing");
-                // Should actually NACK
+        if let Err(e) = poll_ipc_server_sockets(&mut servers) {
+            eprintln!("read error: {}", e);
+        }
+        while let Some(frame) = cmd_server.as_mut().unwrap().recv() {
+            // Undeliverable msgs are NACKed to their source by the dispatcher
+            if let Err(e) = dispatcher.dispatch(&frame.data) {
+                eprintln!("Dispatch failed: {}", e);
             }
-            cmd_server.as_mut().unwrap().clear_buffer();
         }
+        dispatcher.expire_registrations();
+        // Send msgs that were held back by a rate limit or a full socket
+        dispatcher.flush();
     }
 }
@@ -0,0 +1,251 @@
+/*
+Outbound queues for the cmd_dispatcher.
+
+Each destination has one queue per priority class. Msgs are sent critical first, then normal, then bulk,
+so a burst of bulk traffic for a handler can't hold up an urgent cmd for it. A destination can have a
+rate limit (a token bucket) so one busy handler can't flood the others; critical msgs aren't held back
+by it. When a queue is full the msg is dropped and counted, and the dispatcher NACKs it to its sender.
+*/
+
+use common::component_ids::ComponentIds;
+use common::message_structure::{Msg, MsgType};
+use common::{opcodes, registry};
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::Instant;
+
+/// How urgently a msg has to be delivered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Power, resets and attitude safing, and the Acks and Reports on cmds
+    Critical = 0,
+    Normal = 1,
+    /// Large or slow transfers that can wait
+    Bulk = 2,
+}
+
+impl Priority {
+    pub const ALL: [Priority; 3] = [Priority::Critical, Priority::Normal, Priority::Bulk];
+
+    /// Max number of msgs queued for a destination in this class
+    pub fn capacity(self) -> usize {
+        match self {
+            Priority::Critical => 16,
+            Priority::Normal => 32,
+            Priority::Bulk => 64,
+        }
+    }
+
+    pub fn of(msg: &Msg) -> Priority {
+        match msg.header.msg_type {
+            MsgType::Ack | MsgType::Report => return Priority::Critical,
+            MsgType::Bulk => return Priority::Bulk,
+            MsgType::Cmd => (),
+        }
+        let op = msg.header.op_code;
+        let is_reset = registry::component_by_id(msg.header.dest_id)
+            .and_then(|c| c.opcode(&op.to_string()))
+            .is_some_and(|info| info.name == "Reset");
+        if is_reset {
+            return Priority::Critical;
+        }
+        match ComponentIds::try_from(msg.header.dest_id) {
+            Ok(ComponentIds::EPS) => match opcodes::EPS::from(op) {
+                opcodes::EPS::On | opcodes::EPS::Off => Priority::Critical,
+                _ => Priority::Normal,
+            },
+            Ok(ComponentIds::ADCS) if opcodes::ADCS::from(op) == opcodes::ADCS::Detumble => Priority::Critical,
+            Ok(ComponentIds::IRIS) => match opcodes::IRIS::from(op) {
+                opcodes::IRIS::CaptureImage | opcodes::IRIS::FetchImage => Priority::Bulk,
+                _ => Priority::Normal,
+            },
+            Ok(ComponentIds::BulkMsgDispatcher) => Priority::Bulk,
+            _ => Priority::Normal,
+        }
+    }
+}
+
+/// Limit on the rate msgs are sent to a destination: on average `per_sec`, with bursts of up to `burst`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub per_sec: f64,
+    pub burst: u32,
+}
+
+/// Rate limits of destinations that are slow to handle msgs. All others aren't limited
+pub const DEFAULT_RATE_LIMITS: &[(ComponentIds, RateLimit)] = &[
+    (ComponentIds::IRIS, RateLimit { per_sec: 2.0, burst: 4 }),
+    (ComponentIds::BulkMsgDispatcher, RateLimit { per_sec: 1.0, burst: 2 }),
+];
+
+#[derive(Debug)]
+struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit) -> Self {
+        TokenBucket { limit, tokens: limit.burst as f64, refilled_at: Instant::now() }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.per_sec).min(self.limit.burst as f64);
+        self.refilled_at = now;
+    }
+}
+
+/// Counters for one destination's queue, downlinked as the dispatcher's housekeeping
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueueStats {
+    /// Msgs waiting, per priority class
+    pub depth: [usize; 3],
+    pub sent: u32,
+    /// Msgs dropped because their queue was full
+    pub dropped: u32,
+    /// Times the destination's socket was full
+    pub blocked: u32,
+}
+
+impl QueueStats {
+    pub fn is_idle(&self) -> bool {
+        *self == QueueStats::default()
+    }
+}
+
+impl fmt::Display for QueueStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "q{}/{}/{} tx{} drop{} blk{}", self.depth[0], self.depth[1], self.depth[2],
+               self.sent, self.dropped, self.blocked)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct OutboundQueue {
+    queues: [VecDeque<Vec<u8>>; 3],
+    bucket: Option<TokenBucket>,
+    sent: u32,
+    dropped: u32,
+    blocked: u32,
+}
+
+impl OutboundQueue {
+    pub fn set_rate_limit(&mut self, limit: Option<RateLimit>) {
+        self.bucket = limit.map(TokenBucket::new);
+    }
+
+    /// Queue a serialized msg. If its class is full the msg is handed back
+    pub fn push(&mut self, priority: Priority, bytes: Vec<u8>) -> Result<(), Vec<u8>> {
+        let queue = &mut self.queues[priority as usize];
+        if queue.len() >= priority.capacity() {
+            self.dropped += 1;
+            return Err(bytes);
+        }
+        queue.push_back(bytes);
+        Ok(())
+    }
+
+    /// The next msg that may be sent now, if any
+    pub fn peek(&mut self, now: Instant) -> Option<(Priority, &[u8])> {
+        let priority = Priority::ALL.into_iter().find(|p| !self.queues[*p as usize].is_empty())?;
+        if let Some(ref mut bucket) = self.bucket {
+            bucket.refill(now);
+            if priority != Priority::Critical && bucket.tokens < 1.0 {
+                return None;
+            }
+        }
+        self.queues[priority as usize].front().map(|bytes| (priority, bytes.as_slice()))
+    }
+
+    /// Remove the msg returned by `peek`, once it has been sent (or given up on)
+    pub fn pop(&mut self, priority: Priority, sent: bool) -> Option<Vec<u8>> {
+        let bytes = self.queues[priority as usize].pop_front()?;
+        if sent {
+            self.sent += 1;
+            if let Some(ref mut bucket) = self.bucket {
+                bucket.tokens = (bucket.tokens - 1.0).max(0.0);
+            }
+        }
+        Some(bytes)
+    }
+
+    /// The destination's socket is full, the msg at the front stays queued until it drains
+    pub fn blocked(&mut self) {
+        self.blocked += 1;
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        QueueStats {
+            depth: [self.queues[0].len(), self.queues[1].len(), self.queues[2].len()],
+            sent: self.sent,
+            dropped: self.dropped,
+            blocked: self.blocked,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn cmd(dest: ComponentIds, op_code: u8) -> Msg {
+        Msg::new(MsgType::Cmd, 1, dest as u8, ComponentIds::GS as u8, op_code, vec![])
+    }
+
+    #[test]
+    fn test_classification() {
+        assert_eq!(Priority::of(&cmd(ComponentIds::EPS, opcodes::EPS::Reset as u8)), Priority::Critical);
+        assert_eq!(Priority::of(&cmd(ComponentIds::UHF, opcodes::UHF::Reset as u8)), Priority::Critical);
+        assert_eq!(Priority::of(&cmd(ComponentIds::EPS, opcodes::EPS::GetHK as u8)), Priority::Normal);
+        assert_eq!(Priority::of(&cmd(ComponentIds::IRIS, opcodes::IRIS::FetchImage as u8)), Priority::Bulk);
+        assert_eq!(Priority::of(&cmd(ComponentIds::IRIS, opcodes::IRIS::GetTime as u8)), Priority::Normal);
+        let nack = Msg::new_ack(1, ComponentIds::GS as u8, ComponentIds::CmdDispatcher as u8,
+                                common::message_structure::AckCode::Failed, vec![]);
+        assert_eq!(Priority::of(&nack), Priority::Critical);
+    }
+
+    #[test]
+    fn test_priority_order_and_capacity() {
+        let mut queue = OutboundQueue::default();
+        queue.push(Priority::Bulk, vec![3]).unwrap();
+        queue.push(Priority::Normal, vec![2]).unwrap();
+        queue.push(Priority::Critical, vec![1]).unwrap();
+        let now = Instant::now();
+        for expected in 1..=3 {
+            let (priority, bytes) = queue.peek(now).unwrap();
+            assert_eq!(bytes, [expected]);
+            queue.pop(priority, true);
+        }
+        assert!(queue.peek(now).is_none());
+
+        for _ in 0..Priority::Critical.capacity() {
+            queue.push(Priority::Critical, vec![]).unwrap();
+        }
+        assert_eq!(queue.push(Priority::Critical, vec![9]), Err(vec![9]));
+        assert_eq!(queue.stats().dropped, 1);
+        assert_eq!(queue.stats().depth, [Priority::Critical.capacity(), 0, 0]);
+    }
+
+    #[test]
+    fn test_rate_limit_holds_back_all_but_critical() {
+        let mut queue = OutboundQueue::default();
+        queue.set_rate_limit(Some(RateLimit { per_sec: 1.0, burst: 1 }));
+        let start = Instant::now();
+        queue.push(Priority::Normal, vec![1]).unwrap();
+        queue.push(Priority::Normal, vec![2]).unwrap();
+
+        let (priority, _) = queue.peek(start).unwrap();
+        queue.pop(priority, true);
+        assert!(queue.peek(start).is_none());
+
+        queue.push(Priority::Critical, vec![0]).unwrap();
+        assert_eq!(queue.peek(start).unwrap(), (Priority::Critical, &[0u8][..]));
+        queue.pop(Priority::Critical, true);
+
+        // A second later there is another token
+        assert_eq!(queue.peek(start + Duration::from_millis(1100)).unwrap(), (Priority::Normal, &[2u8][..]));
+    }
+}
@@ -0,0 +1,283 @@
+/*
+Routing table for the cmd_dispatcher.
+
+Before a msg is queued its (source, destination, opcode) is looked up in the routing table. The first
+rule that matches decides what happens to the msg, and a msg no rule matches is delivered as addressed.
+Rules are written one per line, with * matching anything:
+
+    # action  source  dest   opcode  [target / dev]
+    allow     GS      SHELL  *       dev
+    deny      *       SHELL  *
+    redirect  *       UHF    *       COMS
+    alias     *       OBC    *       EPS
+
+allow and deny are what they say. A rule marked dev only applies while the dispatcher runs in dev mode.
+redirect delivers the msg to another component without touching it, so that component can still tell
+what the msg was addressed to. alias rewrites the msg's destination, as if it had been addressed to the
+other component in the first place.
+*/
+
+use common::component_ids::ComponentIds;
+use common::registry;
+use std::fmt;
+use std::io::{Error as IoError, ErrorKind};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Rules used unless a routing table is loaded. The shell is for debugging from the ground only, and
+/// the UHF is looked after by the coms_handler
+pub const DEFAULT_RULES: &str = "\
+allow    GS  SHELL  *  dev
+deny     *   SHELL  *
+redirect *   UHF    *  COMS
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Allow,
+    Deny,
+    /// Deliver to another component, leaving the msg addressed as it is
+    Redirect(ComponentIds),
+    /// Deliver to another component as if the msg had been addressed to it
+    Alias(ComponentIds),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub action: Action,
+    /// None matches any source, including ids that aren't components
+    pub source: Option<ComponentIds>,
+    pub dest: Option<ComponentIds>,
+    pub opcode: Option<u8>,
+    /// Only applies in dev mode
+    pub dev_only: bool,
+}
+
+impl Rule {
+    pub fn new(action: Action) -> Rule {
+        Rule { action, source: None, dest: None, opcode: None, dev_only: false }
+    }
+
+    pub fn from(mut self, source: ComponentIds) -> Rule {
+        self.source = Some(source);
+        self
+    }
+
+    pub fn to(mut self, dest: ComponentIds) -> Rule {
+        self.dest = Some(dest);
+        self
+    }
+
+    pub fn opcode(mut self, opcode: u8) -> Rule {
+        self.opcode = Some(opcode);
+        self
+    }
+
+    pub fn dev_only(mut self) -> Rule {
+        self.dev_only = true;
+        self
+    }
+
+    /// Whether the rule covers a msg, regardless of dev mode
+    fn covers(&self, source: Option<ComponentIds>, dest: ComponentIds, opcode: u8) -> bool {
+        self.source.is_none_or(|s| Some(s) == source)
+            && self.dest.is_none_or(|d| d == dest)
+            && self.opcode.is_none_or(|o| o == opcode)
+    }
+}
+
+fn parse_component(token: &str) -> Result<Option<ComponentIds>, String> {
+    match token {
+        "*" => Ok(None),
+        _ => ComponentIds::from_str(token).map(Some).map_err(|_| format!("unknown component {}", token)),
+    }
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if !(4..=5).contains(&tokens.len()) {
+            return Err(format!("expected <action> <source> <dest> <opcode> [target / dev], got '{}'", line));
+        }
+        let source = parse_component(tokens[1])?;
+        let dest = parse_component(tokens[2])?;
+        let opcode = match (tokens[3], dest) {
+            ("*", _) => None,
+            (op, Some(dest)) => Some(dest.info().opcode(op).ok_or(format!("unknown opcode {} for {}", op, dest))?.value),
+            (op, None) => Some(op.parse::<u8>().map_err(|_| format!("opcode {} needs a destination", op))?),
+        };
+        let extra = tokens.get(4).copied();
+        let target = || match extra.map(parse_component) {
+            Some(Ok(Some(target))) => Ok(target),
+            Some(Err(e)) => Err(e),
+            _ => Err(format!("{} needs a target component", tokens[0])),
+        };
+        let action = match tokens[0] {
+            "allow" => Action::Allow,
+            "deny" => Action::Deny,
+            "redirect" => Action::Redirect(target()?),
+            "alias" => Action::Alias(target()?),
+            other => return Err(format!("unknown action {}", other)),
+        };
+        let dev_only = match (action, extra) {
+            (Action::Allow | Action::Deny, Some("dev")) => true,
+            (Action::Allow | Action::Deny, Some(other)) => return Err(format!("unexpected '{}'", other)),
+            _ => false,
+        };
+        Ok(Rule { action, source, dest, opcode, dev_only })
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let any = |c: Option<ComponentIds>| c.map_or("*".to_string(), |c| c.to_string());
+        let (action, target) = match self.action {
+            Action::Allow => ("allow", None),
+            Action::Deny => ("deny", None),
+            Action::Redirect(to) => ("redirect", Some(to)),
+            Action::Alias(to) => ("alias", Some(to)),
+        };
+        let opcode = self.opcode.map_or("*".to_string(), |o| o.to_string());
+        write!(f, "{} {} {} {}", action, any(self.source), any(self.dest), opcode)?;
+        match target {
+            Some(target) => write!(f, " {}", target),
+            None if self.dev_only => write!(f, " dev"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Where a msg goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Delivery {
+    /// Component whose socket the msg is delivered to
+    pub to: ComponentIds,
+    /// What the msg's dest_id should be when it is delivered
+    pub dest: ComponentIds,
+}
+
+#[derive(Debug, Clone)]
+pub struct RoutingTable {
+    rules: Vec<Rule>,
+    /// Enables the rules marked dev
+    pub dev_mode: bool,
+}
+
+impl Default for RoutingTable {
+    fn default() -> Self {
+        RoutingTable::parse(DEFAULT_RULES).expect("default routing rules are valid")
+    }
+}
+
+impl RoutingTable {
+    pub fn new(rules: Vec<Rule>) -> RoutingTable {
+        RoutingTable { rules, dev_mode: false }
+    }
+
+    /// Parse rules, one per line. Blank lines and anything after a # are ignored
+    pub fn parse(text: &str) -> Result<RoutingTable, String> {
+        let rules = text.lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.split('#').next().unwrap_or_default().trim()))
+            .filter(|(_, line)| !line.is_empty())
+            .map(|(n, line)| line.parse::<Rule>().map_err(|e| format!("line {}: {}", n, e)))
+            .collect::<Result<Vec<Rule>, String>>()?;
+        Ok(RoutingTable::new(rules))
+    }
+
+    pub fn load(path: &Path) -> Result<RoutingTable, IoError> {
+        let text = std::fs::read_to_string(path)?;
+        RoutingTable::parse(&text).map_err(|e| IoError::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// Decide where a msg from `source` to `dest` goes, or why it may not be sent
+    pub fn route(&self, source: u8, dest: ComponentIds, opcode: u8) -> Result<Delivery, String> {
+        let source_id = ComponentIds::try_from(source).ok();
+        let rule = self.rules.iter()
+            .filter(|r| self.dev_mode || !r.dev_only)
+            .find(|r| r.covers(source_id, dest, opcode));
+        let action = rule.map_or(Action::Allow, |r| r.action);
+        match action {
+            Action::Allow => Ok(Delivery { to: dest, dest }),
+            Action::Redirect(to) => Ok(Delivery { to, dest }),
+            Action::Alias(to) => Ok(Delivery { to, dest: to }),
+            Action::Deny => {
+                let source_name = source_id.map_or(source.to_string(), |s| s.to_string());
+                let op_name = registry::component_by_id(dest as u8)
+                    .and_then(|c| c.opcode(&opcode.to_string()))
+                    .map_or(opcode.to_string(), |op| op.name.to_string());
+                let mut reason = format!("{} may not send {} to {}", source_name, op_name, dest);
+                // Point out when it would have been allowed in dev mode
+                let dev_allows = self.rules.iter()
+                    .any(|r| r.dev_only && r.action != Action::Deny && r.covers(source_id, dest, opcode));
+                if !self.dev_mode && dev_allows {
+                    reason.push_str(" outside of dev mode");
+                }
+                Err(reason)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::opcodes;
+
+    #[test]
+    fn test_rule_syntax() {
+        let rule: Rule = "allow GS SHELL * dev".parse().unwrap();
+        assert_eq!(rule, Rule::new(Action::Allow).from(ComponentIds::GS).to(ComponentIds::SHELL).dev_only());
+        assert_eq!(rule.to_string(), "allow GS SHELL * dev");
+
+        let rule: Rule = "alias * OBC * EPS".parse().unwrap();
+        assert_eq!(rule, Rule::new(Action::Alias(ComponentIds::EPS)).to(ComponentIds::OBC));
+
+        let rule: Rule = "deny DFGM IRIS FetchImage".parse().unwrap();
+        assert_eq!(rule.opcode, Some(opcodes::IRIS::FetchImage as u8));
+
+        assert!("redirect * UHF *".parse::<Rule>().is_err());
+        assert!("deny * NOPE *".parse::<Rule>().is_err());
+        assert!("deny * * FetchImage".parse::<Rule>().is_err());
+        assert!("allow * * * COMS".parse::<Rule>().is_err());
+        assert!(RoutingTable::parse("allow * * *\nbogus * * *").unwrap_err().starts_with("line 2"));
+    }
+
+    #[test]
+    fn test_default_rules() {
+        let mut table = RoutingTable::default();
+        let shell = ComponentIds::SHELL;
+        let gs = ComponentIds::GS as u8;
+        assert_eq!(table.route(gs, shell, 0), Err("GS may not send 0 to SHELL outside of dev mode".to_string()));
+        table.dev_mode = true;
+        assert_eq!(table.route(gs, shell, 0), Ok(Delivery { to: shell, dest: shell }));
+        assert_eq!(table.route(ComponentIds::EPS as u8, shell, 0), Err("EPS may not send 0 to SHELL".to_string()));
+
+        let uhf = ComponentIds::UHF;
+        assert_eq!(table.route(gs, uhf, opcodes::UHF::GetHK as u8), Ok(Delivery { to: ComponentIds::COMS, dest: uhf }));
+        assert_eq!(table.route(gs, ComponentIds::EPS, 3), Ok(Delivery { to: ComponentIds::EPS, dest: ComponentIds::EPS }));
+    }
+
+    #[test]
+    fn test_first_match_wins() {
+        let table = RoutingTable::parse("
+            # Only the GS can fetch images, everyone else gets them via the bulk dispatcher
+            allow GS   IRIS FetchImage
+            deny  *    IRIS FetchImage
+            alias *    OBC  *           EPS
+        ").unwrap();
+        let fetch = opcodes::IRIS::FetchImage as u8;
+        assert!(table.route(ComponentIds::GS as u8, ComponentIds::IRIS, fetch).is_ok());
+        assert_eq!(table.route(ComponentIds::DFGM as u8, ComponentIds::IRIS, fetch),
+                   Err("DFGM may not send FetchImage to IRIS".to_string()));
+        assert_eq!(table.route(200, ComponentIds::IRIS, fetch), Err("200 may not send FetchImage to IRIS".to_string()));
+        assert_eq!(table.route(ComponentIds::GS as u8, ComponentIds::OBC, 1),
+                   Ok(Delivery { to: ComponentIds::EPS, dest: ComponentIds::EPS }));
+    }
+}
@@ -0,0 +1,229 @@
+/*
+Dispatches msgs over in-process IpcServer/IpcClient pairs, with the servers standing in for the
+components and the coms_handler's GS link.
+*/
+
+use cmd_dispatcher::queue::RateLimit;
+use cmd_dispatcher::routing::RoutingTable;
+use cmd_dispatcher::Dispatcher;
+use common::component_ids::ComponentIds;
+use common::opcodes;
+use common::message_structure::*;
+use interface::ipc::{poll_ipc_server_sockets, IpcServer};
+
+/// Socket names are unique to each test, so the tests can run alongside each other and the real FSW
+fn socket_name(test: &str, component: ComponentIds) -> String {
+    format!("dispatch_test_{}_{}", test, component)
+}
+
+/// Start a server for each of `components`, and a dispatcher that reaches components on the test's sockets
+fn setup(test: &'static str, components: &[ComponentIds]) -> (Dispatcher, Vec<Option<IpcServer>>) {
+    let mut servers: Vec<Option<IpcServer>> = (0..ComponentIds::LAST).map(|_| None).collect();
+    for &c in components {
+        servers[c as usize] = Some(IpcServer::new(socket_name(test, c)).unwrap());
+    }
+    (Dispatcher::new(move |c| socket_name(test, c)), servers)
+}
+
+fn recv(server: &mut Option<IpcServer>) -> Option<Msg> {
+    poll_ipc_server_sockets(&mut vec![&mut *server]).unwrap();
+    let frame = server.as_mut().unwrap().recv()?;
+    Some(deserialize_msg(&frame.data).unwrap())
+}
+
+fn cmd(msg_id: u16, dest: u8, source: ComponentIds) -> Vec<u8> {
+    serialize_msg(&Msg::new(MsgType::Cmd, msg_id, dest, source as u8, 3, vec![1, 2])).unwrap()
+}
+
+fn assert_nack(nack: Msg, msg_id: u16, dest: ComponentIds, reason: &str) {
+    assert_eq!(nack.header.msg_type, MsgType::Ack);
+    assert_eq!(nack.header.msg_id, msg_id);
+    assert_eq!(nack.header.dest_id, dest as u8);
+    assert_eq!(nack.ack_code(), Some(Ok(AckCode::Failed)));
+    let body = String::from_utf8(nack.msg_body).unwrap();
+    assert!(body.contains(reason), "{} doesn't mention {}", body, reason);
+}
+
+#[test]
+fn test_delivers_to_destination() {
+    let (mut dispatcher, mut servers) = setup("deliver", &[ComponentIds::EPS, ComponentIds::DFGM]);
+    let bytes = cmd(0x8001, ComponentIds::EPS as u8, ComponentIds::DFGM);
+    dispatcher.dispatch(&bytes).unwrap();
+
+    let msg = recv(&mut servers[ComponentIds::EPS as usize]).unwrap();
+    assert_eq!(serialize_msg(&msg).unwrap(), bytes);
+    assert!(recv(&mut servers[ComponentIds::DFGM as usize]).is_none());
+}
+
+#[test]
+fn test_nacks_unconnected_destination_to_source() {
+    let (mut dispatcher, mut servers) = setup("unconnected", &[ComponentIds::DFGM]);
+    // Queued, then NACKed when it can't be sent
+    dispatcher.dispatch(&cmd(0x8002, ComponentIds::IRIS as u8, ComponentIds::DFGM)).unwrap();
+
+    let nack = recv(&mut servers[ComponentIds::DFGM as usize]).unwrap();
+    assert_nack(nack, 0x8002, ComponentIds::DFGM, "IRIS not reachable");
+}
+
+#[test]
+fn test_nacks_invalid_destination_to_gs() {
+    let (mut dispatcher, mut servers) = setup("invalid", &[ComponentIds::GS]);
+    assert!(dispatcher.dispatch(&cmd(42, 200, ComponentIds::GS)).is_err());
+
+    let nack = recv(&mut servers[ComponentIds::GS as usize]).unwrap();
+    assert_nack(nack, 42, ComponentIds::GS, "invalid destination 200");
+}
+
+#[test]
+fn test_nacks_destination_that_is_not_listening() {
+    let (mut dispatcher, mut servers) = setup("not_listening", &[ComponentIds::ADCS, ComponentIds::GPS]);
+    // Like a handler that has stopped running
+    servers[ComponentIds::GPS as usize] = None;
+    dispatcher.dispatch(&cmd(0x8003, ComponentIds::GPS as u8, ComponentIds::ADCS)).unwrap();
+
+    let nack = recv(&mut servers[ComponentIds::ADCS as usize]).unwrap();
+    assert_nack(nack, 0x8003, ComponentIds::ADCS, "GPS not reachable");
+}
+
+#[test]
+fn test_undeliverable_nack_is_dropped() {
+    let (mut dispatcher, mut servers) = setup("ack", &[ComponentIds::EPS]);
+    let ack = Msg::new_ack(5, ComponentIds::IRIS as u8, ComponentIds::EPS as u8, AckCode::Success, vec![]);
+    dispatcher.dispatch(&serialize_msg(&ack).unwrap()).unwrap();
+    assert!(recv(&mut servers[ComponentIds::EPS as usize]).is_none());
+}
+
+#[test]
+fn test_reaches_handler_started_after_dispatcher() {
+    let (mut dispatcher, mut servers) = setup("late_start", &[ComponentIds::GS]);
+    dispatcher.dispatch(&cmd(7, ComponentIds::EPS as u8, ComponentIds::GS)).unwrap();
+    assert!(recv(&mut servers[ComponentIds::GS as usize]).is_some());
+
+    servers[ComponentIds::EPS as usize] = Some(IpcServer::new(socket_name("late_start", ComponentIds::EPS)).unwrap());
+    dispatcher.dispatch(&cmd(8, ComponentIds::EPS as u8, ComponentIds::GS)).unwrap();
+    assert_eq!(recv(&mut servers[ComponentIds::EPS as usize]).unwrap().header.msg_id, 8);
+}
+
+fn register(source: ComponentIds, socket_name: &str) -> Vec<u8> {
+    let msg = Msg::new(MsgType::Cmd, 0x8004, ComponentIds::CmdDispatcher as u8, source as u8,
+                       opcodes::CmdDispatcher::Register as u8, socket_name.as_bytes().to_vec());
+    serialize_msg(&msg).unwrap()
+}
+
+#[test]
+fn test_registered_socket_is_used() {
+    let (mut dispatcher, mut servers) = setup("register", &[ComponentIds::DFGM]);
+    let mut registered = Some(IpcServer::new("dispatch_test_register_dfgm_v2".to_string()).unwrap());
+    dispatcher.dispatch(&register(ComponentIds::DFGM, "dispatch_test_register_dfgm_v2")).unwrap();
+    assert!(dispatcher.is_registered(ComponentIds::DFGM));
+
+    dispatcher.dispatch(&cmd(9, ComponentIds::DFGM as u8, ComponentIds::EPS)).unwrap();
+    assert_eq!(recv(&mut registered).unwrap().header.msg_id, 9);
+    assert!(recv(&mut servers[ComponentIds::DFGM as usize]).is_none());
+}
+
+#[test]
+fn test_gs_cannot_register() {
+    let (mut dispatcher, mut servers) = setup("register_gs", &[ComponentIds::GS]);
+    assert!(dispatcher.dispatch(&register(ComponentIds::GS, "somewhere_else")).is_err());
+    assert!(!dispatcher.is_registered(ComponentIds::GS));
+
+    let nack = recv(&mut servers[ComponentIds::GS as usize]).unwrap();
+    assert_nack(nack, 0x8004, ComponentIds::GS, "GS can't register");
+}
+
+#[test]
+fn test_full_socket_backs_up_into_queue() {
+    let (mut dispatcher, mut servers) = setup("backpressure", &[ComponentIds::DFGM]);
+    // More than a unix datagram socket holds before senders have to wait
+    for msg_id in 1..=40 {
+        dispatcher.dispatch(&cmd(msg_id, ComponentIds::DFGM as u8, ComponentIds::GS)).unwrap();
+    }
+    let stats = dispatcher.stats(ComponentIds::DFGM);
+    assert!(stats.blocked > 0);
+    assert!(stats.depth[1] > 0);
+    assert_eq!(stats.sent as usize + stats.depth[1], 40);
+    assert_eq!(stats.dropped, 0);
+
+    // Once the handler catches up everything gets through, in order
+    let mut next_id = 1;
+    while next_id <= 40 {
+        let msg = recv(&mut servers[ComponentIds::DFGM as usize]).unwrap();
+        assert_eq!(msg.header.msg_id, next_id);
+        next_id += 1;
+        dispatcher.flush();
+    }
+    assert_eq!(dispatcher.stats(ComponentIds::DFGM).depth, [0, 0, 0]);
+}
+
+#[test]
+fn test_urgent_cmd_overtakes_rate_limited_burst() {
+    let (mut dispatcher, mut servers) = setup("priority", &[ComponentIds::IRIS, ComponentIds::GS]);
+    dispatcher.set_rate_limit(ComponentIds::IRIS, Some(RateLimit { per_sec: 0.001, burst: 2 }));
+    let fetch = |msg_id| serialize_msg(&Msg::new(MsgType::Cmd, msg_id, ComponentIds::IRIS as u8, ComponentIds::GS as u8,
+                                                 opcodes::IRIS::FetchImage as u8, vec![1])).unwrap();
+    for msg_id in 1..=5 {
+        dispatcher.dispatch(&fetch(msg_id)).unwrap();
+    }
+    let reset = Msg::new(MsgType::Cmd, 6, ComponentIds::IRIS as u8, ComponentIds::GS as u8, opcodes::IRIS::Reset as u8, vec![]);
+    dispatcher.dispatch(&serialize_msg(&reset).unwrap()).unwrap();
+
+    let iris = &mut servers[ComponentIds::IRIS as usize];
+    let received: Vec<u16> = std::iter::from_fn(|| recv(iris)).map(|m| m.header.msg_id).collect();
+    assert_eq!(received, [1, 2, 6]);
+    assert_eq!(dispatcher.stats(ComponentIds::IRIS).depth, [0, 0, 3]);
+
+    // The queue depths can be downlinked
+    let get_hk = Msg::new(MsgType::Cmd, 7, ComponentIds::CmdDispatcher as u8, ComponentIds::GS as u8,
+                          opcodes::CmdDispatcher::GetHK as u8, vec![]);
+    dispatcher.dispatch(&serialize_msg(&get_hk).unwrap()).unwrap();
+    let report = recv(&mut servers[ComponentIds::GS as usize]).unwrap();
+    assert_eq!(report.header.msg_type, MsgType::Report);
+    assert_eq!(report.header.msg_id, 7);
+    assert!(String::from_utf8(report.msg_body).unwrap().contains("IRIS q0/0/3 tx3 drop0 blk0"));
+}
+
+#[test]
+fn test_shell_only_reachable_from_gs_in_dev_mode() {
+    let (mut dispatcher, mut servers) = setup("shell", &[ComponentIds::SHELL, ComponentIds::GS, ComponentIds::EPS]);
+    let err = dispatcher.dispatch(&cmd(10, ComponentIds::SHELL as u8, ComponentIds::GS)).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+    let nack = recv(&mut servers[ComponentIds::GS as usize]).unwrap();
+    assert_nack(nack, 10, ComponentIds::GS, "GS may not send 3 to SHELL outside of dev mode");
+
+    dispatcher.set_dev_mode(true);
+    dispatcher.dispatch(&cmd(11, ComponentIds::SHELL as u8, ComponentIds::GS)).unwrap();
+    assert_eq!(recv(&mut servers[ComponentIds::SHELL as usize]).unwrap().header.msg_id, 11);
+
+    assert!(dispatcher.dispatch(&cmd(0x8005, ComponentIds::SHELL as u8, ComponentIds::EPS)).is_err());
+    let nack = recv(&mut servers[ComponentIds::EPS as usize]).unwrap();
+    assert_nack(nack, 0x8005, ComponentIds::EPS, "EPS may not send 3 to SHELL");
+    assert!(recv(&mut servers[ComponentIds::SHELL as usize]).is_none());
+}
+
+#[test]
+fn test_uhf_msgs_are_redirected_to_coms() {
+    let (mut dispatcher, mut servers) = setup("redirect", &[ComponentIds::COMS, ComponentIds::UHF]);
+    let bytes = cmd(12, ComponentIds::UHF as u8, ComponentIds::GS);
+    dispatcher.dispatch(&bytes).unwrap();
+
+    // Still addressed to the UHF, so the coms_handler knows what it is for
+    let msg = recv(&mut servers[ComponentIds::COMS as usize]).unwrap();
+    assert_eq!(serialize_msg(&msg).unwrap(), bytes);
+    assert!(recv(&mut servers[ComponentIds::UHF as usize]).is_none());
+}
+
+#[test]
+fn test_alias_rewrites_destination() {
+    let (mut dispatcher, mut servers) = setup("alias", &[ComponentIds::EPS, ComponentIds::GS]);
+    dispatcher.set_routing(RoutingTable::parse("alias * OBC * EPS\ndeny DFGM * *").unwrap());
+    dispatcher.dispatch(&cmd(13, ComponentIds::OBC as u8, ComponentIds::GS)).unwrap();
+    let msg = recv(&mut servers[ComponentIds::EPS as usize]).unwrap();
+    assert_eq!(msg.header.msg_id, 13);
+    assert_eq!(msg.header.dest_id, ComponentIds::EPS as u8);
+
+    // The default rules are replaced, not added to
+    dispatcher.dispatch(&cmd(14, ComponentIds::UHF as u8, ComponentIds::GS)).unwrap();
+    let nack = recv(&mut servers[ComponentIds::GS as usize]).unwrap();
+    assert_nack(nack, 14, ComponentIds::GS, "UHF not reachable");
+}
@@ -8,8 +8,10 @@ ex3_simulated_subsystems/ADCS/
 TODO: figure out how to cleanly handle errors such as improper inputs
 TODO: get an idea of the actual ADCS commands and figure out a clean way to send commands
 */
-use common::{opcodes, ports};
-use interface::{ipc::*, tcp::*, Interface};
+use common::cmd_args::ArgValue;
+use common::{config, opcodes, ComponentIds};
+use handler::Reporter;
+use interface::{factory::{InterfaceSpec, Peripheral}, ipc::*, reconnect::Reconnecting, tcp::*, Interface};
 use log::{debug, trace, warn};
 use common::logging::*;
 use common::message_structure::*;
@@ -19,7 +21,6 @@ use std::io::Error;
 use std::io::ErrorKind;
 
 const CMD_DELIMITER: u8 = b":"[0];
-const ADCS_DATA_DIR_PATH: &str = "ex3_obc_fsw/handlers/adcs_handler/adcs_data";
 const ADCS_PACKET_SIZE: usize = 1024;
 
 // TODO check if there is a cleaner way to do this
@@ -80,21 +81,17 @@ pub mod sim_adcs {
 }
 
 struct ADCSHandler {
-    peripheral_interface: Option<TcpInterface>,
-    dispatcher_interface: Option<IpcClient>,
+    peripheral_interface: Reconnecting<Box<dyn Peripheral>>,
+    dispatcher_interface: Option<IpcServer>, // Cmds from the cmd_dispatcher are received on the ADCS socket
+    reporter: Reporter, // Sends the Reports on cmds to the GS, or back through the cmd_dispatcher
 }
 
 impl ADCSHandler {
     pub fn new(
-        adcs_interface: Result<TcpInterface, std::io::Error>,
-        dispatcher_interface: Result<IpcClient, std::io::Error>,
+        adcs_interface: Reconnecting<Box<dyn Peripheral>>,
+        dispatcher_interface: Result<IpcServer, std::io::Error>,
+        reporter: Reporter,
     ) -> ADCSHandler {
-        if adcs_interface.is_err() {
-            warn!(
-                "Error creating ADCS interface: {:?}",
-                adcs_interface.as_ref().err().unwrap()
-            );
-        }
         if dispatcher_interface.is_err() {
             warn!(
                 "Error creating dispatcher interface: {:?}",
@@ -103,57 +100,68 @@ impl ADCSHandler {
         }
 
         ADCSHandler {
-            peripheral_interface: adcs_interface.ok(),
+            peripheral_interface: adcs_interface,
             dispatcher_interface: dispatcher_interface.ok(),
+            reporter,
         }
     }
 
     fn handle_msg_for_adcs(&mut self, msg: Msg) -> Result<(), Error> {
-        match opcodes::ADCS::from(msg.header.op_code) {
+        let opcode = opcodes::ADCS::from(msg.header.op_code);
+        let args = match opcode.decode(&msg.msg_body) {
+            Ok(args) => args,
+            // Reported as an unknown opcode below
+            Err(_) if opcode == opcodes::ADCS::Error => vec![],
+            Err(e) => {
+                warn!("Error: invalid msg body for opcode {}, {}: {}", msg.header.op_code, opcode, e);
+                return Err(e.into());
+            }
+        };
+        // The first arg of most opcodes picks the operation, e.g. getting or setting
+        let action = args.first().and_then(|a| a.int::<u8>());
+
+        match opcode {
             opcodes::ADCS::Detumble => {
                 warn!("Error: Detumble is not implemented");
                 Err(Error::new(
-                    ErrorKind::NotFound,
+                    ErrorKind::Unsupported,
                     "Detumble is not implemented for the ADCS yet",
                 ))
             }
 
-            opcodes::ADCS::OnOff => match msg.msg_body[0] {
-                0 => self.send_cmd(sim_adcs::OFF, msg),
-                1 => self.send_cmd(sim_adcs::ON, msg),
-                2 => self.send_cmd(sim_adcs::GET_STATE, msg),
-                _ => Err(self.invalid_msg_body(msg)),
+            opcodes::ADCS::OnOff => match action {
+                Some(0) => self.send_cmd(sim_adcs::OFF, &args),
+                Some(1) => self.send_cmd(sim_adcs::ON, &args),
+                Some(2) => self.send_cmd(sim_adcs::GET_STATE, &args),
+                _ => Err(self.invalid_msg_body(&msg)),
             },
 
-            opcodes::ADCS::WheelSpeed => match msg.msg_body[0] {
-                0 => self.send_cmd(sim_adcs::GET_WHEEL_SPEED, msg),
-                1 => self.send_cmd(sim_adcs::SET_WHEEL_SPEED, msg),
-                _ => Err(self.invalid_msg_body(msg)),
+            opcodes::ADCS::WheelSpeed => match action {
+                Some(0) => self.send_cmd(sim_adcs::GET_WHEEL_SPEED, &args),
+                Some(1) => self.send_cmd(sim_adcs::SET_WHEEL_SPEED, &args),
+                _ => Err(self.invalid_msg_body(&msg)),
             },
 
-            opcodes::ADCS::GetHk => self.send_cmd(sim_adcs::STATUS_CHECK, msg),
+            opcodes::ADCS::GetHk => self.send_cmd(sim_adcs::STATUS_CHECK, &args),
 
-            opcodes::ADCS::MagnetorquerCurrent => match msg.msg_body[0] {
-                0 => self.send_cmd(sim_adcs::GET_MAGNETORQUER_CURRENT, msg),
-                1 => self.send_cmd(sim_adcs::SET_MAGNETORQUER_CURRENT, msg),
-                _ => Err(self.invalid_msg_body(msg)),
+            opcodes::ADCS::MagnetorquerCurrent => match action {
+                Some(0) => self.send_cmd(sim_adcs::GET_MAGNETORQUER_CURRENT, &args),
+                Some(1) => self.send_cmd(sim_adcs::SET_MAGNETORQUER_CURRENT, &args),
+                _ => Err(self.invalid_msg_body(&msg)),
             },
 
-            opcodes::ADCS::OnboardTime => match msg.msg_body[0] {
-                0 => self.send_cmd(sim_adcs::GET_TIME, msg),
-                1 => self.send_cmd(sim_adcs::SET_TIME, msg),
-                _ => Err(self.invalid_msg_body(msg)),
+            opcodes::ADCS::OnboardTime => match action {
+                Some(0) => self.send_cmd(sim_adcs::GET_TIME, &args),
+                Some(1) => self.send_cmd(sim_adcs::SET_TIME, &args),
+                _ => Err(self.invalid_msg_body(&msg)),
             },
 
-            opcodes::ADCS::GetOrientation => self.send_cmd(sim_adcs::GET_ORIENTATION, msg),
+            opcodes::ADCS::GetOrientation => self.send_cmd(sim_adcs::GET_ORIENTATION, &args),
 
-            opcodes::ADCS::Reset => self.send_cmd(sim_adcs::RESET, msg),
+            opcodes::ADCS::Reset => self.send_cmd(sim_adcs::RESET, &args),
 
             _ => {
-                warn!(
-                    "{}",
-                    format!("Error: Opcode {} not found for ADCS", msg.header.op_code)
-                );
+                warn!("Error: Opcode {} not found for ADCS", msg.header.op_code);
                 Err(Error::new(
                     ErrorKind::NotFound,
                     format!("Error: Opcode {} not found for ADCS", msg.header.op_code),
@@ -162,17 +170,25 @@ impl ADCSHandler {
         }
     }
 
-    /// Main loop for ADCS Handler
-    pub fn run(&mut self) -> std::io::Result<()> {
+    /// Main loop for ADCS Handler. Errors are logged rather than ending it
+    pub fn run(&mut self) -> ! {
+        let mut registration = Registration::new(ComponentIds::ADCS, &ComponentIds::ADCS.to_string());
         loop {
-            if let Ok((n, _)) =
-                poll_ipc_clients(&mut vec![&mut self.dispatcher_interface])
-            {
-                if n > 0 {
-                    let mut socket_buf = self.dispatcher_interface.as_mut().unwrap().read_buffer();
-
-                    self.handle_dispatcher_msg(&mut socket_buf)?;
-                    self.handle_data_storing()?;
+            registration.maintain();
+            self.reporter.maintain();
+            self.poll_dispatcher();
+        }
+    }
+
+    /// Wait a little for cmds from the cmd_dispatcher, and handle any that came
+    fn poll_dispatcher(&mut self) {
+        if let Ok(n) = poll_ipc_server_sockets(&mut vec![&mut self.dispatcher_interface]) {
+            if n > 0 {
+                while let Some(frame) = self.dispatcher_interface.as_mut().and_then(|s| s.recv()) {
+                    self.handle_dispatcher_msg(&frame.data);
+                }
+                if let Err(e) = self.handle_data_storing() {
+                    warn!("Error storing ADCS data: {}", e);
                 }
             }
         }
@@ -180,28 +196,35 @@ impl ADCSHandler {
 
     /// Takes the bytes read from the IPC interface and
     /// sends it to the ADCS if an error occurred the msg
-    /// is stored in ADCS data
-    fn handle_dispatcher_msg(&mut self, buf: &mut Vec<u8>) -> std::io::Result<()> {
-        let recv_msg: Msg = deserialize_msg(buf).unwrap();
+    /// is stored in ADCS data. Bytes that aren't a msg are dropped
+    fn handle_dispatcher_msg(&mut self, buf: &[u8]) {
+        let recv_msg: Msg = match deserialize_msg(buf) {
+            Ok(msg) => msg,
+            Err(e) => {
+                warn!("Dropping ADCS msg that can't be deserialized: {:?}", e);
+                return;
+            }
+        };
 
-        if let Err(invalid_cmd) = self.handle_msg_for_adcs(recv_msg) {
+        let result = self.handle_msg_for_adcs(recv_msg.clone());
+        if let Err(ref invalid_cmd) = result {
             // TODO: create some meaningful error handling here
             let mut msg: Vec<u8> = vec![];
 
             msg.extend_from_slice(invalid_cmd.to_string().as_bytes());
-            pad_zeros(&mut msg, ADCS_PACKET_SIZE)?;
-
-            store_adcs_data(&msg)?;
+            if let Err(e) = pad_zeros(&mut msg, ADCS_PACKET_SIZE).and_then(|_| store_adcs_data(&msg)) {
+                warn!("Error storing failed ADCS cmd {}: {}", recv_msg.header.msg_id, e);
+            }
         }
-
-        buf.flush()
+        // The ADCS replies asynchronously and its replies are stored, so success means the cmd was sent
+        self.reporter.report(&recv_msg, result.map(|_| vec![]));
     }
 
     /// Reads from the tcp buffer and stores non-zero messages
     /// in ADCS Data
     fn handle_data_storing(&mut self) -> Result<(), Error> {
         let mut tcp_buf = [0u8; BUFFER_SIZE];
-        let status = TcpInterface::read(self.peripheral_interface.as_mut().unwrap(), &mut tcp_buf);
+        let status = self.peripheral_interface.read(&mut tcp_buf);
 
         match status {
             Ok(_) => {
@@ -229,28 +252,34 @@ impl ADCSHandler {
     }
 
     /// Builds commands to follow the simulated subsystems expected command structure
-    fn build_cmd(&mut self, cmd: sim_adcs::ADCSCmdParam, msg: Msg) -> Result<Vec<u8>, Error> {
+    fn build_cmd(&mut self, cmd: sim_adcs::ADCSCmdParam, args: &[ArgValue]) -> Result<Vec<u8>, Error> {
         let mut data: Vec<u8> = vec![];
         data.extend_from_slice(cmd.data);
 
-        // TODO: later figure out how to check we've sent the correct amount of parameters using an end-body flag maybe use 0xFF?
-        // First param in msg body will specify the operation type e.g. getting or setting
-        for i in 1..(cmd.params + 1) {
+        // First arg specifies the operation type e.g. getting or setting, the rest are the values for the sim
+        let params = args.get(1..).unwrap_or_default();
+        if params.len() != cmd.params {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Expected {} values for ADCS command, got {}", cmd.params, params.len()),
+            ));
+        }
+        for param in params {
             data.push(CMD_DELIMITER);
-            data.extend_from_slice(msg.msg_body[i].to_string().as_bytes());
+            data.extend_from_slice(param.to_string().as_bytes());
         }
 
         Ok(data)
     }
 
-    fn send_cmd(&mut self, command: sim_adcs::ADCSCmdParam, msg: Msg) -> Result<(), Error> {
-        let cmd = self.build_cmd(command, msg)?;
-        self.peripheral_interface.as_mut().unwrap().send(&cmd)?;
+    fn send_cmd(&mut self, command: sim_adcs::ADCSCmdParam, args: &[ArgValue]) -> Result<(), Error> {
+        let cmd = self.build_cmd(command, args)?;
+        self.peripheral_interface.send(&cmd)?;
 
         Ok(())
     }
 
-    fn invalid_msg_body(&mut self, msg: Msg) -> Error {
+    fn invalid_msg_body(&mut self, msg: &Msg) -> Error {
         warn!(
             "Error: Unknown msg body for opcode {}, {}",
             msg.header.op_code,
@@ -267,22 +296,23 @@ impl ADCSHandler {
     }
 }
 
-/// Helper function to pad an array to a length "n"
+/// Helper function to pad an array to a length "n". Arrays that are already longer are left as they are
 fn pad_zeros(array: &mut Vec<u8>, n: usize) -> std::io::Result<()> {
-    for _ in 0..(n - array.len()) {
+    for _ in array.len()..n {
         array.push(0);
     }
 
     Ok(())
 }
 
-/// Stores `data` into `adcs_data/data`
+/// Stores `data` into `data` in the ADCS's data dir
 fn store_adcs_data(data: &[u8]) -> std::io::Result<()> {
-    std::fs::create_dir_all(ADCS_DATA_DIR_PATH)?;
+    let data_dir = config::get().data_dir("adcs");
+    std::fs::create_dir_all(&data_dir)?;
     let mut file = OpenOptions::new()
         .append(true)
         .create(true)
-        .open(format!("{}/data", ADCS_DATA_DIR_PATH))?;
+        .open(data_dir.join("data"))?;
     file.write_all(data)?;
     Ok(())
 }
@@ -290,19 +320,124 @@ fn store_adcs_data(data: &[u8]) -> std::io::Result<()> {
 fn main() -> Result<(), Error> {
     //For now interfaces are created and if their associated ports are not open, they will be ignored rather than causing the program to panic
 
-    let log_path = "ex3_obc_fsw/handlers/adcs_handler/logs";
-    init_logger(log_path);
+    init_program_logger("adcs_handler");
     trace!("Logger initialized");
     trace!("Beginning ADCS Handler...");
 
-    //Create TCP interface for ADCS handler to talk to simulated ADCS
-    let adcs_interface = TcpInterface::new_client("127.0.0.1".to_string(), ports::SIM_ADCS_PORT);
+    //Create interface for ADCS handler to talk to the ADCS given as the first arg (see interface::factory),
+    //or the one in the config by default. It is reconnected to if the link is lost
+    let adcs_spec: InterfaceSpec = std::env::args()
+        .nth(1)
+        .unwrap_or(config::get().handlers.adcs.interface.clone())
+        .parse()?;
+    let adcs_interface = Reconnecting::open(adcs_spec);
 
-    //Create IPC interface for ADCS handler to talk to message dispatcher
-    let dispatcher_interface = IpcClient::new("ADCS".to_string());
+    //Create IPC interface for the cmd_dispatcher to send the ADCS handler cmds on
+    let dispatcher_interface = IpcServer::new(ComponentIds::ADCS.to_string());
 
     //Create ADCS handler
-    let mut adcs_handler = ADCSHandler::new(adcs_interface, dispatcher_interface);
+    let mut adcs_handler = ADCSHandler::new(adcs_interface, dispatcher_interface, Reporter::new(ComponentIds::ADCS));
 
     adcs_handler.run()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interface::mock::{pipe, PipeEnd};
+
+    /// Handler connected to the end of a pipe, returned with the other end as the ADCS
+    fn handler() -> (ADCSHandler, PipeEnd) {
+        handler_on(Err(Error::from(ErrorKind::NotFound)))
+    }
+
+    /// Handler that receives cmds on `dispatcher_interface`
+    fn handler_on(dispatcher_interface: Result<IpcServer, Error>) -> (ADCSHandler, PipeEnd) {
+        let (adcs, end) = pipe();
+        let mut ends = vec![end];
+        let connect = move || match ends.pop() {
+            Some(end) => Ok(Box::new(end) as Box<dyn Peripheral>),
+            None => Err(Error::from(ErrorKind::ConnectionRefused)),
+        };
+        let reporter = Reporter::with_sockets(ComponentIds::ADCS, "adcs_test_gs", "adcs_test_dispatcher");
+        (ADCSHandler::new(Reconnecting::new("ADCS", connect), dispatcher_interface, reporter), adcs)
+    }
+
+    fn cmd(opcode: opcodes::ADCS, args: &[&str]) -> Msg {
+        Msg::new(MsgType::Cmd, 1, ComponentIds::ADCS as u8, ComponentIds::GS as u8, opcode as u8,
+                 opcode.info().unwrap().encode(args).unwrap())
+    }
+
+    fn sent(adcs: &mut PipeEnd) -> String {
+        let mut buf = [0u8; 64];
+        let n = adcs.read(&mut buf).unwrap();
+        String::from_utf8_lossy(&buf[..n]).to_string()
+    }
+
+    #[test]
+    fn test_builds_cmds() {
+        let (mut handler, _adcs) = handler();
+        let args = [ArgValue::Int(1), ArgValue::Int(100), ArgValue::Int(-5), ArgValue::Int(0)];
+        assert_eq!(handler.build_cmd(sim_adcs::SET_WHEEL_SPEED, &args).unwrap(), b"SWS:100:-5:0");
+        assert_eq!(handler.build_cmd(sim_adcs::GET_TIME, &[ArgValue::Int(0)]).unwrap(), b"GTM");
+        assert_eq!(handler.build_cmd(sim_adcs::RESET, &[]).unwrap(), b"RESET");
+
+        let e = handler.build_cmd(sim_adcs::SET_TIME, &[ArgValue::Int(1)]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidInput);
+        let e = handler.build_cmd(sim_adcs::GET_STATE, &args).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_sends_cmds() {
+        let (mut handler, mut adcs) = handler();
+        handler.handle_msg_for_adcs(cmd(opcodes::ADCS::OnOff, &["1"])).unwrap();
+        assert_eq!(sent(&mut adcs), "ON");
+        handler.handle_msg_for_adcs(cmd(opcodes::ADCS::MagnetorquerCurrent, &["1", "10", "20", "-30"])).unwrap();
+        assert_eq!(sent(&mut adcs), "SMC:10:20:-30");
+        handler.handle_msg_for_adcs(cmd(opcodes::ADCS::OnboardTime, &["1", "1700000000"])).unwrap();
+        assert_eq!(sent(&mut adcs), "STM:1700000000");
+
+        // Setting the wheel speed needs all three speeds
+        let e = handler.handle_msg_for_adcs(cmd(opcodes::ADCS::WheelSpeed, &["1", "100"])).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidInput);
+        let e = handler.handle_msg_for_adcs(cmd(opcodes::ADCS::Detumble, &[])).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Unsupported);
+        assert_eq!(adcs.available(), 0);
+
+        // Once the ADCS is gone cmds fail rather than panicking
+        drop(adcs);
+        let e = handler.handle_msg_for_adcs(cmd(opcodes::ADCS::GetHk, &[])).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::NotConnected);
+    }
+
+    #[test]
+    fn test_receives_cmds_from_dispatcher() {
+        let (mut handler, mut adcs) = handler_on(IpcServer::new("adcs_test_handler".to_string()));
+        let mut dispatcher = IpcClient::new("adcs_test_handler".to_string()).unwrap();
+        dispatcher.send(&serialize_msg(&cmd(opcodes::ADCS::OnOff, &["1"])).unwrap()).unwrap();
+        // Bytes that aren't a msg are dropped, without taking the handler down
+        dispatcher.send(b"\x01\x02").unwrap();
+        dispatcher.send(&serialize_msg(&cmd(opcodes::ADCS::GetOrientation, &[])).unwrap()).unwrap();
+        handler.poll_dispatcher();
+        assert_eq!(sent(&mut adcs), "ONGOR");
+    }
+
+    #[test]
+    fn test_reports_time_to_time_service() {
+        let (mut handler, mut adcs) = handler_on(IpcServer::new("adcs_test_time_handler".to_string()));
+        let mut time_service = Some(IpcServer::new("adcs_test_dispatcher".to_string()).unwrap());
+        let mut dispatcher = IpcClient::new("adcs_test_time_handler".to_string()).unwrap();
+        let mut set_time = cmd(opcodes::ADCS::OnboardTime, &["1", "1700000000"]);
+        set_time.header.source_id = ComponentIds::Time as u8;
+        dispatcher.send(&serialize_msg(&set_time).unwrap()).unwrap();
+        handler.poll_dispatcher();
+        assert_eq!(sent(&mut adcs), "STM:1700000000");
+
+        // The time service gets the Report, through the cmd_dispatcher
+        let _ = poll_ipc_server_sockets(&mut vec![&mut time_service]);
+        let report = deserialize_msg(&time_service.as_mut().unwrap().recv().unwrap().data).unwrap();
+        assert_eq!((report.header.msg_type, report.header.dest_id), (MsgType::Report, ComponentIds::Time as u8));
+        assert_eq!(report.result_code(), Some(Ok(ResultCode::Success)));
+    }
+}
@@ -4,7 +4,20 @@ Summer 2024
 
 For tall thin all we want this to do is talk to this (via TCP) and have it relay its data to the message dispatcher (via IPC unix domain socket)
 
-TODO - Detect if connection to either msg dispatcher or UHF transceiver is lost, and handle that - attempt to reconnect
+Uplinked msgs are authenticated and decrypted with the active link key (see common::link_crypto)
+before anything is forwarded to the msg dispatcher. Keys are kept in the key store named by EX3_KEY_STORE,
+or the one in the config if that isn't set. Without a key store every uplink is rejected.
+
+Each uplinked cmd is Acked as soon as it is accepted or rejected here. Handlers send their responses and
+the Report on executing a cmd to the gs_non_bulk socket, and those are downlinked as they come in. The
+Reports on cmds for the COMS and UHF are sent the same way, or back through the cmd_dispatcher to the
+component onboard that sent them.
+
+The uplinks accepted mark the start and end of GS passes, which are published as events (see pass_tracker.rs).
+
+The UHF transceiver is connected to again whenever the link to it is lost, e.g. when it or its sim restarts.
+
+TODO - Detect if connection to the msg dispatcher is lost, and handle that - attempt to reconnect
 TODO - implement a 'gs' connection flag, which the handler uses to determine whether or not it can downlink messages to the ground station.
 TODO - mucho error handling
 */
@@ -12,60 +25,145 @@ use log::{debug, trace, warn};
 use common::logging::*;
 
 use common::component_ids::ComponentIds;
-use common::constants::UHF_MAX_MESSAGE_SIZE_BYTES;
+use common::constants::{LINK_CRC, UHF_MAX_MESSAGE_SIZE_BYTES};
+use common::msg_id::RESERVED_MSG_ID;
 use common::opcodes;
-use common::ports;
-use interface::{ipc::*, tcp::*, Interface};
-use common::message_structure::{SerializeAndDeserialize,
-                                deserialize_msg, serialize_msg,
-                                AckCode, CmdMsg, Msg, MsgType};
+use common::config;
+use interface::{factory::{InterfaceSpec, Peripheral}, ipc::*, reactor::Reactor, Interface};
+use interface::reconnect::{LinkState, Reconnecting};
+use common::message_structure::{deserialize_msg, next_link_msg, serialize_msg,
+                                AckCode, CrcKind, DecodeError, Msg, MsgType};
+use common::key_store::{parse_rotate_key_body, KeyStore, MASTER_KEY_ID};
+use common::link_crypto::{CryptoError, OpenedMsg};
+use handler::{EventPublisher, Reporter};
+use std::time::{Duration, Instant};
 use std::vec;
+mod pass_tracker;
+use pass_tracker::PassTracker;
 mod uhf_handler;
 use uhf_handler::UHFHandler;
 
-/// Setup function for decrypting incoming messages from the UHF transceiver
-/// This just decrypts the bytes and does not return a message from the bytes
-fn decrypt_bytes_from_gs(encrypted_bytes: &[u8]) -> Result<&[u8], std::io::Error> {
-    // TODO - Decrypt the message
-    let decrypted_byte_vec = encrypted_bytes;
-    Ok(decrypted_byte_vec)
+/// Authenticate and decrypt a msg uplinked from the GS.
+/// On failure the ack code and reason to NACK the msg with are returned
+fn decrypt_msg_from_gs(key_store: &mut Option<KeyStore>, msg: Msg) -> Result<OpenedMsg, (AckCode, String)> {
+    let Some(ref mut store) = key_store else {
+        return Err((AckCode::AuthFailed, "no key store loaded".to_string()));
+    };
+    store.open(msg).map_err(|e| {
+        let code = match e {
+            CryptoError::Replayed { .. } => AckCode::Replayed,
+            _ => AckCode::AuthFailed,
+        };
+        (code, format!("uplink rejected - {}", e))
+    })
 }
 
-/// For messages directed FOR the coms handler directly. Based on the opcode of the message, perform some action
-fn handle_msg_for_coms(msg: &Msg) {
+fn is_key_mgmt_msg(msg: &Msg) -> bool {
+    msg.header.dest_id == ComponentIds::COMS as u8
+        && matches!(opcodes::COMS::from(msg.header.op_code), opcodes::COMS::RotateKey | opcodes::COMS::SelectKey)
+}
+
+/// For messages directed FOR the coms handler directly. Based on the opcode of the message, perform some action.
+/// Key management is only done for msgs that were uplinked sealed with the master key.
+fn handle_msg_for_coms(msg: &Msg, key_store: &mut Option<KeyStore>, sealed_with_master: bool) -> Result<(), String> {
     let opcode_enum = opcodes::COMS::from(msg.header.op_code);
     match opcode_enum {
         opcodes::COMS::GetHK => {
             trace!("Opcode 3: Get House Keeping Data from COMS Handler for UHF");
         }
+        opcodes::COMS::RotateKey | opcodes::COMS::SelectKey if !sealed_with_master => {
+            return Err("key management must be sealed with the master key".to_string());
+        }
+        opcodes::COMS::RotateKey => {
+            let store = key_store.as_mut().ok_or("no key store loaded")?;
+            let (slot, key) = parse_rotate_key_body(&msg.msg_body).map_err(|e| e.to_string())?;
+            store.set_slot(slot, key).map_err(|e| format!("rotating key failed - {}", e))?;
+            trace!("Opcode 7: Installed new key in slot {}", slot);
+        }
+        opcodes::COMS::SelectKey => {
+            let store = key_store.as_mut().ok_or("no key store loaded")?;
+            let args = opcode_enum.decode(&msg.msg_body).map_err(|e| e.to_string())?;
+            // Never fall back to a default slot, which would switch the link key to one the GS didn't ask for
+            let slot = args.first().and_then(|arg| arg.int::<u8>()).ok_or_else(|| {
+                std::io::Error::new(std::io::ErrorKind::InvalidInput, "SelectKey needs a key slot from 0 to 255").to_string()
+            })?;
+            store.select(slot).map_err(|e| format!("selecting key failed - {}", e))?;
+            trace!("Opcode 8: Switched to key slot {}", slot);
+        }
         _ => debug!("Invalid msg opcode"),
     }
+    Ok(())
+}
+
+/// Authenticate an uplinked msg and act on it. Key management cmds are handled here rather than being
+/// passed through the cmd_dispatcher, since only here is it known which key they were sealed with.
+/// On failure the ack code and reason to NACK the msg with are returned
+fn handle_uplink(
+    key_store: &mut Option<KeyStore>,
+    ipc_cmd_interface: &mut Option<IpcClient>,
+    msg: Msg,
+) -> Result<(), (AckCode, String)> {
+    let opened = decrypt_msg_from_gs(key_store, msg)?;
+    let sealed_with_master = opened.key_id == MASTER_KEY_ID;
+    if is_key_mgmt_msg(&opened.msg) {
+        if !sealed_with_master {
+            return Err((AckCode::AuthFailed, "key management must be sealed with the master key".to_string()));
+        }
+        return handle_msg_for_coms(&opened.msg, key_store, true).map_err(|reason| (AckCode::Failed, reason));
+    }
+    if sealed_with_master {
+        return Err((AckCode::AuthFailed, "the master key is only for key management".to_string()));
+    }
+    forward_uplink_to_dispatcher(ipc_cmd_interface, &opened.msg).map_err(|reason| (AckCode::Failed, reason))
 }
 
 /// Function to send the initial messages containing num of 4KB msgs to expect and the number of
 /// data bytes to expect once the msg is rebuilt
-fn send_initial_bulk_to_gs(initial_msg: Msg, interface: &mut TcpInterface) {
+fn send_initial_bulk_to_gs(initial_msg: Msg, interface: &mut dyn Interface) {
     write_msg_to_uhf_for_downlink(interface, initial_msg);
 }
 
-/// Function for sending an ACK to the bulk disp letting it know to send bulk msgs for downlink
-fn send_bulk_ack(iface: &mut IpcClient) -> Result<(), std::io::Error> {
-    let ack_msg = Msg::new(
-        MsgType::Ack as u8,
-        20,
+/// Function for sending an ACK to the bulk disp letting it know to send bulk msgs for downlink.
+/// `msg_id` is the id of the bulk msg announcing the transfer.
+fn send_bulk_ack(iface: &mut IpcClient, msg_id: u16) -> Result<(), std::io::Error> {
+    let ack_msg = Msg::new_ack(
+        msg_id,
         ComponentIds::BulkMsgDispatcher as u8,
         ComponentIds::COMS as u8,
-        0,
+        AckCode::Success,
         vec![0],
     );
     iface.send(&serialize_msg(&ack_msg)?)?;
     Ok(())
 }
 
+/// Forward a decoded uplink msg to the cmd_dispatcher. On failure the reason is returned so it can be NACKed
+fn forward_uplink_to_dispatcher(ipc_cmd_interface: &mut Option<IpcClient>, msg: &Msg) -> Result<(), String> {
+    let Some(ref mut init_ipc_cmd_interface) = ipc_cmd_interface else {
+        return Err("no connection to cmd dispatcher".to_string());
+    };
+    let bytes = serialize_msg(msg).map_err(|e| format!("serializing msg failed - {}", e))?;
+    match init_ipc_cmd_interface.send(&bytes) {
+        Ok(len) => {
+            debug!("coms: forwarded {} bytes", len);
+            Ok(())
+        }
+        Err(e) => Err(format!("write to cmd dispatcher failed - {}", e)),
+    }
+}
+
+/// Frames damaged on the link are NACKed as corrupted so the GS knows a resend may succeed
+fn uplink_nack_code(err: &DecodeError) -> AckCode {
+    match err {
+        DecodeError::CrcMismatch { .. } => AckCode::Corrupted,
+        _ => AckCode::Failed,
+    }
+}
+
 /// All things to be downlinked use this fxn (later on we want a sort of buffer to store what was downlinked until we get confirmation from the GS it was recevied)
 /// This will handle logging all messages attempted to be downlinked, and handle errors associated with writing data to the UHF transceiver for downlink
-fn write_msg_to_uhf_for_downlink(interface: &mut TcpInterface, msg: Msg) {
-    let serialized_msg_result = serialize_msg(&msg);
+fn write_msg_to_uhf_for_downlink(interface: &mut dyn Interface, msg: Msg) {
+    let serialized_msg_result = serialize_msg(&msg.with_crc(LINK_CRC));
     match serialized_msg_result {
         Ok(serialized_msg) => {
             let send_result = interface.send(&serialized_msg);
@@ -87,16 +185,225 @@ fn write_msg_to_uhf_for_downlink(interface: &mut TcpInterface, msg: Msg) {
     }
 }
 
+/// The UHF transceiver, unless the link to it is down and can't be brought back yet
+fn connected(uhf_interface: &mut Reconnecting<Box<dyn Peripheral>>) -> Option<&mut dyn Interface> {
+    match uhf_interface.maintain() {
+        LinkState::Up => Some(uhf_interface),
+        LinkState::Down => None,
+    }
+}
+
+/// How long the reactor waits when nothing happens, before going round the loop again
+const IDLE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The coms_handler's interfaces and the state of the bulk downlink in progress. Each interface is
+/// handled by a callback the reactor runs when it has data
+struct ComsHandler {
+    ipc_coms_interface: Option<IpcServer>,
+    ipc_cmd_interface: Option<IpcClient>,
+    bulk_downlink_interface: Option<IpcClient>,
+    gs_interface_non_bulk: Option<IpcServer>,
+    uhf_interface: Reconnecting<Box<dyn Peripheral>>,
+    /// Bytes read from the UHF transceiver that don't make a whole frame yet
+    uplink_stream: Vec<u8>,
+    key_store: Option<KeyStore>,
+    uhf_handler: UHFHandler,
+    registration: Registration,
+    reporter: Reporter,
+    passes: PassTracker,
+    received_bulk_ack: bool,
+    bulk_msgs_read: u16,
+    expected_msgs: u16,
+}
+
+impl ComsHandler {
+    fn downlink(&mut self, msg: Msg) {
+        match connected(&mut self.uhf_interface) {
+            Some(uhf_interface) => write_msg_to_uhf_for_downlink(uhf_interface, msg),
+            None => warn!("No connection to the UHF transceiver, dropping msg {} for the GS", msg.header.msg_id),
+        }
+    }
+
+    /// Bulk msgs from the bulk_msg_dispatcher
+    fn handle_bulk_downlink(&mut self) -> Result<(), std::io::Error> {
+        while let Some(frame) = self.bulk_downlink_interface.as_mut().and_then(|c| c.recv()) {
+            trace!("Received IPC Msg bytes for GS");
+            match deserialize_msg(&frame.data) {
+                Ok(deserialized_msg) => {
+                    // if the msg bulk type and we have not send the bulk ack then send it to 
+                    // bulk msg dispatcher.
+                    if deserialized_msg.header.msg_type == MsgType::Bulk
+                        && !self.received_bulk_ack
+                    {
+                        // The first msg starts with how many 4KB msgs follow, and isn't acked without it
+                        let Some(&[lo, hi]) = deserialized_msg.msg_body.get(0..2) else {
+                            warn!("Dropping bulk msg {} too short to say how many msgs follow", deserialized_msg.header.msg_id);
+                            continue;
+                        };
+                        trace!("Sending ACK to bulk dispatcher, should be sending messages now");
+                        if let Some(e) = send_bulk_ack(self.bulk_downlink_interface.as_mut().unwrap(), deserialized_msg.header.msg_id).err() {
+                            println!("failed to send bulk ack: {e}");
+                        }
+                        self.received_bulk_ack = true;
+                        self.expected_msgs = u16::from_le_bytes([lo, hi]);
+                        trace!("Expecting {} 4KB msgs", self.expected_msgs);
+                        // Send msg containing num of 4KB msgs and num of bytes to expect
+                        match connected(&mut self.uhf_interface) {
+                            Some(uhf_interface) => send_initial_bulk_to_gs(deserialized_msg, uhf_interface),
+                            None => warn!("No connection to the UHF transceiver, cannot start bulk downlink"),
+                        }
+                    } else if deserialized_msg.header.msg_type == MsgType::Bulk
+                        && self.received_bulk_ack
+                    {
+                        // Here where we read incoming bulk msgs from bulk_msg_disp
+                        if self.bulk_msgs_read < self.expected_msgs
+                            && frame.sender_path().is_some_and(|p| p.to_string_lossy().contains("gs"))
+                        {
+                            println!("Bytes read: {}", frame.len());
+                            self.downlink(deserialized_msg);
+                            self.bulk_msgs_read += 1;
+                        }
+                    } else {
+                        self.downlink(deserialized_msg);
+                    }
+                }
+                Err(e) => {
+                    warn!("Error deserializing GS IPC msg: {:?}", e);
+                    //Handle deserialization of IPC msg failure
+                }
+            };
+            trace!("Bulk msgs read: {}", self.bulk_msgs_read);
+        }
+        // If we are done reading bulk msgs, start protocol with GS
+        if self.received_bulk_ack && self.bulk_msgs_read >= self.expected_msgs {
+            trace!("Bulk downlink completed... Restarting bulk state machine.");
+            self.bulk_msgs_read = 0;
+            self.expected_msgs = 0;
+            self.received_bulk_ack = false;
+        }
+        Ok(())
+    }
+
+    /// Responses and cmd Reports from handlers are downlinked as they come in
+    fn handle_gs_msgs(&mut self) -> Result<(), std::io::Error> {
+        while let Some(frame) = self.gs_interface_non_bulk.as_mut().and_then(|s| s.recv()) {
+            trace!("Received IPC Msg bytes for GS");
+            match deserialize_msg(&frame.data) {
+                Ok(deserialized_msg) => {
+                    if let Some(Ok(code)) = deserialized_msg.result_code() {
+                        debug!("Downlinking report for msg {}: {}", deserialized_msg.header.msg_id, code);
+                    }
+                    self.downlink(deserialized_msg);
+                }
+                Err(e) => {
+                    warn!("Error deserializing GS IPC msg: {:?}", e);
+                }
+            };
+        }
+        Ok(())
+    }
+
+    /// Msgs for the COMS, and for the UHF which the cmd_dispatcher redirects here
+    fn handle_coms_msgs(&mut self) -> Result<(), std::io::Error> {
+        while let Some(frame) = self.ipc_coms_interface.as_mut().and_then(|s| s.recv()) {
+            trace!("Received COMS IPC Msg bytes");
+            match deserialize_msg(&frame.data) {
+                Ok(deserialized_msg) => {
+                    trace!("Dserd msg body len {}", deserialized_msg.msg_body.len());
+                    let result = if deserialized_msg.header.dest_id == ComponentIds::UHF as u8 {
+                        // Handles msg internally for UHF
+                        match connected(&mut self.uhf_interface) {
+                            Some(uhf_interface) => self.uhf_handler.handle_msg_for_uhf(uhf_interface, &deserialized_msg),
+                            None => Err("no connection to the UHF transceiver".to_string()),
+                        }
+                    } else {
+                        // Handles msg internally for COMS
+                        handle_msg_for_coms(&deserialized_msg, &mut self.key_store, false)
+                    };
+                    let result = result.map(|_| vec![]).map_err(std::io::Error::other);
+                    self.reporter.report(&deserialized_msg, result);
+                }
+                Err(e) => {
+                    warn!("Error deserializing COMS IPC msg: {:?}", e);
+                    //Handle deserialization of IPC msg failure
+                }
+            };
+        }
+        Ok(())
+    }
+
+    /// Frames uplinked from the GS through the UHF transceiver
+    fn handle_uplink_frame(&mut self) -> Result<(), std::io::Error> {
+        let mut uhf_buf = vec![0; UHF_MAX_MESSAGE_SIZE_BYTES]; //Buffer to read incoming messages from UHF
+        // A transceiver that closed the link is noticed here. It isn't polled again until it is connected
+        // to again, which the reactor's timer keeps trying
+        let uhf_num_bytes_read = match self.uhf_interface.read(&mut uhf_buf) {
+            Ok(0) => return Ok(()),
+            Ok(num_bytes_read) => num_bytes_read,
+            Err(e) => {
+                warn!("Error reading from UHF transceiver: {:?}", e);
+                // Part of a frame from a link that went down won't be finished by the next one
+                self.uplink_stream.clear();
+                return Ok(());
+            }
+        };
+        trace!("Received bytes from UHF");
+        // The link is a byte stream, so a read can hold several frames or end partway through one
+        self.uplink_stream.extend_from_slice(&uhf_buf[..uhf_num_bytes_read]);
+        while let Some(frame) = next_link_msg(&mut self.uplink_stream) {
+            self.handle_uplink_msg(frame);
+        }
+        Ok(())
+    }
+
+    /// Forward a frame taken off the uplink to the msg_dispatcher if it is well formed and authentic,
+    /// and ack it to the GS
+    fn handle_uplink_msg(&mut self, frame: Result<Msg, DecodeError>) {
+        let (msg_id, result) = match frame {
+            // The CRC only protects the radio link, so it is dropped before the msg goes onboard
+            Ok(msg) => (msg.header.msg_id,
+                        handle_uplink(&mut self.key_store, &mut self.ipc_cmd_interface, msg.with_crc(CrcKind::None))),
+            // The header could not be trusted, so there is no msg id to correlate the NACK with
+            Err(e) => (RESERVED_MSG_ID, Err((uplink_nack_code(&e), format!("malformed uplink frame - {}", e)))),
+        };
+
+        if result.is_ok() {
+            self.passes.uplink(Instant::now());
+        }
+        let ack = match result {
+            // send ack to groundstation if we recv command successfully
+            // not sure what an actual ack would look like, we probably want to include some
+            // information in the ack body, for now it is blank
+            Ok(()) => Msg::new_ack(msg_id, ComponentIds::GS as u8, ComponentIds::COMS as u8,
+                                   AckCode::Success, vec![]),
+            Err((code, reason)) => {
+                let ackbody = format!("Error: {}", reason);
+                warn!("{}", ackbody);
+                /* Nack failed messages back to the sender */
+                Msg::new_ack(msg_id, ComponentIds::GS as u8, ComponentIds::COMS as u8,
+                             code, ackbody.into_bytes())
+            }
+        };
+        self.downlink(ack);
+    }
+}
+
 fn main() {
-    let ipaddr = std::env::args().nth(1).unwrap_or("localhost".to_string());
-    let log_path = "ex3_obc_fsw/handlers/coms_handler/logs";
-    init_logger(log_path);
+    // The UHF transceiver's interface (see interface::factory) can be given as the first arg, the one in
+    // the config otherwise. Uplinks are only read once the reactor sees they are there, the spec's read
+    // timeout just keeps waiting on the response to a UHF cmd from hanging the handler
+    let uhf_spec: InterfaceSpec = std::env::args()
+        .nth(1)
+        .unwrap_or(config::get().handlers.coms.interface.clone())
+        .parse()
+        .expect("Invalid UHF interface spec");
+    init_program_logger("coms_handler");
     trace!("Logger initialized");
-    trace!("Beginning Coms Handler on {ipaddr}:{}", ports::SIM_ESAT_UART_PORT);
+    trace!("Beginning Coms Handler on {uhf_spec}");
 
     // Setup interface for comm with OBC FSW components (IPC), for passing messages to and from the UHF specifically
     let ipc_coms_interface_res = IpcServer::new("COMS".to_string());
-    let mut ipc_coms_interface = match ipc_coms_interface_res {
+    let ipc_coms_interface = match ipc_coms_interface_res {
         Ok(i) => Some(i),
         Err(e) => {
             warn!("Cannot create COMS pipeline: {e}");
@@ -106,7 +413,7 @@ fn main() {
 
     // Interface for IPC of cmd_dispatcher cmds that get sent up with a certain destination
     let ipc_cmd_interface_res = IpcClient::new("cmd_dispatcher".to_string());
-    let mut ipc_cmd_interface = match ipc_cmd_interface_res {
+    let ipc_cmd_interface = match ipc_cmd_interface_res {
         Ok(i) => Some(i),
         Err(e) => {
             warn!("Cannot create COMS pipeline: {e}");
@@ -116,7 +423,7 @@ fn main() {
 
     // This is the client that listens for bulk messages to be transmit to the groundstaion
     let ipc_gs_interfac_res = IpcClient::new("gs_bulk".to_string());
-    let mut bulk_downlink_interface = match ipc_gs_interfac_res {
+    let bulk_downlink_interface = match ipc_gs_interfac_res {
         Ok(i) => Some(i),
         Err(e) => {
             warn!("Cannot connect to bulk interface: {e}");
@@ -124,7 +431,7 @@ fn main() {
         }
     };
 
-    let mut gs_interface_non_bulk: Option<IpcServer> =
+    let gs_interface_non_bulk: Option<IpcServer> =
         match IpcServer::new("gs_non_bulk".to_string()) {
             Ok(server) => Some(server),
             Err(e) => {
@@ -133,253 +440,261 @@ fn main() {
             }
         };
 
-    // Initialize ipc interface for UHF handler
-    let ipc_uhf_interface_res = IpcServer::new("UHF".to_string());
-    let mut ipc_uhf_interface = match ipc_uhf_interface_res {
-        Ok(i) => Some(i),
+    // Uplinks are only accepted if they authenticate against a key in the key store
+    let key_store = match KeyStore::load_default() {
+        Ok(store) => Some(store),
         Err(e) => {
-            warn!("Cannot create UHF handler pipeline: {e}");
+            warn!("Cannot load key store {:?}, all uplinks will be rejected: {e}", KeyStore::default_path());
             None
         }
     };
 
-    // Initialize UHF handler struct
-    let mut uhf_handler = UHFHandler::new();
-    std::thread::sleep(std::time::Duration::from_secs(1));
-    //Setup interface for comm with UHF transceiver [ground station] (TCP for now)
-    let mut tcp_interface =
-        match TcpInterface::new_client(ipaddr, ports::SIM_ESAT_UART_PORT) {
-            Ok(tcp) => Some(tcp),
-            Err(e) => {
-                warn!("Error creating UHF interface: {e}");
-                None
-            }
-        };
-    // Temporary fix, this should be in a poll with all the other servers
-    // This sets the read to be completely unblocking, so it comes with quite a bit of function
-    // call overhead.
-    let _ = tcp_interface.as_ref().as_mut().unwrap().stream.set_read_timeout(Some(std::time::Duration::from_millis(50)));
-    let mut uhf_buf = vec![0; UHF_MAX_MESSAGE_SIZE_BYTES]; //Buffer to read incoming messages from UHF
-    let mut uhf_num_bytes_read = 0;
-    let mut received_bulk_ack = false;
-    let mut bulk_msgs_read = 0;
-    let mut expected_msgs = 0;
+    std::thread::sleep(Duration::from_secs(1));
+    //Setup interface for comm with UHF transceiver [ground station]
+    let uhf_interface = Reconnecting::open(uhf_spec);
+
+    let mut handler = ComsHandler {
+        ipc_coms_interface,
+        ipc_cmd_interface,
+        bulk_downlink_interface,
+        gs_interface_non_bulk,
+        uhf_interface,
+        uplink_stream: vec![],
+        key_store,
+        uhf_handler: UHFHandler::new(),
+        // Msgs for the UHF come in on the COMS socket too, the cmd_dispatcher redirects them here
+        registration: Registration::new(ComponentIds::COMS, "COMS"),
+        // Reports for the GS come back in on gs_non_bulk, and are downlinked like any other
+        reporter: Reporter::new(ComponentIds::COMS),
+        passes: PassTracker::new(EventPublisher::new(ComponentIds::COMS)),
+        received_bulk_ack: false,
+        bulk_msgs_read: 0,
+        expected_msgs: 0,
+    };
+
+    let mut reactor = Reactor::new();
+    reactor.add_source(|h: &mut ComsHandler| h.bulk_downlink_interface.as_mut(), ComsHandler::handle_bulk_downlink);
+    reactor.add_source(|h: &mut ComsHandler| h.gs_interface_non_bulk.as_mut(), ComsHandler::handle_gs_msgs);
+    reactor.add_source(|h: &mut ComsHandler| h.ipc_coms_interface.as_mut(), ComsHandler::handle_coms_msgs);
+    reactor.add_source(|h: &mut ComsHandler| Some(&mut h.uhf_interface), ComsHandler::handle_uplink_frame);
+    // Renews the registration when it is due, reconnects the UHF transceiver and the Reporter's links if
+    // they are down, and notices the end of a pass
+    reactor.every(Duration::from_secs(1), |h: &mut ComsHandler| {
+        h.registration.maintain();
+        h.reporter.maintain();
+        h.uhf_interface.maintain();
+        h.passes.check(Instant::now());
+        Ok(())
+    });
 
     loop {
-        uhf_buf.fill(0);
-        // Poll both the UHF transceiver and IPC unix domain socket for the GS channel
-        let mut clients = vec![
-            &mut bulk_downlink_interface,
-            &mut ipc_cmd_interface,
-        ];
-        let mut servers = vec![
-            &mut ipc_coms_interface,
-            &mut ipc_uhf_interface,
-        ];
-        let _ = poll_ipc_server_sockets(&mut servers);
-        let ipc_bytes_read_res = poll_ipc_clients(&mut clients);
-
-        if let Some(ref mut init_ipc_gs_interface) = bulk_downlink_interface {
-            if init_ipc_gs_interface.buffer != [0u8; IPC_BUFFER_SIZE] {
-                trace!("Received IPC Msg bytes for GS");
-                let deserialized_msg_result = deserialize_msg(&init_ipc_gs_interface.buffer);
-                match deserialized_msg_result {
-                    Ok(deserialized_msg) => {
-                        // if the msg bulk type and we have not send the bulk ack then send it to 
-                        // bulk msg dispatcher.
-                        if deserialized_msg.header.msg_type == MsgType::Bulk as u8
-                            && !received_bulk_ack
-                        {
-                            trace!("Sending ACK to bulk dispatcher, should be sending messages now");
-                            if let Some(e) = send_bulk_ack(init_ipc_gs_interface).err() {
-                                println!("failed to send bulk ack: {e}");
-                            }
-                            received_bulk_ack = true;
-                            let expected_msgs_bytes =
-                                [deserialized_msg.msg_body[0], deserialized_msg.msg_body[1]];
-                            expected_msgs = u16::from_le_bytes(expected_msgs_bytes);
-                            trace!("Expecting {} 4KB msgs", expected_msgs);
-                            // Send msg containing num of 4KB msgs and num of bytes to expect
-                            send_initial_bulk_to_gs(
-                                deserialized_msg,
-                                tcp_interface.as_mut().unwrap(),
-                            );
-                        } else if deserialized_msg.header.msg_type == MsgType::Bulk as u8
-                            && received_bulk_ack
-                        {
-                            // Here where we read incoming bulk msgs from bulk_msg_disp
-                            if bulk_msgs_read < expected_msgs {
-                                if let Ok((ipc_bytes_read, _ipc_name)) = ipc_bytes_read_res {
-                                    if let Some(client_addr) = init_ipc_gs_interface.server_addr {
-                                        if client_addr.path().unwrap().to_str().unwrap().contains("gs") {
-                                            let cur_buf =
-                                                init_ipc_gs_interface.buffer[..ipc_bytes_read].to_vec();
-                                            println!("Bytes read: {}", cur_buf.len());
-                                            let cur_msg = deserialize_msg(&cur_buf).unwrap();
-                                            write_msg_to_uhf_for_downlink(
-                                                tcp_interface.as_mut().unwrap(),
-                                                cur_msg,
-                                            );
-                                            bulk_msgs_read += 1;
-                                        }
-                                    }
-                                } else {
-                                    warn!("Error reading bytes from poll.");
-                                }
-                            }
-                        } else {
-                            write_msg_to_uhf_for_downlink(
-                                tcp_interface.as_mut().unwrap(),
-                                deserialized_msg,
-                            );
-                        }
-                    }
-                    Err(e) => {
-                        warn!("Error deserializing GS IPC msg: {:?}", e);
-                        //Handle deserialization of IPC msg failure
-                    }
-                };
-                trace!("Bulk msgs read: {}", bulk_msgs_read);
-                init_ipc_gs_interface.clear_buffer();
+        match reactor.run_once(&mut handler, IDLE_TIMEOUT) {
+            Ok(errors) => {
+                for (source, e) in errors {
+                    warn!("Error handling {:?}: {}", source, e);
+                }
             }
+            Err(e) => warn!("Error waiting for input: {}", e),
         }
-        // If we are done reading bulk msgs, start protocol with GS
-        if received_bulk_ack && bulk_msgs_read >= expected_msgs {
-            trace!("Bulk downlink completed... Restarting bulk state machine.");
-            bulk_msgs_read = 0;
-            expected_msgs = 0;
-            received_bulk_ack = false;
-            bulk_downlink_interface.as_mut().unwrap().clear_buffer();
-        }
+    }
+}
 
-        // Poll the IPC unix domain socket for the COMS channel
-        if let Some(ref mut init_ipc_coms_interface) = ipc_coms_interface {
-            if init_ipc_coms_interface.buffer != [0u8; IPC_BUFFER_SIZE] {
-                trace!("Received COMS IPC Msg bytes");
-                let deserialized_msg_result = deserialize_msg(&init_ipc_coms_interface.buffer);
-                match deserialized_msg_result {
-                    Ok(deserialized_msg) => {
-                        trace!("Dserd msg body len {}", deserialized_msg.msg_body.len());
-                        // Handles msg internally for COMS
-                        handle_msg_for_coms(&deserialized_msg);
-                    }
-                    Err(e) => {
-                        warn!("Error deserializing COMS IPC msg: {:?}", e);
-                        //Handle deserialization of IPC msg failure
-                    }
-                };
-                init_ipc_coms_interface.clear_buffer();
-            }
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::message_structure::{deserialize_link_msg, ResultCode, HEADER_SIZE};
+    use interface::reactor::Pollable;
 
-        // Poll the IPC unix domain socket for the UHF handler channel
-        if let Some(ref mut init_ipc_uhf_interface) = ipc_uhf_interface {
-            if init_ipc_uhf_interface.buffer != [0u8; IPC_BUFFER_SIZE] {
-                trace!("Received UHF Handler IPC Msg bytes");
-                let deserialized_msg_result = deserialize_msg(&init_ipc_uhf_interface.buffer);
-                match deserialized_msg_result {
-                    Ok(deserialized_msg) => {
-                        trace!("Dserd msg body len {}", deserialized_msg.msg_body.len());
-                        // Handles msg internally for UHF
-                        uhf_handler
-                            .handle_msg_for_uhf(tcp_interface.as_mut().unwrap(), &deserialized_msg);
-                    }
-                    Err(e) => {
-                        warn!("Error deserializing UHF Handler IPC msg: {:?}", e);
-                        //Handle deserialization of IPC msg failure
-                    }
-                };
-                init_ipc_uhf_interface.clear_buffer();
-            }
-        }
-        let uhf_bytes_read_result = tcp_interface.as_mut().unwrap().read(&mut uhf_buf);
-        match uhf_bytes_read_result {
-            Ok(num_bytes_read) => {
-                uhf_num_bytes_read = num_bytes_read;
-            },
-            // Part of the temporary blocking fix.
-            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                uhf_num_bytes_read = 0;
-            },
-            Err(e) => {
-                warn!("Error reading from UHF transceiver: {:?}", e);
-            }
+    #[test]
+    fn corrupt_uplink_is_nacked_as_corrupted() {
+        let msg = Msg::new(MsgType::Cmd, 3, ComponentIds::EPS as u8, ComponentIds::GS as u8, 1, vec![1, 2, 3]);
+        let mut frame = serialize_msg(&msg.with_crc(LINK_CRC)).unwrap();
+        let last = frame.len() - 1;
+        frame[last] ^= 0x10;
+
+        let err = deserialize_link_msg(&frame).unwrap_err();
+        assert_eq!(uplink_nack_code(&err), AckCode::Corrupted);
+    }
+
+    fn key_store_pair(dir: &std::path::Path) -> (KeyStore, Option<KeyStore>) {
+        let gs = KeyStore::generate(&dir.join("gs_keys")).unwrap();
+        std::fs::copy(dir.join("gs_keys"), dir.join("obc_keys")).unwrap();
+        (gs, Some(KeyStore::load(&dir.join("obc_keys")).unwrap()))
+    }
+
+    fn shell_cmd() -> Msg {
+        Msg::new(MsgType::Cmd, 3, ComponentIds::SHELL as u8, ComponentIds::GS as u8, 0, b"ls".to_vec())
+    }
+
+    fn key_cmd(opcode: opcodes::COMS, body: Vec<u8>) -> Msg {
+        Msg::new(MsgType::Cmd, 4, ComponentIds::COMS as u8, ComponentIds::GS as u8, opcode as u8, body)
+    }
+
+    #[test]
+    fn uplink_auth_failures_are_nacked() {
+        let dir = tempdir::TempDir::new("coms_keys").unwrap();
+        let (mut gs, mut obc) = key_store_pair(dir.path());
+        let sealed = gs.seal(shell_cmd()).unwrap();
+
+        let mut no_store = None;
+        assert_eq!(decrypt_msg_from_gs(&mut no_store, sealed.clone()).unwrap_err().0, AckCode::AuthFailed);
+
+        assert_eq!(decrypt_msg_from_gs(&mut obc, shell_cmd()).unwrap_err().0, AckCode::AuthFailed);
+        assert_eq!(decrypt_msg_from_gs(&mut obc, sealed.clone()).unwrap().msg.msg_body, b"ls");
+        assert_eq!(decrypt_msg_from_gs(&mut obc, sealed).unwrap_err().0, AckCode::Replayed);
+    }
+
+    #[test]
+    fn key_rotation_requires_master_key() {
+        let dir = tempdir::TempDir::new("coms_keys").unwrap();
+        let (mut gs, mut obc) = key_store_pair(dir.path());
+        let mut no_dispatcher = None;
+        let new_key = [5; 32];
+
+        let rotate = key_cmd(opcodes::COMS::RotateKey, common::key_store::rotate_key_body(1, &new_key));
+        let with_active_key = gs.seal(rotate.clone()).unwrap();
+        assert_eq!(handle_uplink(&mut obc, &mut no_dispatcher, with_active_key).unwrap_err().0, AckCode::AuthFailed);
+        assert!(obc.as_ref().unwrap().slot(1).is_none());
+
+        let with_master = gs.seal_with_master(rotate).unwrap();
+        assert!(handle_uplink(&mut obc, &mut no_dispatcher, with_master).is_ok());
+        assert_eq!(obc.as_ref().unwrap().slot(1).unwrap().bytes(), &new_key);
+
+        let select = gs.seal_with_master(key_cmd(opcodes::COMS::SelectKey, vec![1])).unwrap();
+        assert!(handle_uplink(&mut obc, &mut no_dispatcher, select).is_ok());
+        assert_eq!(obc.as_ref().unwrap().active_slot(), 1);
+
+        // A SelectKey without a slot is refused, rather than switching to slot 0
+        let no_slot = key_cmd(opcodes::COMS::SelectKey, vec![]);
+        assert!(handle_msg_for_coms(&no_slot, &mut obc, true).is_err());
+        assert_eq!(obc.as_ref().unwrap().active_slot(), 1);
+
+        // The switch survives a restart
+        assert_eq!(KeyStore::load(&dir.path().join("obc_keys")).unwrap().active_slot(), 1);
+
+        // Only key management may be sealed with the master key
+        let master_shell = gs.seal_with_master(shell_cmd()).unwrap();
+        assert_eq!(handle_uplink(&mut obc, &mut no_dispatcher, master_shell).unwrap_err().0, AckCode::AuthFailed);
+
+        // and it can't be done over IPC either
+        let ipc_select = key_cmd(opcodes::COMS::SelectKey, vec![0]);
+        assert!(handle_msg_for_coms(&ipc_select, &mut obc, false).is_err());
+        assert_eq!(obc.as_ref().unwrap().active_slot(), 1);
+    }
+
+    #[test]
+    fn uplink_without_crc_is_rejected() {
+        let msg = Msg::new(MsgType::Cmd, 3, ComponentIds::EPS as u8, ComponentIds::GS as u8, 1, vec![1, 2, 3]);
+        let frame = serialize_msg(&msg).unwrap();
+
+        let err = deserialize_link_msg(&frame).unwrap_err();
+        assert_eq!(uplink_nack_code(&err), AckCode::Failed);
+    }
+
+    /// Handler on the UHF transceivers `ends` opens in turn, with its Reports sent to test sockets
+    fn coms_handler(mut ends: Vec<interface::mock::PipeEnd>) -> ComsHandler {
+        let connect = move || ends.pop().map(|end| Box::new(end) as Box<dyn Peripheral>)
+            .ok_or(std::io::Error::from(std::io::ErrorKind::ConnectionRefused));
+        let (bus, _events) = interface::mock::pipe();
+        ComsHandler {
+            ipc_coms_interface: None,
+            ipc_cmd_interface: None,
+            bulk_downlink_interface: None,
+            gs_interface_non_bulk: None,
+            uhf_interface: Reconnecting::new("UHF", connect).backoff(Duration::ZERO, Duration::ZERO),
+            uplink_stream: vec![],
+            key_store: None,
+            uhf_handler: UHFHandler::new(),
+            registration: Registration::new(ComponentIds::COMS, "coms_test"),
+            reporter: Reporter::with_sockets(ComponentIds::COMS, "coms_test_gs", "coms_test_dispatcher"),
+            passes: PassTracker::new(EventPublisher::with_bus(ComponentIds::COMS, Box::new(bus))),
+            received_bulk_ack: false,
+            bulk_msgs_read: 0,
+            expected_msgs: 0,
         }
-        if uhf_num_bytes_read > 0 {
-            trace!("Received bytes from UHF");
-            //TODO - Decrypt incomming encrypted bytes
-            let decrypted_byte_result = decrypt_bytes_from_gs(&uhf_buf);
-            let mut status = AckCode::Success;
-            let mut ackbody = "Error: ".to_string();
-            let cmd: CmdMsg = match decrypted_byte_result {
-                // After decrypting, send directly to the msg_dispatcher
-                Ok(msg) => {
-                    if let Some(ref mut init_ipc_cmd_interface) = ipc_cmd_interface {
-                        if let Some(_server_addr) = init_ipc_cmd_interface.server_addr {
-                            match init_ipc_cmd_interface.send(msg) {
-                                Ok(len) => debug!("coms: forwarded {} bytes", len),
-                                Err(e) => {
-                                    status = AckCode::Failed;
-                                    ackbody.push_str(&format!("write to cmd dispatcher failed - {}", e));
-                                }
-                            };
-                        }
-                    }
-                    else {
-                        status = AckCode::Failed;
-                        ackbody.push_str("no connection to cmd dispatcher");
-                    }
-                    CmdMsg::deserialize_from_bytes(msg)
-                }
-                Err(e) => {
-                    status = AckCode::Failed;
-                    ackbody.push_str(&format!("decryption failed - {}", e));
-                    CmdMsg::deserialize_from_bytes(&uhf_buf)
-                }
-            };
+    }
 
-            if status == AckCode::Failed {
-                warn!("{}", ackbody);
-                /* Nack failed messages back to the sender */
-                let nack = Msg::new(MsgType::Ack as u8, cmd.header.msg_id,
-                                    ComponentIds::GS as u8, ComponentIds::COMS as u8,
-                                    status as u8, ackbody.as_bytes().to_vec());
-                write_msg_to_uhf_for_downlink(tcp_interface.as_mut().unwrap(), nack);
-                uhf_buf.fill(0);
-            }
-            else {
-                // send ack to groundstation if we recv command successfully
-                // not sure what an actual ack would look like, we probably want to include some
-                // information in the ack body, for now it is blank
-                let ackbody = [];
-                let ack = Msg::new(MsgType::Ack as u8, cmd.header.msg_id,
-                                    ComponentIds::GS as u8, ComponentIds::COMS as u8,
-                                    status as u8, ackbody.to_vec());
-                write_msg_to_uhf_for_downlink(tcp_interface.as_mut().unwrap(), ack);
-                uhf_buf.fill(0);
-            }
+    #[test]
+    fn uhf_link_is_reconnected() {
+        let (gs1, uhf1) = interface::mock::pipe();
+        let (mut gs2, uhf2) = interface::mock::pipe();
+        let mut coms = coms_handler(vec![uhf2, uhf1]);
+
+        // The transceiver restarting closes the link, which is then opened again rather than given up on
+        drop(gs1);
+        coms.handle_uplink_frame().unwrap();
+        assert_eq!(coms.uhf_interface.state(), LinkState::Down);
+        assert_eq!(coms.uhf_interface.maintain(), LinkState::Up);
+
+        // and uplinks are handled again, this one NACKed for having no key store
+        gs2.send(&serialize_msg(&shell_cmd().with_crc(LINK_CRC)).unwrap()).unwrap();
+        coms.handle_uplink_frame().unwrap();
+        let mut buf = [0u8; UHF_MAX_MESSAGE_SIZE_BYTES];
+        let len = gs2.read(&mut buf).unwrap();
+        let nack = deserialize_link_msg(&buf[..len]).unwrap();
+        assert_eq!((nack.header.msg_id, nack.ack_code()), (3, Some(Ok(AckCode::AuthFailed))));
+    }
+
+    #[test]
+    fn uplink_stream_is_reframed() {
+        let (mut gs, uhf) = interface::mock::pipe();
+        let mut coms = coms_handler(vec![uhf]);
+        assert_eq!(coms.uhf_interface.maintain(), LinkState::Up);
+        let frame = |msg_id| {
+            let mut cmd = shell_cmd();
+            cmd.header.msg_id = msg_id;
+            serialize_msg(&cmd.with_crc(LINK_CRC)).unwrap()
+        };
+        let third = frame(5);
+
+        // Two frames read together, then one split across reads, are each NACKed for having no key store
+        gs.send(&[frame(3), frame(4), third[..HEADER_SIZE + 1].to_vec()].concat()).unwrap();
+        coms.handle_uplink_frame().unwrap();
+        gs.send(&third[HEADER_SIZE + 1..]).unwrap();
+        coms.handle_uplink_frame().unwrap();
+
+        let mut downlink = vec![0u8; UHF_MAX_MESSAGE_SIZE_BYTES];
+        let len = gs.read(&mut downlink).unwrap();
+        downlink.truncate(len);
+        let mut nacked = vec![];
+        while let Some(nack) = next_link_msg(&mut downlink) {
+            let nack = nack.unwrap();
+            assert_eq!(nack.ack_code(), Some(Ok(AckCode::AuthFailed)));
+            nacked.push(nack.header.msg_id);
         }
+        assert_eq!(nacked, vec![3, 4, 5]);
+    }
 
-        let mut servers: Vec<&mut Option<IpcServer>> = vec![&mut gs_interface_non_bulk];
-        let _ = poll_ipc_server_sockets(&mut servers);
-        // Handle regular messages for GS
-        if let Some(ref mut gs_if) = gs_interface_non_bulk {
-            if gs_if.buffer != [0u8; IPC_BUFFER_SIZE] {
-                trace!("GS msg server \"{}\" received data", gs_if.socket_path);
-                match deserialize_msg(&gs_if.buffer) {
-                    Ok(msg) => {
-                        trace!("got {:?}", msg);
-                        write_msg_to_uhf_for_downlink(tcp_interface.as_mut().unwrap(), msg);
-                        gs_if.clear_buffer();
-                    }
-                    Err(err) => {
-                        warn!("Error deserialising message for gs ({:?})", err);
-                    }
-                }
-            }
+    #[test]
+    fn coms_cmds_are_reported() {
+        let (_gs, uhf) = interface::mock::pipe();
+        let mut coms = coms_handler(vec![uhf]);
+        coms.ipc_coms_interface = Some(IpcServer::new("coms_test_handler".to_string()).unwrap());
+        let mut gs = Some(IpcServer::new("coms_test_gs".to_string()).unwrap());
+        let mut onboard = Some(IpcServer::new("coms_test_dispatcher".to_string()).unwrap());
+        let mut dispatcher = IpcClient::new("coms_test_handler".to_string()).unwrap();
+        let coms_id = ComponentIds::COMS as u8;
+        let cmds = [
+            Msg::new(MsgType::Cmd, 1, coms_id, ComponentIds::GS as u8, opcodes::COMS::GetHK as u8, vec![]),
+            // Not sealed with the master key, so it fails
+            Msg::new(MsgType::Cmd, 2, coms_id, ComponentIds::Sequencer as u8, opcodes::COMS::SelectKey as u8, vec![1]),
+        ];
+        for cmd in &cmds {
+            dispatcher.send(&serialize_msg(cmd).unwrap()).unwrap();
         }
+        while coms.ipc_coms_interface.as_ref().is_some_and(|s| !s.has_pending()) {
+            let _ = poll_ipc_server_sockets(&mut vec![&mut coms.ipc_coms_interface]);
+        }
+        coms.handle_coms_msgs().unwrap();
+
+        let recv = |server: &mut Option<IpcServer>| {
+            let _ = poll_ipc_server_sockets(&mut vec![server]);
+            deserialize_msg(&server.as_mut().unwrap().recv().unwrap().data).unwrap()
+        };
+        let report = recv(&mut gs);
+        assert_eq!((report.header.msg_id, report.result_code()), (1, Some(Ok(ResultCode::Success))));
+        let report = recv(&mut onboard);
+        assert_eq!((report.header.msg_id, report.header.dest_id), (2, ComponentIds::Sequencer as u8));
+        assert_ne!(report.result_code(), Some(Ok(ResultCode::Success)));
     }
 }
-
-#[cfg(test)]
-mod tests {}
@@ -0,0 +1,90 @@
+/*
+Ground station passes, as seen from the uplinks the COMS accepts.
+
+A pass starts with the first uplink accepted after a quiet spell, and ends once nothing has been accepted
+for PASS_TIMEOUT. Each start is published as a GsPassStart event, and each end as a GsPassEnd event with
+the length of the pass in s.
+*/
+
+use common::events::EventKind;
+use handler::EventPublisher;
+use log::info;
+use std::time::{Duration, Instant};
+
+/// How long without an uplink before a pass is taken to be over
+pub const PASS_TIMEOUT: Duration = Duration::from_secs(120);
+
+pub struct PassTracker {
+    events: EventPublisher,
+    /// When the pass in progress started, and when its last uplink was
+    pass: Option<(Instant, Instant)>,
+}
+
+impl PassTracker {
+    pub fn new(events: EventPublisher) -> PassTracker {
+        PassTracker { events, pass: None }
+    }
+
+    /// An uplink was accepted at `now`
+    pub fn uplink(&mut self, now: Instant) {
+        match self.pass {
+            Some((start, _)) => self.pass = Some((start, now)),
+            None => {
+                info!("GS pass started");
+                self.pass = Some((now, now));
+                self.events.publish(EventKind::GsPassStart, 0);
+            }
+        }
+    }
+
+    /// End the pass in progress if it has been quiet for too long
+    pub fn check(&mut self, now: Instant) {
+        let Some((start, last_uplink)) = self.pass else {
+            return;
+        };
+        if now.duration_since(last_uplink) >= PASS_TIMEOUT {
+            let length = last_uplink.duration_since(start).as_secs();
+            info!("GS pass ended after {} s", length);
+            self.pass = None;
+            self.events.publish(EventKind::GsPassEnd, length.min(i32::MAX as u64) as i32);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::message_structure::deserialize_msg;
+    use interface::mock::{pipe, PipeEnd};
+    use interface::Interface;
+
+    /// The events published since last time, as (event, value)
+    fn published(bus: &mut PipeEnd) -> Vec<(u8, i32)> {
+        let mut events = vec![];
+        let mut buf = [0u8; 64];
+        while bus.available() > 0 {
+            let len = bus.read(&mut buf).unwrap();
+            let body = deserialize_msg(&buf[..len]).unwrap().msg_body;
+            events.push((body[0], i32::from_le_bytes([body[1], body[2], body[3], body[4]])));
+        }
+        events
+    }
+
+    #[test]
+    fn test_passes_start_and_end() {
+        let (publisher, mut bus) = pipe();
+        let mut passes = PassTracker::new(EventPublisher::with_bus(common::ComponentIds::COMS, Box::new(publisher)));
+        let t0 = Instant::now();
+        passes.check(t0);
+        passes.uplink(t0);
+        passes.uplink(t0 + Duration::from_secs(30));
+        passes.check(t0 + Duration::from_secs(60));
+        assert_eq!(published(&mut bus), [(EventKind::GsPassStart as u8, 0)]);
+
+        passes.check(t0 + Duration::from_secs(30) + PASS_TIMEOUT);
+        assert_eq!(published(&mut bus), [(EventKind::GsPassEnd as u8, 30)]);
+        passes.check(t0 + Duration::from_secs(600));
+        passes.uplink(t0 + Duration::from_secs(600));
+        assert_eq!(published(&mut bus), [(EventKind::GsPassStart as u8, 0)]);
+    }
+}
@@ -9,7 +9,7 @@ use common::constants::UHF_MAX_MESSAGE_SIZE_BYTES;
 use common::opcodes;
 use log::{debug, trace, warn};
 use common::message_structure::*;
-use interface::{tcp::*, Interface};
+use interface::Interface;
 
 // Struct containing UHF parameters to be modified
 pub struct UHFHandler {
@@ -28,10 +28,19 @@ impl UHFHandler {
             buffer: vec![0; UHF_MAX_MESSAGE_SIZE_BYTES],
         }
     }
-    pub fn handle_msg_for_uhf(&mut self, uhf_interface: &mut TcpInterface, msg: &Msg) {
+    /// Act on a cmd for the UHF. Errors for cmds that can't be made sense of are returned to be reported
+    pub fn handle_msg_for_uhf(&mut self, uhf_interface: &mut dyn Interface, msg: &Msg) -> Result<(), String> {
         // Can Only use this function when we have simulated UHF integrated with rest of OBC software
         let opcode = opcodes::UHF::from(msg.header.op_code);
-        let data = msg.msg_body.clone();
+        let args = match opcode.decode(&msg.msg_body) {
+            Ok(args) => args,
+            Err(e) if opcode != opcodes::UHF::Error => {
+                warn!("Invalid msg body for UHF opcode {}: {}", msg.header.op_code, e);
+                return Err(e.to_string());
+            }
+            // Reported as an invalid opcode below
+            Err(_) => vec![],
+        };
         match opcode {
             opcodes::UHF::GetHK => {
                 trace!("Opcode 3 for UHF: Getting Housekeeping data");
@@ -39,7 +48,8 @@ impl UHFHandler {
             }
             opcodes::UHF::SetBeacon => {
                 trace!("Opcode 4 for UHF: Setting beacon value.");
-                self.set_beacon_value(uhf_interface, data);
+                let beacon = args[0].as_str().unwrap_or_default();
+                self.set_beacon_value(uhf_interface, beacon.as_bytes().to_vec());
             }
             opcodes::UHF::GetBeacon => {
                 trace!("Opcode 5 for UHF: Getting beacon value.");
@@ -47,7 +57,7 @@ impl UHFHandler {
             }
             opcodes::UHF::SetMode => {
                 trace!("Opcode 6 for UHF: Setting UHF mode value.");
-                self.set_mode(uhf_interface, data);
+                self.set_mode(uhf_interface, args[0].int::<u8>().unwrap_or_default());
             }
             opcodes::UHF::Reset => {
                 trace!("Opcode 7 for UHF: Resetting UHF.");
@@ -59,13 +69,16 @@ impl UHFHandler {
             }
             _ => {
                 warn!("Invalid opcode for UHF handler");
+                self.clear_buffer();
+                return Err(format!("invalid UHF opcode {}", msg.header.op_code));
             }
         }
         // clear uhf buffer after command is handled
         self.clear_buffer();
+        Ok(())
     }
 
-    fn set_beacon_value(&mut self, uhf_interface: &mut TcpInterface, data: Vec<u8>) {
+    fn set_beacon_value(&mut self, uhf_interface: &mut dyn Interface, data: Vec<u8>) {
         // Extract useful bytes from data
         let new_beacon_as_bytes = extract_non_null_bytes(data);
         // Beacon bytes can only be ASCII encoded letters or numbers, if other return early
@@ -104,7 +117,7 @@ impl UHFHandler {
         self.beacon = new_beacon_as_string;
     }
 
-    fn get_beacon_value(&mut self, uhf_interface: &mut TcpInterface) {
+    fn get_beacon_value(&mut self, uhf_interface: &mut dyn Interface) {
         // construct command to get UHF beacon
         let cmd: Vec<u8> = "UHF:GET_BEACON:".as_bytes().to_vec();
         // send command
@@ -129,43 +142,10 @@ impl UHFHandler {
         trace!("Current UHF Beacon Message: {}", self.beacon);
     }
 
-    fn set_mode(&mut self, uhf_interface: &mut TcpInterface, data: Vec<u8>) {
-        // Extract useful bytes from data
-        let new_mode_as_bytes = extract_non_null_bytes(data);
-        for ascii_byte in &new_mode_as_bytes {
-            if is_valid_ascii_digit(*ascii_byte) {
-                continue;
-            } else {
-                warn!(
-                    "Byte {}, is not a valid ascii encoded digit. ",
-                    *ascii_byte as char
-                );
-                warn!("Abort setting mode value.");
-                return;
-            }
-        }
-        // Check if data can be converted to UTF-8, return early if not able to
-        let new_mode_as_string = match String::from_utf8(new_mode_as_bytes.clone()) {
-            Ok(mode_str) => mode_str,
-            Err(e) => {
-                warn!("Error converting bytes to UTF-8: {}", e);
-                warn!("Abort setting mode value.");
-                return;
-            }
-        };
-
-        let new_mode_as_u8: u8 = match new_mode_as_string.trim().parse::<u8>() {
-            Ok(new_mode) => new_mode,
-            Err(e) => {
-                warn!("Error occured parsing mode into integer: {e}");
-                warn!("Aborting setting mode value");
-                return;
-            }
-        };
+    fn set_mode(&mut self, uhf_interface: &mut dyn Interface, new_mode: u8) {
         // Create Command.
         let prefix: Vec<u8> = "UHF:SET_MODE:".as_bytes().to_vec();
-        // Remove extra bytes from the new beacon value msg
-        let mut cmd: Vec<u8> = new_mode_as_bytes;
+        let mut cmd: Vec<u8> = new_mode.to_string().into_bytes();
         cmd.splice(0..0, prefix);
 
         // Send Command.
@@ -174,11 +154,11 @@ impl UHFHandler {
         // TODO, add error handling here to see if UHF gets error
         self.read_into_buffer(uhf_interface);
         self.clear_buffer();
-        self.mode = new_mode_as_u8;
+        self.mode = new_mode;
         trace!("UHF Mode Set to: {}", self.mode);
     }
 
-    fn get_mode(&mut self, uhf_interface: &mut TcpInterface) {
+    fn get_mode(&mut self, uhf_interface: &mut dyn Interface) {
         // construct command to get UHF beacon
         let cmd: Vec<u8> = "UHF:GET_MODE:".as_bytes().to_vec();
         // send command
@@ -220,10 +200,9 @@ impl UHFHandler {
         trace!("Resetting UHF");
     }
 
-    fn read_into_buffer(&mut self, uhf_interface: &mut TcpInterface) {
+    fn read_into_buffer(&mut self, uhf_interface: &mut dyn Interface) {
         // read bytes into UHF buffer
-        let read_result: Result<usize, std::io::Error> =
-            TcpInterface::read(uhf_interface, &mut self.buffer);
+        let read_result: Result<usize, std::io::Error> = uhf_interface.read(&mut self.buffer);
         match read_result {
             Ok(n) => {
                 trace!("Command response length: {} bytes ", n)
@@ -234,7 +213,7 @@ impl UHFHandler {
         }
     }
 
-    fn send_msg(&mut self, uhf_interface: &mut TcpInterface, content: Vec<u8>) {
+    fn send_msg(&mut self, uhf_interface: &mut dyn Interface, content: Vec<u8>) {
         let send_result = uhf_interface.send(&content);
         match send_result {
             Ok(_) => trace!("Sent command successfully"),
@@ -266,7 +245,54 @@ fn is_valid_ascii_digit_or_letter(byte: u8) -> bool {
     matches!(byte, 48..=57 | 65..=90 | 97..=122)
 }
 
-fn is_valid_ascii_digit(byte: u8) -> bool {
-    // checks if byte is a valid base 10 ascii encoded digit
-    matches!(byte, 48..=57)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::component_ids::ComponentIds;
+    use interface::mock::{Fault, FaultInjector, ScriptedPeripheral};
+
+    fn cmd(opcode: opcodes::UHF, args: &[&str]) -> Msg {
+        let body = opcode.info().unwrap().encode(args).unwrap();
+        Msg::new(MsgType::Cmd, 1, ComponentIds::UHF as u8, ComponentIds::GS as u8, opcode as u8, body)
+    }
+
+    #[test]
+    fn test_beacon() {
+        let mut handler = UHFHandler::new();
+        let mut uhf = ScriptedPeripheral::new()
+            .expect(b"UHF:SET_BEACON:Ex3Beacon1", b"OK")
+            .expect(b"UHF:GET_BEACON:", b"FromUHF\0\0");
+        handler.handle_msg_for_uhf(&mut uhf, &cmd(opcodes::UHF::SetBeacon, &["Ex3Beacon1"])).unwrap();
+        assert_eq!(handler.beacon, "Ex3Beacon1");
+        handler.handle_msg_for_uhf(&mut uhf, &cmd(opcodes::UHF::GetBeacon, &[])).unwrap();
+        assert_eq!(handler.beacon, "FromUHF");
+        assert!(uhf.is_done());
+        assert!(handler.buffer.iter().all(|&b| b == 0));
+
+        // Only letters and digits can be beaconed, so nothing is sent for anything else
+        handler.handle_msg_for_uhf(&mut uhf, &cmd(opcodes::UHF::SetBeacon, &["not", "valid!"])).unwrap();
+        assert_eq!(handler.beacon, "FromUHF");
+        assert_eq!(uhf.sent().len(), 2);
+    }
+
+    #[test]
+    fn test_mode() {
+        let mut handler = UHFHandler::new();
+        let mut uhf = ScriptedPeripheral::new()
+            .expect(b"UHF:SET_MODE:3", b"OK")
+            .expect(b"UHF:GET_MODE:", b"5\n")
+            .expect(b"UHF:GET_MODE:", b"fast");
+        handler.handle_msg_for_uhf(&mut uhf, &cmd(opcodes::UHF::SetMode, &["3"])).unwrap();
+        assert_eq!(handler.mode, 3);
+        handler.handle_msg_for_uhf(&mut uhf, &cmd(opcodes::UHF::GetMode, &[])).unwrap();
+        assert_eq!(handler.mode, 5);
+        // A response that isn't a mode, or is lost, leaves it as it was
+        handler.handle_msg_for_uhf(&mut uhf, &cmd(opcodes::UHF::GetMode, &[])).unwrap();
+        assert_eq!(handler.mode, 5);
+        let mut uhf = FaultInjector::new(ScriptedPeripheral::new().expect(b"UHF:GET_MODE:", b"7"))
+            .on_read(Fault::Drop);
+        handler.handle_msg_for_uhf(&mut uhf, &cmd(opcodes::UHF::GetMode, &[])).unwrap();
+        assert_eq!(handler.mode, 5);
+        assert!(uhf.get_ref().is_done());
+    }
 }
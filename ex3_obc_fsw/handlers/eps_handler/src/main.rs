@@ -1,91 +1,46 @@
 /*
 Written by Kaaden RumanCam
 Fall 2024
+
+The battery voltage is read every BATTERY_POLL and published as a BatteryVoltage event, for cmds
+scheduled on it.
 */
 
-use log::{debug, trace, warn};
-use std::io::Error;
+use log::{debug, trace};
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+
+use common::{config, message_structure::*, opcodes};
+use common::component_ids::ComponentIds;
+use common::events::EventKind;
+use handler::{EventPublisher, Handler, HandlerRuntime, Response};
+use interface::{factory::InterfaceSpec, tcp::*, Interface};
 
-use common::{logging::*, message_structure::*, opcodes, ports};
-use common::component_ids::ComponentIds::{EPS, GS};
-use common::constants::DOWNLINK_MSG_BODY_SIZE;
-use interface::{ipc::*, tcp::*, Interface};
+/// How often the battery voltage is published
+const BATTERY_POLL: Duration = Duration::from_secs(10);
 
 struct EPSHandler {
-    eps_interface: Option<TcpInterface>, // To communicate with the EPS
-    msg_dispatcher_interface: Option<IpcServer>, // For communcation with other FSW components [internal to OBC]
-    gs_interface: Option<IpcClient>, // To send messages to the GS through the coms_handler
+    events: EventPublisher,
 }
 
 impl EPSHandler {
-    pub fn new(
-        eps_interface: Result<TcpInterface, std::io::Error>,
-        msg_dispatcher_interface: Result<IpcServer, std::io::Error>,
-        gs_interface: Result<IpcClient, std::io::Error>,
-    ) -> EPSHandler {
-        if eps_interface.is_err() {
-            warn!(
-                "Error creating eps interface: {:?}",
-                eps_interface.as_ref().err().unwrap()
-            );
-        }
-        if msg_dispatcher_interface.is_err() {
-            warn!(
-                "Error creating dispatcher interface: {:?}",
-                msg_dispatcher_interface.as_ref().err().unwrap()
-            );
-        }
-        if gs_interface.is_err() {
-            warn!(
-                "Error creating gs interface: {:?}",
-                gs_interface.as_ref().err().unwrap()
-            );
-        }
-        EPSHandler {
-            eps_interface: eps_interface.ok(),
-            msg_dispatcher_interface: msg_dispatcher_interface.ok(),
-            gs_interface: gs_interface.ok(),
-        }
-    }
-
-    // Sets up threads for reading and writing to its interaces, and sets up channels for communication between threads and the handler
-    pub fn run(&mut self) -> std::io::Result<()> {
-        // Poll for messages
-        loop {
-            // First, take the Option<IpcClient> out of `self.dispatcher_interface`
-            // This consumes the Option, so you can work with the owned IpcClient
-            let msg_dispatcher_interface = self.msg_dispatcher_interface.take().expect("Cmd_Disp has value of None");
-
-            // Create a mutable Option<IpcClient> so its lifetime persists
-            let mut msg_dispatcher_interface_option = Some(msg_dispatcher_interface);
-
-            // Now you can borrow this mutable option and place it in the vector
-            let mut server: Vec<&mut Option<IpcServer>> = vec![
-                &mut msg_dispatcher_interface_option,
-            ];
-
-            let _ = poll_ipc_server_sockets(&mut server);
-
-            // restore the value back into `self.dispatcher_interface` after polling. May have been mutated
-            self.msg_dispatcher_interface = msg_dispatcher_interface_option;
-
-            // Handling the bulk message dispatcher interface
-            let msg_dispatcher_interface = self.msg_dispatcher_interface.as_ref().unwrap();
-            if msg_dispatcher_interface.buffer != [0u8; IPC_BUFFER_SIZE] {
-                let recv_msg: Msg = deserialize_msg(&msg_dispatcher_interface.buffer).unwrap();
-                debug!("Received and deserialized msg");
-                self.handle_msg(recv_msg)?;
-            }
-        }
+    /// Send the EPS a request, returning its reply
+    fn request(eps_interface: &mut dyn Interface, cmd: &str) -> Result<String, Error> {
+        let mut tcp_buf = [0u8;BUFFER_SIZE];
+        eps_interface.send(cmd.as_bytes())?;
+        eps_interface.read(&mut tcp_buf)?;
+        let tmp = String::from_utf8_lossy(&tcp_buf).to_string();
+        Ok(tmp.trim_end_matches(char::from(0)).to_string())
     }
+}
 
-    fn handle_msg(&mut self, msg: Msg) -> Result<(), Error> {
-        self.msg_dispatcher_interface.as_mut().unwrap().clear_buffer();
+impl Handler for EPSHandler {
+    const COMPONENT: ComponentIds = ComponentIds::EPS;
 
+    /// Execute a cmd, returning the EPS's response
+    fn on_command(&mut self, msg: &Msg, peripheral: Option<&mut dyn Interface>) -> Result<Response, Error> {
         trace!("EPS msg opcode: {} {:?}", msg.header.op_code, msg.msg_body);
 
-        let mut tcp_buf = [0u8;BUFFER_SIZE];
-
         let opcode = opcodes::EPS::from(msg.header.op_code);
         let mut cmd = "dummy";
         match opcode {
@@ -105,41 +60,113 @@ impl EPSHandler {
             }
             opcodes::EPS::Error => {
                 debug!("Unrecognised opcode");
+                return Err(Error::new(
+                    ErrorKind::NotFound,
+                    format!("Opcode {} not found for EPS", msg.header.op_code),
+                ));
             }
         }
 
-        TcpInterface::send(self.eps_interface.as_mut().unwrap(), cmd.as_bytes())?;
-        TcpInterface::read(self.eps_interface.as_mut().unwrap(), &mut tcp_buf)?;
-        let tmp = String::from_utf8(tcp_buf.to_vec()).unwrap();
-        let mut resp = tmp.trim_end_matches(char::from(0)).to_string();
+        let eps_interface = peripheral
+            .ok_or_else(|| Error::new(ErrorKind::NotConnected, "EPS is not connected"))?;
+        let resp = Self::request(eps_interface, cmd)?;
         trace!("From EPS got: {:?}",resp);
-        resp.truncate(DOWNLINK_MSG_BODY_SIZE);
 
-        let msg = Msg::new(MsgType::Cmd as u8, 0, GS as u8, EPS as u8, 0, resp.as_bytes().to_vec());
-        if let Some(ref mut gs_resp_interface) = self.gs_interface {
-            let _ = gs_resp_interface.send(&serialize_msg(&msg)?);
-        } else  {
-            debug!("Response not sent to gs. IPC interface not created");
-        }
+        Ok(resp.into_bytes())
+    }
 
+    /// Publish the battery voltage
+    fn on_tick(&mut self, peripheral: Option<&mut dyn Interface>) -> Result<(), Error> {
+        let Some(eps_interface) = peripheral else {
+            return Ok(());
+        };
+        let resp = Self::request(eps_interface, "request:BatteryVoltage")?;
+        let millivolts = resp.strip_prefix("BatteryVoltage:").and_then(|v| v.trim().parse::<i32>().ok())
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("bad battery voltage from EPS: {:?}", resp)))?;
+        self.events.publish(EventKind::BatteryVoltage, millivolts);
         Ok(())
     }
 }
 
 fn main() {
-    let log_path = "ex3_obc_fsw/handlers/eps_handler/logs";
-    init_logger(log_path);
+    let config = config::get();
+    // The EPS's interface (see interface::factory) can be given as the first arg, the one in the config otherwise
+    let eps_spec: InterfaceSpec = std::env::args()
+        .nth(1)
+        .unwrap_or(config.handlers.eps.interface.clone())
+        .parse()
+        .expect("Invalid EPS interface spec");
+
+    let eps = EPSHandler { events: EventPublisher::new(ComponentIds::EPS) };
+    let mut runtime = HandlerRuntime::new(eps)
+        .logs(config.log_dir("eps_handler"))
+        .peripheral_spec(eps_spec)
+        .tick(BATTERY_POLL);
+    runtime.run();
+}
 
-    trace!("Starting EPS Handler...");
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interface::mock::{pipe, Fault, FaultInjector, PipeEnd, ScriptedPeripheral};
 
-    // Create Unix domain socket interface for to talk to message dispatcher
-    let msg_dispatcher_interface = IpcServer::new("EPS".to_string());
+    fn cmd(opcode: opcodes::EPS) -> Msg {
+        Msg::new(MsgType::Cmd, 1, ComponentIds::EPS as u8, ComponentIds::GS as u8, opcode as u8, vec![])
+    }
 
-    let gs_interface = IpcClient::new("gs_non_bulk".to_string());
+    /// A handler, and the end of the event bus its events come out of
+    fn eps_handler() -> (EPSHandler, PipeEnd) {
+        let (bus, events) = pipe();
+        (EPSHandler { events: EventPublisher::with_bus(ComponentIds::EPS, Box::new(bus)) }, events)
+    }
 
-    let eps_interface = TcpInterface::new_client("127.0.0.1".to_string(), ports::SIM_EPS_PORT);
+    #[test]
+    fn test_requests_and_responses() {
+        let mut eps = ScriptedPeripheral::new()
+            .expect(b"request:Temperature", b"Temperature: 21\0\0")
+            .expect(b"execute:ResetDevice", b"Resetting");
+        let (mut handler, _events) = eps_handler();
+        let response = handler.on_command(&cmd(opcodes::EPS::GetHK), Some(&mut eps)).unwrap();
+        assert_eq!(response, b"Temperature: 21");
+        let response = handler.on_command(&cmd(opcodes::EPS::Reset), Some(&mut eps)).unwrap();
+        assert_eq!(response, b"Resetting");
+        assert!(eps.is_done());
+    }
 
-    let mut eps_handler = EPSHandler::new(eps_interface, msg_dispatcher_interface, gs_interface);
+    #[test]
+    fn test_failures() {
+        let (mut handler, _events) = eps_handler();
+        let e = handler.on_command(&cmd(opcodes::EPS::GetHK), None).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::NotConnected);
+
+        let mut eps = ScriptedPeripheral::new();
+        let mut invalid = cmd(opcodes::EPS::GetHK);
+        invalid.header.op_code = 99;
+        assert_eq!(handler.on_command(&invalid, Some(&mut eps)).unwrap_err().kind(), ErrorKind::NotFound);
+        assert!(eps.sent().is_empty());
+
+        // The EPS not answering fails the cmd rather than giving an empty response
+        let mut eps = FaultInjector::new(ScriptedPeripheral::new().expect(b"request:Temperature", b"21"))
+            .on_read(Fault::Error(ErrorKind::TimedOut));
+        let e = handler.on_command(&cmd(opcodes::EPS::GetHK), Some(&mut eps)).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::TimedOut);
+    }
 
-    let _ = eps_handler.run();
+    #[test]
+    fn test_publishes_battery_voltage() {
+        let (mut handler, mut events) = eps_handler();
+        let mut eps = ScriptedPeripheral::new()
+            .expect(b"request:BatteryVoltage", b"BatteryVoltage: 7400\0")
+            .expect(b"request:BatteryVoltage", b"ERROR: no field BatteryVoltage");
+        handler.on_tick(Some(&mut eps)).unwrap();
+        let mut buf = [0u8; 64];
+        let len = events.read(&mut buf).unwrap();
+        let event = deserialize_msg(&buf[..len]).unwrap();
+        assert_eq!(event.header.op_code, opcodes::Scheduler::PublishEvent as u8);
+        assert_eq!(event.msg_body, [EventKind::BatteryVoltage as u8, 0xe8, 0x1c, 0, 0]);
+
+        assert_eq!(handler.on_tick(Some(&mut eps)).unwrap_err().kind(), ErrorKind::InvalidData);
+        handler.on_tick(None).unwrap();
+        assert_eq!(events.available(), 0);
+    }
 }
@@ -8,97 +8,109 @@ Three things to do:
     one in the example, talks to the sim gps rn, and get time lat long etc 
     and the one to send thingfs backt o the ground station (example of that in the shell handler file rn ie when send things back to client, 
 
+The first data from the GPS after the handler starts is taken as it having a fix, and published as a
+GpsFix event.
 
+SetTime, which the time service sends to keep the GPS's clock with the onboard one, is sent to the GPS
+as "set_time <secs>", in the same text requests as "time".
 */
 
-use log::{debug, trace, warn};
-use common::logging::*;
-use std::io::Error;
+use log::info;
+use std::io::{Error, ErrorKind};
 
-use interface::ipc::{IpcClient, IpcServer, IPC_BUFFER_SIZE, poll_ipc_clients, poll_ipc_server_sockets};
+use common::{config, opcodes, ComponentIds};
+use common::events::EventKind;
 use common::message_structure::*;
-
-use std::{thread, time};
+use handler::{EventPublisher, Handler, HandlerRuntime, Response};
+use interface::{factory::InterfaceSpec, Interface};
 
 struct GPSHandler {
-    // Olivia and ben write the interface from the example here!!!!
-    msg_dispatcher_interface: Option<IpcServer>, // For communcation with other FSW components [internal to OBC]
+    events: EventPublisher,
+    has_fix: bool,
 }
 
-impl GPSHandler {
-    pub fn new(
-        msg_dispatcher_interface: Result<IpcServer, std::io::Error>,
-    ) -> GPSHandler {
-        if msg_dispatcher_interface.is_err() {
-            warn!(
-                "Error creating dispatcher interface: {:?}",
-                msg_dispatcher_interface.as_ref().err().unwrap()
-            );
-        }
-        GPSHandler {
-            msg_dispatcher_interface: msg_dispatcher_interface.ok(),
-        }
-    }
-
-    // Sets up threads for reading and writing to its interaces, and sets up channels for communication between threads and the handler
-    pub fn run(&mut self) -> std::io::Result<()> {
-        // Poll for messages
-        loop {
-            // First, take the Option<IpcClient> out of `self.dispatcher_interface`
-            // This consumes the Option, so you can work with the owned IpcClient
-            let msg_dispatcher_interface = self.msg_dispatcher_interface.take().expect("Cmd_Disp has value of None");
-
-            // Create a mutable Option<IpcClient> so its lifetime persists
-            let mut msg_dispatcher_interface_option = Some(msg_dispatcher_interface);
+impl Handler for GPSHandler {
+    const COMPONENT: ComponentIds = ComponentIds::GPS;
 
-            // Now you can borrow this mutable option and place it in the vector
-            let mut server: Vec<&mut Option<IpcServer>> = vec![
-                &mut msg_dispatcher_interface_option,
-            ];
-
-            let _ = poll_ipc_server_sockets(&mut server);
-
-            // restore the value back into `self.dispatcher_interface` after polling. May have been mutated
-            self.msg_dispatcher_interface = msg_dispatcher_interface_option;
-
-            // Handling the bulk message dispatcher interface
-            let msg_dispatcher_interface = self.msg_dispatcher_interface.as_ref().unwrap();
-            if msg_dispatcher_interface.buffer != [0u8; IPC_BUFFER_SIZE] {
-                let recv_msg: Msg = deserialize_msg(&msg_dispatcher_interface.buffer).unwrap();
-                debug!("Received and deserialized msg");
-                self.handle_msg(recv_msg)?;
-            }
-        }
-    }
 // HANDLE MATCH STATEMENTS
-    fn handle_msg(&mut self, msg: Msg) -> Result<(), Error> {
-        self.msg_dispatcher_interface.as_mut().unwrap().clear_buffer();
+    fn on_command(&mut self, msg: &Msg, gps_interface: Option<&mut dyn Interface>) -> Result<Response, Error> {
         println!("GPS msg opcode: {} {:?}", msg.header.op_code, msg.msg_body);
         // handle opcodes: https://docs.google.com/spreadsheets/d/1rWde3jjrgyzO2fsg2rrVAKxkPa2hy-DDaqlfQTDaNxg/edit?gid=0#gid=0
+        match opcodes::GPS::from(msg.header.op_code) {
+            opcodes::GPS::SetTime => {
+                let args = opcodes::GPS::SetTime.decode(&msg.msg_body)?;
+                let gps = gps_interface.ok_or_else(|| Error::new(ErrorKind::NotConnected, "GPS not connected"))?;
+                gps.send(format!("set_time {}", args[0]).as_bytes())?;
+                Ok(vec![])
+            }
+            _ => Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("Opcode {} is not implemented for GPS yet", msg.header.op_code),
+            )),
+        }
+    }
+
+    fn on_peripheral_data(&mut self, data: &[u8]) -> Result<(), Error> {
+        info!("Got \"{}\" from GPS", String::from_utf8_lossy(data));
+        if !self.has_fix && !data.is_empty() {
+            self.has_fix = true;
+            self.events.publish(EventKind::GpsFix, 0);
+        }
         Ok(())
     }
 }
 
 fn main() {
-    let log_path = "ex3_obc_fsw/handlers/gps_handler/logs";
-    init_logger(log_path);
-
-    trace!("Starting GPS Handler...");
-
-    // Create Unix domain socket interface for to talk to message dispatcher
-    let msg_dispatcher_interface = IpcServer::new("GPS".to_string());
-
-    let mut gps_handler = GPSHandler::new(msg_dispatcher_interface);
-
-    // example (TODO add gps_interface to GPSHandler object and poll in run loop)
-    let mut gps_interface = IpcClient::new("gps_device".to_string()).ok();      // connect("/tmp/fifo_socket_gps_device")
-    if let Some(ref mut iface) = gps_interface {
-        let _ = iface.send("time".as_bytes());
+    let config = config::get();
+    // The GPS's interface (see interface::factory) can be given as the first arg. By default it is the
+    // one in the config, the sim gps, which answers requests for the time, lat, long etc
+    let gps_spec: InterfaceSpec = std::env::args()
+        .nth(1)
+        .unwrap_or(config.handlers.gps.interface.clone())
+        .parse()
+        .expect("Invalid GPS interface spec");
+
+    let gps = GPSHandler { events: EventPublisher::new(ComponentIds::GPS), has_fix: false };
+    let mut runtime = HandlerRuntime::new(gps)
+        .logs(config.log_dir("gps_handler"))
+        .peripheral(move || {
+            let mut gps_interface = gps_spec.open()?;
+            // example, the answer is logged by on_peripheral_data
+            gps_interface.send("time".as_bytes())?;
+            Ok(gps_interface)
+        });
+    runtime.run();
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interface::mock::pipe;
+
+    #[test]
+    fn test_publishes_first_fix() {
+        let (bus, events) = pipe();
+        let mut gps = GPSHandler { events: EventPublisher::with_bus(ComponentIds::GPS, Box::new(bus)), has_fix: false };
+        gps.on_peripheral_data(b"").unwrap();
+        assert_eq!(events.available(), 0);
+        gps.on_peripheral_data(b"time: 1717243200").unwrap();
+        let published = events.available();
+        assert!(published > 0);
+        gps.on_peripheral_data(b"time: 1717243201").unwrap();
+        assert_eq!(events.available(), published);
     }
-    thread::sleep(time::Duration::from_millis(100));                            // wait (only for example)
-    let _ = poll_ipc_clients(&mut vec![&mut gps_interface]);                    // recv()
-    println!("Got \"{}\"", String::from_utf8(gps_interface.as_mut().unwrap().read_buffer()).unwrap());
 
-    let _ = gps_handler.run();
+    #[test]
+    fn test_set_time() {
+        let (bus, _events) = pipe();
+        let mut gps = GPSHandler { events: EventPublisher::with_bus(ComponentIds::GPS, Box::new(bus)), has_fix: false };
+        let body = opcodes::GPS::SetTime.info().unwrap().encode(&["1717243200"]).unwrap();
+        let cmd = Msg::new(MsgType::Cmd, 1, ComponentIds::GPS as u8, ComponentIds::Time as u8, opcodes::GPS::SetTime as u8, body);
+        let (mut handler_end, mut gps_end) = pipe();
+        gps.on_command(&cmd, Some(&mut handler_end)).unwrap();
+        let mut buf = [0u8; 32];
+        let len = gps_end.read(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"set_time 1717243200");
+        assert_eq!(gps.on_command(&cmd, None).unwrap_err().kind(), ErrorKind::NotConnected);
+    }
 }
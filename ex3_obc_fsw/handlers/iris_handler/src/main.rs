@@ -11,8 +11,6 @@ require multiple commands on the IRIS to activate, the handler should recognize
 
 TODO - implement iris handler and interfacing (need to figure out how)
 
-TODO - If connection is lost with an interface, attempt to reconnect every 5 seconds
-TOOD - Figure out way to use polymorphism and have the interfaces be configurable at runtime (i.e. TCP, UART, etc.)
 TODO - Get state variables from a state manager (channels?) upon instantiation and update them as needed.
 TODO - Setup a way to handle opcodes from messages passed to the handler
 
@@ -21,10 +19,12 @@ TODO - Setup a way to handle opcodes from messages passed to the handler
 
 use common::logging::*;
 use log::{debug, trace, warn};
-use common::{opcodes, ports, ComponentIds};
+use common::{config, opcodes, ComponentIds};
 use common::opcodes::IRIS::GetHK;
-use interface::{ipc::*, tcp::*, Interface};
+use interface::{factory::{InterfaceSpec, Peripheral}, ipc::*, reconnect::Reconnecting, Interface};
 use common::message_structure::*;
+use handler::Reporter;
+use common::msg_id::MsgIdAllocator;
 use std::fs::OpenOptions;
 use std::{io, thread};
 use std::io::prelude::*;
@@ -33,33 +33,29 @@ use std::time::{Instant, Duration};
 use std::collections::HashMap;
 use serde_json::json;
 
-const IRIS_DATA_DIR_PATH: &str = "ex3_obc_fsw/handlers/iris_handler/iris_data";
 const IRIS_PACKET_SIZE: usize = 1252;
 const IRIS_INTERFACE_BUFFER_SIZE: usize = IRIS_PACKET_SIZE;
 
-/// Opcodes for messages relating to IRIS functionality
+// Opcodes for messages relating to IRIS functionality
 // pub enum OpCode {
 
 // }
 
 /// Interfaces are option types incase they are not properly created upon running this handler, so the program does not panic
 struct IRISHandler {
-    peripheral_interface: Option<TcpInterface>, // For communication with the IRIS peripheral [external to OBC]. Will be dynamic
-    dispatcher_interface: Option<IpcClient>, // For communcation with other FSW components [internal to OBC] (i.e. message dispatcher)
+    peripheral_interface: Reconnecting<Box<dyn Peripheral>>, // For communication with the IRIS peripheral [external to OBC]. Will be dynamic
+    dispatcher_interface: Option<IpcServer>, // For communcation with other FSW components [internal to OBC] (i.e. message dispatcher)
+    reporter: Reporter, // To send the Reports on cmds back to the GS or the component onboard that sent them
+    msg_ids: MsgIdAllocator, // For msgs this handler originates itself
 }
 
 impl IRISHandler {
     pub fn new(
-        iris_interface: Result<TcpInterface, std::io::Error>,
-        dispatcher_interface: Result<IpcClient, std::io::Error>,
+        iris_interface: Reconnecting<Box<dyn Peripheral>>,
+        dispatcher_interface: Result<IpcServer, std::io::Error>,
+        reporter: Reporter,
     ) -> IRISHandler {
         //if either interfaces are error, print this
-        if iris_interface.is_err() {
-            warn!(
-                "Error creating IRIS interface: {:?}",
-                iris_interface.as_ref().err().unwrap()
-            );
-        }
         if dispatcher_interface.is_err() {
             warn!(
                 "Error creating dispatcher interface: {:?}",
@@ -69,27 +65,36 @@ impl IRISHandler {
 
         IRISHandler {
 
-            peripheral_interface: iris_interface.ok(),
+            peripheral_interface: iris_interface,
             dispatcher_interface: dispatcher_interface.ok(),
+            reporter,
+            msg_ids: MsgIdAllocator::spacecraft(ComponentIds::IRIS),
         }
     }
 
-    fn handle_msg_for_iris(&mut self, msg: Msg) -> Option<String> {
-        self.dispatcher_interface.as_mut().unwrap().clear_buffer();
-        let mut hk = false;
+    /// Send a cmd to the IRIS, returning its response
+    fn handle_msg_for_iris(&mut self, msg: &Msg) -> Result<String, Error> {
         let op: String;
-        let (command_msg, success) = match opcodes::IRIS::from(msg.header.op_code) {
+        let opcode = opcodes::IRIS::from(msg.header.op_code);
+        let args = match opcode.decode(&msg.msg_body) {
+            Ok(args) => args,
+            // Reported as an unknown opcode below
+            Err(_) if opcode == opcodes::IRIS::Error => vec![],
+            Err(e) => {
+                warn!("Invalid msg body for IRIS opcode {}: {}", msg.header.op_code, e);
+                return Err(e.into());
+            }
+        };
+        let (command_msg, success) = match opcode {
             opcodes::IRIS::Reset=> {
                 ("RST", true)
             }
             // Image commands
             opcodes::IRIS::ToggleSensor=> {
-                if msg.msg_body[0] == 1 {
+                if args[0].as_bool() == Some(true) {
                     ("ON", true)
-                } else if msg.msg_body[0] == 0 {
-                    ("OFF", true)
                 } else {
-                    ("Error: invalid msg body for opcode 1", false)
+                    ("OFF", true)
                 }
             }
             opcodes::IRIS::CaptureImage=> {
@@ -97,19 +102,19 @@ impl IRISHandler {
             }
             opcodes::IRIS::FetchImage=> {
                 // Assumes that there are not more than 255 images being request at any one time
-                op = format!("FTI:{}", msg.msg_body[0]);
+                op = format!("FTI:{}", args[0]);
                 (op.as_str(), true)
             }
             opcodes::IRIS::GetImageSize=> {
                 // Currently can only access the first 255 images stored on IRIS, will be updated if needed
-                op = format!("FSI:{}", msg.msg_body[0]);
+                op = format!("FSI:{}", args[0]);
                 (op.as_str(), true)
             }
             opcodes::IRIS::GetNImagesAvailable=> {
                 ("FNI", true)
             }
             opcodes::IRIS::DelImage=> {
-                op = format!("DTI:{}", msg.msg_body[0]);
+                op = format!("DTI:{}", args[0]);
                 (op.as_str(),true)
             }
             // Housekeeping commands
@@ -117,12 +122,10 @@ impl IRISHandler {
                 ("FTT", true)
             }
             opcodes::IRIS::SetTime=> {
-                // Placeholder for reading the total time need to determine how we will handle >255 values (ie. epoch time)
-                op = format!("STT:{}", msg.msg_body[0]);
+                op = format!("STT:{}", args[0]);
                 (op.as_str(), true)
             }
             opcodes::IRIS::GetHK=> {
-                hk = true;
                 ("FTH", true)
             }
             opcodes::IRIS::Error => {
@@ -131,29 +134,17 @@ impl IRISHandler {
                 
             }
         };
-        if success {
-            // Send command message to IRIS
-            let status = TcpInterface::send(self.peripheral_interface.as_mut().unwrap(), command_msg.as_bytes());
-
-            if write_status(status, command_msg) { // Write succeeded
-                let status = receive_response(self.peripheral_interface.as_mut().unwrap());
-                
-                match status {
-                    // Ok(_data_len) => { println!("Got data {:?}", std::str::from_utf8(&response)); }
-                    Ok(response) => {
-                    trace!("Got data {:?}", response);
-                        if hk {
-                            return Some(response);
-                        }
-                    }
-                    Err(e) => { debug!("Error: {}", e); }
-                }
-
-            }
-            return None;
+        if !success {
+            trace!("Command: {}", command_msg);
+            return Err(Error::new(ErrorKind::NotFound, command_msg.to_string()));
         }
-        trace!("Command: {}", command_msg);
-        None
+        // Send command message to IRIS
+        self.peripheral_interface.send(command_msg.as_bytes()).inspect_err(|e| debug!("Error: {}", e))?;
+        trace!("Command {} successfully sent", command_msg);
+
+        let response = receive_response(&mut self.peripheral_interface).inspect_err(|e| debug!("Error: {}", e))?;
+        trace!("Got data {:?}", response);
+        Ok(response)
     }
     // Sets up threads for reading and writing to its interaces, and sets up channels for communication between threads and the handler
     pub fn run(&mut self) -> std::io::Result<()> {
@@ -161,9 +152,12 @@ impl IRISHandler {
         // TMP 5 secs. More realistically every couple mins or so
         let hk_interval = Duration::from_secs(5);
         let mut last_hk_collect = Instant::now();
+        let mut registration = Registration::new(ComponentIds::IRIS, &ComponentIds::IRIS.to_string());
 
         // Read and poll for input for a message
         loop {
+            registration.maintain();
+            self.reporter.maintain();
 
             // Check if we need to collect HK
             if last_hk_collect.elapsed() >= hk_interval {
@@ -181,47 +175,33 @@ impl IRISHandler {
             // Sleep to prevent busy waiting
             // TODO - is ths necessary? What condition does this prevent? It works without sleep
             thread::sleep(Duration::from_millis(500));
-            
-            // First, take the Option<IpcClient> out of `self.dispatcher_interface`
-            // This consumes the Option, so you can work with the owned IpcClient
-            let msg_dispatcher_interface = self.dispatcher_interface.take().expect("Cmd_Disp has value of None");
-
-            // Create a mutable Option<IpcClient> so its lifetime persists
-            let mut msg_dispatcher_interface_option = Some(msg_dispatcher_interface);
-
-            // Now you can borrow this mutable option and place it in the vector
-            let mut clients: Vec<&mut Option<IpcClient>> = vec![
-                &mut msg_dispatcher_interface_option,
-            ];
 
-            poll_ipc_clients(&mut clients)?;
-
-            // restore the value back into `self.dispatcher_interface` after polling. May have been mutated
-            self.dispatcher_interface = msg_dispatcher_interface_option;
+            self.poll_dispatcher();
+        }
+    }
 
+    /// Wait a little for cmds from the cmd_dispatcher, and handle any that came
+    fn poll_dispatcher(&mut self) {
+        let _ = poll_ipc_server_sockets(&mut vec![&mut self.dispatcher_interface]);
 
-            
-            // Handling the bulk message dispatcher interface
-            if let Some(cmd_msg_dispatcher) = self.dispatcher_interface.as_mut() {
-                if cmd_msg_dispatcher.buffer != [0u8; IPC_BUFFER_SIZE] {
-                    let recv_msg: Msg = deserialize_msg(&cmd_msg_dispatcher.buffer).unwrap();
-                    trace!("Received and deserialized msg");
-                    self.handle_msg_for_iris(recv_msg);
-                }
-            }
+        // Handle every msg received from the dispatcher since the last poll
+        while let Some(frame) = self.dispatcher_interface.as_mut().and_then(|s| s.recv()) {
+            let recv_msg: Msg = deserialize_msg(&frame.data).unwrap();
+            trace!("Received and deserialized msg");
+            let result = self.handle_msg_for_iris(&recv_msg);
+            self.reporter.report(&recv_msg, result.map(String::into_bytes));
         }
     }
 
     /// This function is a first iteration of how a handler will collect HK.
     /// Each handler will have a different version of this function as each HK is unique
     fn collect_hk(&mut self) -> io::Result<()> {
-        let hk_msg = Msg::new(55, 55,
+        let hk_msg = Msg::new(MsgType::Cmd, self.msg_ids.next_id(),
                               ComponentIds::IRIS as u8, ComponentIds::IRIS as u8,
                               GetHK as u8, vec![]);
-        if let Some(hk_string) = self.handle_msg_for_iris(hk_msg) {
-            let hk_bytes = format_iris_hk(hk_string.as_bytes())?;
-            store_iris_data("hk_test", &hk_bytes)?;
-        }
+        let hk_string = self.handle_msg_for_iris(&hk_msg)?;
+        let hk_bytes = format_iris_hk(hk_string.as_bytes())?;
+        store_iris_data("hk_test", &hk_bytes)?;
 
         Ok(())
     }
@@ -235,11 +215,12 @@ impl IRISHandler {
 /// Later on we likely want to specify a path to specific storage medium (sd card 1 or 2)
 /// We may also want to implement something generic to handle 'payload data' storage so we can have it duplicated, stored in multiple locations, or compressed etc.
 fn store_iris_data(filename: &str, data: &[u8]) -> std::io::Result<()> {
-    std::fs::create_dir_all(IRIS_DATA_DIR_PATH)?;
+    let data_dir = config::get().data_dir("iris");
+    std::fs::create_dir_all(&data_dir)?;
     let mut file = OpenOptions::new()
         .append(true)
         .create(true)
-        .open(format!("{}/{}", IRIS_DATA_DIR_PATH, filename))?;
+        .open(data_dir.join(filename))?;
     file.write_all(data)?;
     Ok(())
 }
@@ -269,7 +250,7 @@ fn format_iris_hk(data: &[u8]) -> Result<Vec<u8>, std::io::Error> {
 }
 
 
-fn receive_response(peripheral_interface: &mut TcpInterface) ->  Result<String, Error>{
+fn receive_response(peripheral_interface: &mut dyn Interface) ->  Result<String, Error>{
     let mut packet_content = [0u8; IRIS_INTERFACE_BUFFER_SIZE];
     let packet_len = parse_packet(peripheral_interface, &mut packet_content, false,"None")?;
    
@@ -308,7 +289,7 @@ fn receive_response(peripheral_interface: &mut TcpInterface) ->  Result<String,
 /// Receives and translates IRIS packet, currently the IRIS simulated subsystem sends packets in the following format:
 /// FLAG:length:...data...|END|, where length is replaced with the length of data
 /// Until we know for certain the commands and their response structures this will have to make do
-fn parse_packet(peripheral_interface: &mut TcpInterface,  response:  &mut [u8; IRIS_INTERFACE_BUFFER_SIZE], is_image:  bool, image_name: &str) ->  Result<usize, Error>{
+fn parse_packet(peripheral_interface: &mut dyn Interface,  response:  &mut [u8; IRIS_INTERFACE_BUFFER_SIZE], is_image:  bool, image_name: &str) ->  Result<usize, Error>{
     let flag: [u8; 4] = [70, 76, 65, 71]; // is "FLAG" in bytes
     let mut flag_match: usize = 0;
     let delim = 58; // is the delimiter for the simulated subsystem ":"
@@ -321,39 +302,39 @@ fn parse_packet(peripheral_interface: &mut TcpInterface,  response:  &mut [u8; I
     
     // Check for flag
     while flag_match < flag.len() {
-        TcpInterface::read(peripheral_interface, &mut packet_byte)?;
+        peripheral_interface.read(&mut packet_byte)?;
         if packet_byte[0] == flag[flag_match]{
             flag_match += 1;
         }
         else { flag_match = 0; }
     }
-    TcpInterface::read(peripheral_interface, &mut packet_byte)?; // Consume delimiter
-    TcpInterface::read(peripheral_interface, &mut packet_byte)?; // Read first int of packet length
+    peripheral_interface.read(&mut packet_byte)?; // Consume delimiter
+    peripheral_interface.read(&mut packet_byte)?; // Read first int of packet length
     
 
     // Get packet length
     while packet_byte[0] != delim {
         packet_length = (packet_length*10) + ((packet_byte[0]-48) as usize); // Increase packet length  (Assumes there is a present for packet length)    
-        TcpInterface::read(peripheral_interface, &mut packet_byte)?;
+        peripheral_interface.read(&mut packet_byte)?;
     }
 
     // Read packet, currently only images are > 1 packet
     if is_image {
         let mut temp_length = packet_length;
         while temp_length > IRIS_INTERFACE_BUFFER_SIZE{ // Read in full packets
-            TcpInterface::read(peripheral_interface, &mut packet_buffer)?;
+            peripheral_interface.read(&mut packet_buffer)?;
             store_iris_data(image_name, &packet_buffer)?;
             temp_length -= IRIS_INTERFACE_BUFFER_SIZE;
         }
         for buffer_byte in packet_buffer.iter_mut().take(temp_length) { // Read the final partial packet
-            TcpInterface::read(peripheral_interface, &mut packet_byte)?; 
+            peripheral_interface.read(&mut packet_byte)?; 
             *buffer_byte = packet_byte[0];
         }
         store_iris_data(image_name, &packet_buffer)?; // Append packet to image file
     }
     else {
         for res_byte in response.iter_mut().take(packet_length) {
-            TcpInterface::read(peripheral_interface, &mut packet_byte)?; 
+            peripheral_interface.read(&mut packet_byte)?; 
             *res_byte = packet_byte[0];
             // print!("{}", packet_byte[0] as char);
         }
@@ -362,36 +343,26 @@ fn parse_packet(peripheral_interface: &mut TcpInterface,  response:  &mut [u8; I
     Ok(packet_length)
 }
 
-/// Verify that a command was successfully sent via checking the return status of a tcp write
-/// Returns either true on successful send or false on failed send
-fn write_status(status: Result<usize, Error>, cmd: &str) -> bool{
-    match status {
-        Ok(_data_len) => {
-            trace!("Command {} successfully sent", cmd);
-            true
-        }
-        Err(e) => {
-            debug!("Error: {}", e);
-            false
-        }
-    }
-}
-
 fn main() {
     //For now interfaces are created and if their associated ports are not open, they will be ignored rather than causing the program to panic
 
-    //Create TCP interface for IRIS handler to talk to simulated IRIS
-    let iris_interface = TcpInterface::new_client("127.0.0.1".to_string(), ports::SIM_IRIS_PORT);
+    //Create interface for IRIS handler to talk to the IRIS given as the first arg (see interface::factory),
+    //or the one in the config by default. It is reconnected to if the link is lost
+    let iris_spec: InterfaceSpec = std::env::args()
+        .nth(1)
+        .unwrap_or(config::get().handlers.iris.interface.clone())
+        .parse()
+        .expect("Invalid IRIS interface spec");
+    let iris_interface = Reconnecting::open(iris_spec);
 
-    //Create IPC interface for IRIS handler to talk to message dispatcher
-    let dispatcher_interface = IpcClient::new("iris_handler".to_string());
+    //Create IPC interface for the cmd_dispatcher to send the IRIS handler cmds on
+    let dispatcher_interface = IpcServer::new(ComponentIds::IRIS.to_string());
 
     //Create IRIS handler
-    let mut iris_handler = IRISHandler::new(iris_interface, dispatcher_interface);
+    let mut iris_handler = IRISHandler::new(iris_interface, dispatcher_interface, Reporter::new(ComponentIds::IRIS));
 
     // Initialize logging
-    let log_path = "ex3_obc_fsw/handlers/iris_handler/logs";
-    init_logger(log_path);
+    init_program_logger("iris_handler");
     
     //Start the IRIS handler
     match iris_handler.run() {
@@ -399,3 +370,94 @@ fn main() {
         Err(e) => debug!("Error running IRIS handler: {}", e),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interface::mock::{pipe, FaultInjector, ScriptedPeripheral};
+
+    #[test]
+    fn test_parses_packets() {
+        // Anything before the flag is skipped
+        let mut iris = ScriptedPeripheral::new().unprompted(b"noiseFLAG:5:HELLO|END|");
+        let mut response = [0u8; IRIS_INTERFACE_BUFFER_SIZE];
+        assert_eq!(parse_packet(&mut iris, &mut response, false, "None").unwrap(), 5);
+        assert_eq!(&response[..6], b"HELLO\0");
+
+        // A packet that arrives a bit at a time
+        let (mut sim, end) = pipe();
+        let mut iris = FaultInjector::new(end).max_read(1);
+        sim.send(b"FLA").unwrap();
+        sim.send(b"G:12:").unwrap();
+        sim.send(b"IMAGES:3 OK!|END|").unwrap();
+        let mut response = [0u8; IRIS_INTERFACE_BUFFER_SIZE];
+        assert_eq!(parse_packet(&mut iris, &mut response, false, "None").unwrap(), 12);
+        assert_eq!(&response[..12], b"IMAGES:3 OK!");
+    }
+
+    #[test]
+    fn test_truncated_packets_fail() {
+        let mut response = [0u8; IRIS_INTERFACE_BUFFER_SIZE];
+        for packet in [&b"FLAG:10:abc"[..], b"FLAG:1", b"FLA", b""] {
+            let mut iris = ScriptedPeripheral::new().unprompted(packet);
+            let e = parse_packet(&mut iris, &mut response, false, "None").unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::WouldBlock);
+        }
+    }
+
+    #[test]
+    fn test_receives_responses() {
+        let mut iris = ScriptedPeripheral::new()
+            .expect(b"FTT", b"FLAG:8:12:00:00|END|");
+        iris.send(b"FTT").unwrap();
+        assert_eq!(receive_response(&mut iris).unwrap(), "12:00:00");
+    }
+
+    #[test]
+    fn test_reports_cmds() {
+        let iris = ScriptedPeripheral::new()
+            .expect(b"ON", b"FLAG:2:OK|END|")
+            .expect(b"TKI", b"")
+            .expect(b"FNI", b"FLAG:1:3|END|");
+        let mut peripheral = Some(Box::new(iris) as Box<dyn Peripheral>);
+        let connect = move || peripheral.take().ok_or(Error::from(ErrorKind::ConnectionRefused));
+        let mut gs = Some(IpcServer::new("iris_test_gs".to_string()).unwrap());
+        let mut onboard = Some(IpcServer::new("iris_test_dispatcher".to_string()).unwrap());
+        let reporter = Reporter::with_sockets(ComponentIds::IRIS, "iris_test_gs", "iris_test_dispatcher");
+        let mut handler = IRISHandler::new(Reconnecting::new("IRIS", connect),
+                                           IpcServer::new("iris_test_handler".to_string()), reporter);
+        let mut dispatcher = IpcClient::new("iris_test_handler".to_string()).unwrap();
+        let cmd = |msg_id, source: ComponentIds, opcode: opcodes::IRIS, args: &[&str]| {
+            let body = opcode.info().unwrap().encode(args).unwrap();
+            Msg::new(MsgType::Cmd, msg_id, ComponentIds::IRIS as u8, source as u8, opcode as u8, body)
+        };
+        let cmds = [
+            cmd(1, ComponentIds::GS, opcodes::IRIS::ToggleSensor, &["1"]),
+            cmd(2, ComponentIds::GS, opcodes::IRIS::CaptureImage, &[]),
+            // Reported back through the cmd_dispatcher
+            cmd(3, ComponentIds::Sequencer, opcodes::IRIS::GetNImagesAvailable, &[]),
+        ];
+        for cmd in cmds {
+            dispatcher.send(&serialize_msg(&cmd).unwrap()).unwrap();
+        }
+        handler.poll_dispatcher();
+
+        let mut reports = vec![];
+        while reports.len() < 2 {
+            let _ = poll_ipc_server_sockets(&mut vec![&mut gs]);
+            while let Some(frame) = gs.as_mut().unwrap().recv() {
+                reports.push(deserialize_msg(&frame.data).unwrap());
+            }
+        }
+        assert_eq!((reports[0].header.msg_id, reports[0].result_code()), (1, Some(Ok(ResultCode::Success))));
+        assert_eq!(reports[0].msg_body, b"OK");
+        // The IRIS never answered the capture
+        assert_eq!(reports[1].header.msg_id, 2);
+        assert_ne!(reports[1].result_code(), Some(Ok(ResultCode::Success)));
+
+        let _ = poll_ipc_server_sockets(&mut vec![&mut onboard]);
+        let report = deserialize_msg(&onboard.as_mut().unwrap().recv().unwrap().data).unwrap();
+        assert_eq!((report.header.msg_id, report.header.dest_id), (3, ComponentIds::Sequencer as u8));
+        assert_eq!(report.msg_body, b"3");
+    }
+}
@@ -16,7 +16,8 @@ use std::process::Command;
 
 use common::component_ids::ComponentIds::{GS, SHELL};
 use common::constants::DOWNLINK_MSG_BODY_SIZE;
-use interface::ipc::{IpcClient, IpcServer, IPC_BUFFER_SIZE, poll_ipc_server_sockets};
+use handler::Reporter;
+use interface::ipc::{IpcClient, IpcServer, poll_ipc_server_sockets, Registration};
 use log::{debug, trace, warn};
 use common::logging::*;
 use common::message_structure::*;
@@ -24,12 +25,14 @@ use common::message_structure::*;
 struct ShellHandler {
     msg_dispatcher_interface: Option<IpcServer>, // For communcation with other FSW components [internal to OBC]
     gs_interface: Option<IpcClient>, // To send messages to the GS through the coms_handler
+    reporter: Reporter, // To send the Report on each cmd to whoever sent it
 }
 
 impl ShellHandler {
     pub fn new(
         msg_dispatcher_interface: Result<IpcServer, std::io::Error>,
         gs_interface: Result<IpcClient, std::io::Error>,
+        reporter: Reporter,
     ) -> ShellHandler {
         if msg_dispatcher_interface.is_err() {
             warn!(
@@ -46,13 +49,17 @@ impl ShellHandler {
         ShellHandler {
             msg_dispatcher_interface: msg_dispatcher_interface.ok(),
             gs_interface: gs_interface.ok(),
+            reporter,
         }
     }
 
     // Sets up threads for reading and writing to its interaces, and sets up channels for communication between threads and the handler
     pub fn run(&mut self) -> std::io::Result<()> {
+        let mut registration = Registration::new(SHELL, "SHELL");
         // Poll for messages
         loop {
+            registration.maintain();
+            self.reporter.maintain();
             // First, take the Option<IpcClient> out of `self.dispatcher_interface`
             // This consumes the Option, so you can work with the owned IpcClient
             let msg_dispatcher_interface = self.msg_dispatcher_interface.take().expect("Cmd_Disp has value of None");
@@ -70,48 +77,54 @@ impl ShellHandler {
             // restore the value back into `self.dispatcher_interface` after polling. May have been mutated
             self.msg_dispatcher_interface = msg_dispatcher_interface_option;
 
-            // Handling the bulk message dispatcher interface
-            let msg_dispatcher_interface = self.msg_dispatcher_interface.as_ref().unwrap();
-            if msg_dispatcher_interface.buffer != [0u8; IPC_BUFFER_SIZE] {
-                let recv_msg: Msg = deserialize_msg(&msg_dispatcher_interface.buffer).unwrap();
+            // Handle every msg received from the dispatcher since the last poll
+            while let Some(frame) = self.msg_dispatcher_interface.as_mut().unwrap().recv() {
+                let recv_msg: Msg = deserialize_msg(&frame.data).unwrap();
                 debug!("Received and deserialized msg");
                 self.handle_msg(recv_msg)?;
             }
         }
     }
 
+    /// Run the cmd's body in bash, sending the GS its output and then the Report on the cmd to whoever sent it
     fn handle_msg(&mut self, msg: Msg) -> Result<(), Error> {
-        self.msg_dispatcher_interface.as_mut().unwrap().clear_buffer();
-
         trace!("SHELL msg opcode: {} {:?} = {}", msg.header.op_code, msg.msg_body, String::from_utf8(msg.msg_body.clone()).unwrap());
 
-        let body = String::from_utf8(msg.msg_body).unwrap();
+        let msg_id = msg.header.msg_id;
+        let body = String::from_utf8(msg.msg_body.clone()).unwrap();
 
-        match Command::new("bash").arg("-c").arg(body).output() {
+        let result = match Command::new("bash").arg("-c").arg(body).output() {
             Ok(out) => {
                 trace!("command outputted: {}", String::from_utf8(out.stdout.clone()).unwrap());
 
                 for chunk in out.stdout.chunks(DOWNLINK_MSG_BODY_SIZE) {
-                    let msg = Msg::new(MsgType::Cmd as u8, 0, GS as u8, SHELL as u8, 0, chunk.to_vec());
+                    // The output carries the id of the cmd so the GS can match it up
+                    let msg = Msg::new(MsgType::Cmd, msg_id, GS as u8, SHELL as u8, 0, chunk.to_vec());
                     if let Some(ref mut gs_resp_interface) = self.gs_interface {
                         let _ = gs_resp_interface.send(&serialize_msg(&msg)?);
                     } else {
                         debug!("Response not sent to gs. IPC interface not created");
                     }
                 }
+                if out.status.success() {
+                    Ok(vec![])
+                } else {
+                    Err(Error::other(format!("command {}", out.status)))
+                }
             },
             Err(e) => {
                 trace!("command failed: {e}");
+                Err(e)
             },
         };
 
+        self.reporter.report(&msg, result);
         Ok(())
     }
 }
 
 fn main() {
-    let log_path = "ex3_obc_fsw/handlers/shell_handler/logs";
-    init_logger(log_path);
+    init_program_logger("shell_handler");
 
     trace!("Starting Shell Handler...");
 
@@ -120,7 +133,44 @@ fn main() {
 
     let gs_interface = IpcClient::new("gs_non_bulk".to_string());
 
-    let mut shell_handler = ShellHandler::new(msg_dispatcher_interface, gs_interface);
+    let mut shell_handler = ShellHandler::new(msg_dispatcher_interface, gs_interface, Reporter::new(SHELL));
 
     let _ = shell_handler.run();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::component_ids::ComponentIds;
+
+    fn recv(gs: &mut Option<IpcServer>) -> Msg {
+        let _ = poll_ipc_server_sockets(&mut vec![gs]);
+        deserialize_msg(&gs.as_mut().unwrap().recv().unwrap().data).unwrap()
+    }
+
+    #[test]
+    fn test_reports_cmds() {
+        let mut gs = Some(IpcServer::new("shell_test_gs".to_string()).unwrap());
+        let mut onboard = Some(IpcServer::new("shell_test_dispatcher".to_string()).unwrap());
+        let gs_interface = IpcClient::new("shell_test_gs".to_string());
+        let reporter = Reporter::with_sockets(SHELL, "shell_test_gs", "shell_test_dispatcher");
+        let mut shell = ShellHandler::new(Err(Error::other("no dispatcher")), gs_interface, reporter);
+
+        shell.handle_msg(Msg::new(MsgType::Cmd, 7, SHELL as u8, GS as u8, 0, b"echo hi".to_vec())).unwrap();
+        let output = recv(&mut gs);
+        assert_eq!((output.header.msg_id, output.msg_body.as_slice()), (7, &b"hi\n"[..]));
+        let report = recv(&mut gs);
+        assert_eq!((report.header.msg_type, report.header.msg_id), (MsgType::Report, 7));
+        assert_eq!(report.result_code(), Some(Ok(ResultCode::Success)));
+
+        shell.handle_msg(Msg::new(MsgType::Cmd, 8, SHELL as u8, GS as u8, 0, b"exit 3".to_vec())).unwrap();
+        let report = recv(&mut gs);
+        assert_eq!((report.header.msg_id, report.result_code()), (8, Some(Ok(ResultCode::Failed))));
+        assert!(String::from_utf8_lossy(&report.msg_body).contains("3"));
+
+        // A cmd from the sequencer is reported back through the cmd_dispatcher
+        shell.handle_msg(Msg::new(MsgType::Cmd, 9, SHELL as u8, ComponentIds::Sequencer as u8, 0, b"true".to_vec())).unwrap();
+        let report = recv(&mut onboard);
+        assert_eq!((report.header.msg_id, report.header.dest_id), (9, ComponentIds::Sequencer as u8));
+    }
+}
@@ -1,6 +0,0 @@
-#!/bin/bash
-# Gets rid of extra directories created when running the scheduler
-# on a local machine
-
-rm -r ../scheduler/saved_messages
-rm -r ../scheduler/scheduler_logs
@@ -1,19 +0,0 @@
-use log::{debug, error, info};
-use log4rs;
-
-pub fn init_logger() {
-    debug!("Initializing Logger.");
-    if let Err(e) = log4rs::init_file("scheduler/log4rs.toml", Default::default()) {
-        println!("Error initializing log4rs: {}", e);
-    } else {
-        println!("Logger initialized successfully.");
-    }
-}
-
-pub fn log_error(e: String, id: u8) {
-    error!("{} ID: {}", e, id);
-}
-
-pub fn log_info(e: String, id: u8) {
-    info!("{} ID: {}", e, id);
-}
\ No newline at end of file
@@ -1,155 +1,65 @@
-// /*  Written by: Rowan Rasmusson
-
-//     References: https://www.geeksforgeeks.org/process-schedulers-in-operating-system/
-//         - Justification for having multiple message states
-
-//     Saved_messages: name of the file that is created is the time of execution of the command
-// */
-
-// TMP for cargo build
-fn main() {}
-
-// use std::{time::Duration, sync::{Arc, Mutex}, collections::HashSet};
-// use std::sync::mpsc;
-// use std::thread;
-// pub mod schedule_message;
-// use crate::schedule_message::*;
-// pub mod scheduler;
-// use crate::scheduler::*;
-// pub mod log;
-// use crate::log::*;
-// use tcp_interface::{self, TCP_BUFFER_SIZE};
-// use message_structure::*;
-// use common::{self, ports::SCHEDULER_DISPATCHER_PORT};
-// const CHECK_DELAY: u8 = 100;
-
-// fn main() {
-//     init_logger();
-//     check_saved_messages();
-//     run_scheduler();
-// }
-
-// fn check_saved_messages() {
-//     let already_read = Arc::new(Mutex::new(HashSet::new()));
-
-//     thread::spawn(move || loop {
-//         let already_read_clone = Arc::clone(&already_read);
-//         let curr_time = get_current_time_millis();
-//         process_saved_messages("scheduler/saved_messages", curr_time, &already_read_clone);
-//         thread::sleep(Duration::from_millis(CHECK_DELAY as u64));
-//     });
-// }
-
-// fn run_scheduler() {
-//     let input: Arc<Mutex<String>> = Arc::new(Mutex::new(String::new()));
-
-//     let ip = "127.0.0.1".to_string();
-//     let port = SCHEDULER_DISPATCHER_PORT;
-//     loop {
-
-//         let tcp_interface = tcp_interface::TcpInterface::new_server(ip.clone(), port).unwrap();
-
-//         let (sched_reader_tx, sched_reader_rx) = mpsc::channel();
-//         // let (sched_writer_tx, sched_writer_rx) = mpsc::channel();
-
-//         tcp_interface::async_read(tcp_interface.clone(), sched_reader_tx, TCP_BUFFER_SIZE);
-
-//         let tcp_interface = tcp_interface::TcpInterface::new_server(ip.clone(), port).unwrap();
-
-//         let (sched_reader_tx, sched_reader_rx) = mpsc::channel();
-//         // let (sched_writer_tx, sched_writer_rx) = mpsc::channel();
-
-//         tcp_interface::async_read(tcp_interface.clone(), sched_reader_tx, TCP_BUFFER_SIZE);
-//         match sched_reader_rx.recv() {
-//             Ok(buffer) => {
-//                 let deserialized_msg: Msg = deserialize_msg(buffer).unwrap();
-//                 process_message(deserialized_msg, &input);
-//             }
-//             Err(e) => {
-//                 // ID 5 is arbitrary ID for error message
-//                 log_error(format!("Failed to deserialize message {}", e), 5);
-//             }
-//         }
-//     }
-// }
-
-// fn process_message(deserialized_msg: Msg, input: &Arc<Mutex<String>>) {
-//     // unwrap message to get inner message for the subsystem
-//     // the message body is the serialized message
-//     let subsystem_msg: Msg = deserialize_msg(deserialized_msg.msg_body).unwrap();
-
-//     let command_time: u64 = get_time(subsystem_msg.msg_body);
-//     let curr_time_millis: u64 = get_current_time_millis();
-//     let input_tuple: (u64, u8) = (command_time, subsystem_msg.header.msg_id);
-
-//     println!("Command Time: {:?} ms, ID: {} Current time is {:?} ms", input_tuple.0, input_tuple.1, curr_time_millis);
-
-//     if command_time <= curr_time_millis {
-//         log_error("Received command from past".to_string(), deserialized_msg.header.msg_id);
-//     } else {
-//         if let Err(err) = write_input_tuple_to_rolling_file(&input_tuple) {
-//             eprintln!("Failed to write input tuple to file: {}", err);
-//         } else {
-//             println!("Input tuple written to file successfully.");
-//         }
-//         log_info("Command stored and scheduled for later".to_string(), deserialized_msg.header.msg_id);
-
-//         let mut shared_input = input.lock().unwrap();
-//         *shared_input = deserialized_msg.header.msg_id.to_string();
-//     }
-// }
-
-// #[cfg(test)]
-// mod tests {
-//     use super::*;
-//     use std::fs::{self};
-
-//     #[test]
-//     fn test_write_input_tuple_creates_file() {
-//         let test_dir = "scheduler/saved_messages".to_string();
-//         let input_tuple: (u64, u8) = (1717110630000, 30);
-
-//         let result = write_input_tuple_to_rolling_file(&input_tuple);
-//         assert!(result.is_ok());
-
-//         let files: Vec<_> = fs::read_dir(&test_dir).unwrap().collect();
-//         assert!(files.len() != 0);
-
-//     }
-
-//     #[test]
-//     fn test_oldest_file_deletion() {
-//         let test_dir = "scheduler/saved_messages";
-//         fs::create_dir_all(test_dir).unwrap();
-
-//         let input_tuple = (1717428208, 66);
-
-//         // Create files to exceed the max size
-//         for i in 0..2000 {
-//             let new_timestamp: u64 = input_tuple.0.clone() + i;
-//             write_input_tuple_to_rolling_file(&(new_timestamp, input_tuple.1.clone())).unwrap();
-//         }
-
-//         // Check initial number of files
-//         let initial_files: Vec<_> = fs::read_dir(test_dir).unwrap().collect();
-
-//         // Write an input tuple to trigger the removal of the oldest file
-//         let input_tuple = (2717428208, 77);
-//         write_input_tuple_to_rolling_file(&(input_tuple.0, input_tuple.1)).unwrap();
-
-//         // Check final number of files
-//         let final_files: Vec<_> = fs::read_dir(test_dir).unwrap().collect();
-//         assert_eq!(final_files.len(), initial_files.len());
-
-//         // Ensure the oldest file was removed
-//         let files: Vec<_> = fs::read_dir(test_dir)
-//             .unwrap()
-//             .map(|res| res.unwrap().file_name().into_string().unwrap())
-//             .collect();
-//         assert!(!files.contains(&String::from("1717428208.txt")));
-
-//         // Cleanup
-//         fs::remove_dir_all(test_dir).unwrap();
-//     }
-
-// }
\ No newline at end of file
+/*  Written by: Rowan Rasmusson
+
+    References: https://www.geeksforgeeks.org/process-schedulers-in-operating-system/
+        - Justification for having multiple message states
+
+    Runs time-tagged cmds at their time. A Scheduler ScheduleCmd carries the time to run a cmd at and the
+    cmd itself; the cmd is saved (see scheduler.rs) and handed to the cmd_dispatcher once its time comes.
+    The schedule is kept in the scheduler's data dir from the config, e.g. data/scheduler, and HK is
+    written there too. Cmds can also wait on events other components publish, and on the OBC booting.
+*/
+
+pub mod schedule_message;
+pub mod scheduler;
+
+use common::config;
+use common::house_keeping::HK_FILE;
+use common::logging::init_program_logger;
+use handler::HandlerRuntime;
+use interface::ipc::{IpcClient, CMD_DISPATCHER_SOCKET};
+use schedule_message::get_current_time_millis;
+use scheduler::Scheduler;
+use std::fs;
+use std::time::Duration;
+
+/// How often the schedule is checked for cmds that are due
+const CHECK_DELAY: Duration = Duration::from_millis(100);
+/// How often the scheduler writes its HK
+const HK_PERIOD: Duration = Duration::from_secs(60);
+
+/// Id of the current boot of the OBC and when it booted, in ms since the Unix epoch. Where the kernel
+/// doesn't tell, each start of the scheduler is taken as a boot
+fn current_boot() -> (String, u64) {
+    let now = get_current_time_millis();
+    let boot_id = fs::read_to_string("/proc/sys/kernel/random/boot_id").map(|id| id.trim().to_string());
+    let uptime = fs::read_to_string("/proc/uptime").ok()
+        .and_then(|uptime| uptime.split_whitespace().next()?.parse::<f64>().ok());
+    match (boot_id, uptime) {
+        (Ok(boot_id), Some(uptime)) => (boot_id, now.saturating_sub((uptime * 1000.0) as u64)),
+        _ => (format!("start at {}", now), now),
+    }
+}
+
+fn main() {
+    init_program_logger("scheduler");
+
+    let dir = config::get().data_dir("scheduler");
+    let mut scheduler = match Scheduler::open(&dir) {
+        Ok(scheduler) => scheduler,
+        Err(e) => {
+            eprintln!("Cannot open the schedule: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let (boot_id, boot_time) = current_boot();
+    if let Err(e) = scheduler.boot(&boot_id, boot_time) {
+        eprintln!("Cannot record boot {}: {}", boot_id, e);
+    }
+
+    // The cmd_dispatcher is the scheduler's peripheral, which cmds are sent to when they are due
+    let mut runtime: HandlerRuntime<Scheduler, IpcClient> = HandlerRuntime::new(scheduler)
+        .peripheral(|| IpcClient::new(CMD_DISPATCHER_SOCKET.to_string()))
+        .tick(CHECK_DELAY)
+        .hk(HK_PERIOD, &dir.join(HK_FILE).to_string_lossy());
+    runtime.run();
+}
@@ -1,50 +1,112 @@
+/*
+A cmd waiting in the scheduler, and the states it goes through:
+
+        ┌─> Armed ─┐
+    New ┴──────────┴> Waiting ──> Running ──> Done
+                         ^           │
+                         └ Suspended ┘
+
+A cmd is New until it has been saved, then Waiting until its time comes. A cmd triggered by an event is
+Armed until the event happens, and then Waiting like any other, for the time the event sets. It is
+Running while it is being handed to the cmd_dispatcher, and Done once it has been, at which point it is
+forgotten. If the cmd_dispatcher can't be reached it is Suspended, and is tried again on the next tick.
+
+On disk a cmd is stored as the body of the ScheduleCmd or ScheduleOnEvent that scheduled it is sent: its
+time tag or event trigger (see common::schedule), then the cmd.
+*/
+
+use common::component_ids::ComponentIds;
+use common::message_structure::{deserialize_msg, serialize_msg, Msg};
+use common::schedule::{encode_time, EventTrigger, TaskRow, TRIGGER_SIZE};
 use log::info;
+use std::io::{Error as IoError, ErrorKind};
 
-#[derive(Clone)]
-pub enum MessageState {
-    New,
-    Suspended,
-    Waiting,
-    Running,
-    Done,
-}
-#[derive(Clone)]
+pub use common::schedule::{MessageState, TIME_SIZE};
+
+#[derive(Debug, Clone)]
 pub struct Message {
-    pub time: Result<u64, String>,
+    /// When to run the cmd, in ms since the Unix epoch. Not set until the trigger, if there is one, happens
+    pub time: u64,
+    /// The event the cmd is waiting on, while it is Armed
+    pub trigger: Option<EventTrigger>,
     pub state: MessageState,
-    pub id: u32,
-    pub command: String,
+    /// Id of the entry in the schedule, which is not the msg_id of the cmd
+    pub id: u16,
+    pub command: Msg,
+}
+
+impl Message {
+    pub fn new(id: u16, time: u64, command: Msg) -> Message {
+        let msg = Message { time, trigger: None, state: MessageState::New, id, command };
+        handle_state(&msg);
+        msg
+    }
+
+    /// A cmd to run once `trigger` happens
+    pub fn on_event(id: u16, trigger: EventTrigger, command: Msg) -> Message {
+        let msg = Message { time: 0, trigger: Some(trigger), state: MessageState::New, id, command };
+        handle_state(&msg);
+        msg
+    }
+
+    /// Move to `state`, logging the transition
+    pub fn set_state(&mut self, state: MessageState) {
+        if state != self.state {
+            self.state = state;
+            handle_state(self);
+        }
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, IoError> {
+        let mut bytes = match self.trigger {
+            Some(trigger) => trigger.to_bytes().to_vec(),
+            None => encode_time(self.time).to_vec(),
+        };
+        bytes.extend(serialize_msg(&self.command)?);
+        Ok(bytes)
+    }
+
+    /// The task's row in the table downlinked for ListTasks
+    pub fn row(&self) -> TaskRow {
+        let cmd = &self.command.header;
+        TaskRow { id: self.id, time: self.time, state: self.state, dest: cmd.dest_id, opcode: cmd.op_code,
+                  msg_id: cmd.msg_id, trigger: self.trigger }
+    }
+
+    /// Read back a cmd saved with `to_bytes`. It is Waiting, or Armed if it was saved `armed`
+    pub fn from_bytes(id: u16, bytes: &[u8], armed: bool) -> Result<Message, IoError> {
+        if armed {
+            let trigger = EventTrigger::from_bytes(bytes)?;
+            let command = deserialize_msg(&bytes[TRIGGER_SIZE..])?;
+            return Ok(Message { time: 0, trigger: Some(trigger), state: MessageState::Armed, id, command });
+        }
+        let time = get_time(bytes)?;
+        let command = deserialize_msg(&bytes[TIME_SIZE..])?;
+        Ok(Message { time, trigger: None, state: MessageState::Waiting, id, command })
+    }
 }
 
 pub fn handle_state(msg: &Message) {
+    let cmd = &msg.command.header;
     match msg.state {
         MessageState::New => {
-            info!("New task received at {:?}. #{}",msg.time, msg.id);
-        }
-        MessageState::Running => {
-            info!("Task #{} is running.", msg.id);
-            println!("Sent to CmdDispatcher");
-            // Send to CmdDispatcher
-        }
-        MessageState::Done => {
-            info!("Task #{} is done running.", msg.id)
-            // Acknowledgement from CmdDispatcher
-        }
-        MessageState::Waiting => {
-            info!("Task #{} is waiting to run.", msg.id);
-            // Put into CmdDispatcher buffer
-        }
-        MessageState::Suspended => {
-            info!("Task #{} is suspended.", msg.id);
-            // Put into non-volatile memory
+            let dest = ComponentIds::try_from(cmd.dest_id).map_or(cmd.dest_id.to_string(), |c| c.to_string());
+            let when = msg.trigger.map_or(format!("{} ms", msg.time), |t| t.to_string());
+            info!("New task #{} at {}: {} opcode {}, msg {}", msg.id, when, dest, cmd.op_code, cmd.msg_id);
         }
+        MessageState::Armed => info!("Task #{} is waiting on {}.", msg.id, msg.trigger.map_or(String::new(), |t| t.to_string())),
+        MessageState::Waiting => info!("Task #{} is waiting to run at {} ms.", msg.id, msg.time),
+        MessageState::Running => info!("Task #{} is running.", msg.id),
+        MessageState::Done => info!("Task #{} is done, sent to the cmd_dispatcher.", msg.id),
+        MessageState::Suspended => info!("Task #{} is suspended until the cmd_dispatcher can be reached.", msg.id),
     }
 }
 
 // This function exists so the message is able to be scheduled. Reads time bytes as LITTLE-ENDIAN
-pub fn get_time(msg_body: Vec<u8>) -> u64 {
-    assert!(msg_body.len() >= 6, "msg_body must be at least 6 bytes long");
-    let time_bytes = &msg_body[0..6];
+pub fn get_time(msg_body: &[u8]) -> Result<u64, IoError> {
+    let time_bytes = msg_body.get(0..TIME_SIZE).ok_or_else(|| {
+        IoError::new(ErrorKind::InvalidInput, format!("a time tag is {} bytes, got {}", TIME_SIZE, msg_body.len()))
+    })?;
 
     // Convert bytes to a u64 (assuming little-endian order)
     let mut time: u64 = 0;
@@ -52,5 +114,10 @@ pub fn get_time(msg_body: Vec<u8>) -> u64 {
         time |= (byte as u64) << (i * 8);
     }
 
-    time
-}
\ No newline at end of file
+    Ok(time)
+}
+
+/// Onboard UTC in ms, which task times are in
+pub fn get_current_time_millis() -> u64 {
+    common::time::utc_millis().max(0) as u64
+}
@@ -1,115 +1,595 @@
-use std::fs;
-use std::fs::File;
-use std::path::Path;
-use std::io;
-use std::io::Write;
-use std::time::SystemTime;
-use std::io::BufRead;
-use std::collections::HashSet;
-use std::sync::{Arc, Mutex};
-use crate::{log_info, log_error};
-
-pub fn process_saved_messages(dir: &str, curr_time_millis: u64, already_read: &Arc<Mutex<HashSet<String>>>) {
-    let saved_messages_dir = Path::new(dir);
-    if saved_messages_dir.exists() && saved_messages_dir.is_dir() {
-        match fs::read_dir(saved_messages_dir) {
-            Ok(entries) => {
-                for entry in entries.flatten() {
-                    process_entry(entry, curr_time_millis, already_read);
+/*
+The schedule of time-tagged cmds.
+
+Each cmd is saved in a file of its own, <task id>.cmd in the schedule's directory, as soon as it is
+scheduled, so the schedule survives the OBC rebooting. Files are written to a temporary file and renamed
+into place, so a reset part way through a write never leaves half a cmd behind. Cmds are run at most
+once: a cmd's file is removed before it is handed to the cmd_dispatcher, and only written back if that
+fails. Cmds whose time passed while the scheduler was down are run as soon as it starts.
+
+The cmd runs with the msg_id and source of the ScheduleCmd that scheduled it, so its Report goes back to
+whoever scheduled it, tagged with the id they are tracking.
+
+Tasks that are waiting can be listed, cancelled or moved to another time by their id. A change is saved
+before it is acknowledged, like a new task.
+
+A cmd can also be triggered by an event (see common::events) instead of a time. It is saved as
+<task id>.evt with its trigger until the event happens, when it is given a time - the time of the event
+plus the trigger's delay - and becomes a task like any other. The Boot event is raised here, when the
+scheduler starts on a boot it hasn't seen before, with the time the OBC booted at.
+*/
+
+use crate::schedule_message::*;
+use common::component_ids::ComponentIds;
+use common::events::{Event, EventKind};
+use common::house_keeping::{HKData, HK_FILE};
+use common::message_structure::{deserialize_msg, serialize_msg, Msg, MsgType};
+use common::opcodes;
+use common::schedule::{EventTrigger, TaskTable, ROWS_PER_PAGE, TRIGGER_SIZE};
+use handler::{Handler, Response};
+use interface::Interface;
+use log::{error, info, warn};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{Error as IoError, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+/// Most cmds that can be waiting at once
+pub const MAX_TASKS: usize = 256;
+const TASK_FILE_EXTENSION: &str = "cmd";
+/// Extension of the files of tasks waiting on an event
+const ARMED_FILE_EXTENSION: &str = "evt";
+/// Where the id of the last boot seen is kept
+const BOOT_ID_FILE: &str = "boot_id";
+/// How late a cmd can run before it is logged as late
+const LATE_MS: u64 = 1000;
+
+pub struct Scheduler {
+    dir: PathBuf,
+    /// Keyed by task id
+    tasks: BTreeMap<u16, Message>,
+    next_id: u16,
+}
+
+impl Scheduler {
+    /// Open the schedule saved in `dir`, creating the directory if there isn't one
+    pub fn open(dir: &Path) -> Result<Scheduler, IoError> {
+        fs::create_dir_all(dir)
+            .map_err(|e| IoError::new(e.kind(), format!("cannot create schedule dir {:?}: {}", dir, e)))?;
+        let mut tasks = BTreeMap::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "tmp") {
+                // Left behind by a reset while a cmd was being saved, before it was acknowledged
+                let _ = fs::remove_file(&path);
+                continue;
+            }
+            if path.file_name().is_some_and(|name| name == BOOT_ID_FILE || name == HK_FILE) {
+                continue;
+            }
+            let Some((id, armed)) = task_id(&path) else {
+                warn!("Ignoring {:?} in the schedule dir", path);
+                continue;
+            };
+            // A reset between saving a triggered task with its time and removing it with its trigger
+            // leaves both, and the time is the one to keep
+            if armed && path.with_extension(TASK_FILE_EXTENSION).exists() {
+                let _ = fs::remove_file(&path);
+                continue;
+            }
+            match fs::read(&path).and_then(|bytes| Message::from_bytes(id, &bytes, armed)) {
+                Ok(task) => {
+                    tasks.insert(id, task);
                 }
+                Err(e) => error!("Cannot read scheduled task {:?}: {}", path, e),
             }
-            Err(e) => eprintln!("Error reading directory: {:?}", e),
         }
-    } else {
-        log_error("Directory does not exist or is not a directory.".to_string(), 54);
+        let next_id = tasks.keys().next_back().map_or(1, |id| id.wrapping_add(1).max(1));
+        Ok(Scheduler { dir: dir.to_path_buf(), tasks, next_id })
+    }
+
+    /// Tasks waiting to run, soonest first, then those waiting on an event
+    pub fn tasks(&self) -> Vec<&Message> {
+        let mut tasks: Vec<&Message> = self.tasks.values().collect();
+        tasks.sort_by_key(|t| (t.trigger.is_some(), t.time, t.id));
+        tasks
+    }
+
+    /// Schedule the cmd in the body of a ScheduleCmd `request`. Returns the id of its task
+    pub fn schedule(&mut self, request: &Msg, now: u64) -> Result<u16, IoError> {
+        let time = get_time(&request.msg_body)?;
+        let command = self.command_in(request, TIME_SIZE)?;
+        if time <= now {
+            return Err(IoError::new(ErrorKind::InvalidInput, format!("{} ms is in the past, it is {} ms now", time, now)));
+        }
+        let id = self.allocate_id();
+        self.add(Message::new(id, time, command), MessageState::Waiting)?;
+        Ok(id)
+    }
+
+    /// Schedule the cmd in the body of a ScheduleOnEvent `request`, to run when its trigger happens.
+    /// Returns the id of its task
+    pub fn schedule_on_event(&mut self, request: &Msg) -> Result<u16, IoError> {
+        let trigger = EventTrigger::from_bytes(&request.msg_body)?;
+        let command = self.command_in(request, TRIGGER_SIZE)?;
+        let id = self.allocate_id();
+        self.add(Message::on_event(id, trigger, command), MessageState::Armed)?;
+        Ok(id)
+    }
+
+    /// The cmd to schedule, from `offset` in the body of `request`. It runs as if it were the request
+    fn command_in(&self, request: &Msg, offset: usize) -> Result<Msg, IoError> {
+        let mut command = deserialize_msg(&request.msg_body[offset..])?;
+        if command.header.msg_type != MsgType::Cmd {
+            return Err(IoError::new(ErrorKind::InvalidInput, format!("only cmds can be scheduled, not {:?}", command.header.msg_type)));
+        }
+        if self.tasks.len() >= MAX_TASKS {
+            return Err(IoError::new(ErrorKind::StorageFull, format!("the schedule is full, with {} tasks", MAX_TASKS)));
+        }
+        command.header.msg_id = request.header.msg_id;
+        command.header.source_id = request.header.source_id;
+        Ok(command)
+    }
+
+    /// Save a new task, and then move it to `state`
+    fn add(&mut self, mut task: Message, state: MessageState) -> Result<(), IoError> {
+        self.save(&task)?;
+        task.set_state(state);
+        self.tasks.insert(task.id, task);
+        Ok(())
     }
-}
 
-fn process_entry(entry: fs::DirEntry, curr_time_millis: u64, already_read: &Arc<Mutex<HashSet<String>>>) {
-    if let Some(file_name) = entry.file_name().to_str() {
-        let file_name = file_name.to_string();
-        let file_path = entry.path();
-        if file_name.ends_with(".txt") {
-            if let Ok(file_time) = file_name.trim_end_matches(".txt").parse::<u64>() {
-                if file_time <= curr_time_millis {
-                    let mut already_read_set = already_read.lock().unwrap();
-                    if !already_read_set.contains(&file_name) {
-                        already_read_set.insert(file_name.clone());
-                        drop(already_read_set); // Release the lock before calling send_message
-                        send_message(&file_path, &file_name);
+    /// Give every task waiting on `event` a time, `time` being when the event happened. Returns how many
+    /// were triggered
+    pub fn on_event(&mut self, event: Event, time: u64) -> usize {
+        let triggered: Vec<u16> = self.tasks.values()
+            .filter(|t| t.trigger.is_some_and(|trigger| trigger.is_triggered_by(&event)))
+            .map(|t| t.id)
+            .collect();
+        let mut count = 0;
+        for id in triggered {
+            let mut task = self.tasks[&id].clone();
+            let delay = task.trigger.take().map_or(0, |trigger| trigger.delay as u64 * 1000);
+            task.time = time + delay;
+            info!("Task #{} triggered by {} {}", id, event.kind, event.value);
+            // Still Armed on disk until the time is saved, so a reset now leaves it waiting on the next event
+            if let Err(e) = self.save(&task) {
+                error!("Cannot save triggered task #{}, it will wait for the next {}: {}", id, event.kind, e);
+                continue;
+            }
+            task.set_state(MessageState::Waiting);
+            self.tasks.insert(id, task);
+            count += 1;
+        }
+        count
+    }
+
+    /// Raise the Boot event if `boot_id` isn't the boot the scheduler last started on. `boot_time` is when
+    /// the OBC booted, in ms since the Unix epoch
+    pub fn boot(&mut self, boot_id: &str, boot_time: u64) -> Result<(), IoError> {
+        let path = self.dir.join(BOOT_ID_FILE);
+        if fs::read_to_string(&path).is_ok_and(|last| last == boot_id) {
+            return Ok(());
+        }
+        info!("First start since booting at {} ms", boot_time);
+        // Saved first, so a reset while the triggered tasks are saved doesn't trigger them twice
+        fs::write(&path, boot_id)?;
+        self.on_event(Event { kind: EventKind::Boot, value: 0 }, boot_time);
+        Ok(())
+    }
+
+    /// A page of the schedule, starting from the `first` task to run
+    pub fn list(&self, first: u16) -> TaskTable {
+        let tasks = self.tasks();
+        let rows = tasks.iter().skip(first as usize).take(ROWS_PER_PAGE).map(|t| t.row()).collect();
+        TaskTable { total: tasks.len() as u16, first, rows }
+    }
+
+    fn task(&self, id: u16) -> Result<&Message, IoError> {
+        self.tasks.get(&id).ok_or_else(|| IoError::new(ErrorKind::InvalidInput, format!("no task {}", id)))
+    }
+
+    /// Cancel a task, so its cmd never runs
+    pub fn cancel(&mut self, id: u16) -> Result<(), IoError> {
+        let armed = self.task(id)?.trigger.is_some();
+        fs::remove_file(self.dir.join(task_file_name(id, armed)))?;
+        self.tasks.remove(&id);
+        info!("Task #{} cancelled", id);
+        Ok(())
+    }
+
+    /// Cancel every task. Returns how many there were
+    pub fn clear(&mut self) -> Result<usize, IoError> {
+        let ids: Vec<u16> = self.tasks.keys().copied().collect();
+        for &id in &ids {
+            self.cancel(id)?;
+        }
+        Ok(ids.len())
+    }
+
+    /// Run a task at `time` instead, in ms since the Unix epoch. A task waiting on an event stops waiting
+    pub fn set_time(&mut self, id: u16, time: u64, now: u64) -> Result<(), IoError> {
+        if time <= now {
+            return Err(IoError::new(ErrorKind::InvalidInput, format!("{} ms is in the past, it is {} ms now", time, now)));
+        }
+        let mut task = self.task(id)?.clone();
+        match task.trigger.take() {
+            Some(trigger) => info!("Task #{} moved from {} to {} ms", id, trigger, time),
+            None => info!("Task #{} moved from {} ms to {} ms", id, task.time, time),
+        }
+        task.time = time;
+        self.save(&task)?;
+        task.set_state(MessageState::Waiting);
+        self.tasks.insert(id, task);
+        Ok(())
+    }
+
+    /// Run a task `delay` ms later, or earlier if it is negative
+    pub fn shift(&mut self, id: u16, delay: i64, now: u64) -> Result<(), IoError> {
+        let task = self.task(id)?;
+        if let Some(trigger) = task.trigger {
+            return Err(IoError::new(ErrorKind::InvalidInput, format!("task {} has no time yet, it is waiting on {}", id, trigger)));
+        }
+        let time = task.time.checked_add_signed(delay)
+            .ok_or_else(|| IoError::new(ErrorKind::InvalidInput, format!("can't shift task {} by {} ms", id, delay)))?;
+        self.set_time(id, time, now)
+    }
+
+    /// Hand every task that is due by `now` to the cmd_dispatcher. Those that can't be handed over are
+    /// suspended until the next time this is called
+    pub fn release_due(&mut self, now: u64, mut dispatcher: Option<&mut dyn Interface>) {
+        let due: Vec<u16> = self.tasks().iter().filter(|t| t.trigger.is_none() && t.time <= now).map(|t| t.id).collect();
+        for id in due {
+            let task = self.tasks.get_mut(&id).unwrap();
+            let Some(ref mut dispatcher) = dispatcher else {
+                task.set_state(MessageState::Suspended);
+                continue;
+            };
+            task.set_state(MessageState::Running);
+            if now - task.time > LATE_MS {
+                warn!("Task #{} is running {} ms late", id, now - task.time);
+            }
+            if let Err(e) = fs::remove_file(self.dir.join(task_file_name(id, false))) {
+                error!("Cannot remove task #{} from the schedule, it may run again: {}", id, e);
+            }
+            match serialize_msg(&task.command).and_then(|bytes| dispatcher.send(&bytes)) {
+                Ok(_) => {
+                    task.set_state(MessageState::Done);
+                    self.tasks.remove(&id);
+                }
+                Err(e) => {
+                    warn!("Cannot send task #{} to the cmd_dispatcher: {}", id, e);
+                    task.set_state(MessageState::Suspended);
+                    let task = task.clone();
+                    if let Err(e) = self.save(&task) {
+                        error!("Cannot save suspended task #{}, it will be lost on a reboot: {}", id, e);
                     }
                 }
             }
         }
     }
-}
 
-fn send_message(file_path: &Path, file_name: &str) {
-    match fs::File::open(file_path) {
-        Ok(file) => {
-            let lines: Vec<String> = io::BufReader::new(file)
-                .lines()
-                .filter_map(Result::ok)
-                .collect();
-            if lines.len() > 1 {
-                println!("Sent Message ID {} at {}", lines[1], file_name);
-            } else {
-                println!("File {} does not have an ID.", file_name);
+    /// The next free task id. Ids wrap around, skipping 0 and any still in use
+    fn allocate_id(&mut self) -> u16 {
+        while self.next_id == 0 || self.tasks.contains_key(&self.next_id) {
+            self.next_id = self.next_id.wrapping_add(1);
+        }
+        let id = self.next_id;
+        self.next_id = id.wrapping_add(1);
+        id
+    }
+
+    /// Save a task, removing the file it had while it was waiting on an event if it no longer is
+    fn save(&self, task: &Message) -> Result<(), IoError> {
+        let armed = task.trigger.is_some();
+        let path = self.dir.join(task_file_name(task.id, armed));
+        let tmp_path = path.with_extension("tmp");
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(&task.to_bytes()?)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &path)?;
+        if !armed {
+            match fs::remove_file(self.dir.join(task_file_name(task.id, true))) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+                _ => (),
             }
-            log_info(format!("Processed file: {}", file_name), 0); // Message ID can be managed as needed
         }
-        Err(e) => eprintln!("Failed to open file {}: {:?}", file_name, e),
+        Ok(())
     }
 }
 
-pub fn get_current_time_millis() -> u64 {
-    match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
-        Ok(duration) => duration.as_millis() as u64,
-        Err(e) => {
-            eprint!("Error {:?}", e);
-            0
+fn task_file_name(id: u16, armed: bool) -> String {
+    format!("{}.{}", id, if armed { ARMED_FILE_EXTENSION } else { TASK_FILE_EXTENSION })
+}
+
+/// Id of the task saved at `path`, and whether it is waiting on an event, if it is a task file
+fn task_id(path: &Path) -> Option<(u16, bool)> {
+    let armed = match path.extension()?.to_str()? {
+        TASK_FILE_EXTENSION => false,
+        ARMED_FILE_EXTENSION => true,
+        _ => return None,
+    };
+    Some((path.file_stem()?.to_str()?.parse().ok()?, armed))
+}
+
+impl Handler for Scheduler {
+    const COMPONENT: ComponentIds = ComponentIds::Scheduler;
+
+    fn on_command(&mut self, cmd: &Msg, _dispatcher: Option<&mut dyn Interface>) -> Result<Response, IoError> {
+        let opcode = opcodes::Scheduler::from(cmd.header.op_code);
+        let now = get_current_time_millis();
+        // The body of a ScheduleCmd or ScheduleOnEvent isn't described by args
+        let args = match opcode {
+            opcodes::Scheduler::ScheduleCmd | opcodes::Scheduler::ScheduleOnEvent | opcodes::Scheduler::Error => vec![],
+            _ => opcode.decode(&cmd.msg_body)?,
+        };
+        let int = |i: usize| args.get(i).and_then(|arg| arg.int::<i64>()).unwrap_or_default();
+        match opcode {
+            opcodes::Scheduler::ScheduleCmd => {
+                let id = self.schedule(cmd, now)?;
+                Ok(format!("Scheduled as task {}", id).into_bytes())
+            }
+            opcodes::Scheduler::ListTasks => Ok(self.list(int(0) as u16).to_bytes()),
+            opcodes::Scheduler::CancelTask => {
+                self.cancel(int(0) as u16)?;
+                Ok(format!("Cancelled task {}", int(0)).into_bytes())
+            }
+            opcodes::Scheduler::ShiftTask => {
+                self.shift(int(0) as u16, int(1) * 1000, now)?;
+                Ok(format!("Shifted task {} by {} s", int(0), int(1)).into_bytes())
+            }
+            opcodes::Scheduler::SetTaskTime => {
+                self.set_time(int(0) as u16, int(1) as u64 * 1000, now)?;
+                Ok(format!("Task {} runs at {} s", int(0), int(1)).into_bytes())
+            }
+            opcodes::Scheduler::ClearTasks => Ok(format!("Cancelled {} tasks", self.clear()?).into_bytes()),
+            opcodes::Scheduler::ScheduleOnEvent => {
+                let id = self.schedule_on_event(cmd)?;
+                Ok(format!("Scheduled as task {}", id).into_bytes())
+            }
+            opcodes::Scheduler::PublishEvent => {
+                let event = Event { kind: EventKind::try_from(int(0) as u8)?, value: int(1) as i32 };
+                let triggered = self.on_event(event, now);
+                Ok(format!("{} {} triggered {} tasks", event.kind, event.value, triggered).into_bytes())
+            }
+            opcodes::Scheduler::Error => {
+                Err(IoError::new(ErrorKind::Unsupported, format!("unknown scheduler opcode {}", cmd.header.op_code)))
+            }
         }
     }
+
+    fn on_tick(&mut self, dispatcher: Option<&mut dyn Interface>) -> Result<(), IoError> {
+        self.release_due(get_current_time_millis(), dispatcher);
+        Ok(())
+    }
+
+    fn collect_hk(&mut self, hk: &mut HKData, _dispatcher: Option<&mut dyn Interface>) -> Result<(), IoError> {
+        let tasks = self.tasks();
+        hk.key_value_pair("TASKS", json!(tasks.len()));
+        hk.key_value_pair("SUSPENDED", json!(tasks.iter().filter(|t| t.state == MessageState::Suspended).count()));
+        hk.key_value_pair("ARMED", json!(tasks.iter().filter(|t| t.trigger.is_some()).count()));
+        hk.key_value_pair("NEXT_TIME", json!(tasks.first().filter(|t| t.trigger.is_none()).map(|t| t.time)));
+        Ok(())
+    }
 }
 
-pub fn write_input_tuple_to_rolling_file(input_tuple: &(u64, u8)) -> Result<(), io::Error> {
-    // Create the directory if it doesn't exist
-    let dir_path = "scheduler/saved_messages";
-    fs::create_dir_all(dir_path)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use interface::mock::ScriptedPeripheral;
 
-    // Get the total size of files in the directory
-    let total_size: u64 = fs::read_dir(dir_path)?
-        .filter_map(|res| res.ok())
-        .map(|entry| entry.metadata().ok().map(|m| m.len()).unwrap_or(0))
-        .sum();
+    const NOW: u64 = 1_717_110_630_000;
 
-    // Specify the maximum size of saved_messages directory in bytes
-    let max_size_bytes: u64 = 2048; // 2 KB
+    fn cmd(msg_id: u16, dest: ComponentIds) -> Msg {
+        Msg::new(MsgType::Cmd, msg_id, dest as u8, ComponentIds::OBC as u8, 3, vec![1, 2])
+    }
 
-    // If the total size exceeds the maximum size, remove the oldest file
-    if total_size >= max_size_bytes {
-        remove_oldest_file(&dir_path)?;
+    /// A ScheduleCmd from the GS to run `command` at `time`
+    fn request(msg_id: u16, time: u64, command: &Msg) -> Msg {
+        let mut body = time.to_le_bytes()[..TIME_SIZE].to_vec();
+        body.extend(serialize_msg(command).unwrap());
+        Msg::new(MsgType::Cmd, msg_id, ComponentIds::Scheduler as u8, ComponentIds::GS as u8, 0, body)
     }
 
-    // Create a new file
-    let file_name = format!("{}.txt", input_tuple.0);
-    let file_path = Path::new(dir_path).join(&file_name);
-    let mut file = File::create(&file_path)?;
+    /// What the scheduler should send the cmd_dispatcher for `command` scheduled by request `msg_id`
+    fn released(msg_id: u16, command: &Msg) -> Vec<u8> {
+        let mut command = command.clone();
+        command.header.msg_id = msg_id;
+        command.header.source_id = ComponentIds::GS as u8;
+        serialize_msg(&command).unwrap()
+    }
 
-    // Write input_tuple to the file
-    writeln!(file, "{:?}\n{}", input_tuple.0, input_tuple.1)?;
+    #[test]
+    fn test_releases_in_time_order() {
+        let dir = tempdir::TempDir::new("scheduler").unwrap();
+        let mut scheduler = Scheduler::open(dir.path()).unwrap();
+        let (eps, iris) = (cmd(0, ComponentIds::EPS), cmd(0, ComponentIds::IRIS));
+        assert_eq!(scheduler.schedule(&request(10, NOW + 2000, &eps), NOW).unwrap(), 1);
+        assert_eq!(scheduler.schedule(&request(11, NOW + 1000, &iris), NOW).unwrap(), 2);
+        assert_eq!(scheduler.tasks().iter().map(|t| t.id).collect::<Vec<_>>(), [2, 1]);
+        assert!(scheduler.tasks().iter().all(|t| t.state == MessageState::Waiting));
 
-    Ok(())
-}
+        let mut dispatcher = ScriptedPeripheral::new();
+        scheduler.release_due(NOW + 999, Some(&mut dispatcher));
+        assert_eq!(scheduler.tasks().len(), 2);
 
-fn remove_oldest_file(dir_path: &str) -> Result<(), io::Error> {
-    let oldest_file = fs::read_dir(dir_path)?
-        .filter_map(|res| res.ok())
-        .min_by_key(|entry: &fs::DirEntry| entry.metadata().unwrap().modified().unwrap());
+        let mut dispatcher = ScriptedPeripheral::new().expect(&released(11, &iris), b"").expect(&released(10, &eps), b"");
+        scheduler.release_due(NOW + 5000, Some(&mut dispatcher));
+        assert!(scheduler.tasks().is_empty());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_survives_restart() {
+        let dir = tempdir::TempDir::new("scheduler").unwrap();
+        let eps = cmd(0, ComponentIds::EPS);
+        let mut scheduler = Scheduler::open(dir.path()).unwrap();
+        scheduler.schedule(&request(10, NOW + 1000, &eps), NOW).unwrap();
+        scheduler.schedule(&request(11, NOW + 3000, &eps), NOW).unwrap();
+        // The HK written alongside the schedule isn't taken for a task
+        let mut hk = HKData::new(ComponentIds::Scheduler);
+        scheduler.collect_hk(&mut hk, None).unwrap();
+        hk.write_to_file(&dir.path().join(HK_FILE).to_string_lossy()).unwrap();
+        drop(scheduler);
+
+        let mut scheduler = Scheduler::open(dir.path()).unwrap();
+        let tasks = scheduler.tasks();
+        assert_eq!(tasks.iter().map(|t| (t.id, t.time)).collect::<Vec<_>>(), [(1, NOW + 1000), (2, NOW + 3000)]);
+        assert_eq!(tasks[0].command.header.msg_id, 10);
+        // Ids carry on from the saved tasks
+        assert_eq!(scheduler.schedule(&request(12, NOW + 2000, &eps), NOW).unwrap(), 3);
+
+        // Missed while the scheduler was down, so it runs straight away
+        let mut dispatcher = ScriptedPeripheral::new().expect(&released(10, &eps), b"");
+        scheduler.release_due(NOW + 1500, Some(&mut dispatcher));
+        let scheduler = Scheduler::open(dir.path()).unwrap();
+        assert_eq!(scheduler.tasks().iter().map(|t| t.id).collect::<Vec<_>>(), [3, 2]);
+    }
+
+    #[test]
+    fn test_suspends_until_dispatcher_is_back() {
+        let dir = tempdir::TempDir::new("scheduler").unwrap();
+        let eps = cmd(0, ComponentIds::EPS);
+        let mut scheduler = Scheduler::open(dir.path()).unwrap();
+        scheduler.schedule(&request(10, NOW + 1000, &eps), NOW).unwrap();
+
+        scheduler.release_due(NOW + 1000, None);
+        assert_eq!(scheduler.tasks()[0].state, MessageState::Suspended);
+        // Nothing is expected, so the send fails
+        scheduler.release_due(NOW + 1100, Some(&mut ScriptedPeripheral::new()));
+        assert_eq!(scheduler.tasks()[0].state, MessageState::Suspended);
+        assert_eq!(Scheduler::open(dir.path()).unwrap().tasks().len(), 1);
 
-    if let Some(oldest_file) = oldest_file {
-        fs::remove_file(oldest_file.path())?;
+        let mut dispatcher = ScriptedPeripheral::new().expect(&released(10, &eps), b"");
+        scheduler.release_due(NOW + 1200, Some(&mut dispatcher));
+        assert!(scheduler.tasks().is_empty());
     }
 
-    Ok(())
-}
\ No newline at end of file
+    #[test]
+    fn test_list_cancel_and_move() {
+        let dir = tempdir::TempDir::new("scheduler").unwrap();
+        let eps = cmd(0, ComponentIds::EPS);
+        let mut scheduler = Scheduler::open(dir.path()).unwrap();
+        for i in 0..10 {
+            scheduler.schedule(&request(100 + i, NOW + 1000 * (10 - i as u64), &eps), NOW).unwrap();
+        }
+        let table = scheduler.list(0);
+        assert_eq!((table.total, table.first, table.rows.len()), (10, 0, ROWS_PER_PAGE));
+        assert_eq!((table.rows[0].id, table.rows[0].time, table.rows[0].msg_id), (10, NOW + 1000, 109));
+        assert_eq!(table.rows[0].state, MessageState::Waiting);
+        assert_eq!(scheduler.list(8).rows.iter().map(|r| r.id).collect::<Vec<_>>(), [2, 1]);
+        assert!(scheduler.list(20).rows.is_empty());
+
+        scheduler.cancel(10).unwrap();
+        assert_eq!(scheduler.cancel(10).unwrap_err().kind(), ErrorKind::InvalidInput);
+        scheduler.shift(9, 60_000, NOW).unwrap();
+        assert!(scheduler.shift(8, -3000, NOW).is_err());
+        scheduler.set_time(1, NOW + 500, NOW).unwrap();
+        assert!(scheduler.set_time(2, NOW, NOW).is_err());
+        assert_eq!(scheduler.list(0).rows[0].id, 1);
+
+        // Changes are saved
+        let mut scheduler = Scheduler::open(dir.path()).unwrap();
+        let times: Vec<(u16, u64)> = scheduler.tasks().iter().map(|t| (t.id, t.time)).collect();
+        assert_eq!(times.len(), 9);
+        assert_eq!(times[0], (1, NOW + 500));
+        assert_eq!(times[8], (9, NOW + 62_000));
+        assert_eq!(scheduler.clear().unwrap(), 9);
+        assert!(Scheduler::open(dir.path()).unwrap().tasks().is_empty());
+    }
+
+    /// A ScheduleOnEvent from the GS to run `command` on `trigger`
+    fn on_event_request(msg_id: u16, trigger: EventTrigger, command: &Msg) -> Msg {
+        let mut body = trigger.to_bytes().to_vec();
+        body.extend(serialize_msg(command).unwrap());
+        Msg::new(MsgType::Cmd, msg_id, ComponentIds::Scheduler as u8, ComponentIds::GS as u8,
+                 opcodes::Scheduler::ScheduleOnEvent as u8, body)
+    }
+
+    #[test]
+    fn test_triggers_on_events() {
+        use common::schedule::Condition;
+        let dir = tempdir::TempDir::new("scheduler").unwrap();
+        let (eps, iris) = (cmd(0, ComponentIds::EPS), cmd(0, ComponentIds::IRIS));
+        let mut scheduler = Scheduler::open(dir.path()).unwrap();
+        let charged = EventTrigger { event: EventKind::BatteryVoltage, condition: Condition::Above(7400), delay: 60 };
+        let pass = EventTrigger { event: EventKind::GsPassStart, condition: Condition::Any, delay: 0 };
+        assert_eq!(scheduler.schedule_on_event(&on_event_request(10, charged, &iris)).unwrap(), 1);
+        assert_eq!(scheduler.schedule_on_event(&on_event_request(11, pass, &eps)).unwrap(), 2);
+        scheduler.schedule(&request(12, NOW + 1000, &eps), NOW).unwrap();
+        // Tasks waiting on events come after those with a time, and are never due
+        assert_eq!(scheduler.tasks().iter().map(|t| t.id).collect::<Vec<_>>(), [3, 1, 2]);
+        assert_eq!(scheduler.list(0).rows[1].trigger, Some(charged));
+        let mut dispatcher = ScriptedPeripheral::new().expect(&released(12, &eps), b"");
+        scheduler.release_due(NOW + 1000, Some(&mut dispatcher));
+        assert_eq!(scheduler.tasks().len(), 2);
+
+        let voltage = |value| Event { kind: EventKind::BatteryVoltage, value };
+        assert_eq!(scheduler.on_event(voltage(7300), NOW + 2000), 0);
+        assert_eq!(scheduler.on_event(voltage(7500), NOW + 3000), 1);
+        assert_eq!(scheduler.task(1).unwrap().state, MessageState::Waiting);
+        assert_eq!(scheduler.task(1).unwrap().time, NOW + 63_000);
+        // Only the next event triggers a task
+        assert_eq!(scheduler.on_event(voltage(7600), NOW + 4000), 0);
+
+        // Both the triggered task and the one still waiting survive a restart
+        let mut scheduler = Scheduler::open(dir.path()).unwrap();
+        assert_eq!(scheduler.task(1).unwrap().time, NOW + 63_000);
+        assert_eq!(scheduler.task(2).unwrap().state, MessageState::Armed);
+        assert!(scheduler.shift(2, 1000, NOW).is_err());
+        scheduler.set_time(2, NOW + 5000, NOW).unwrap();
+        let scheduler = Scheduler::open(dir.path()).unwrap();
+        assert!(scheduler.task(2).unwrap().trigger.is_none());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_boot_triggers_once() {
+        use common::schedule::Condition;
+        let dir = tempdir::TempDir::new("scheduler").unwrap();
+        let eps = cmd(0, ComponentIds::EPS);
+        let mut scheduler = Scheduler::open(dir.path()).unwrap();
+        let after_boot = EventTrigger { event: EventKind::Boot, condition: Condition::Any, delay: 30 };
+        scheduler.schedule_on_event(&on_event_request(10, after_boot, &eps)).unwrap();
+        scheduler.schedule_on_event(&on_event_request(11, after_boot, &eps)).unwrap();
+        scheduler.boot("a", NOW).unwrap();
+        assert!(scheduler.tasks().iter().all(|t| t.time == NOW + 30_000));
+
+        // Restarting the scheduler isn't a boot
+        scheduler.cancel(1).unwrap();
+        scheduler.set_time(2, NOW + 60_000, NOW).unwrap();
+        scheduler.schedule_on_event(&on_event_request(12, after_boot, &eps)).unwrap();
+        let mut scheduler = Scheduler::open(dir.path()).unwrap();
+        scheduler.boot("a", NOW + 40_000).unwrap();
+        assert_eq!(scheduler.task(3).unwrap().state, MessageState::Armed);
+        scheduler.boot("b", NOW + 50_000).unwrap();
+        assert_eq!(scheduler.task(3).unwrap().time, NOW + 80_000);
+    }
+
+    #[test]
+    fn test_rejects_bad_requests() {
+        let dir = tempdir::TempDir::new("scheduler").unwrap();
+        let mut scheduler = Scheduler::open(dir.path()).unwrap();
+        let eps = cmd(0, ComponentIds::EPS);
+        let past = scheduler.schedule(&request(10, NOW, &eps), NOW).unwrap_err();
+        assert_eq!(past.kind(), ErrorKind::InvalidInput);
+        let mut short = request(11, NOW + 1000, &eps);
+        short.msg_body.truncate(4);
+        assert!(scheduler.schedule(&short, NOW).is_err());
+        let report = eps.report(Ok(vec![]));
+        assert!(scheduler.schedule(&request(12, NOW + 1000, &report), NOW).is_err());
+        let mut bad_event = on_event_request(13, EventTrigger {
+            event: EventKind::GpsFix, condition: common::schedule::Condition::Any, delay: 0,
+        }, &eps);
+        bad_event.msg_body[0] = 99;
+        assert_eq!(scheduler.schedule_on_event(&bad_event).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert!(scheduler.tasks().is_empty());
+
+        for i in 0..MAX_TASKS as u16 {
+            scheduler.schedule(&request(i + 1, NOW + 1000, &eps), NOW).unwrap();
+        }
+        let full = scheduler.schedule(&request(999, NOW + 1000, &eps), NOW).unwrap_err();
+        assert_eq!(full.kind(), ErrorKind::StorageFull);
+    }
+}
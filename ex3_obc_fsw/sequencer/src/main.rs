@@ -0,0 +1,42 @@
+/*
+Runs stored cmd sequences. A sequence is a list of cmds with delays, aborts on how the cmds went, and
+loops (see common::sequence), uploaded from the ground and started, paused or aborted with Sequencer
+cmds. Its cmds are handed to the cmd_dispatcher, and their Reports come back to the sequencer through it.
+The sequences are kept in the sequencer's data dir from the config, e.g. data/sequencer, next to its HK.
+*/
+
+pub mod run;
+pub mod sequencer;
+
+use common::config;
+use common::house_keeping::HK_FILE;
+use common::logging::init_program_logger;
+use handler::HandlerRuntime;
+use interface::ipc::{IpcClient, CMD_DISPATCHER_SOCKET};
+use sequencer::Sequencer;
+use std::time::Duration;
+
+/// How often the running sequence is checked for steps that are due
+const STEP_DELAY: Duration = Duration::from_millis(100);
+/// How often the sequencer writes its HK
+const HK_PERIOD: Duration = Duration::from_secs(60);
+
+fn main() {
+    init_program_logger("sequencer");
+
+    let dir = config::get().data_dir("sequencer");
+    let sequencer = match Sequencer::open(&dir) {
+        Ok(sequencer) => sequencer,
+        Err(e) => {
+            eprintln!("Cannot open the sequences: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // The cmd_dispatcher is the sequencer's peripheral, which the cmds of the running sequence are sent to
+    let mut runtime: HandlerRuntime<Sequencer, IpcClient> = HandlerRuntime::new(sequencer)
+        .peripheral(|| IpcClient::new(CMD_DISPATCHER_SOCKET.to_string()))
+        .tick(STEP_DELAY)
+        .hk(HK_PERIOD, &dir.join(HK_FILE).to_string_lossy());
+    runtime.run();
+}
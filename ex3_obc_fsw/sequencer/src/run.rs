@@ -0,0 +1,303 @@
+/*
+A sequence being run.
+
+The steps are run in order, as many as can be in one go, until the run has to wait - on the Report on a
+cmd it sent, or on a wait step - or it ends. Each cmd is sent with a msg_id of the sequencer's own, so
+its Report, or the cmd_dispatcher's NACK if it couldn't be delivered, can be told apart from any other.
+A cmd whose Report doesn't come within its timeout has the outcome NoReply, and the run carries on.
+
+Pausing only stops the run before its next step: a cmd already sent can still get its Report, and a
+wait already started keeps counting down.
+*/
+
+use common::message_structure::{serialize_msg, AckCode, Msg, MsgType, ResultCode};
+use common::msg_id::MsgIdAllocator;
+use common::sequence::{Outcome, Sequence, Step};
+use interface::Interface;
+use log::{debug, info, warn};
+use std::collections::HashMap;
+use std::io::{Error as IoError, ErrorKind};
+use std::time::{Duration, Instant};
+
+/// Most steps run in one go, so a sequence that loops without waiting can't hold up the sequencer
+const MAX_STEPS_AT_ONCE: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Waiting {
+    /// On the Report on the cmd with this msg_id
+    Reply { msg_id: u16, until: Instant },
+    Delay { until: Instant },
+}
+
+/// Where a run is at after running what it could
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Progress {
+    Running,
+    Done,
+    Aborted(String),
+}
+
+pub struct Run {
+    pub name: String,
+    sequence: Sequence,
+    /// Index of the next step to run
+    next: usize,
+    waiting: Option<Waiting>,
+    last_outcome: Option<Outcome>,
+    /// How many more times each repeat step goes back, keyed by its index, for the loops in progress
+    repeats: HashMap<usize, u16>,
+    pub paused: bool,
+}
+
+impl Run {
+    pub fn new(name: &str, sequence: Sequence) -> Run {
+        Run {
+            name: name.to_string(),
+            sequence,
+            next: 0,
+            waiting: None,
+            last_outcome: None,
+            repeats: HashMap::new(),
+            paused: false,
+        }
+    }
+
+    /// Run the steps that are due at `now`, sending cmds to `dispatcher`
+    pub fn advance(&mut self, now: Instant, mut dispatcher: Option<&mut dyn Interface>,
+                   msg_ids: &mut MsgIdAllocator) -> Progress {
+        for _ in 0..MAX_STEPS_AT_ONCE {
+            match self.waiting {
+                Some(Waiting::Reply { msg_id, until }) if now >= until => {
+                    warn!("Sequence {}: no Report on msg {}", self.name, msg_id);
+                    self.last_outcome = Some(Outcome::NoReply);
+                    self.waiting = None;
+                }
+                Some(Waiting::Delay { until }) if now >= until => self.waiting = None,
+                Some(_) => return Progress::Running,
+                None => (),
+            }
+            if self.paused {
+                return Progress::Running;
+            }
+            let Some(step) = self.sequence.steps.get(self.next) else {
+                return Progress::Done;
+            };
+            let index = self.next;
+            self.next += 1;
+            match step {
+                Step::Cmd { timeout, cmd } => {
+                    let mut cmd = cmd.clone();
+                    cmd.header.msg_id = msg_ids.next_id();
+                    match send(&cmd, dispatcher.as_deref_mut()) {
+                        Ok(()) => {
+                            debug!("Sequence {} step {}: sent msg {}", self.name, index, cmd.header.msg_id);
+                            let until = now + Duration::from_secs(*timeout as u64);
+                            self.waiting = Some(Waiting::Reply { msg_id: cmd.header.msg_id, until });
+                        }
+                        Err(e) => {
+                            warn!("Sequence {} step {}: cannot send msg {}: {}", self.name, index, cmd.header.msg_id, e);
+                            self.last_outcome = Some(Outcome::Nacked);
+                        }
+                    }
+                }
+                Step::Wait(secs) => self.waiting = Some(Waiting::Delay { until: now + Duration::from_secs(*secs as u64) }),
+                Step::AbortIf(mask) => {
+                    if let Some(outcome) = self.last_outcome.filter(|o| mask & o.bit() != 0) {
+                        return Progress::Aborted(format!("step {}: last cmd {}", index, outcome));
+                    }
+                }
+                Step::Repeat { to, times } => {
+                    let left = self.repeats.entry(index).or_insert(*times);
+                    if *left > 0 {
+                        *left -= 1;
+                        self.next = *to as usize;
+                    } else {
+                        // So the loop starts over if an outer loop comes back to it
+                        self.repeats.remove(&index);
+                    }
+                }
+            }
+        }
+        Progress::Running
+    }
+
+    /// A Report or Ack sent to the sequencer. Returns whether it was the one the run was waiting on
+    pub fn on_reply(&mut self, msg: &Msg) -> bool {
+        let Some(Waiting::Reply { msg_id, .. }) = self.waiting else {
+            return false;
+        };
+        if msg.header.msg_id != msg_id {
+            return false;
+        }
+        let outcome = match (msg.result_code(), msg.ack_code()) {
+            (Some(code), _) => Outcome::Result(code.unwrap_or(ResultCode::Failed)),
+            // An Ack that it was delivered says nothing about how the cmd went
+            (_, Some(Ok(AckCode::Success))) => return false,
+            (_, Some(_)) => Outcome::Nacked,
+            _ => return false,
+        };
+        info!("Sequence {}: msg {} {}", self.name, msg_id, outcome);
+        self.last_outcome = Some(outcome);
+        self.waiting = None;
+        true
+    }
+
+    /// e.g. "capture step 3/7, waiting on msg 33000, last cmd Success"
+    pub fn status(&self) -> String {
+        let mut status = format!("{} step {}/{}", self.name, self.next, self.sequence.steps.len());
+        if self.paused {
+            status += ", paused";
+        }
+        match self.waiting {
+            Some(Waiting::Reply { msg_id, .. }) => status += &format!(", waiting on msg {}", msg_id),
+            Some(Waiting::Delay { .. }) => status += ", waiting",
+            None => (),
+        }
+        if let Some(outcome) = self.last_outcome {
+            status += &format!(", last cmd {}", outcome);
+        }
+        status
+    }
+
+    /// Index of the next step to run
+    pub fn step(&self) -> usize {
+        self.next
+    }
+}
+
+fn send(cmd: &Msg, dispatcher: Option<&mut (dyn Interface + '_)>) -> Result<(), IoError> {
+    debug_assert_eq!(cmd.header.msg_type, MsgType::Cmd);
+    let dispatcher = dispatcher.ok_or_else(|| IoError::new(ErrorKind::NotConnected, "cmd_dispatcher not connected"))?;
+    dispatcher.send(&serialize_msg(cmd)?).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::component_ids::ComponentIds;
+    use common::message_structure::deserialize_msg;
+    use interface::mock::{pipe, PipeEnd};
+
+    /// The cmds sent to the cmd_dispatcher since last time
+    fn sent(dispatcher: &mut PipeEnd) -> Vec<Msg> {
+        let mut cmds = vec![];
+        let mut buf = [0u8; 256];
+        while dispatcher.available() > 0 {
+            let len = dispatcher.read(&mut buf).unwrap();
+            cmds.push(deserialize_msg(&buf[..len]).unwrap());
+        }
+        cmds
+    }
+
+    fn report(cmd: &Msg, code: ResultCode) -> Msg {
+        Msg::new(MsgType::Report, cmd.header.msg_id, cmd.header.source_id, cmd.header.dest_id, code as u8, vec![])
+    }
+
+    fn run(text: &str) -> (Run, PipeEnd, PipeEnd, MsgIdAllocator) {
+        let (sequencer, dispatcher) = pipe();
+        let run = Run::new("test", Sequence::parse(text).unwrap());
+        (run, sequencer, dispatcher, MsgIdAllocator::spacecraft(ComponentIds::Sequencer))
+    }
+
+    #[test]
+    fn test_waits_on_reports_and_delays() {
+        let (mut run, mut sequencer, mut dispatcher, mut ids) = run("
+            timeout 5
+            EPS GetHK
+            wait 10
+            IRIS GetHK
+        ");
+        let t0 = Instant::now();
+        assert_eq!(run.advance(t0, Some(&mut sequencer), &mut ids), Progress::Running);
+        let cmds = sent(&mut dispatcher);
+        assert_eq!(cmds.len(), 1);
+        assert_eq!(cmds[0].header.dest_id, ComponentIds::EPS as u8);
+        assert!(ids.contains(cmds[0].header.msg_id));
+
+        // Someone else's Report doesn't count
+        let mut other = cmds[0].clone();
+        other.header.msg_id += 1;
+        assert!(!run.on_reply(&report(&other, ResultCode::Success)));
+        assert!(run.on_reply(&report(&cmds[0], ResultCode::Success)));
+        assert_eq!(run.advance(t0 + Duration::from_secs(1), Some(&mut sequencer), &mut ids), Progress::Running);
+        assert!(sent(&mut dispatcher).is_empty());
+        assert_eq!(run.status(), "test step 2/3, waiting, last cmd Success");
+
+        assert_eq!(run.advance(t0 + Duration::from_secs(11), Some(&mut sequencer), &mut ids), Progress::Running);
+        assert_eq!(sent(&mut dispatcher)[0].header.dest_id, ComponentIds::IRIS as u8);
+        assert_eq!(run.advance(t0 + Duration::from_secs(16), Some(&mut sequencer), &mut ids), Progress::Done);
+        assert_eq!(run.last_outcome, Some(Outcome::NoReply));
+    }
+
+    #[test]
+    fn test_aborts_on_outcome() {
+        let (mut run, mut sequencer, mut dispatcher, mut ids) = run("
+            EPS GetHK
+            abort if Nacked
+            IRIS GetHK
+            abort unless Success
+            UHF GetHK
+        ");
+        let t0 = Instant::now();
+        run.advance(t0, Some(&mut sequencer), &mut ids);
+        let cmd = sent(&mut dispatcher).remove(0);
+        // Delivered, which doesn't end the wait for its Report
+        assert!(!run.on_reply(&Msg::new_ack(cmd.header.msg_id, cmd.header.source_id, 0, AckCode::Success, vec![])));
+        assert!(run.on_reply(&report(&cmd, ResultCode::Failed)));
+        run.advance(t0, Some(&mut sequencer), &mut ids);
+        let cmd = sent(&mut dispatcher).remove(0);
+        assert_eq!(cmd.header.dest_id, ComponentIds::IRIS as u8);
+        assert!(run.on_reply(&Msg::new_ack(cmd.header.msg_id, cmd.header.source_id, 0, AckCode::Failed, vec![])));
+        assert_eq!(run.advance(t0, Some(&mut sequencer), &mut ids), Progress::Aborted("step 3: last cmd Nacked".to_string()));
+        assert!(sent(&mut dispatcher).is_empty());
+
+        // Cmds that can't be sent count as NACKed
+        let (mut run, _, _, mut ids) = self::run("EPS GetHK\nabort if Nacked");
+        assert!(matches!(run.advance(t0, None, &mut ids), Progress::Aborted(_)));
+    }
+
+    #[test]
+    fn test_loops() {
+        let (mut run, mut sequencer, mut dispatcher, mut ids) = run("
+            loop 2
+                EPS GetHK
+                abort if NoReply
+                loop 3
+                    wait 0
+                end
+            end
+        ");
+        let t0 = Instant::now();
+        let mut cmds = 0;
+        while run.advance(t0, Some(&mut sequencer), &mut ids) == Progress::Running {
+            for cmd in sent(&mut dispatcher) {
+                run.on_reply(&report(&cmd, ResultCode::Success));
+                cmds += 1;
+            }
+        }
+        assert_eq!(cmds, 2);
+        assert_eq!(run.step(), 5);
+        assert!(run.repeats.is_empty());
+    }
+
+    #[test]
+    fn test_pause() {
+        let (mut run, mut sequencer, mut dispatcher, mut ids) = run("wait 1\nEPS GetHK");
+        let t0 = Instant::now();
+        run.advance(t0, Some(&mut sequencer), &mut ids);
+        run.paused = true;
+        assert_eq!(run.advance(t0 + Duration::from_secs(2), Some(&mut sequencer), &mut ids), Progress::Running);
+        assert!(sent(&mut dispatcher).is_empty());
+        assert_eq!(run.status(), "test step 1/2, paused");
+        run.paused = false;
+        run.advance(t0 + Duration::from_secs(2), Some(&mut sequencer), &mut ids);
+        assert_eq!(sent(&mut dispatcher).len(), 1);
+    }
+
+    #[test]
+    fn test_runs_a_bounded_number_of_steps_at_once() {
+        let (mut run, mut sequencer, _, mut ids) = run("loop 1000\nwait 0\nend");
+        assert_eq!(run.advance(Instant::now(), Some(&mut sequencer), &mut ids), Progress::Running);
+        assert_eq!(run.repeats[&1], 999 - MAX_STEPS_AT_ONCE as u16 / 2);
+    }
+}
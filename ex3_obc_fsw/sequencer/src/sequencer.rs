@@ -0,0 +1,405 @@
+/*
+The sequences stored onboard, and the one running.
+
+A sequence is uploaded in chunks (see common::sequence for what it holds): BeginUpload gives its size and
+name, UploadChunk the bytes at an offset, and EndUpload the CRC32 of the whole of it. Chunks can be sent
+again, e.g. when their Report didn't make it down, but not past a gap in what has been received. Only
+once every byte is in, the CRC matches and the steps decode is the sequence stored, as <name>.seq in the
+sequencer's directory, written to a temporary file and renamed into place like the scheduler's tasks.
+Uploading a sequence with the name of one already stored replaces it.
+
+One sequence runs at a time. A run isn't saved, so a sequence running when the OBC resets doesn't carry
+on after it.
+*/
+
+use crate::run::{Progress, Run};
+use common::component_ids::ComponentIds;
+use common::house_keeping::HKData;
+use common::message_structure::{crc32, Msg};
+use common::msg_id::MsgIdAllocator;
+use common::opcodes;
+use common::sequence::{check_name, Sequence, MAX_SEQUENCE_SIZE};
+use handler::{Handler, Response};
+use interface::Interface;
+use log::{info, warn};
+use serde_json::json;
+use std::fs::{self, File};
+use std::io::{Error as IoError, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+const SEQUENCE_FILE_EXTENSION: &str = "seq";
+
+struct Upload {
+    name: String,
+    bytes: Vec<u8>,
+    /// Bytes received from the start, with no gaps
+    received: usize,
+}
+
+pub struct Sequencer {
+    dir: PathBuf,
+    upload: Option<Upload>,
+    run: Option<Run>,
+    /// How the last run ended, e.g. "capture done"
+    last_run: Option<String>,
+    msg_ids: MsgIdAllocator,
+}
+
+impl Sequencer {
+    /// Open the sequences stored in `dir`, creating the directory if there isn't one
+    pub fn open(dir: &Path) -> Result<Sequencer, IoError> {
+        fs::create_dir_all(dir)
+            .map_err(|e| IoError::new(e.kind(), format!("cannot create sequence dir {:?}: {}", dir, e)))?;
+        Ok(Sequencer {
+            dir: dir.to_path_buf(),
+            upload: None,
+            run: None,
+            last_run: None,
+            msg_ids: MsgIdAllocator::spacecraft(ComponentIds::Sequencer),
+        })
+    }
+
+    /// Names of the stored sequences, in order
+    pub fn sequences(&self) -> Result<Vec<String>, IoError> {
+        let mut names: Vec<String> = fs::read_dir(&self.dir)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == SEQUENCE_FILE_EXTENSION))
+            .filter_map(|path| Some(path.file_stem()?.to_str()?.to_string()))
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    pub fn begin_upload(&mut self, name: &str, size: usize) -> Result<(), IoError> {
+        check_name(name)?;
+        if size > MAX_SEQUENCE_SIZE {
+            return Err(IoError::new(ErrorKind::InvalidInput,
+                                    format!("{} bytes is too big, sequences can be up to {}", size, MAX_SEQUENCE_SIZE)));
+        }
+        if let Some(ref upload) = self.upload {
+            warn!("Dropping the upload of {} at {}/{} bytes", upload.name, upload.received, upload.bytes.len());
+        }
+        self.upload = Some(Upload { name: name.to_string(), bytes: vec![0; size], received: 0 });
+        Ok(())
+    }
+
+    /// Add the chunk in `body`, its offset followed by its bytes. Returns the bytes received so far
+    pub fn upload_chunk(&mut self, body: &[u8]) -> Result<usize, IoError> {
+        let upload = self.upload.as_mut().ok_or_else(|| IoError::new(ErrorKind::InvalidInput, "no upload in progress"))?;
+        if body.len() < 2 {
+            return Err(IoError::new(ErrorKind::InvalidInput, "chunk without an offset"));
+        }
+        let offset = u16::from_le_bytes([body[0], body[1]]) as usize;
+        let chunk = &body[2..];
+        let end = offset + chunk.len();
+        if offset > upload.received || end > upload.bytes.len() {
+            return Err(IoError::new(ErrorKind::InvalidInput, format!(
+                "chunk at {}..{} doesn't follow the {} of {} bytes received", offset, end, upload.received, upload.bytes.len())));
+        }
+        upload.bytes[offset..end].copy_from_slice(chunk);
+        upload.received = upload.received.max(end);
+        Ok(upload.received)
+    }
+
+    /// Check the upload and store it. Returns its name and number of steps
+    pub fn end_upload(&mut self, crc: u32) -> Result<(String, usize), IoError> {
+        let upload = self.upload.take().ok_or_else(|| IoError::new(ErrorKind::InvalidInput, "no upload in progress"))?;
+        if upload.received < upload.bytes.len() {
+            let missing = upload.bytes.len() - upload.received;
+            self.upload = Some(upload);
+            return Err(IoError::new(ErrorKind::InvalidData, format!("{} bytes still to upload", missing)));
+        }
+        if crc32(&upload.bytes) != crc {
+            // Sending the same chunks again won't help, so the upload has to start over
+            return Err(IoError::new(ErrorKind::InvalidData, format!("{} doesn't match its CRC, upload it again", upload.name)));
+        }
+        let sequence = Sequence::from_bytes(&upload.bytes)?;
+        let path = self.path(&upload.name);
+        let tmp_path = path.with_extension("tmp");
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(&upload.bytes)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &path)?;
+        info!("Stored sequence {} of {} steps", upload.name, sequence.steps.len());
+        Ok((upload.name, sequence.steps.len()))
+    }
+
+    pub fn load(&self, name: &str) -> Result<Sequence, IoError> {
+        check_name(name)?;
+        let bytes = fs::read(self.path(name))
+            .map_err(|e| IoError::new(e.kind(), format!("cannot read sequence {}: {}", name, e)))?;
+        Sequence::from_bytes(&bytes)
+    }
+
+    pub fn delete(&mut self, name: &str) -> Result<(), IoError> {
+        check_name(name)?;
+        fs::remove_file(self.path(name)).map_err(|e| IoError::new(e.kind(), format!("cannot delete sequence {}: {}", name, e)))
+    }
+
+    pub fn start(&mut self, name: &str) -> Result<(), IoError> {
+        if let Some(ref run) = self.run {
+            return Err(IoError::other(format!("{} is already running", run.name)));
+        }
+        let sequence = self.load(name)?;
+        info!("Starting sequence {}", name);
+        self.run = Some(Run::new(name, sequence));
+        Ok(())
+    }
+
+    fn running(&mut self) -> Result<&mut Run, IoError> {
+        self.run.as_mut().ok_or_else(|| IoError::other("no sequence is running"))
+    }
+
+    pub fn abort(&mut self) -> Result<String, IoError> {
+        let run = self.running()?;
+        let status = run.status();
+        let step = run.step();
+        self.end(format!("aborted at step {}", step));
+        Ok(status)
+    }
+
+    /// Run the steps of the running sequence that are due at `now`
+    pub fn advance(&mut self, now: Instant, dispatcher: Option<&mut dyn Interface>) {
+        let Some(ref mut run) = self.run else {
+            return;
+        };
+        match run.advance(now, dispatcher, &mut self.msg_ids) {
+            Progress::Running => (),
+            Progress::Done => self.end("done".to_string()),
+            Progress::Aborted(reason) => self.end(format!("aborted at {}", reason)),
+        }
+    }
+
+    fn end(&mut self, how: String) {
+        if let Some(run) = self.run.take() {
+            let how = format!("{} {}", run.name, how);
+            info!("Sequence {}", how);
+            self.last_run = Some(how);
+        }
+    }
+
+    pub fn status(&self) -> String {
+        match (&self.run, &self.last_run) {
+            (Some(run), _) => format!("Running {}", run.status()),
+            (None, Some(last_run)) => format!("Idle, last {}", last_run),
+            (None, None) => "Idle".to_string(),
+        }
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", name, SEQUENCE_FILE_EXTENSION))
+    }
+}
+
+impl Handler for Sequencer {
+    const COMPONENT: ComponentIds = ComponentIds::Sequencer;
+
+    fn on_command(&mut self, cmd: &Msg, _dispatcher: Option<&mut dyn Interface>) -> Result<Response, IoError> {
+        let opcode = opcodes::Sequencer::from(cmd.header.op_code);
+        // The body of an UploadChunk isn't described by args
+        let args = match opcode {
+            opcodes::Sequencer::UploadChunk | opcodes::Sequencer::Error => vec![],
+            _ => opcode.decode(&cmd.msg_body)?,
+        };
+        let int = |i: usize| args.get(i).and_then(|arg| arg.int::<i64>()).unwrap_or_default();
+        let name = args.iter().find_map(|arg| arg.as_str()).unwrap_or_default();
+        match opcode {
+            opcodes::Sequencer::BeginUpload => {
+                self.begin_upload(name, int(0) as usize)?;
+                Ok(format!("Uploading {}", name).into_bytes())
+            }
+            opcodes::Sequencer::UploadChunk => Ok(format!("{} bytes received", self.upload_chunk(&cmd.msg_body)?).into_bytes()),
+            opcodes::Sequencer::EndUpload => {
+                let (name, steps) = self.end_upload(int(0) as u32)?;
+                Ok(format!("Stored {} of {} steps", name, steps).into_bytes())
+            }
+            opcodes::Sequencer::StartSequence => {
+                self.start(name)?;
+                Ok(format!("Started {}", name).into_bytes())
+            }
+            opcodes::Sequencer::PauseSequence => {
+                let run = self.running()?;
+                run.paused = true;
+                Ok(format!("Paused {}", run.status()).into_bytes())
+            }
+            opcodes::Sequencer::ResumeSequence => {
+                let run = self.running()?;
+                run.paused = false;
+                Ok(format!("Resumed {}", run.status()).into_bytes())
+            }
+            opcodes::Sequencer::AbortSequence => Ok(format!("Aborted {}", self.abort()?).into_bytes()),
+            opcodes::Sequencer::ListSequences => Ok(self.sequences()?.join(" ").into_bytes()),
+            opcodes::Sequencer::DeleteSequence => {
+                self.delete(name)?;
+                Ok(format!("Deleted {}", name).into_bytes())
+            }
+            opcodes::Sequencer::GetStatus => Ok(self.status().into_bytes()),
+            opcodes::Sequencer::Error => {
+                Err(IoError::new(ErrorKind::Unsupported, format!("unknown sequencer opcode {}", cmd.header.op_code)))
+            }
+        }
+    }
+
+    fn on_report(&mut self, msg: &Msg) -> Result<(), IoError> {
+        if let Some(ref mut run) = self.run {
+            run.on_reply(msg);
+        }
+        Ok(())
+    }
+
+    fn on_tick(&mut self, dispatcher: Option<&mut dyn Interface>) -> Result<(), IoError> {
+        self.advance(Instant::now(), dispatcher);
+        Ok(())
+    }
+
+    fn collect_hk(&mut self, hk: &mut HKData, _dispatcher: Option<&mut dyn Interface>) -> Result<(), IoError> {
+        hk.key_value_pair("STORED", json!(self.sequences()?.len()));
+        hk.key_value_pair("RUNNING", json!(self.run.as_ref().map(|run| run.name.clone())));
+        hk.key_value_pair("STEP", json!(self.run.as_ref().map(|run| run.step())));
+        hk.key_value_pair("PAUSED", json!(self.run.as_ref().is_some_and(|run| run.paused)));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::message_structure::{deserialize_msg, MsgType, ResultCode};
+    use interface::mock::{pipe, PipeEnd};
+    use std::time::Duration;
+    use tempdir::TempDir;
+
+    fn cmd(opcode: opcodes::Sequencer, body: Vec<u8>) -> Msg {
+        Msg::new(MsgType::Cmd, 1, ComponentIds::Sequencer as u8, ComponentIds::GS as u8, opcode as u8, body)
+    }
+
+    fn typed(opcode: opcodes::Sequencer, args: &[&str]) -> Msg {
+        cmd(opcode, opcode.info().unwrap().encode(args).unwrap())
+    }
+
+    fn respond(sequencer: &mut Sequencer, cmd: &Msg) -> Result<String, IoError> {
+        sequencer.on_command(cmd, None).map(|response| String::from_utf8(response).unwrap())
+    }
+
+    /// Upload `bytes` as `name` in chunks of `chunk_size`
+    fn upload(sequencer: &mut Sequencer, name: &str, bytes: &[u8], chunk_size: usize) -> Result<String, IoError> {
+        respond(sequencer, &typed(opcodes::Sequencer::BeginUpload, &[&bytes.len().to_string(), name]))?;
+        for (i, chunk) in bytes.chunks(chunk_size).enumerate() {
+            let mut body = ((i * chunk_size) as u16).to_le_bytes().to_vec();
+            body.extend(chunk);
+            respond(sequencer, &cmd(opcodes::Sequencer::UploadChunk, body))?;
+        }
+        respond(sequencer, &typed(opcodes::Sequencer::EndUpload, &[&crc32(bytes).to_string()]))
+    }
+
+    fn sequence(text: &str) -> Vec<u8> {
+        Sequence::parse(text).unwrap().to_bytes().unwrap()
+    }
+
+    #[test]
+    fn test_example_sequences_compile() {
+        let capture = Sequence::parse(include_str!("../sequences/capture_images.txt")).unwrap();
+        assert_eq!(capture.steps.len(), 10);
+        assert!(capture.to_bytes().is_ok());
+    }
+
+    #[test]
+    fn test_upload() {
+        let dir = TempDir::new("sequencer").unwrap();
+        let mut sequencer = Sequencer::open(dir.path()).unwrap();
+        let bytes = sequence("IRIS ToggleSensor on\nwait 5\nIRIS CaptureImage\nabort unless Success");
+        assert_eq!(upload(&mut sequencer, "capture", &bytes, 16).unwrap(), "Stored capture of 4 steps");
+        assert_eq!(upload(&mut sequencer, "hk", &sequence("EPS GetHK"), 64).unwrap(), "Stored hk of 1 steps");
+        assert_eq!(respond(&mut sequencer, &typed(opcodes::Sequencer::ListSequences, &[])).unwrap(), "capture hk");
+        assert_eq!(Sequencer::open(dir.path()).unwrap().load("capture").unwrap().to_bytes().unwrap(), bytes);
+
+        // A chunk sent again is fine, one past a gap isn't
+        respond(&mut sequencer, &typed(opcodes::Sequencer::BeginUpload, &[&bytes.len().to_string(), "retry"])).unwrap();
+        let chunk = |offset: usize| {
+            let mut body = (offset as u16).to_le_bytes().to_vec();
+            body.extend(&bytes[offset..(offset + 8).min(bytes.len())]);
+            cmd(opcodes::Sequencer::UploadChunk, body)
+        };
+        assert_eq!(respond(&mut sequencer, &chunk(0)).unwrap(), "8 bytes received");
+        assert_eq!(respond(&mut sequencer, &chunk(0)).unwrap(), "8 bytes received");
+        assert_eq!(respond(&mut sequencer, &chunk(16)).unwrap_err().kind(), ErrorKind::InvalidInput);
+        // Ending early leaves the upload to be finished
+        let end = typed(opcodes::Sequencer::EndUpload, &[&crc32(&bytes).to_string()]);
+        assert_eq!(respond(&mut sequencer, &end).unwrap_err().kind(), ErrorKind::InvalidData);
+        for offset in (8..bytes.len()).step_by(8) {
+            respond(&mut sequencer, &chunk(offset)).unwrap();
+        }
+        assert_eq!(respond(&mut sequencer, &end).unwrap(), "Stored retry of 4 steps");
+    }
+
+    #[test]
+    fn test_rejects_bad_uploads() {
+        let dir = TempDir::new("sequencer").unwrap();
+        let mut sequencer = Sequencer::open(dir.path()).unwrap();
+        let bytes = sequence("EPS GetHK\nwait 1");
+        let mut corrupted = bytes.clone();
+        corrupted[3] ^= 1;
+        respond(&mut sequencer, &typed(opcodes::Sequencer::BeginUpload, &[&bytes.len().to_string(), "bad"])).unwrap();
+        let mut body = vec![0, 0];
+        body.extend(&corrupted);
+        respond(&mut sequencer, &cmd(opcodes::Sequencer::UploadChunk, body)).unwrap();
+        let end = typed(opcodes::Sequencer::EndUpload, &[&crc32(&bytes).to_string()]);
+        assert_eq!(respond(&mut sequencer, &end).unwrap_err().kind(), ErrorKind::InvalidData);
+
+        // The CRC is right, but it isn't a sequence
+        assert!(upload(&mut sequencer, "bad", &[1, 0, 9], 8).is_err());
+        assert!(upload(&mut sequencer, "../bad", &bytes, 8).is_err());
+        assert!(upload(&mut sequencer, "big", &vec![0; MAX_SEQUENCE_SIZE + 1], 64).is_err());
+        assert!(respond(&mut sequencer, &cmd(opcodes::Sequencer::UploadChunk, vec![0, 0, 1])).is_err());
+        assert!(sequencer.sequences().unwrap().is_empty());
+    }
+
+    /// The cmds sent to the cmd_dispatcher since last time
+    fn sent(dispatcher: &mut PipeEnd) -> Vec<Msg> {
+        let mut cmds = vec![];
+        let mut buf = [0u8; 256];
+        while dispatcher.available() > 0 {
+            let len = dispatcher.read(&mut buf).unwrap();
+            cmds.push(deserialize_msg(&buf[..len]).unwrap());
+        }
+        cmds
+    }
+
+    #[test]
+    fn test_runs_sequences() {
+        let dir = TempDir::new("sequencer").unwrap();
+        let mut sequencer = Sequencer::open(dir.path()).unwrap();
+        upload(&mut sequencer, "hk", &sequence("EPS GetHK\nabort unless Success\nIRIS GetHK"), 64).unwrap();
+        let (mut to_dispatcher, mut dispatcher) = pipe();
+
+        let start = typed(opcodes::Sequencer::StartSequence, &["hk"]);
+        assert_eq!(respond(&mut sequencer, &start).unwrap(), "Started hk");
+        assert!(respond(&mut sequencer, &start).is_err());
+        let t0 = Instant::now();
+        sequencer.advance(t0, Some(&mut to_dispatcher));
+        let eps = sent(&mut dispatcher).remove(0);
+        assert_eq!(eps.header.source_id, ComponentIds::Sequencer as u8);
+        assert_eq!(respond(&mut sequencer, &typed(opcodes::Sequencer::GetStatus, &[])).unwrap(),
+                   format!("Running hk step 1/3, waiting on msg {}", eps.header.msg_id));
+
+        sequencer.on_report(&Msg::new(MsgType::Report, eps.header.msg_id, eps.header.source_id, eps.header.dest_id,
+                                      ResultCode::Unavailable as u8, vec![])).unwrap();
+        sequencer.advance(t0, Some(&mut to_dispatcher));
+        assert!(sent(&mut dispatcher).is_empty());
+        assert_eq!(sequencer.status(), "Idle, last hk aborted at step 1: last cmd Unavailable");
+
+        // Paused before its first step, then aborted
+        respond(&mut sequencer, &start).unwrap();
+        respond(&mut sequencer, &typed(opcodes::Sequencer::PauseSequence, &[])).unwrap();
+        sequencer.advance(t0 + Duration::from_secs(1), Some(&mut to_dispatcher));
+        assert!(sent(&mut dispatcher).is_empty());
+        assert_eq!(respond(&mut sequencer, &typed(opcodes::Sequencer::AbortSequence, &[])).unwrap(),
+                   "Aborted hk step 0/3, paused");
+        assert!(respond(&mut sequencer, &typed(opcodes::Sequencer::ResumeSequence, &[])).is_err());
+
+        respond(&mut sequencer, &typed(opcodes::Sequencer::DeleteSequence, &["hk"])).unwrap();
+        assert!(respond(&mut sequencer, &start).is_err());
+    }
+}
@@ -0,0 +1,41 @@
+/*
+Keeps the onboard time: UTC, corrected by syncs from the GS, and mission elapsed time (see common::time).
+Syncs and the other Time cmds come through the cmd_dispatcher, and the time is handed on to IRIS, the
+ADCS and the GPS through it too. The clock is kept in the time service's data dir from the config,
+e.g. data/time, where the other processes read it, and the service's HK is written beside it.
+*/
+
+pub mod time_service;
+
+use common::house_keeping::HK_FILE;
+use common::logging::init_program_logger;
+use common::time::{self, Clock};
+use handler::HandlerRuntime;
+use interface::ipc::{IpcClient, CMD_DISPATCHER_SOCKET};
+use std::time::Duration;
+use time_service::TimeService;
+
+/// How often the service checks whether the payloads are due the time
+const TICK: Duration = Duration::from_secs(1);
+/// How often the service writes its HK
+const HK_PERIOD: Duration = Duration::from_secs(60);
+
+fn main() {
+    init_program_logger("time_service");
+
+    let clock_path = time::clock_path();
+    let service = match Clock::open(&clock_path).and_then(TimeService::new) {
+        Ok(service) => service,
+        Err(e) => {
+            eprintln!("Cannot open the clock: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // The cmd_dispatcher is the service's peripheral, which the time is sent to the payloads through
+    let mut runtime: HandlerRuntime<TimeService, IpcClient> = HandlerRuntime::new(service)
+        .peripheral(|| IpcClient::new(CMD_DISPATCHER_SOCKET.to_string()))
+        .tick(TICK)
+        .hk(HK_PERIOD, &clock_path.with_file_name(HK_FILE).to_string_lossy());
+    runtime.run();
+}
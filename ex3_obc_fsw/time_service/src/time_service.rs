@@ -0,0 +1,239 @@
+/*
+The time service, which keeps the onboard clock (see common::time) and hands the time on to the payloads.
+
+The GS syncs the clock with a SyncTime carrying its UTC when it sent the cmd and the round trip delay it
+measured to the satellite. The clock is set to the GS's time plus half of the round trip, on the basis
+that the cmd took about half of it to get up.
+
+IRIS, the ADCS and the GPS keep time of their own, so they are sent the onboard UTC, in whole seconds,
+when the service starts, after each sync, and every PROPAGATE_PERIOD to keep their drift down. Their
+Reports come back through the cmd_dispatcher, and the service only logs the ones that failed.
+*/
+
+use common::component_ids::ComponentIds;
+use common::house_keeping::HKData;
+use common::message_structure::{serialize_msg, AckCode, Msg, MsgType, ResultCode};
+use common::msg_id::MsgIdAllocator;
+use common::opcodes;
+use common::time::Clock;
+use chrono::{DateTime, SecondsFormat};
+use handler::{Handler, Response};
+use interface::Interface;
+use log::{info, warn};
+use serde_json::json;
+use std::io::{Error as IoError, ErrorKind};
+use std::time::{Duration, Instant};
+
+/// How often the payloads are sent the time, besides after each sync
+pub const PROPAGATE_PERIOD: Duration = Duration::from_secs(3600);
+
+pub struct TimeService {
+    clock: Clock,
+    msg_ids: MsgIdAllocator,
+    /// When the time was last sent to the payloads, None until it first is
+    propagated_at: Option<Instant>,
+}
+
+/// e.g. 2024-06-01T12:00:00.123Z
+fn format_utc(ms: i64) -> String {
+    DateTime::from_timestamp_millis(ms).unwrap_or_default().to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+impl TimeService {
+    /// Keep `clock`, starting MET if it hasn't been
+    pub fn new(mut clock: Clock) -> Result<TimeService, IoError> {
+        clock.start_met()?;
+        Ok(TimeService { clock, msg_ids: MsgIdAllocator::spacecraft(ComponentIds::Time), propagated_at: None })
+    }
+
+    /// Send the onboard UTC to the payloads that keep time. Returns how many it was sent to
+    pub fn propagate(&mut self, now: Instant, dispatcher: Option<&mut dyn Interface>) -> Result<usize, IoError> {
+        let dispatcher = dispatcher.ok_or_else(|| IoError::new(ErrorKind::NotConnected, "cmd_dispatcher not connected"))?;
+        self.propagated_at = Some(now);
+        let secs = (self.clock.utc_millis() / 1000).to_string();
+        let cmds = [
+            (ComponentIds::IRIS, opcodes::IRIS::SetTime.info(), vec![secs.as_str()]),
+            (ComponentIds::ADCS, opcodes::ADCS::OnboardTime.info(), vec!["1", secs.as_str()]),
+            (ComponentIds::GPS, opcodes::GPS::SetTime.info(), vec![secs.as_str()]),
+        ];
+        let mut sent = 0;
+        for (component, op, args) in cmds {
+            let op = op.expect("time opcodes are in the registry");
+            let body = op.encode(&args).map_err(|e| IoError::new(ErrorKind::InvalidData, e.to_string()))?;
+            let cmd = Msg::new(MsgType::Cmd, self.msg_ids.next_id(), component as u8, ComponentIds::Time as u8, op.value, body);
+            match serialize_msg(&cmd).and_then(|bytes| dispatcher.send(&bytes)) {
+                Ok(_) => sent += 1,
+                Err(e) => warn!("Cannot send {} the time: {}", component, e),
+            }
+        }
+        info!("Sent {} payloads the time, {} s", sent, secs);
+        Ok(sent)
+    }
+
+    /// Propagate the time if it hasn't been for PROPAGATE_PERIOD
+    pub fn propagate_if_due(&mut self, now: Instant, dispatcher: Option<&mut dyn Interface>) {
+        if self.propagated_at.is_none_or(|at| now.duration_since(at) >= PROPAGATE_PERIOD) {
+            if let Err(e) = self.propagate(now, dispatcher) {
+                warn!("Cannot propagate the time: {}", e);
+            }
+        }
+    }
+
+    /// e.g. "UTC 2024-06-01T12:00:00.123Z MET 86400.123s offset +1234ms synced 2024-06-01T11:00:00.000Z rtt 800ms"
+    pub fn status(&mut self) -> String {
+        let state = self.clock.state();
+        let mut status = format!("UTC {} MET {:.3}s offset {:+}ms", format_utc(self.clock.utc_millis()),
+                                 self.clock.met_millis() as f64 / 1000.0, state.offset_ms);
+        if let (Some(synced), Some(rtt)) = (state.synced_ms, state.rtt_ms) {
+            status += &format!(" synced {} rtt {}ms", format_utc(synced), rtt);
+        }
+        status
+    }
+}
+
+impl Handler for TimeService {
+    const COMPONENT: ComponentIds = ComponentIds::Time;
+
+    fn on_command(&mut self, cmd: &Msg, dispatcher: Option<&mut dyn Interface>) -> Result<Response, IoError> {
+        let opcode = opcodes::Time::from(cmd.header.op_code);
+        let args = opcode.decode(&cmd.msg_body)?;
+        let int = |i: usize| args.get(i).and_then(|arg| arg.int::<i64>()).unwrap_or_default();
+        match opcode {
+            opcodes::Time::SyncTime => {
+                let moved = self.clock.sync(int(0), int(1) as u32)?;
+                info!("Synced the clock, moving it {:+} ms", moved);
+                if let Err(e) = self.propagate(Instant::now(), dispatcher) {
+                    warn!("Cannot propagate the synced time: {}", e);
+                }
+                Ok(format!("Moved the clock {:+} ms", moved).into_bytes())
+            }
+            opcodes::Time::GetTime => Ok(self.status().into_bytes()),
+            opcodes::Time::SetMetEpoch => {
+                self.clock.set_met_epoch(int(0))?;
+                Ok(format!("MET counts from {}", format_utc(int(0))).into_bytes())
+            }
+            opcodes::Time::PropagateTime => {
+                let sent = self.propagate(Instant::now(), dispatcher)?;
+                Ok(format!("Sent {} payloads the time", sent).into_bytes())
+            }
+            opcodes::Time::Error => {
+                Err(IoError::new(ErrorKind::Unsupported, format!("unknown time opcode {}", cmd.header.op_code)))
+            }
+        }
+    }
+
+    fn on_report(&mut self, msg: &Msg) -> Result<(), IoError> {
+        let failed = match (msg.result_code(), msg.ack_code()) {
+            (Some(code), _) => code != Ok(ResultCode::Success),
+            (_, Some(code)) => code != Ok(AckCode::Success),
+            _ => false,
+        };
+        if failed {
+            warn!("{} didn't take the time: {}", msg.header.source_id, String::from_utf8_lossy(&msg.msg_body));
+        }
+        Ok(())
+    }
+
+    fn on_tick(&mut self, dispatcher: Option<&mut dyn Interface>) -> Result<(), IoError> {
+        self.propagate_if_due(Instant::now(), dispatcher);
+        Ok(())
+    }
+
+    fn collect_hk(&mut self, hk: &mut HKData, _dispatcher: Option<&mut dyn Interface>) -> Result<(), IoError> {
+        let state = self.clock.state();
+        hk.key_value_pair("OFFSET_MS", json!(state.offset_ms));
+        hk.key_value_pair("SYNCED", json!(state.synced_ms.map(format_utc)));
+        hk.key_value_pair("RTT_MS", json!(state.rtt_ms));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::message_structure::deserialize_msg;
+    use common::time::CLOCK_FILE;
+    use interface::mock::{pipe, PipeEnd};
+    use tempdir::TempDir;
+
+    fn service(dir: &TempDir) -> TimeService {
+        TimeService::new(Clock::open(&dir.path().join(CLOCK_FILE)).unwrap()).unwrap()
+    }
+
+    fn cmd(opcode: opcodes::Time, args: &[&str]) -> Msg {
+        Msg::new(MsgType::Cmd, 1, ComponentIds::Time as u8, ComponentIds::GS as u8, opcode as u8,
+                 opcode.info().unwrap().encode(args).unwrap())
+    }
+
+    /// The cmds sent to the cmd_dispatcher since last time, which come out of the pipe back to back
+    fn sent(dispatcher: &mut PipeEnd) -> Vec<Msg> {
+        let mut bytes = vec![0u8; dispatcher.available()];
+        if !bytes.is_empty() {
+            dispatcher.read(&mut bytes).unwrap();
+        }
+        let mut cmds = vec![];
+        let mut rest = &bytes[..];
+        while !rest.is_empty() {
+            let cmd = deserialize_msg(rest).unwrap();
+            rest = &rest[cmd.header.msg_len as usize..];
+            cmds.push(cmd);
+        }
+        cmds
+    }
+
+    #[test]
+    fn test_sync_propagates() {
+        let dir = TempDir::new("time_service").unwrap();
+        let mut time = service(&dir);
+        let (mut to_dispatcher, mut dispatcher) = pipe();
+        // A year ahead of the system clock, with a 1 s round trip
+        let gs_time = common::time::utc_millis() + 365 * 86_400_000;
+        let sync = cmd(opcodes::Time::SyncTime, &[&gs_time.to_string(), "1000"]);
+        let report = String::from_utf8(time.on_command(&sync, Some(&mut to_dispatcher)).unwrap()).unwrap();
+        assert!(report.starts_with("Moved the clock +31536"), "{}", report);
+
+        let cmds = sent(&mut dispatcher);
+        let dests: Vec<u8> = cmds.iter().map(|c| c.header.dest_id).collect();
+        assert_eq!(dests, [ComponentIds::IRIS as u8, ComponentIds::ADCS as u8, ComponentIds::GPS as u8]);
+        let secs = opcodes::IRIS::SetTime.decode(&cmds[0].msg_body).unwrap()[0].int::<i64>().unwrap();
+        assert!((secs - (gs_time + 500) / 1000).abs() <= 1);
+        let adcs = opcodes::ADCS::OnboardTime.decode(&cmds[1].msg_body).unwrap();
+        assert_eq!((adcs[0].int::<u8>(), adcs[1].int::<i64>()), (Some(1), Some(secs)));
+        assert!(cmds.iter().all(|c| c.header.source_id == ComponentIds::Time as u8));
+
+        let status = String::from_utf8(time.on_command(&cmd(opcodes::Time::GetTime, &[]), None).unwrap()).unwrap();
+        assert!(status.contains(" rtt 1000ms"), "{}", status);
+        assert!(status.len() <= common::constants::DOWNLINK_MSG_BODY_SIZE);
+        // The clock file was updated, so a restart keeps the time
+        assert!(service(&dir).status().contains(" rtt 1000ms"));
+    }
+
+    #[test]
+    fn test_propagates_periodically() {
+        let dir = TempDir::new("time_service").unwrap();
+        let mut time = service(&dir);
+        let (mut to_dispatcher, mut dispatcher) = pipe();
+        let t0 = Instant::now();
+        time.propagate_if_due(t0, Some(&mut to_dispatcher));
+        assert_eq!(sent(&mut dispatcher).len(), 3);
+        time.propagate_if_due(t0 + PROPAGATE_PERIOD / 2, Some(&mut to_dispatcher));
+        assert!(sent(&mut dispatcher).is_empty());
+        time.propagate_if_due(t0 + PROPAGATE_PERIOD, Some(&mut to_dispatcher));
+        assert_eq!(sent(&mut dispatcher).len(), 3);
+
+        let propagate = cmd(opcodes::Time::PropagateTime, &[]);
+        assert_eq!(time.on_command(&propagate, None).unwrap_err().kind(), ErrorKind::NotConnected);
+    }
+
+    #[test]
+    fn test_met_epoch() {
+        let dir = TempDir::new("time_service").unwrap();
+        let mut time = service(&dir);
+        let epoch = common::time::utc_millis() - 10_000;
+        time.on_command(&cmd(opcodes::Time::SetMetEpoch, &[&epoch.to_string()]), None).unwrap();
+        let met = time.clock.met_millis();
+        assert!((10_000..11_000).contains(&met), "{}", met);
+        // Restarting doesn't restart MET
+        assert!(service(&dir).clock.met_millis() >= met);
+    }
+}
@@ -76,7 +76,7 @@ pub fn reconstruct_msg(messages: Vec<Msg>) -> Result<Msg, &'static str> {
     }
 
     let first_msg = &messages[0];
-    if first_msg.msg_body.is_empty() {
+    if first_msg.msg_body.len() < 2 {
         return Err("First message body empty");
     }
 
@@ -88,7 +88,7 @@ pub fn reconstruct_msg(messages: Vec<Msg>) -> Result<Msg, &'static str> {
     let mut full_body: Vec<u8> = Vec::new();
 
     for (i,msg) in messages.iter().skip(1).enumerate() {
-        if msg.msg_body.is_empty() {
+        if msg.msg_body.len() < 2 {
             return Err("Empty message body");
         } else if u16::from_le_bytes([msg.msg_body[0], msg.msg_body[1]]) as usize != i + 1 {
             eprintln!("Invalid sequence number {}.\nExpected sequence number of {}", u16::from_le_bytes([msg.msg_body[0], msg.msg_body[1]]), i+1);
@@ -113,7 +113,7 @@ mod tests {
         for i in 0..=408 {
             original_body.push((i % 256) as u8);
         }
-        let large_msg: Msg = Msg::new(0,2,5,1,5, original_body);
+        let large_msg: Msg = Msg::new(MsgType::Cmd,2,5,1,5, original_body);
 
         // Handle edge case of max body size and length of msg being one off
         let messages: Vec<Msg> = handle_large_msg(large_msg.clone(), 408).unwrap();
@@ -135,16 +135,16 @@ mod tests {
         for i in 0..=408 {
             original_body.push((i % 256) as u8);
         }
-        let large_msg: Msg = Msg::new(0,2,5,1,5, original_body);
+        let large_msg: Msg = Msg::new(MsgType::Cmd,2,5,1,5, original_body);
         let messages: Vec<Msg> = handle_large_msg(large_msg.clone(), max_body_size).unwrap();
-        let number_of_packets: usize = (large_msg.msg_body.len() + max_body_size - 1) / max_body_size;
+        let number_of_packets: usize = large_msg.msg_body.len().div_ceil(max_body_size);
         assert_eq!(messages.len(), number_of_packets + 1);
     }
 
     #[test]
     fn test_small_msg() {
         let max_body_size = 128;
-        let small_msg = Msg::new(2,0,7,3,0,vec![2,5]);
+        let small_msg = Msg::new(MsgType::Bulk,0,7,3,0,vec![2,5]);
         let sliced_small = handle_large_msg(small_msg.clone(), max_body_size).unwrap();
         println!("Small vec: {:?}", sliced_small);
         // 1 message in vec
@@ -164,7 +164,7 @@ mod tests {
         for i in 0..512 { // 0.5KB of data
             original_body.push((i % 256) as u8); // Different numbered bytes
         }
-        let large_msg = Msg::new(2, 2, 5, 1, 5, original_body.clone());
+        let large_msg = Msg::new(MsgType::Bulk, 2, 5, 1, 5, original_body.clone());
 
         // Handle the large message, slicing it into smaller packets
         let max_body_size = 128; // 128B packets
@@ -177,6 +177,14 @@ mod tests {
         assert_eq!(reconstructed_msg.msg_body, original_body, "The reconstructed message does not match the original message");
     }
 
+    #[test]
+    fn test_reconstruct_refuses_msgs_without_sequence_numbers() {
+        let large_msg = Msg::new(MsgType::Bulk, 2, 5, 1, 5, vec![7; 300]);
+        let mut sliced_msgs = handle_large_msg(large_msg, 128).unwrap();
+        sliced_msgs[1].msg_body.truncate(1);
+        assert!(reconstruct_msg(sliced_msgs).is_err());
+    }
+
     // This test represents how data will be sliced and reconstructed when being downlinked from the satellite
     #[test]
     fn test_spacecraft_reconstruct() {
@@ -185,7 +193,7 @@ mod tests {
         for i in 0..6144 { // 6KB of data
             original_body.push((i % 256) as u8);
         }
-        let large_msg = Msg::new(MsgType::Bulk as u8, 2, 7, 3, 0, original_body.clone());
+        let large_msg = Msg::new(MsgType::Bulk, 2, 7, 3, 0, original_body.clone());
 
         // First, slice the large message into 2KB packets
         let first_level_packets = handle_large_msg(large_msg.clone(), 2048).unwrap(); // 2KB packets
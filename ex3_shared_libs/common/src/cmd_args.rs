@@ -0,0 +1,438 @@
+/*
+Typed argument schemas for cmd opcodes.
+
+Each opcode in the registry lists the arguments its msg body carries. A schema gives the type, the
+accepted range, the units and the byte order of each argument, which is enough for the ground station
+to validate and pack what an operator typed, and for the handler to unpack it again:
+
+    operator tokens --encode_args--> msg body bytes --decode_args--> ArgValues
+
+Fixed size arguments are packed back to back with no padding or delimiters. A `Str` argument takes the
+rest of the body, so it can only be the last argument. Trailing arguments can be marked optional, in
+which case the body simply ends early.
+*/
+
+use serde::Serialize;
+use std::fmt;
+use std::io::Error as IoError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ArgType {
+    /// One byte, 0 or 1. Typed as 0/1, true/false or on/off
+    Bool,
+    U8,
+    U16,
+    U32,
+    I8,
+    I16,
+    I32,
+    /// Eight bytes, e.g. for times in ms
+    I64,
+    /// UTF-8 text filling the rest of the body
+    Str,
+    /// A fixed number of raw bytes, typed as hex
+    Bytes(usize),
+}
+
+impl ArgType {
+    /// Encoded size in bytes, or None if the argument takes the rest of the body
+    pub const fn size(&self) -> Option<usize> {
+        match self {
+            ArgType::Bool | ArgType::U8 | ArgType::I8 => Some(1),
+            ArgType::U16 | ArgType::I16 => Some(2),
+            ArgType::U32 | ArgType::I32 => Some(4),
+            ArgType::I64 => Some(8),
+            ArgType::Str => None,
+            ArgType::Bytes(n) => Some(*n),
+        }
+    }
+
+    /// Range of values the type can hold, for integer types
+    const fn bounds(&self) -> Option<(i64, i64)> {
+        match self {
+            ArgType::U8 => Some((0, u8::MAX as i64)),
+            ArgType::U16 => Some((0, u16::MAX as i64)),
+            ArgType::U32 => Some((0, u32::MAX as i64)),
+            ArgType::I8 => Some((i8::MIN as i64, i8::MAX as i64)),
+            ArgType::I16 => Some((i16::MIN as i64, i16::MAX as i64)),
+            ArgType::I32 => Some((i32::MIN as i64, i32::MAX as i64)),
+            ArgType::I64 => Some((i64::MIN, i64::MAX)),
+            _ => None,
+        }
+    }
+
+    fn is_signed(&self) -> bool {
+        matches!(self, ArgType::I8 | ArgType::I16 | ArgType::I32 | ArgType::I64)
+    }
+}
+
+impl fmt::Display for ArgType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArgType::Bool => write!(f, "bool"),
+            ArgType::U8 => write!(f, "u8"),
+            ArgType::U16 => write!(f, "u16"),
+            ArgType::U32 => write!(f, "u32"),
+            ArgType::I8 => write!(f, "i8"),
+            ArgType::I16 => write!(f, "i16"),
+            ArgType::I32 => write!(f, "i32"),
+            ArgType::I64 => write!(f, "i64"),
+            ArgType::Str => write!(f, "text"),
+            ArgType::Bytes(n) => write!(f, "{} hex bytes", n),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+/// Describes one argument of an opcode. Built with `arg` and the chained setters, so that the
+/// registry can be written as a const table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ArgSpec {
+    pub name: &'static str,
+    pub kind: ArgType,
+    /// Inclusive range accepted for integer args, None to accept anything the type can hold
+    pub range: Option<(i64, i64)>,
+    pub units: &'static str,
+    pub endian: Endian,
+    pub optional: bool,
+}
+
+/// A required, little-endian argument with no units or range restriction
+pub const fn arg(name: &'static str, kind: ArgType) -> ArgSpec {
+    ArgSpec { name, kind, range: None, units: "", endian: Endian::Little, optional: false }
+}
+
+impl ArgSpec {
+    pub const fn range(mut self, min: i64, max: i64) -> Self {
+        self.range = Some((min, max));
+        self
+    }
+
+    pub const fn units(mut self, units: &'static str) -> Self {
+        self.units = units;
+        self
+    }
+
+    pub const fn big_endian(mut self) -> Self {
+        self.endian = Endian::Big;
+        self
+    }
+
+    pub const fn optional(mut self) -> Self {
+        self.optional = true;
+        self
+    }
+
+    fn limits(&self) -> Option<(i64, i64)> {
+        match self.range {
+            Some(range) => Some(range),
+            None => self.kind.bounds(),
+        }
+    }
+
+    fn check_range(&self, value: i64) -> Result<i64, ArgError> {
+        match self.limits() {
+            Some((min, max)) if value < min || value > max => {
+                Err(ArgError::OutOfRange { arg: self.name, value, min, max })
+            }
+            _ => Ok(value),
+        }
+    }
+
+    fn encode(&self, token: &str, body: &mut Vec<u8>) -> Result<(), ArgError> {
+        let invalid = || ArgError::Invalid { arg: self.name, input: token.to_string() };
+        match self.kind {
+            ArgType::Bool => {
+                let value = match token.to_ascii_lowercase().as_str() {
+                    "0" | "false" | "off" => 0,
+                    "1" | "true" | "on" => 1,
+                    _ => return Err(invalid()),
+                };
+                body.push(value);
+            }
+            ArgType::Str => body.extend_from_slice(token.as_bytes()),
+            ArgType::Bytes(n) => {
+                let bytes = hex::decode(token).map_err(|_| invalid())?;
+                if bytes.len() != n {
+                    return Err(invalid());
+                }
+                body.extend(bytes);
+            }
+            _ => {
+                let value = parse_int(token).ok_or_else(invalid)?;
+                let value = self.check_range(value)?;
+                let size = self.kind.size().unwrap_or(0);
+                // Little-endian two's complement truncated to size is the right encoding for both signed and unsigned
+                let bytes = &value.to_le_bytes()[..size];
+                match self.endian {
+                    Endian::Little => body.extend_from_slice(bytes),
+                    Endian::Big => body.extend(bytes.iter().rev()),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<ArgValue, ArgError> {
+        match self.kind {
+            ArgType::Bool => match bytes[0] {
+                0 => Ok(ArgValue::Bool(false)),
+                1 => Ok(ArgValue::Bool(true)),
+                b => Err(ArgError::OutOfRange { arg: self.name, value: b as i64, min: 0, max: 1 }),
+            },
+            ArgType::Str => String::from_utf8(bytes.to_vec())
+                .map(ArgValue::Str)
+                .map_err(|_| ArgError::NotUtf8(self.name)),
+            ArgType::Bytes(_) => Ok(ArgValue::Bytes(bytes.to_vec())),
+            _ => {
+                let mut le = [0u8; 8];
+                match self.endian {
+                    Endian::Little => le[..bytes.len()].copy_from_slice(bytes),
+                    Endian::Big => bytes.iter().rev().enumerate().for_each(|(i, b)| le[i] = *b),
+                }
+                let mut value = u64::from_le_bytes(le) as i64;
+                if self.kind.is_signed() {
+                    // Sign extend from the encoded width
+                    let shift = 64 - 8 * bytes.len() as u32;
+                    value = (value << shift) >> shift;
+                }
+                self.check_range(value).map(ArgValue::Int)
+            }
+        }
+    }
+}
+
+/// Shown in usage strings, e.g. `<speed: i16 -8000..=8000 rpm>`, with optional args in brackets
+impl fmt::Display for ArgSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut text = format!("{}: {}", self.name, self.kind);
+        if let Some((min, max)) = self.range {
+            text.push_str(&format!(" {}..={}", min, max));
+        }
+        if !self.units.is_empty() {
+            text.push_str(&format!(" {}", self.units));
+        }
+        if self.endian == Endian::Big {
+            text.push_str(" BE");
+        }
+        if self.optional {
+            write!(f, "[{}]", text)
+        } else {
+            write!(f, "<{}>", text)
+        }
+    }
+}
+
+fn parse_int(token: &str) -> Option<i64> {
+    match token.strip_prefix("0x").or_else(|| token.strip_prefix("0X")) {
+        Some(hex) => i64::from_str_radix(hex, 16).ok(),
+        None => token.parse::<i64>().ok(),
+    }
+}
+
+/// A decoded argument
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArgValue {
+    Int(i64),
+    Bool(bool),
+    Str(String),
+    Bytes(Vec<u8>),
+}
+
+impl ArgValue {
+    /// The value as any integer type it fits in
+    pub fn int<T: TryFrom<i64>>(&self) -> Option<T> {
+        match self {
+            ArgValue::Int(value) => T::try_from(*value).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            ArgValue::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            ArgValue::Str(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            ArgValue::Bytes(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ArgValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArgValue::Int(value) => write!(f, "{}", value),
+            ArgValue::Bool(value) => write!(f, "{}", *value as u8),
+            ArgValue::Str(value) => write!(f, "{}", value),
+            ArgValue::Bytes(value) => write!(f, "{}", hex::encode(value)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArgError {
+    /// A required argument was not given, or the body ended before it
+    Missing(&'static str),
+    /// The operator's input could not be parsed as the argument's type
+    Invalid { arg: &'static str, input: String },
+    OutOfRange { arg: &'static str, value: i64, min: i64, max: i64 },
+    /// The body ended part way through an argument
+    Truncated(&'static str),
+    NotUtf8(&'static str),
+    /// More tokens were given than the opcode takes
+    TooMany(usize),
+    /// Bytes were left over after decoding every argument
+    Trailing(usize),
+}
+
+impl fmt::Display for ArgError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArgError::Missing(arg) => write!(f, "missing argument '{}'", arg),
+            ArgError::Invalid { arg, input } => write!(f, "invalid value '{}' for '{}'", input, arg),
+            ArgError::OutOfRange { arg, value, min, max } => {
+                write!(f, "'{}' must be from {} to {}, got {}", arg, min, max, value)
+            }
+            ArgError::Truncated(arg) => write!(f, "msg body truncated in '{}'", arg),
+            ArgError::NotUtf8(arg) => write!(f, "'{}' is not valid UTF-8", arg),
+            ArgError::TooMany(n) => write!(f, "{} more arguments than expected", n),
+            ArgError::Trailing(n) => write!(f, "{} unexpected bytes after the last argument", n),
+        }
+    }
+}
+
+impl std::error::Error for ArgError {}
+
+impl From<ArgError> for IoError {
+    fn from(err: ArgError) -> Self {
+        IoError::new(std::io::ErrorKind::InvalidData, err)
+    }
+}
+
+/// Validate and pack operator tokens into a msg body
+pub fn encode_args(specs: &[ArgSpec], tokens: &[&str]) -> Result<Vec<u8>, ArgError> {
+    let mut body = Vec::new();
+    let mut tokens = tokens.iter().filter(|t| !t.is_empty());
+    for spec in specs {
+        if spec.kind == ArgType::Str {
+            let rest: Vec<&str> = tokens.by_ref().copied().collect();
+            if rest.is_empty() && !spec.optional {
+                return Err(ArgError::Missing(spec.name));
+            }
+            spec.encode(&rest.join(" "), &mut body)?;
+            continue;
+        }
+        match tokens.next() {
+            Some(token) => spec.encode(token, &mut body)?,
+            None if spec.optional => break,
+            None => return Err(ArgError::Missing(spec.name)),
+        }
+    }
+    match tokens.count() {
+        0 => Ok(body),
+        n => Err(ArgError::TooMany(n)),
+    }
+}
+
+/// Unpack and validate a msg body
+pub fn decode_args(specs: &[ArgSpec], body: &[u8]) -> Result<Vec<ArgValue>, ArgError> {
+    let mut values = Vec::with_capacity(specs.len());
+    let mut rest = body;
+    for spec in specs {
+        if rest.is_empty() && spec.optional {
+            break;
+        }
+        if rest.is_empty() {
+            return Err(ArgError::Missing(spec.name));
+        }
+        let size = spec.kind.size().unwrap_or(rest.len());
+        if rest.len() < size {
+            return Err(ArgError::Truncated(spec.name));
+        }
+        let (bytes, remaining) = rest.split_at(size);
+        values.push(spec.decode(bytes)?);
+        rest = remaining;
+    }
+    match rest.len() {
+        0 => Ok(values),
+        n => Err(ArgError::Trailing(n)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ArgType::*;
+
+    #[test]
+    fn test_round_trip() {
+        let specs = [
+            arg("state", Bool),
+            arg("speed", I16).range(-8000, 8000).units("rpm"),
+            arg("time", U32).big_endian(),
+            arg("key", Bytes(2)),
+            arg("offset", I64),
+            arg("note", Str),
+        ];
+        let body = encode_args(&specs, &["on", "-300", "0x01020304", "beef", "-2", "hello", "world"]).unwrap();
+        assert_eq!(body, [&[1, 0xd4, 0xfe, 1, 2, 3, 4, 0xbe, 0xef][..], &[0xfe], &[0xff; 7], b"hello world"].concat());
+        let values = decode_args(&specs, &body).unwrap();
+        assert_eq!(values[0].as_bool(), Some(true));
+        assert_eq!(values[1].int::<i16>(), Some(-300));
+        assert_eq!(values[2].int::<u32>(), Some(0x01020304));
+        assert_eq!(values[3].as_bytes(), Some(&[0xbe, 0xef][..]));
+        assert_eq!(values[4].int::<i64>(), Some(-2));
+        assert_eq!(values[5].as_str(), Some("hello world"));
+    }
+
+    #[test]
+    fn test_encode_errors() {
+        let specs = [arg("count", U8).range(1, 10)];
+        assert_eq!(encode_args(&specs, &[]), Err(ArgError::Missing("count")));
+        assert_eq!(
+            encode_args(&specs, &["11"]),
+            Err(ArgError::OutOfRange { arg: "count", value: 11, min: 1, max: 10 })
+        );
+        assert_eq!(encode_args(&specs, &["one"]), Err(ArgError::Invalid { arg: "count", input: "one".into() }));
+        assert_eq!(encode_args(&specs, &["1", "2"]), Err(ArgError::TooMany(1)));
+        // The type's own bounds apply when there is no explicit range
+        assert!(encode_args(&[arg("x", U8)], &["256"]).is_err());
+        assert!(encode_args(&[arg("x", I8)], &["-129"]).is_err());
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let specs = [arg("action", U8).range(0, 1), arg("speed", U16).optional()];
+        assert_eq!(decode_args(&specs, &[0]).unwrap(), vec![ArgValue::Int(0)]);
+        assert_eq!(decode_args(&specs, &[1, 0x10, 0]).unwrap(), vec![ArgValue::Int(1), ArgValue::Int(16)]);
+        assert_eq!(decode_args(&specs, &[]), Err(ArgError::Missing("action")));
+        assert_eq!(decode_args(&specs, &[1, 0x10]), Err(ArgError::Truncated("speed")));
+        assert_eq!(decode_args(&specs, &[1, 0, 0, 9]), Err(ArgError::Trailing(1)));
+        assert!(matches!(decode_args(&specs, &[2]), Err(ArgError::OutOfRange { .. })));
+        assert!(decode_args(&[arg("state", Bool)], b"1").is_err());
+        assert_eq!(decode_args(&[], &[]).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_usage_text() {
+        assert_eq!(arg("speed", I16).range(-10, 10).units("rpm").to_string(), "<speed: i16 -10..=10 rpm>");
+        assert_eq!(arg("time", U32).optional().to_string(), "[time: u32]");
+    }
+}
@@ -0,0 +1,348 @@
+/*
+Configuration shared by the OBC FSW and the GS: where logs, payload data and the key store go, the
+prefix of the IPC sockets, the interface each handler's subsystem is reached through, and where the GS
+finds the spacecraft.
+
+It is read from the TOML file named by the EX3_CONFIG env var, or else from the first ex3.toml found in
+the working directory or one of its parents, so the stack runs the same from anywhere in the repo. With
+neither, the defaults below are used. Any key can then be overridden by an env var named after its
+section and key, e.g. EX3_PATHS_LOG_DIR or EX3_HANDLERS_DFGM_INTERFACE. The schema, with every key and
+its default, is documented in ex3.toml at the root of the repo.
+
+Relative paths are relative to `root`, which is itself relative to the directory of the config file
+(the working directory if there is none).
+*/
+
+use crate::ports;
+use serde::{Deserialize, Serialize};
+use std::io::{Error as IoError, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Env var naming the config file to use
+pub const CONFIG_ENV: &str = "EX3_CONFIG";
+/// Name of the config file searched for when CONFIG_ENV isn't set
+pub const CONFIG_FILE_NAME: &str = "ex3.toml";
+/// Prefix of the env vars that override config keys
+const ENV_PREFIX: &str = "EX3";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub root: PathBuf,
+    pub paths: Paths,
+    pub ipc: Ipc,
+    pub handlers: Handlers,
+    pub ground_station: GroundStation,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Paths {
+    /// Each program logs to a directory of its own in here
+    pub log_dir: PathBuf,
+    /// Each payload's data is stored in a directory of its own in here
+    pub data_dir: PathBuf,
+    pub key_store: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Ipc {
+    /// Sockets are created at <socket_prefix><socket name>
+    pub socket_prefix: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HandlerConfig {
+    /// Interface spec of the subsystem (see interface::factory)
+    pub interface: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Handlers {
+    pub adcs: HandlerConfig,
+    pub coms: HandlerConfig,
+    pub dfgm: HandlerConfig,
+    pub eps: HandlerConfig,
+    pub gps: HandlerConfig,
+    pub iris: HandlerConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GroundStation {
+    /// Host of the UHF transceiver the cli_ground_station uplinks through
+    pub uhf_host: String,
+    pub uhf_port: u16,
+    pub beacon_port: u16,
+    /// <host>:<port> the GS server sends the dashboard's cmds to
+    pub obc_server: String,
+    /// Bulk downlinks are saved in a directory per component in here
+    pub downlink_dir: PathBuf,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            root: PathBuf::from("."),
+            paths: Paths::default(),
+            ipc: Ipc::default(),
+            handlers: Handlers::default(),
+            ground_station: GroundStation::default(),
+        }
+    }
+}
+
+impl Default for Paths {
+    fn default() -> Self {
+        Paths {
+            log_dir: PathBuf::from("logs"),
+            data_dir: PathBuf::from("data"),
+            key_store: PathBuf::from("link_keys"),
+        }
+    }
+}
+
+impl Default for Ipc {
+    fn default() -> Self {
+        Ipc { socket_prefix: "/tmp/fifo_socket_".to_string() }
+    }
+}
+
+fn sim_tcp(port: u16) -> HandlerConfig {
+    HandlerConfig { interface: format!("tcp://127.0.0.1:{}", port) }
+}
+
+impl Default for Handlers {
+    fn default() -> Self {
+        Handlers {
+            adcs: sim_tcp(ports::SIM_ADCS_PORT),
+            // Responses to UHF cmds are read synchronously, so reads must not block for long
+            coms: HandlerConfig {
+                interface: format!("tcp://127.0.0.1:{}?read_timeout_ms=500", ports::SIM_ESAT_UART_PORT),
+            },
+            dfgm: sim_tcp(ports::SIM_DFGM_PORT),
+            eps: sim_tcp(ports::SIM_EPS_PORT),
+            gps: HandlerConfig { interface: "ipc://gps_device".to_string() },
+            iris: sim_tcp(ports::SIM_IRIS_PORT),
+        }
+    }
+}
+
+impl Default for GroundStation {
+    fn default() -> Self {
+        GroundStation {
+            uhf_host: "localhost".to_string(),
+            uhf_port: ports::SIM_ESAT_UHF_PORT,
+            beacon_port: ports::SIM_ESAT_BEACON_PORT,
+            obc_server: "localhost:50000".to_string(),
+            downlink_dir: PathBuf::from("ex3_ground_station"),
+        }
+    }
+}
+
+fn invalid(reason: String) -> IoError {
+    IoError::new(ErrorKind::InvalidInput, format!("invalid config: {}", reason))
+}
+
+impl Config {
+    /// Load the config file named by CONFIG_ENV, or the nearest ex3.toml, applying env overrides
+    pub fn load() -> Result<Config, IoError> {
+        let file = match std::env::var_os(CONFIG_ENV) {
+            Some(path) => Some(PathBuf::from(path)),
+            None => find_config_file(&std::env::current_dir()?),
+        };
+        let (text, dir) = match file {
+            Some(ref file) => {
+                let text = std::fs::read_to_string(file)
+                    .map_err(|e| IoError::new(e.kind(), format!("cannot read config {:?}: {}", file, e)))?;
+                let dir = file.parent().unwrap_or(Path::new("")).to_path_buf();
+                (text, dir)
+            }
+            None => (String::new(), PathBuf::new()),
+        };
+        Config::parse(&text, &std::env::current_dir()?.join(dir), |name| std::env::var(name).ok())
+    }
+
+    /// Parse a config, overriding keys with the values `env` gives for their env vars. `root` is
+    /// resolved against `dir`
+    pub fn parse<F>(text: &str, dir: &Path, env: F) -> Result<Config, IoError>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let config: Config = toml::from_str(text).map_err(|e| invalid(e.to_string()))?;
+        // Overrides are applied to the complete config, so every key has a value to take the type of
+        let mut table = toml::Table::try_from(&config).map_err(|e| invalid(e.to_string()))?;
+        apply_env_overrides(&mut table, ENV_PREFIX, &env)?;
+        let mut config: Config = table.try_into().map_err(|e: toml::de::Error| invalid(e.to_string()))?;
+        config.root = dir.join(&config.root);
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), IoError> {
+        if !self.ipc.socket_prefix.starts_with('/') {
+            return Err(invalid(format!("ipc.socket_prefix '{}' must be an absolute path", self.ipc.socket_prefix)));
+        }
+        let handlers = [
+            ("adcs", &self.handlers.adcs),
+            ("coms", &self.handlers.coms),
+            ("dfgm", &self.handlers.dfgm),
+            ("eps", &self.handlers.eps),
+            ("gps", &self.handlers.gps),
+            ("iris", &self.handlers.iris),
+        ];
+        // The interface crate checks the rest of the spec when it is opened
+        for (name, handler) in handlers {
+            if !handler.interface.contains("://") {
+                return Err(invalid(format!("handlers.{}.interface '{}' is not an interface spec", name, handler.interface)));
+            }
+        }
+        let gs = &self.ground_station;
+        if gs.uhf_port == 0 || gs.beacon_port == 0 {
+            return Err(invalid("ground_station ports must not be 0".to_string()));
+        }
+        match gs.obc_server.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok_and(|p| p != 0) => (),
+            _ => return Err(invalid(format!("ground_station.obc_server '{}' must be <host>:<port>", gs.obc_server))),
+        }
+        let paths = [("log_dir", &self.paths.log_dir), ("data_dir", &self.paths.data_dir),
+                     ("key_store", &self.paths.key_store), ("downlink_dir", &gs.downlink_dir)];
+        for (name, path) in paths {
+            if path.as_os_str().is_empty() {
+                return Err(invalid(format!("{} must not be empty", name)));
+            }
+        }
+        Ok(())
+    }
+
+    /// `path` relative to the root, unless it is absolute
+    pub fn resolve(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.root.join(path)
+    }
+
+    /// Directory the program `name` logs to
+    pub fn log_dir(&self, name: &str) -> PathBuf {
+        self.resolve(&self.paths.log_dir).join(name)
+    }
+
+    /// Directory the data of the payload `name` is stored in
+    pub fn data_dir(&self, name: &str) -> PathBuf {
+        self.resolve(&self.paths.data_dir).join(name)
+    }
+
+    pub fn key_store(&self) -> PathBuf {
+        self.resolve(&self.paths.key_store)
+    }
+
+    pub fn downlink_dir(&self) -> PathBuf {
+        self.resolve(&self.ground_station.downlink_dir)
+    }
+}
+
+/// The nearest config file in `dir` or its parents
+fn find_config_file(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors().map(|d| d.join(CONFIG_FILE_NAME)).find(|file| file.is_file())
+}
+
+/// Replace every key of `table` that has an env var set, parsing its value as the type the key has
+fn apply_env_overrides<F>(table: &mut toml::Table, prefix: &str, env: &F) -> Result<(), IoError>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    for (key, value) in table.iter_mut() {
+        let name = format!("{}_{}", prefix, key.to_uppercase());
+        if let toml::Value::Table(section) = value {
+            apply_env_overrides(section, &name, env)?;
+            continue;
+        }
+        let Some(text) = env(&name) else {
+            continue;
+        };
+        let bad = |kind: &str| invalid(format!("{}={} must be {}", name, text, kind));
+        *value = match value {
+            toml::Value::String(_) => toml::Value::String(text.clone()),
+            toml::Value::Integer(_) => toml::Value::Integer(text.parse().map_err(|_| bad("an integer"))?),
+            toml::Value::Boolean(_) => toml::Value::Boolean(text.parse().map_err(|_| bad("true or false"))?),
+            toml::Value::Float(_) => toml::Value::Float(text.parse().map_err(|_| bad("a number"))?),
+            _ => return Err(invalid(format!("{} cannot be set from the environment", name))),
+        };
+    }
+    Ok(())
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// The config, loaded the first time it is needed. Panics if it can't be loaded, as nothing can run
+/// with a broken config
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(|| Config::load().unwrap_or_else(|e| panic!("Cannot load config: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn parse(text: &str, env: &[(&str, &str)]) -> Result<Config, IoError> {
+        let env: HashMap<String, String> = env.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        Config::parse(text, Path::new("/flight"), |name| env.get(name).cloned())
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = parse("", &[]).unwrap();
+        assert_eq!(config.root, Path::new("/flight/."));
+        assert_eq!(config.log_dir("dfgm_handler"), Path::new("/flight/./logs/dfgm_handler"));
+        assert_eq!(config.handlers.eps.interface, "tcp://127.0.0.1:1804");
+        assert_eq!(config.ipc.socket_prefix, "/tmp/fifo_socket_");
+    }
+
+    #[test]
+    fn test_repo_config_is_the_defaults() {
+        let text = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/../../ex3.toml")).unwrap();
+        assert_eq!(parse(&text, &[]).unwrap(), parse("", &[]).unwrap());
+    }
+
+    #[test]
+    fn test_file_and_env_overrides() {
+        let text = r#"
+            root = "/data/ex3"
+            [paths]
+            data_dir = "/mnt/sd1/payloads"
+            [handlers.dfgm]
+            interface = "uart:///dev/ttyS1?baud=115200"
+        "#;
+        let env = [("EX3_PATHS_LOG_DIR", "/var/log/ex3"), ("EX3_GROUND_STATION_UHF_PORT", "2000"),
+                   ("EX3_HANDLERS_EPS_INTERFACE", "i2c:///dev/i2c-1@0x40")];
+        let config = parse(text, &env).unwrap();
+        assert_eq!(config.data_dir("dfgm"), Path::new("/mnt/sd1/payloads/dfgm"));
+        assert_eq!(config.log_dir("eps_handler"), Path::new("/var/log/ex3/eps_handler"));
+        assert_eq!(config.key_store(), Path::new("/data/ex3/link_keys"));
+        assert_eq!(config.handlers.dfgm.interface, "uart:///dev/ttyS1?baud=115200");
+        assert_eq!(config.handlers.eps.interface, "i2c:///dev/i2c-1@0x40");
+        assert_eq!(config.handlers.adcs.interface, "tcp://127.0.0.1:1803");
+        assert_eq!(config.ground_station.uhf_port, 2000);
+    }
+
+    #[test]
+    fn test_invalid_configs() {
+        let bad = [
+            ("[paths]\nlogs = \"x\"", vec![]),
+            ("[ipc]\nsocket_prefix = \"fifo_\"", vec![]),
+            ("[handlers.iris]\ninterface = \"127.0.0.1:1806\"", vec![]),
+            ("[handlers.iris]", vec![]),
+            ("[ground_station]\nobc_server = \"localhost\"", vec![]),
+            ("", vec![("EX3_GROUND_STATION_BEACON_PORT", "beacon")]),
+            ("", vec![("EX3_GROUND_STATION_UHF_PORT", "70000")]),
+            ("root = 5", vec![]),
+        ];
+        for (text, env) in bad {
+            let e = parse(text, &env).unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::InvalidInput, "{} {:?}", text, env);
+        }
+    }
+}
@@ -0,0 +1,78 @@
+/*
+Events published on the event bus, for the scheduler to trigger cmds on.
+
+Components publish an event by sending the Scheduler a PublishEvent cmd through the cmd_dispatcher, with
+the event's kind and a value whose meaning depends on the kind. The scheduler is the only subscriber.
+*/
+
+use std::fmt;
+use std::io::{Error as IoError, ErrorKind};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    /// The OBC booted. Raised by the scheduler itself, with the value 0
+    Boot = 0,
+    /// The first uplink of a pass was received by the COMS, with the value 0
+    GsPassStart = 1,
+    /// Nothing has been uplinked for a while, with the length of the pass in s as the value
+    GsPassEnd = 2,
+    /// A battery voltage reading from the EPS, in mV
+    BatteryVoltage = 3,
+    /// The GPS has a fix, with the value 0
+    GpsFix = 4,
+}
+
+pub const EVENT_KINDS: [EventKind; 5] =
+    [EventKind::Boot, EventKind::GsPassStart, EventKind::GsPassEnd, EventKind::BatteryVoltage, EventKind::GpsFix];
+
+impl fmt::Display for EventKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl TryFrom<u8> for EventKind {
+    type Error = IoError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        EVENT_KINDS.get(value as usize).copied()
+            .ok_or_else(|| IoError::new(ErrorKind::InvalidData, format!("invalid event {}", value)))
+    }
+}
+
+/// Parses the name of an event, in any case, or its value
+impl FromStr for EventKind {
+    type Err = IoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(value) = s.parse::<u8>() {
+            return EventKind::try_from(value);
+        }
+        EVENT_KINDS.iter().find(|kind| kind.to_string().eq_ignore_ascii_case(s)).copied()
+            .ok_or_else(|| IoError::new(ErrorKind::InvalidInput, format!("no event named {}", s)))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    pub kind: EventKind,
+    pub value: i32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_kind_names() {
+        for kind in EVENT_KINDS {
+            assert_eq!(EventKind::try_from(kind as u8).unwrap(), kind);
+            assert_eq!(kind.to_string().parse::<EventKind>().unwrap(), kind);
+        }
+        assert_eq!("gspassstart".parse::<EventKind>().unwrap(), EventKind::GsPassStart);
+        assert_eq!("3".parse::<EventKind>().unwrap(), EventKind::BatteryVoltage);
+        assert!("Eclipse".parse::<EventKind>().is_err());
+        assert!(EventKind::try_from(EVENT_KINDS.len() as u8).is_err());
+    }
+}
@@ -5,7 +5,10 @@ use serde_json::{Value, json};
 use std::fs;
 use std::io::{BufReader, BufWriter};
 use crate::component_ids::ComponentIds;
-use chrono::prelude::*;
+use crate::time;
+
+/// Name of the file the OBC's services write their HK to, in their data dir
+pub const HK_FILE: &str = "hk.json";
 
 pub struct HKData {
     json: Value
@@ -13,9 +16,10 @@ pub struct HKData {
 
 impl HKData {
     pub fn new(id: ComponentIds) -> Self {
-        let utc_time = Utc::now().to_string();
+        let utc_time = time::utc_now().to_string();
         let json = json!({
             "TIME": utc_time,
+            "MET": time::met_millis(),
             "SUBSYSTEM": id as u8,
         });
         HKData{json}
@@ -0,0 +1,325 @@
+/*
+Key store for the link crypto, kept on both the OBC and the GS.
+
+It holds a master key and a handful of key slots. Normal traffic is sealed with the key in the active
+slot, while the master key is reserved for the COMS key management commands that install a key in a
+slot or switch the active slot. The store also keeps the link counter (the last counter accepted on the
+OBC, the last counter sent on the GS), so replay protection survives a restart.
+
+It is saved as a text file after every change:
+
+    active <slot>
+    counter <last counter>
+    master <64 hex digits>
+    slot <slot> <64 hex digits>
+    ...
+*/
+
+use crate::link_crypto::{open_msg, seal_msg, CryptoError, LinkKey, OpenedMsg, KEY_SIZE};
+use crate::message_structure::Msg;
+use log::warn;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use std::fs::OpenOptions;
+use std::io::{Error as IoError, ErrorKind, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const KEY_SLOTS: usize = 4;
+/// Key id used in the envelope of msgs sealed with the master key
+pub const MASTER_KEY_ID: u8 = 0xFF;
+
+/// Env var that can point the GS and the OBC at a key store other than the one in the config
+pub const KEY_STORE_ENV: &str = "EX3_KEY_STORE";
+
+/// Generate a new random key
+pub fn random_key() -> [u8; KEY_SIZE] {
+    let mut key = [0u8; KEY_SIZE];
+    OsRng.fill_bytes(&mut key);
+    key
+}
+
+/// Body of a COMS RotateKey command: the slot to install the key in, followed by the key
+pub fn rotate_key_body(slot: u8, key: &[u8; KEY_SIZE]) -> Vec<u8> {
+    let mut body = vec![slot];
+    body.extend_from_slice(key);
+    body
+}
+
+pub fn parse_rotate_key_body(body: &[u8]) -> Result<(u8, [u8; KEY_SIZE]), IoError> {
+    if body.len() != 1 + KEY_SIZE {
+        return Err(IoError::new(
+            ErrorKind::InvalidData,
+            format!("rotate key body must be {} bytes, got {}", 1 + KEY_SIZE, body.len()),
+        ));
+    }
+    let mut key = [0u8; KEY_SIZE];
+    key.copy_from_slice(&body[1..]);
+    Ok((body[0], key))
+}
+
+#[derive(Debug)]
+pub struct KeyStore {
+    path: PathBuf,
+    master: LinkKey,
+    slots: [Option<LinkKey>; KEY_SLOTS],
+    active: u8,
+    counter: u64,
+}
+
+impl KeyStore {
+    /// Create a new store with a random master key and a random key in slot 0, and save it to `path`.
+    /// Used to provision a pair of matching stores for the GS and the OBC.
+    pub fn generate(path: &Path) -> Result<Self, IoError> {
+        let mut slots: [Option<LinkKey>; KEY_SLOTS] = Default::default();
+        slots[0] = Some(LinkKey::new(0, random_key()));
+        let store = KeyStore {
+            path: path.to_path_buf(),
+            master: LinkKey::new(MASTER_KEY_ID, random_key()),
+            slots,
+            active: 0,
+            counter: 0,
+        };
+        store.save()?;
+        Ok(store)
+    }
+
+    /// Path of the store named by `KEY_STORE_ENV`, or the one in the config if it isn't set
+    pub fn default_path() -> PathBuf {
+        std::env::var_os(KEY_STORE_ENV).map(PathBuf::from).unwrap_or_else(|| crate::config::get().key_store())
+    }
+
+    pub fn load_default() -> Result<Self, IoError> {
+        KeyStore::load(&KeyStore::default_path())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, IoError> {
+        let contents = std::fs::read_to_string(path)?;
+        let invalid = |line: &str| IoError::new(ErrorKind::InvalidData, format!("bad key store line '{}'", line));
+
+        let mut master = None;
+        let mut slots: [Option<LinkKey>; KEY_SLOTS] = Default::default();
+        let mut active = None;
+        let mut counter = 0;
+        for line in contents.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields[..] {
+                ["active", slot] => active = Some(slot.parse::<u8>().map_err(|_| invalid(line))?),
+                ["counter", value] => counter = value.parse::<u64>().map_err(|_| invalid(line))?,
+                ["master", key] => master = Some(LinkKey::from_hex(MASTER_KEY_ID, key)?),
+                ["slot", slot, key] => {
+                    let slot = slot.parse::<u8>().map_err(|_| invalid(line))?;
+                    let entry = slots.get_mut(slot as usize).ok_or_else(|| invalid(line))?;
+                    *entry = Some(LinkKey::from_hex(slot, key)?);
+                }
+                _ => return Err(invalid(line)),
+            }
+        }
+
+        let master = master.ok_or_else(|| IoError::new(ErrorKind::InvalidData, "key store has no master key"))?;
+        let active = active.ok_or_else(|| IoError::new(ErrorKind::InvalidData, "key store has no active slot"))?;
+        if slots.get(active as usize).is_none_or(|s| s.is_none()) {
+            return Err(IoError::new(ErrorKind::InvalidData, format!("active slot {} is empty", active)));
+        }
+        Ok(KeyStore { path: path.to_path_buf(), master, slots, active, counter })
+    }
+
+    /// Write the store out. It is written to a temporary file first and renamed over the old one,
+    /// so a reset part way through can't leave a truncated store behind.
+    pub fn save(&self) -> Result<(), IoError> {
+        let mut contents = format!(
+            "active {}\ncounter {}\nmaster {}\n",
+            self.active,
+            self.counter,
+            hex::encode(self.master.bytes())
+        );
+        for key in self.slots.iter().flatten() {
+            contents.push_str(&format!("slot {} {}\n", key.id, hex::encode(key.bytes())));
+        }
+
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let mut file = OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(&tmp_path)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, &self.path)
+    }
+
+    pub fn active_slot(&self) -> u8 {
+        self.active
+    }
+
+    pub fn slot(&self, slot: u8) -> Option<&LinkKey> {
+        self.slots.get(slot as usize).and_then(|s| s.as_ref())
+    }
+
+    /// Install a key in a slot. The active slot can't be overwritten, as that would cut off the link
+    /// if the other end doesn't get the same key.
+    pub fn set_slot(&mut self, slot: u8, key: [u8; KEY_SIZE]) -> Result<(), IoError> {
+        if slot == self.active {
+            return Err(IoError::new(ErrorKind::InvalidInput, format!("slot {} is active", slot)));
+        }
+        let entry = self.slots.get_mut(slot as usize).ok_or_else(|| {
+            IoError::new(ErrorKind::InvalidInput, format!("no key slot {}", slot))
+        })?;
+        *entry = Some(LinkKey::new(slot, key));
+        self.save()
+    }
+
+    /// Make the key in `slot` the one used for normal traffic
+    pub fn select(&mut self, slot: u8) -> Result<(), IoError> {
+        if self.slot(slot).is_none() {
+            return Err(IoError::new(ErrorKind::InvalidInput, format!("key slot {} is empty", slot)));
+        }
+        self.active = slot;
+        self.save()
+    }
+
+    /// Counter for the next msg sent. It never goes below the current time in microseconds, so the GS
+    /// stays ahead of the OBC even if its store is restored from an old copy.
+    fn next_counter(&mut self) -> Result<u64, IoError> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as u64;
+        self.counter = (self.counter + 1).max(now);
+        self.save()?;
+        Ok(self.counter)
+    }
+
+    /// Seal a msg with the active key
+    pub fn seal(&mut self, msg: Msg) -> Result<Msg, IoError> {
+        let counter = self.next_counter()?;
+        let key = self.slots[self.active as usize].as_ref().expect("active slot is never empty");
+        Ok(seal_msg(key, counter, msg))
+    }
+
+    /// Seal a key management msg with the master key
+    pub fn seal_with_master(&mut self, msg: Msg) -> Result<Msg, IoError> {
+        let counter = self.next_counter()?;
+        Ok(seal_msg(&self.master, counter, msg))
+    }
+
+    /// Authenticate and decrypt a msg sealed with the active key or the master key, and reject replays.
+    /// The caller decides what a msg sealed with the master key may do, using `OpenedMsg::key_id`.
+    pub fn open(&mut self, msg: Msg) -> Result<OpenedMsg, CryptoError> {
+        let active = self.active;
+        let opened = open_msg(
+            |id| match id {
+                MASTER_KEY_ID => Some(self.master.clone()),
+                id if id == active => self.slot(id).cloned(),
+                _ => None,
+            },
+            msg,
+        )?;
+        if opened.counter <= self.counter {
+            return Err(CryptoError::Replayed { counter: opened.counter, last: self.counter });
+        }
+        self.counter = opened.counter;
+        if let Err(e) = self.save() {
+            warn!("Failed to save link counter: {}", e);
+        }
+        Ok(opened)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_structure::MsgType;
+
+    fn cmd() -> Msg {
+        Msg::new(MsgType::Cmd, 5, 10, 7, 1, b"echo hi".to_vec())
+    }
+
+    /// A GS and an OBC store provisioned with the same keys
+    fn pair(dir: &Path) -> (KeyStore, KeyStore) {
+        let gs = KeyStore::generate(&dir.join("gs_keys")).unwrap();
+        std::fs::copy(dir.join("gs_keys"), dir.join("obc_keys")).unwrap();
+        (gs, KeyStore::load(&dir.join("obc_keys")).unwrap())
+    }
+
+    #[test]
+    fn test_save_load_round_trip() {
+        let dir = tempdir::TempDir::new("key_store").unwrap();
+        let mut store = KeyStore::generate(&dir.path().join("keys")).unwrap();
+        store.set_slot(2, [7; KEY_SIZE]).unwrap();
+        store.select(2).unwrap();
+        store.seal(cmd()).unwrap();
+
+        let loaded = KeyStore::load(&dir.path().join("keys")).unwrap();
+        assert_eq!(loaded.active_slot(), 2);
+        assert_eq!(loaded.slot(2), Some(&LinkKey::new(2, [7; KEY_SIZE])));
+        assert_eq!(loaded.slot(0), store.slot(0));
+        assert_eq!(loaded.slot(1), None);
+        assert_eq!(loaded.master, store.master);
+        assert_eq!(loaded.counter, store.counter);
+    }
+
+    #[test]
+    fn test_invalid_stores_rejected() {
+        let dir = tempdir::TempDir::new("key_store").unwrap();
+        let path = dir.path().join("keys");
+        let key = "11".repeat(KEY_SIZE);
+
+        std::fs::write(&path, format!("active 0\nslot 0 {}\n", key)).unwrap();
+        assert!(KeyStore::load(&path).is_err());
+        std::fs::write(&path, format!("active 1\nmaster {}\nslot 0 {}\n", key, key)).unwrap();
+        assert!(KeyStore::load(&path).is_err());
+        std::fs::write(&path, format!("active 0\nmaster {}\nslot 9 {}\n", key, key)).unwrap();
+        assert!(KeyStore::load(&path).is_err());
+        std::fs::write(&path, format!("active 0\nmaster {}\nslot 0 {}\n", key, key)).unwrap();
+        assert!(KeyStore::load(&path).is_ok());
+    }
+
+    #[test]
+    fn test_slot_changes() {
+        let dir = tempdir::TempDir::new("key_store").unwrap();
+        let mut store = KeyStore::generate(&dir.path().join("keys")).unwrap();
+        assert!(store.set_slot(0, [1; KEY_SIZE]).is_err());
+        assert!(store.set_slot(KEY_SLOTS as u8, [1; KEY_SIZE]).is_err());
+        assert!(store.select(1).is_err());
+        assert_eq!(store.active_slot(), 0);
+    }
+
+    #[test]
+    fn test_open_active_and_master_only() {
+        let dir = tempdir::TempDir::new("key_store").unwrap();
+        let (mut gs, mut obc) = pair(dir.path());
+
+        let opened = obc.open(gs.seal(cmd()).unwrap()).unwrap();
+        assert_eq!((opened.key_id, opened.msg.msg_body.as_slice()), (0, &b"echo hi"[..]));
+        let opened = obc.open(gs.seal_with_master(cmd()).unwrap()).unwrap();
+        assert_eq!(opened.key_id, MASTER_KEY_ID);
+
+        // A key the OBC holds but isn't active isn't accepted
+        gs.set_slot(1, [3; KEY_SIZE]).unwrap();
+        obc.set_slot(1, [3; KEY_SIZE]).unwrap();
+        gs.select(1).unwrap();
+        assert_eq!(obc.open(gs.seal(cmd()).unwrap()).err(), Some(CryptoError::UnknownKey(1)));
+        obc.select(1).unwrap();
+        assert!(obc.open(gs.seal(cmd()).unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_replay_rejected_across_restart() {
+        let dir = tempdir::TempDir::new("key_store").unwrap();
+        let (mut gs, mut obc) = pair(dir.path());
+
+        let first = gs.seal(cmd()).unwrap();
+        let second = gs.seal(cmd()).unwrap();
+        assert!(obc.open(second.clone()).is_ok());
+        assert!(matches!(obc.open(second.clone()), Err(CryptoError::Replayed { .. })));
+        assert!(matches!(obc.open(first), Err(CryptoError::Replayed { .. })));
+
+        // The counter is persisted, so a restarted OBC still refuses the replay
+        let mut restarted = KeyStore::load(&dir.path().join("obc_keys")).unwrap();
+        assert!(matches!(restarted.open(second), Err(CryptoError::Replayed { .. })));
+        assert!(restarted.open(gs.seal(cmd()).unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_rotate_key_body() {
+        let body = rotate_key_body(3, &[9; KEY_SIZE]);
+        assert_eq!(parse_rotate_key_body(&body).unwrap(), (3, [9; KEY_SIZE]));
+        assert!(parse_rotate_key_body(&body[..10]).is_err());
+    }
+}
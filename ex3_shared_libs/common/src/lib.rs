@@ -1,9 +1,19 @@
-pub mod component_ids;
+pub mod cmd_args;
+pub mod registry;
+pub use registry::{component_ids, opcodes};
 pub use component_ids::ComponentIds;
 pub mod message_structure;
+pub mod msg_id;
+pub mod link_crypto;
+pub mod key_store;
 pub mod bulk_msg_slicing;
 pub mod logging;
 pub mod house_keeping;
+pub mod config;
+pub mod events;
+pub mod schedule;
+pub mod sequence;
+pub mod time;
 
 /// Ports used for communication between handlers and simulated subsystems / payloads
 pub mod ports {
@@ -25,175 +35,15 @@ pub mod ports {
 pub mod constants {
     pub const UHF_MAX_MESSAGE_SIZE_BYTES: usize = 128;
 
-    pub const DOWNLINK_MSG_BODY_SIZE: usize = UHF_MAX_MESSAGE_SIZE_BYTES - crate::message_structure::HEADER_SIZE;
-}
-
-/// Here opcodes and their associated meaning are defined for each component
-/// This is in common lib because components will need to know what opcodes to use when sending messages to other components
-/// For example if a message is sent to the OBC to get housekeeping data,
-pub mod opcodes {
-    pub enum COMS {
-        GetHK = 3,
-        SetBeacon = 4,
-        GetBeacon = 5,
-        Error = 6,
-    }
-    pub enum EPS {
-        On = 1,
-        Off = 2,
-        GetHK = 3,
-        Reset = 7,
-        Error = 99,
-    }
-    pub enum DFGM {
-        ToggleDataCollection = 0,
-        Error = 99,
-    }
+    /// CRC carried by every frame crossing the UHF link, in both directions
+    pub const LINK_CRC: crate::message_structure::CrcKind = crate::message_structure::CrcKind::Crc32;
 
-    // For IRIS subsystem
-    pub enum IRIS {
-        CaptureImage = 0,
-        ToggleSensor = 1,
-        FetchImage = 2,
-        GetHK = 3,
-        GetNImagesAvailable = 4,
-        SetTime = 5,
-        GetTime = 6,
-        Reset = 7,
-        DelImage = 8,
-        GetImageSize = 9,
-        Error = 99,
-    }
+    pub const DOWNLINK_MSG_BODY_SIZE: usize =
+        UHF_MAX_MESSAGE_SIZE_BYTES - crate::message_structure::HEADER_SIZE - LINK_CRC.size();
 
-    pub enum UHF {
-        GetHK = 3,
-        SetBeacon = 4,
-        GetBeacon = 5,
-        SetMode = 6,
-        Reset = 7,
-        GetMode = 8,
-        Error = 99,
-    }
-
-    impl From<u8> for COMS {
-        fn from(value: u8) -> Self {
-            match value {
-                3 => COMS::GetHK,
-                4 => COMS::SetBeacon,
-                5 => COMS::GetBeacon,
-                _ => {
-                    COMS::Error // or choose a default value or handle the error in a different way
-                }
-            }
-        }
-    }
-
-    impl From<u8> for DFGM {
-        fn from(value: u8) -> Self {
-            match value {
-                0 => DFGM::ToggleDataCollection,
-                _ => {
-                    DFGM::Error // or choose a default value or handle the error in a different way
-                }
-            }
-        }
-    }
-
-    impl From<u8> for EPS {
-        fn from(value: u8) -> Self {
-            match value {
-                1 => EPS::On,
-                2 => EPS::Off,
-                3 => EPS::GetHK,
-                7 => EPS::Reset,
-                _ => EPS::Error,
-            }
-        }
-    }
-
-    impl From<u8> for IRIS {
-        fn from(value: u8) -> Self {
-            match value {
-                0 => IRIS::CaptureImage,
-                1 => IRIS::ToggleSensor,
-                2 => IRIS::FetchImage,
-                3 => IRIS::GetHK,
-                4 => IRIS::GetNImagesAvailable,
-                5 => IRIS::SetTime,
-                6 => IRIS::GetTime,
-                7 => IRIS::Reset,
-                8 => IRIS::DelImage,
-                9 => IRIS::GetImageSize,
-                _ => {
-                    IRIS::Error // or choose a default value or handle the error in a different way
-                }
-            }
-        }
-    }
-
-    impl From<u8> for UHF {
-        fn from(value: u8) -> Self {
-            match value {
-                3 => UHF::GetHK,
-                4 => UHF::SetBeacon,
-                5 => UHF::GetBeacon,
-                6 => UHF::SetMode,
-                7 => UHF::Reset,
-                8 => UHF::GetMode,
-                _ => {
-                    UHF::Error // or choose a default value or handle the error in a different way
-                }
-            }
-        }
-    }
-
-    pub enum ADCS {
-        Detumble = 0,
-        OnOff = 1,
-        WheelSpeed = 2,
-        GetHk = 3,
-        MagnetorquerCurrent = 4,
-        OnboardTime = 5,
-        GetOrientation = 6,
-        Reset = 7,
-        OrientToSBand = 9,
-        Error = 99,
-    }
-    impl From<u8> for ADCS {
-        fn from(value: u8) -> Self {
-            match value {
-                0 => ADCS::Detumble,
-                1 => ADCS::OnOff,
-                2 => ADCS::WheelSpeed,
-                3 => ADCS::GetHk,
-                4 => ADCS::MagnetorquerCurrent,
-                5 => ADCS::OnboardTime,
-                6 => ADCS::GetOrientation,
-                7 => ADCS::Reset,
-                9 => ADCS::OrientToSBand,
-                _ => {
-                    eprintln!("Invalid opcode: {}", value);
-                    ADCS::Error
-                }
-            }
-        }
-    }
-    impl std::fmt::Display for ADCS {
-        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-            match *self {
-                ADCS::Detumble => write!(f, "Detumble"),
-                ADCS::OnOff => write!(f, "On/Off"),
-                ADCS::WheelSpeed => write!(f, "Wheel Speed"),
-                ADCS::GetHk => write!(f, "Get Housekeeping"),
-                ADCS::MagnetorquerCurrent => write!(f, "Magnetorquer Current"),
-                ADCS::OnboardTime => write!(f, "Onboard Time"),
-                ADCS::Reset => write!(f, "Reset"),
-                ADCS::GetOrientation => write!(f, "Get Orientation"),
-                ADCS::OrientToSBand => write!(f, "Orient to S-Band"),
-                ADCS::Error => write!(f, "INVALID OPCODE"),
-            }
-        }
-    }
+    /// Size of the 4KB packets bulk data is sliced into by the bulk_msg_dispatcher, before they are
+    /// sliced again to fit in UHF msgs
+    pub const BULK_PACKET_SIZE_BYTES: usize = 4096;
 }
 
 #[cfg(test)]
@@ -17,18 +17,36 @@ TODOs:
 
 */
 
-use log::{LevelFilter};
+use chrono::SecondsFormat;
+use log::{LevelFilter, Record};
 use log4rs::filter::threshold::ThresholdFilter;
 use log4rs::{
     append::console::ConsoleAppender,
     append::rolling_file::RollingFileAppender,
     config::{Appender, Config, Logger, Root},
-    encode::pattern::PatternEncoder,
+    encode::{pattern::PatternEncoder, Encode, Write},
 };
 use log4rs::append::rolling_file::policy::compound::{
     CompoundPolicy, roll::fixed_window::FixedWindowRoller, trigger::size::SizeTrigger,
 };
 
+/// Stamps each line with the onboard time (see crate::time) rather than the system clock's
+#[derive(Debug)]
+struct OnboardTimeEncoder(PatternEncoder);
+
+impl OnboardTimeEncoder {
+    fn new(pattern: &str) -> Self {
+        OnboardTimeEncoder(PatternEncoder::new(pattern))
+    }
+}
+
+impl Encode for OnboardTimeEncoder {
+    fn encode(&self, w: &mut dyn Write, record: &Record) -> anyhow::Result<()> {
+        write!(w, "{} - ", crate::time::utc_now().to_rfc3339_opts(SecondsFormat::Millis, true))?;
+        self.0.encode(w, record)
+    }
+}
+
 fn configure_logger(
     all_log_level: LevelFilter,
     filtered_log_level: LevelFilter,
@@ -52,7 +70,7 @@ fn configure_logger(
     // Create a rolling file appender for all logs
     let all_log_file = format!("{}/all_logs.log", log_path);
     let all_file = RollingFileAppender::builder()
-        .encoder(Box::new(PatternEncoder::new("{d} - {l} - {m}{n}")))
+        .encoder(Box::new(OnboardTimeEncoder::new("{l} - {m}{n}")))
         .build(all_log_file, Box::new(all_policy))
         .unwrap();
 
@@ -69,7 +87,7 @@ fn configure_logger(
     // Create a rolling file appender for warning and error logs
     let filtered_log_file = format!("{}/error_and_warning_logs.log", log_path);
     let filtered_file = RollingFileAppender::builder()
-        .encoder(Box::new(PatternEncoder::new("{d} - {l} - {m}{n}")))
+        .encoder(Box::new(OnboardTimeEncoder::new("{l} - {m}{n}")))
         .build(filtered_log_file, Box::new(filtered_policy))
         .unwrap();
 
@@ -114,8 +132,12 @@ pub fn init_logger(log_path: &str) {
     let _handle = log4rs::init_config(config).unwrap();
 }
 
-#[cfg(test)]
+/// Log to the directory the config gives the program `name` (see config::Config::log_dir)
+pub fn init_program_logger(name: &str) {
+    init_logger(&crate::config::get().log_dir(name).to_string_lossy());
+}
 
+#[cfg(test)]
 mod tests {
     use log::{debug, error, info, trace, warn};
 
@@ -10,246 +10,288 @@ References:
     - https://crates.io/crates/serde-pickle
 */
 use crate::component_ids::ComponentIds;
+use crate::constants::DOWNLINK_MSG_BODY_SIZE;
+use crc::{Crc, CRC_16_IBM_3740, CRC_32_ISO_HDLC};
 use std::fmt;
 use std::io::Error as IoError;
 
-//TODO - add ref to common component id
+/// Version of the frame layout produced by `serialize_msg`. Bump this whenever the header layout changes
+/// so that old and new software on either end of the link can tell the frames apart.
+pub const MSG_VERSION: u8 = 2;
 
-/// Used when passing messages between around - between components and between GS and SC
-pub trait SerializeAndDeserialize {
-    fn serialize_to_bytes(&self) -> Vec<u8>;
-    fn deserialize_from_bytes(byte_vec: &[u8]) -> Self
-    where
-        Self: Sized; //Must be sized so compiler can allocated enough space for instances of this type on the stack
+/// Size of the serialized header, in bytes:
+///
+/// | offset | size | field      |
+/// |--------|------|------------|
+/// | 0      | 1    | version    |
+/// | 1      | 1    | flags      |
+/// | 2      | 1    | msg_type   |
+/// | 3      | 2    | msg_id     |
+/// | 5      | 1    | dest_id    |
+/// | 6      | 1    | source_id  |
+/// | 7      | 1    | op_code    |
+/// | 8      | 2    | msg_len    |
+///
+/// All multi-byte fields are little-endian. `msg_len` is the length of the header plus the body.
+/// If the flags select a CRC, the CRC of the header and body follows the body as a trailer and is
+/// NOT counted in `msg_len`.
+pub const HEADER_SIZE: usize = 10;
+
+/// Largest body a msg can carry, as `msg_len` counts the header and body in a u16
+pub const MAX_MSG_BODY_SIZE: usize = u16::MAX as usize - HEADER_SIZE;
+
+/// Largest trailer a frame can carry, so receive buffers can be sized for a full frame
+pub const MAX_CRC_SIZE: usize = 4;
+
+const FLAGS_CRC_MASK: u8 = 0b0000_0011;
+
+/// CRC-16/CCITT-FALSE, the same CRC used by CCSDS transfer frames
+const CRC16: Crc<u16> = Crc::<u16>::new(&CRC_16_IBM_3740);
+const CRC32: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// CRC-32 of `bytes`, as in a Crc32 trailer. Also checks data that is uploaded over several msgs
+pub fn crc32(bytes: &[u8]) -> u32 {
+    CRC32.checksum(bytes)
 }
 
-#[derive(Debug, Clone, Copy)]
-pub enum MsgType {
-    Cmd = 0,
-    Ack = 1,
-    Bulk = 2,
-    //.. Scheduled msg?
+/// Optional CRC trailer appended to a serialized frame. Anything crossing the radio link should carry one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrcKind {
+    None = 0,
+    Crc16 = 1,
+    Crc32 = 2,
 }
 
-impl fmt::Display for MsgType {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            MsgType::Cmd => write!(f, "Cmd"),
-            MsgType::Ack => write!(f, "Ack"),
-            MsgType::Bulk => write!(f, "Bulk"),
+impl CrcKind {
+    /// Number of trailer bytes this CRC adds to a frame
+    pub const fn size(&self) -> usize {
+        match self {
+            CrcKind::None => 0,
+            CrcKind::Crc16 => 2,
+            CrcKind::Crc32 => 4,
         }
     }
-}
 
-//Convert byte equivalent value to MsgType enum
-impl From<u8> for MsgType {
-    fn from(byte_val: u8) -> Self {
-        match byte_val {
-            0 => MsgType::Cmd,
-            1 => MsgType::Ack,
-            2 => MsgType::Bulk,
-            _ => panic!("Invalid MsgType byte value"),
+    fn from_flags(flags: u8) -> Result<Self, DecodeError> {
+        match flags & FLAGS_CRC_MASK {
+            0 => Ok(CrcKind::None),
+            1 => Ok(CrcKind::Crc16),
+            2 => Ok(CrcKind::Crc32),
+            _ => Err(DecodeError::InvalidFlags(flags)),
         }
     }
-}
 
-//EVERY message should have this header - they all need an id, a dest, and a source
-#[derive(Debug, Clone, Copy)]
-pub struct MsgHeaderNew {
-    pub msg_id: u16, // hold up to ~64 thousand unique ids before rollover
-    pub msg_type: MsgType,
-    pub dest_id: u8,
-    pub source_id: u8,
-}
-impl MsgHeaderNew {
-    pub const DEST_INDEX: usize = 3;
-
-    pub fn new(msg_id: u16, msg_type: MsgType, dest_id: u8, source_id: u8) -> Self {
-        MsgHeaderNew {
-            msg_id,
-            msg_type,
-            dest_id,
-            source_id,
+    /// CRC of `bytes` as little-endian trailer bytes
+    fn checksum(&self, bytes: &[u8]) -> Vec<u8> {
+        match self {
+            CrcKind::None => vec![],
+            CrcKind::Crc16 => CRC16.checksum(bytes).to_le_bytes().to_vec(),
+            CrcKind::Crc32 => CRC32.checksum(bytes).to_le_bytes().to_vec(),
         }
     }
 }
 
-/// Use the ComponentId enum to display the source and destination ids actual name
-impl fmt::Display for MsgHeaderNew {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "MsgId: {},\n\tMsgType: {},\n\tDestId: {},\n\tSourceId: {}",
-            self.msg_id,
-            self.msg_type,
-            ComponentIds::try_from(self.dest_id).unwrap(),
-            ComponentIds::try_from(self.source_id).unwrap(),
-        )
-    }
+/// Reasons a byte slice could not be decoded into a `Msg`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// Fewer bytes were provided than the header or `msg_len` field requires
+    Truncated { expected: usize, actual: usize },
+    /// The version byte does not match a frame layout this software understands
+    UnsupportedVersion(u8),
+    /// The msg_type byte does not map to a `MsgType`
+    InvalidMsgType(u8),
+    /// The msg_len field is smaller than the header itself
+    InvalidLength(u16),
+    /// The op_code of an Ack does not map to an `AckCode`
+    InvalidAckCode(u8),
+    /// The op_code of a Report does not map to a `ResultCode`
+    InvalidResultCode(u8),
+    /// The flags byte has bits set that this software does not understand
+    InvalidFlags(u8),
+    /// The frame was required to carry a CRC but did not
+    MissingCrc,
+    /// The CRC trailer does not match the header and body - the frame was corrupted in transit
+    CrcMismatch { expected: u32, actual: u32 },
+    /// The frame was required to be exactly one msg long but had extra bytes
+    LengthMismatch { expected: usize, actual: usize },
 }
 
-impl SerializeAndDeserialize for MsgHeaderNew {
-    fn serialize_to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        bytes.extend_from_slice(&self.msg_id.to_be_bytes());
-        bytes.push(self.msg_type as u8);
-        bytes.push(self.dest_id);
-        bytes.push(self.source_id);
-        bytes
-    }
-    fn deserialize_from_bytes(serialized_bytes_slice: &[u8]) -> Self {
-        MsgHeaderNew {
-            msg_id: u16::from_be_bytes([serialized_bytes_slice[0], serialized_bytes_slice[1]]),
-            msg_type: serialized_bytes_slice[2].into(),
-            dest_id: serialized_bytes_slice[MsgHeaderNew::DEST_INDEX],
-            source_id: serialized_bytes_slice[4],
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecodeError::Truncated { expected, actual } => {
+                write!(f, "frame truncated: expected {} bytes, got {}", expected, actual)
+            }
+            DecodeError::UnsupportedVersion(v) => write!(f, "unsupported frame version {}", v),
+            DecodeError::InvalidMsgType(t) => write!(f, "invalid msg type {}", t),
+            DecodeError::InvalidLength(l) => write!(f, "invalid msg length {}", l),
+            DecodeError::InvalidAckCode(c) => write!(f, "invalid ack code {}", c),
+            DecodeError::InvalidResultCode(c) => write!(f, "invalid result code {}", c),
+            DecodeError::InvalidFlags(flags) => write!(f, "invalid flags {:#04x}", flags),
+            DecodeError::MissingCrc => write!(f, "frame has no CRC"),
+            DecodeError::CrcMismatch { expected, actual } => {
+                write!(f, "CRC mismatch: frame carries {:#x}, computed {:#x}", expected, actual)
+            }
+            DecodeError::LengthMismatch { expected, actual } => {
+                write!(f, "frame length mismatch: expected {} bytes, got {}", expected, actual)
+            }
         }
     }
 }
 
-/// Command a handler or other OBC FSW component to do something
-pub struct CmdMsg {
-    pub header: MsgHeaderNew,
-    pub opcode: u8,
-    pub data: Vec<u8>,
-}
-impl CmdMsg {
-    pub fn new(msg_id: u16, dest_id: u8, source_id: u8, opcode: u8, data: Vec<u8>) -> Self {
-        let header = MsgHeaderNew::new(msg_id, MsgType::Cmd, dest_id, source_id);
-        CmdMsg {
-            header,
-            opcode,
-            data,
-        }
+impl std::error::Error for DecodeError {}
+
+/// Lets callers that work in terms of `std::io::Error` keep using `?` on the decoder
+impl From<DecodeError> for IoError {
+    fn from(err: DecodeError) -> Self {
+        IoError::new(std::io::ErrorKind::InvalidData, err)
     }
 }
 
-// TODO - print associated opcode name
-impl fmt::Display for CmdMsg {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MsgType {
+    Cmd = 0,
+    Ack = 1,
+    Bulk = 2,
+    /// Sent by the handler that executed a cmd once it is done, see `ResultCode`
+    Report = 3,
+    //.. Scheduled msg?
+}
+
+impl fmt::Display for MsgType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "CmdMsg:\nHeader: {}, \nOpcode: {}, \nData: {:?}",
-            self.header, self.opcode, self.data
-        )
+        match *self {
+            MsgType::Cmd => write!(f, "Cmd"),
+            MsgType::Ack => write!(f, "Ack"),
+            MsgType::Bulk => write!(f, "Bulk"),
+            MsgType::Report => write!(f, "Report"),
+        }
     }
 }
 
-impl SerializeAndDeserialize for CmdMsg {
-    fn serialize_to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        bytes.extend_from_slice(&self.header.serialize_to_bytes());
-        bytes.push(self.opcode);
-        bytes.extend_from_slice(&self.data);
-        bytes
-    }
-    fn deserialize_from_bytes(byte_vec: &[u8]) -> Self
-    where
-        Self: Sized,
-    {
-        let header = MsgHeaderNew::deserialize_from_bytes(&byte_vec[0..5]);
-        let opcode = byte_vec[5];
-        let data = byte_vec[6..].to_vec();
-        CmdMsg {
-            header,
-            opcode,
-            data,
+//Convert byte equivalent value to MsgType enum
+impl TryFrom<u8> for MsgType {
+    type Error = DecodeError;
+
+    fn try_from(byte_val: u8) -> Result<Self, Self::Error> {
+        match byte_val {
+            0 => Ok(MsgType::Cmd),
+            1 => Ok(MsgType::Ack),
+            2 => Ok(MsgType::Bulk),
+            3 => Ok(MsgType::Report),
+            _ => Err(DecodeError::InvalidMsgType(byte_val)),
         }
     }
 }
 
-/// Inform a sender of a message that the message was received and processed successfully
-/// - This DOES NOT indicate the command was successful, just that the message was received and processed
+/// Carried in the op_code field of an Ack msg.
+/// An Ack informs the sender of a message that the message was received and processed
+/// - This DOES NOT indicate the command was successful, just that the message was received and processed.
+///   Whether it succeeded is sent afterwards in a Report from the handler that executed it
 ///
-/// The ACK will have the same ID as the CmdMsg it's responding to
-pub struct AckMsg {
-    header: MsgHeaderNew,
-    ack_code: AckCode,     // Success or Failure
-    context_data: Vec<u8>, // Context as to what failed / why
+/// The ACK will have the same ID as the msg it's responding to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AckCode {
+    Success = 0,
+    Failed = 1,
+    /// The msg failed its CRC check, so it was dropped instead of being processed
+    Corrupted = 2,
+    /// The msg did not authenticate against the link key, so it was dropped
+    AuthFailed = 3,
+    /// The msg authenticated but its counter was already used, so it was dropped as a replay
+    Replayed = 4,
 }
-impl AckMsg {
-    pub fn new(
-        msg_id: u16,
-        dest_id: u8,
-        source_id: u8,
-        ack_code: AckCode,
-        context_data: Vec<u8>,
-    ) -> Self {
-        let header = MsgHeaderNew::new(msg_id, MsgType::Ack, dest_id, source_id);
-        AckMsg {
-            header,
-            ack_code,
-            context_data,
+
+impl TryFrom<u8> for AckCode {
+    type Error = DecodeError;
+
+    fn try_from(byte_val: u8) -> Result<Self, Self::Error> {
+        match byte_val {
+            0 => Ok(AckCode::Success),
+            1 => Ok(AckCode::Failed),
+            2 => Ok(AckCode::Corrupted),
+            3 => Ok(AckCode::AuthFailed),
+            4 => Ok(AckCode::Replayed),
+            _ => Err(DecodeError::InvalidAckCode(byte_val)),
         }
     }
 }
 
-impl fmt::Display for AckMsg {
+impl fmt::Display for AckCode {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "AckMsg: \nHeader: {}, \nAckCode: {}, \nContextData: {:?}",
-            self.header, self.ack_code, self.context_data
-        )
-    }
-}
-
-impl SerializeAndDeserialize for AckMsg {
-    fn serialize_to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        bytes.extend_from_slice(&self.header.serialize_to_bytes());
-        bytes.push(self.ack_code as u8);
-        bytes.extend_from_slice(&self.context_data);
-        bytes
-    }
-    fn deserialize_from_bytes(byte_vec: &[u8]) -> Self
-    where
-        Self: Sized,
-    {
-        let header = MsgHeaderNew::deserialize_from_bytes(&byte_vec[0..5]);
-        let ack_code = AckCode::from(byte_vec[5]);
-        let context_data = byte_vec[6..].to_vec();
-        AckMsg {
-            header,
-            ack_code,
-            context_data,
+        match *self {
+            AckCode::Success => write!(f, "Success"),
+            AckCode::Failed => write!(f, "Failed"),
+            AckCode::Corrupted => write!(f, "Corrupted"),
+            AckCode::AuthFailed => write!(f, "AuthFailed"),
+            AckCode::Replayed => write!(f, "Replayed"),
         }
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub enum AckCode {
+/// Carried in the op_code field of a Report msg: the outcome of executing a cmd.
+/// Commands are reported on in two stages. The coms_handler Acks a cmd when it accepts it from the link,
+/// and the handler it was destined for sends a Report once it has executed it.
+///
+/// The Report will have the same ID as the cmd it's reporting on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResultCode {
     Success = 0,
     Failed = 1,
+    /// The cmd body did not match the opcode's arguments
+    InvalidArgs = 2,
+    /// The handler does not implement the opcode yet
+    NotImplemented = 3,
+    /// The peripheral needed to execute the cmd is not connected
+    Unavailable = 4,
+}
+
+impl ResultCode {
+    /// The result code to report for a cmd that failed with `err`
+    pub fn from_io_error(err: &IoError) -> Self {
+        match err.kind() {
+            std::io::ErrorKind::InvalidData | std::io::ErrorKind::InvalidInput => ResultCode::InvalidArgs,
+            std::io::ErrorKind::Unsupported => ResultCode::NotImplemented,
+            std::io::ErrorKind::NotConnected => ResultCode::Unavailable,
+            _ => ResultCode::Failed,
+        }
+    }
 }
 
-impl From<u8> for AckCode {
-    fn from(byte_val: u8) -> Self {
+impl TryFrom<u8> for ResultCode {
+    type Error = DecodeError;
+
+    fn try_from(byte_val: u8) -> Result<Self, Self::Error> {
         match byte_val {
-            0 => AckCode::Success,
-            1 => AckCode::Failed,
-            _ => panic!("Invalid AckCode byte value"),
+            0 => Ok(ResultCode::Success),
+            1 => Ok(ResultCode::Failed),
+            2 => Ok(ResultCode::InvalidArgs),
+            3 => Ok(ResultCode::NotImplemented),
+            4 => Ok(ResultCode::Unavailable),
+            _ => Err(DecodeError::InvalidResultCode(byte_val)),
         }
     }
 }
 
-impl fmt::Display for AckCode {
+impl fmt::Display for ResultCode {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            AckCode::Success => write!(f, "Success"),
-            AckCode::Failed => write!(f, "Failed"),
+            ResultCode::Success => write!(f, "Success"),
+            ResultCode::Failed => write!(f, "Failed"),
+            ResultCode::InvalidArgs => write!(f, "InvalidArgs"),
+            ResultCode::NotImplemented => write!(f, "NotImplemented"),
+            ResultCode::Unavailable => write!(f, "Unavailable"),
         }
     }
 }
 
-// ---------------------------------------------------------------------
-pub const HEADER_SIZE: usize = 8;
-
 /// This message header is shared by all message types
 #[derive(Debug, Clone)]
 pub struct MsgHeader {
+    pub version: u8,
+    pub crc: CrcKind,
+    pub msg_type: MsgType,
     pub msg_id: u16,
-    pub msg_type: u8,
     pub dest_id: u8,
     pub source_id: u8,
     pub op_code: u8,
@@ -257,35 +299,66 @@ pub struct MsgHeader {
 }
 
 impl MsgHeader {
-    pub const DEST_INDEX: usize = 3;
+    pub const DEST_INDEX: usize = 5;
 
-    fn to_bytes(&self) -> Result<Vec<u8>, IoError> {
-        let mut bytes: Vec<u8> = self.msg_id.to_le_bytes().to_vec();
-        let tmp = vec![self.msg_type, self.dest_id, self.source_id, self.op_code];
-        bytes.extend(tmp);
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::with_capacity(HEADER_SIZE);
+        bytes.push(self.version);
+        bytes.push(self.crc as u8);
+        bytes.push(self.msg_type as u8);
+        bytes.extend(self.msg_id.to_le_bytes());
+        bytes.extend([self.dest_id, self.source_id, self.op_code]);
         bytes.extend(self.msg_len.to_le_bytes());
-        Ok(bytes)
+        bytes
     }
 
-    fn from_bytes(bytes: &[u8]) -> Result<Self, IoError> {
+    fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
         if bytes.len() < HEADER_SIZE {
-            return Err(IoError::new(
-                std::io::ErrorKind::InvalidData,
-                "Header bytes too short",
-            ));
+            return Err(DecodeError::Truncated {
+                expected: HEADER_SIZE,
+                actual: bytes.len(),
+            });
+        }
+        if bytes[0] != MSG_VERSION {
+            return Err(DecodeError::UnsupportedVersion(bytes[0]));
+        }
+
+        if bytes[1] & !FLAGS_CRC_MASK != 0 {
+            return Err(DecodeError::InvalidFlags(bytes[1]));
         }
 
         Ok(MsgHeader {
-            msg_id: u16::from_le_bytes([bytes[0], bytes[1]]),
-            msg_type: bytes[2],
-            dest_id: bytes[3],
-            source_id: bytes[4],
-            op_code: bytes[5],
-            msg_len: u16::from_le_bytes([bytes[6], bytes[7]]),
+            version: bytes[0],
+            crc: CrcKind::from_flags(bytes[1])?,
+            msg_type: MsgType::try_from(bytes[2])?,
+            msg_id: u16::from_le_bytes([bytes[3], bytes[4]]),
+            dest_id: bytes[5],
+            source_id: bytes[6],
+            op_code: bytes[7],
+            msg_len: u16::from_le_bytes([bytes[8], bytes[9]]),
         })
     }
 }
 
+/// Use the ComponentId enum to display the source and destination ids actual name
+impl fmt::Display for MsgHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = |id: u8| match ComponentIds::try_from(id) {
+            Ok(c) => c.to_string(),
+            Err(_) => format!("unknown({})", id),
+        };
+        write!(
+            f,
+            "MsgId: {},\n\tMsgType: {},\n\tDestId: {},\n\tSourceId: {},\n\tOpcode: {}",
+            self.msg_id,
+            self.msg_type,
+            name(self.dest_id),
+            name(self.source_id),
+            self.op_code,
+        )
+    }
+}
+
 /// Message struct with header and body
 #[derive(Debug, Clone)]
 pub struct Msg {
@@ -294,11 +367,14 @@ pub struct Msg {
 }
 
 impl Msg {
-    pub fn new(msg_type: u8, msg_id: u16, dest_id: u8, source_id: u8, op_code: u8, data: Vec<u8>) -> Self {
-        let msg_len: u16 = (HEADER_SIZE + data.len()) as u16;
+    pub fn new(msg_type: MsgType, msg_id: u16, dest_id: u8, source_id: u8, op_code: u8, data: Vec<u8>) -> Self {
+        // A body too long for msg_len is refused when the msg is serialized
+        let msg_len = u16::try_from(HEADER_SIZE + data.len()).unwrap_or(u16::MAX);
         let header = MsgHeader {
-            msg_id,
+            version: MSG_VERSION,
+            crc: CrcKind::None,
             msg_type,
+            msg_id,
             dest_id,
             source_id,
             op_code,
@@ -310,76 +386,220 @@ impl Msg {
         }
     }
 
+    /// Build an Ack for the msg with id `msg_id`. The ack code is carried in the op_code field and
+    /// `context` can hold a human readable reason (e.g. why a command failed)
+    pub fn new_ack(msg_id: u16, dest_id: u8, source_id: u8, ack_code: AckCode, context: Vec<u8>) -> Self {
+        Msg::new(MsgType::Ack, msg_id, dest_id, source_id, ack_code as u8, context)
+    }
+
+    /// Build the Report on executing the cmd `self`, addressed back to whoever sent it. On success the
+    /// context is whatever data the cmd produced, on failure it is the error. The context is cut to what
+    /// fits in a single downlink frame.
+    pub fn report(&self, result: Result<Vec<u8>, IoError>) -> Msg {
+        let (code, mut context) = match result {
+            Ok(data) => (ResultCode::Success, data),
+            Err(e) => (ResultCode::from_io_error(&e), e.to_string().into_bytes()),
+        };
+        context.truncate(DOWNLINK_MSG_BODY_SIZE);
+        Msg::new(MsgType::Report, self.header.msg_id, self.header.source_id, self.header.dest_id, code as u8, context)
+    }
+
+    /// Result code carried by a Report msg. Returns None for any other msg type
+    pub fn result_code(&self) -> Option<Result<ResultCode, DecodeError>> {
+        match self.header.msg_type {
+            MsgType::Report => Some(ResultCode::try_from(self.header.op_code)),
+            _ => None,
+        }
+    }
+
+    /// Ack code carried by an Ack msg. Returns None for any other msg type
+    pub fn ack_code(&self) -> Option<Result<AckCode, DecodeError>> {
+        match self.header.msg_type {
+            MsgType::Ack => Some(AckCode::try_from(self.header.op_code)),
+            _ => None,
+        }
+    }
+
+    /// Select the CRC trailer appended when this msg is serialized
+    pub fn with_crc(mut self, crc: CrcKind) -> Self {
+        self.header.crc = crc;
+        self
+    }
+
+    /// Number of bytes this msg occupies once serialized, including any CRC trailer
+    pub fn frame_len(&self) -> usize {
+        self.header.msg_len as usize + self.header.crc.size()
+    }
+
     fn to_bytes(&self) -> Result<Vec<u8>, IoError> {
-        let mut bytes = self.header.to_bytes()?;
+        if self.msg_body.len() > MAX_MSG_BODY_SIZE {
+            return Err(IoError::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("msg body of {} bytes is longer than {}", self.msg_body.len(), MAX_MSG_BODY_SIZE),
+            ));
+        }
+        let mut bytes = self.header.to_bytes();
         bytes.extend_from_slice(&self.msg_body);
+        let trailer = self.header.crc.checksum(&bytes);
+        bytes.extend(trailer);
         Ok(bytes)
     }
 
-    fn from_bytes(bytes: &[u8]) -> Result<Self, IoError> {
-        let header = MsgHeader::from_bytes(&bytes[0..HEADER_SIZE])?;
-        let msg_body = bytes[HEADER_SIZE..header.msg_len as usize].to_vec(); // don't include trailing nulls in body
+    fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let header = MsgHeader::from_bytes(bytes)?;
+        let msg_len = header.msg_len as usize;
+        if msg_len < HEADER_SIZE {
+            return Err(DecodeError::InvalidLength(header.msg_len));
+        }
+        let frame_len = msg_len + header.crc.size();
+        if bytes.len() < frame_len {
+            return Err(DecodeError::Truncated {
+                expected: frame_len,
+                actual: bytes.len(),
+            });
+        }
+        let computed = header.crc.checksum(&bytes[..msg_len]);
+        if computed[..] != bytes[msg_len..frame_len] {
+            return Err(DecodeError::CrcMismatch {
+                expected: trailer_value(&bytes[msg_len..frame_len]),
+                actual: trailer_value(&computed),
+            });
+        }
+        // don't include trailing nulls in body
+        let msg_body = bytes[HEADER_SIZE..msg_len].to_vec();
         Ok(Msg { header, msg_body })
     }
 }
 
-/// Serialize Msg struct to bytes
+impl fmt::Display for Msg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Msg:\nHeader: {}, \nBody: {:?}", self.header, self.msg_body)
+    }
+}
+
+fn trailer_value(trailer: &[u8]) -> u32 {
+    trailer.iter().rev().fold(0, |acc, b| (acc << 8) | *b as u32)
+}
+
+/// Serialize Msg struct to bytes. Fails if the body is longer than MAX_MSG_BODY_SIZE
 pub fn serialize_msg(msg: &Msg) -> Result<Vec<u8>, IoError> {
-    let bytes = msg.to_bytes()?;
-    Ok(bytes)
+    msg.to_bytes()
+}
+
+/// Deserialize bytes into Msg struct.
+/// Any bytes past the header's msg_len (e.g. the unused tail of an IPC buffer) are ignored.
+/// This never panics - malformed input is reported as a `DecodeError`.
+pub fn deserialize_msg(bytes: &[u8]) -> Result<Msg, DecodeError> {
+    Msg::from_bytes(bytes)
 }
 
-/// Deserialize bytes into Msg struct
-pub fn deserialize_msg(bytes: &[u8]) -> Result<Msg, IoError> {
+/// Deserialize a frame received over the radio link.
+/// Unlike `deserialize_msg`, the frame must carry a CRC and `bytes` must be exactly one frame long, so
+/// corruption of the flags or length fields cannot hide a corrupted body.
+pub fn deserialize_link_msg(bytes: &[u8]) -> Result<Msg, DecodeError> {
     let msg = Msg::from_bytes(bytes)?;
+    if msg.header.crc == CrcKind::None {
+        return Err(DecodeError::MissingCrc);
+    }
+    if bytes.len() != msg.frame_len() {
+        return Err(DecodeError::LengthMismatch {
+            expected: msg.frame_len(),
+            actual: bytes.len(),
+        });
+    }
     Ok(msg)
 }
 
+/// Take the next frame off the front of `stream`, the bytes received so far over the radio link.
+/// Reads of a byte stream can hold several frames or part of one, so this returns None until a whole
+/// frame (`msg_len` plus the CRC size from the flags) has arrived, and leaves any bytes after it in
+/// `stream` for the next call. A frame that fails `deserialize_link_msg` leaves the boundary of the
+/// next one unknown, so the whole of `stream` is dropped along with it.
+pub fn next_link_msg(stream: &mut Vec<u8>) -> Option<Result<Msg, DecodeError>> {
+    if stream.len() < HEADER_SIZE {
+        return None;
+    }
+    let frame_len = match MsgHeader::from_bytes(stream) {
+        Ok(header) if header.msg_len as usize >= HEADER_SIZE => header.msg_len as usize + header.crc.size(),
+        Ok(header) => {
+            stream.clear();
+            return Some(Err(DecodeError::InvalidLength(header.msg_len)));
+        }
+        Err(e) => {
+            stream.clear();
+            return Some(Err(e));
+        }
+    };
+    if stream.len() < frame_len {
+        return None;
+    }
+    let result = deserialize_link_msg(&stream[..frame_len]);
+    if result.is_ok() {
+        stream.drain(..frame_len);
+    } else {
+        stream.clear();
+    }
+    Some(result)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
-    fn test_new_msg_print() {
-        //Create Command Message
-        let cmd_msg = CmdMsg::new(0, 5, 1, 0, vec![0, 1, 2, 3, 4, 5, 6]);
+    fn test_msg_print() {
+        let cmd_msg = Msg::new(MsgType::Cmd, 0, 5, 1, 0, vec![0, 1, 2, 3, 4, 5, 6]);
         println!("{}", cmd_msg);
 
-        //Create Ack Message
-        let ack_msg = AckMsg::new(0, 5, 1, AckCode::Success, vec![0, 1, 2, 3, 4, 5, 6]);
+        let ack_msg = Msg::new_ack(0, 5, 1, AckCode::Success, vec![0, 1, 2, 3, 4, 5, 6]);
         println!("{}", ack_msg);
     }
 
     #[test]
-    fn test_new_msg_ser_and_des() {
-        //Create Cmd Msg
-        let cmd_msg = CmdMsg::new(0, 5, 1, 0, vec![0, 1, 2, 3, 4, 5, 6]);
-        let serialize_cmd_msg = cmd_msg.serialize_to_bytes();
-        let deserialized_cmd_msg = CmdMsg::deserialize_from_bytes(&serialize_cmd_msg);
-        assert_eq!(deserialized_cmd_msg.header.msg_id, 0);
-        assert_eq!(deserialized_cmd_msg.header.dest_id, 5);
-        assert_eq!(deserialized_cmd_msg.header.source_id, 1);
-        assert!(matches!(deserialized_cmd_msg.header.msg_type, MsgType::Cmd));
-        assert_eq!(deserialized_cmd_msg.opcode, 0);
-        assert_eq!(deserialized_cmd_msg.data, vec![0, 1, 2, 3, 4, 5, 6]);
-
-        let ack_msg = AckMsg::new(0, 5, 1, AckCode::Success, vec![0, 1, 2, 3, 4, 5, 6]);
-        let serialize_ack_msg = ack_msg.serialize_to_bytes();
-        let deserialized_ack_msg = AckMsg::deserialize_from_bytes(&serialize_ack_msg);
-        assert_eq!(deserialized_ack_msg.header.msg_id, 0);
+    fn test_ack_ser_and_des() {
+        let ack_msg = Msg::new_ack(12, 5, 1, AckCode::Failed, vec![0, 1, 2, 3, 4, 5, 6]);
+        let serialized_ack_msg = serialize_msg(&ack_msg).unwrap();
+        let deserialized_ack_msg = deserialize_msg(&serialized_ack_msg).unwrap();
+        assert_eq!(deserialized_ack_msg.header.msg_id, 12);
         assert_eq!(deserialized_ack_msg.header.dest_id, 5);
         assert_eq!(deserialized_ack_msg.header.source_id, 1);
-        assert!(matches!(deserialized_ack_msg.header.msg_type, MsgType::Ack));
-        assert_eq!(deserialized_ack_msg.context_data, vec![0, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(deserialized_ack_msg.header.msg_type, MsgType::Ack);
+        assert_eq!(deserialized_ack_msg.ack_code(), Some(Ok(AckCode::Failed)));
+        assert_eq!(deserialized_ack_msg.msg_body, vec![0, 1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_report_round_trip() {
+        let cmd = Msg::new(MsgType::Cmd, 42, ComponentIds::EPS as u8, ComponentIds::GS as u8, 3, vec![]);
+        let report = cmd.report(Ok(b"temp 20C".to_vec()));
+        let decoded = deserialize_msg(&serialize_msg(&report).unwrap()).unwrap();
+        assert_eq!(decoded.header.msg_type, MsgType::Report);
+        assert_eq!(decoded.header.msg_id, 42);
+        assert_eq!(decoded.header.dest_id, ComponentIds::GS as u8);
+        assert_eq!(decoded.header.source_id, ComponentIds::EPS as u8);
+        assert_eq!(decoded.result_code(), Some(Ok(ResultCode::Success)));
+        assert_eq!(decoded.ack_code(), None);
+        assert_eq!(decoded.msg_body, b"temp 20C");
+
+        let bad_args = IoError::new(std::io::ErrorKind::InvalidData, "missing argument 'state'");
+        let failed = cmd.report(Err(bad_args));
+        assert_eq!(failed.result_code(), Some(Ok(ResultCode::InvalidArgs)));
+        assert_eq!(failed.msg_body, b"missing argument 'state'");
+
+        // Big results are cut to fit in one downlink frame
+        let long = cmd.report(Ok(vec![b'x'; 1000]));
+        assert_eq!(long.msg_body.len(), DOWNLINK_MSG_BODY_SIZE);
     }
 
     #[test]
     fn test_serialize_deserialize() {
-        let msg: Msg = Msg::new(MsgType::Bulk as u8, 0,ComponentIds::GS as u8, ComponentIds::DFGM as u8,2, vec![113,1]);
+        let msg: Msg = Msg::new(MsgType::Bulk, 0,ComponentIds::GS as u8, ComponentIds::DFGM as u8,2, vec![113,1]);
 
         // Serialize
         let serialized_msg = serialize_msg(&msg).unwrap();
         println!("ser msg: {:?}", serialized_msg);
+        assert_eq!(serialized_msg[0], MSG_VERSION);
+        assert_eq!(serialized_msg[MsgHeader::DEST_INDEX], ComponentIds::GS as u8);
 
         // Deserialize
         let deserialized_msg = deserialize_msg(&serialized_msg).unwrap();
@@ -396,26 +616,24 @@ mod tests {
 
     #[test]
     fn test_serialize_empty_body() {
-        let msg = Msg::new(0,1, 2, 3, 4, vec![]);
+        let msg = Msg::new(MsgType::Cmd,1, 2, 3, 4, vec![]);
 
         // Serialize
-        let serialized_msg_result = msg.to_bytes();
-        assert!(serialized_msg_result.is_ok(), "Serialization failed");
-        let serialized_msg = serialized_msg_result.unwrap();
+        let serialized_msg = msg.to_bytes().unwrap();
 
         // Deserialize
         let deserialized_msg = Msg::from_bytes(&serialized_msg).unwrap();
 
         // Assert equality
-        assert_eq!(deserialized_msg.header.msg_type, 0);
-        //assert_eq!(deserialized_msg.msg_body, vec![]);
+        assert_eq!(deserialized_msg.header.msg_type, MsgType::Cmd);
+        assert!(deserialized_msg.msg_body.is_empty());
     }
 
     #[test]
     fn test_serialize_max_length_body() {
         // Create a message with the maximum possible body size
         let max_body_size = u8::MAX as usize - 5; // Maximum u8 value minus header size
-        let msg = Msg::new(0,1, 2, 3, 4, vec![0; max_body_size]);
+        let msg = Msg::new(MsgType::Cmd,1, 2, 3, 4, vec![0; max_body_size]);
 
         // Serialize
         let serialized_msg = msg.to_bytes().unwrap();
@@ -424,24 +642,210 @@ mod tests {
         let deserialized_msg = Msg::from_bytes(&serialized_msg).unwrap();
 
         // Assert equality
-        assert_eq!(deserialized_msg.header.msg_type, 0);
+        assert_eq!(deserialized_msg.header.msg_type, MsgType::Cmd);
         assert_eq!(deserialized_msg.msg_body.len(), max_body_size);
         assert_eq!(deserialized_msg.msg_body, vec![0; max_body_size]);
     }
 
-    #[should_panic]
+    #[test]
+    fn test_refuses_body_too_long_for_msg_len() {
+        let msg = Msg::new(MsgType::Bulk, 1, 2, 3, 4, vec![7; MAX_MSG_BODY_SIZE]);
+        let bytes = serialize_msg(&msg).unwrap();
+        assert_eq!(deserialize_msg(&bytes).unwrap().msg_body.len(), MAX_MSG_BODY_SIZE);
+
+        // One byte more would wrap msg_len, and the body would be cut short when decoded
+        let msg = Msg::new(MsgType::Bulk, 1, 2, 3, 4, vec![7; MAX_MSG_BODY_SIZE + 1]);
+        assert_eq!(serialize_msg(&msg).unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_deserialize_ignores_trailing_bytes() {
+        let msg = Msg::new(MsgType::Cmd, 3, 2, 7, 1, vec![9, 8, 7]);
+        let mut buffer = serialize_msg(&msg).unwrap();
+        buffer.resize(4096, 0);
+
+        let deserialized_msg = deserialize_msg(&buffer).unwrap();
+        assert_eq!(deserialized_msg.msg_body, vec![9, 8, 7]);
+    }
+
     #[test]
     fn test_deserialize_invalid_data() {
         // Provide insufficient bytes for header
-        let bytes = vec![0, 1, 2];
+        let bytes = vec![MSG_VERSION, 1, 2];
 
         // Deserialize should fail
         let deserialized_msg_result = Msg::from_bytes(&bytes);
-        assert!(
-            deserialized_msg_result.is_err(),
-            "Deserialization succeeded unexpectedly"
+        assert_eq!(
+            deserialized_msg_result.err(),
+            Some(DecodeError::Truncated { expected: HEADER_SIZE, actual: 3 })
         );
-        let err = deserialized_msg_result.err().unwrap();
+        let err: IoError = deserialize_msg(&bytes).unwrap_err().into();
         assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
     }
+
+    #[test]
+    fn test_deserialize_rejects_bad_fields() {
+        let msg = Msg::new(MsgType::Ack, 3, 2, 7, 1, vec![9, 8, 7]);
+        let good = serialize_msg(&msg).unwrap();
+
+        let mut bad_version = good.clone();
+        bad_version[0] = MSG_VERSION + 1;
+        assert_eq!(deserialize_msg(&bad_version).err(), Some(DecodeError::UnsupportedVersion(MSG_VERSION + 1)));
+
+        let mut bad_type = good.clone();
+        bad_type[2] = 200;
+        assert_eq!(deserialize_msg(&bad_type).err(), Some(DecodeError::InvalidMsgType(200)));
+
+        // msg_len claims more bytes than were provided
+        let mut too_long = good.clone();
+        too_long[8..10].copy_from_slice(&100u16.to_le_bytes());
+        assert_eq!(deserialize_msg(&too_long).err(), Some(DecodeError::Truncated { expected: 100, actual: good.len() }));
+
+        // msg_len smaller than the header
+        let mut too_short = good.clone();
+        too_short[8..10].copy_from_slice(&2u16.to_le_bytes());
+        assert_eq!(deserialize_msg(&too_short).err(), Some(DecodeError::InvalidLength(2)));
+
+        let mut bad_flags = good.clone();
+        bad_flags[1] = 0x80;
+        assert_eq!(deserialize_msg(&bad_flags).err(), Some(DecodeError::InvalidFlags(0x80)));
+
+        let mut bad_ack = good;
+        bad_ack[7] = 42;
+        let ack = deserialize_msg(&bad_ack).unwrap();
+        assert_eq!(ack.ack_code(), Some(Err(DecodeError::InvalidAckCode(42))));
+    }
+
+    #[test]
+    fn test_deserialize_never_panics_on_garbage() {
+        for len in 0..64 {
+            let bytes: Vec<u8> = (0..len).map(|i| (i * 37 + len) as u8).collect();
+            let _ = deserialize_msg(&bytes);
+            let mut versioned = bytes.clone();
+            if let Some(b) = versioned.first_mut() {
+                *b = MSG_VERSION;
+            }
+            let _ = deserialize_msg(&versioned);
+        }
+    }
+
+    #[test]
+    fn test_crc_round_trip() {
+        for crc in [CrcKind::None, CrcKind::Crc16, CrcKind::Crc32] {
+            let msg = Msg::new(MsgType::Cmd, 7, 4, 7, 2, vec![1, 2, 3, 4]).with_crc(crc);
+            let bytes = serialize_msg(&msg).unwrap();
+            assert_eq!(bytes.len(), HEADER_SIZE + 4 + crc.size());
+            assert_eq!(bytes.len(), msg.frame_len());
+
+            let decoded = deserialize_msg(&bytes).unwrap();
+            assert_eq!(decoded.header.crc, crc);
+            assert_eq!(decoded.msg_body, vec![1, 2, 3, 4]);
+        }
+    }
+
+    #[test]
+    fn test_crc_mismatch_detected() {
+        let msg = Msg::new(MsgType::Cmd, 7, 4, 7, 2, vec![1, 2, 3, 4]).with_crc(CrcKind::Crc16);
+        let mut bytes = serialize_msg(&msg).unwrap();
+        bytes[HEADER_SIZE] ^= 0x01;
+        assert!(matches!(deserialize_msg(&bytes), Err(DecodeError::CrcMismatch { .. })));
+    }
+
+    #[test]
+    fn test_link_msg_requirements() {
+        let plain = serialize_msg(&Msg::new(MsgType::Cmd, 7, 4, 7, 2, vec![1])).unwrap();
+        assert_eq!(deserialize_link_msg(&plain).err(), Some(DecodeError::MissingCrc));
+
+        let mut framed = serialize_msg(&Msg::new(MsgType::Cmd, 7, 4, 7, 2, vec![1]).with_crc(CrcKind::Crc16)).unwrap();
+        assert!(deserialize_link_msg(&framed).is_ok());
+        framed.push(0);
+        assert_eq!(
+            deserialize_link_msg(&framed).err(),
+            Some(DecodeError::LengthMismatch { expected: framed.len() - 1, actual: framed.len() })
+        );
+    }
+
+    #[test]
+    fn test_next_link_msg_reframes_stream() {
+        let first = Msg::new(MsgType::Cmd, 1, 4, 7, 2, vec![1, 2, 3]).with_crc(CrcKind::Crc16);
+        let second = Msg::new(MsgType::Cmd, 2, 4, 7, 2, vec![4]).with_crc(CrcKind::Crc32);
+        let bytes = [serialize_msg(&first).unwrap(), serialize_msg(&second).unwrap()].concat();
+
+        // Both frames in one read, with the first bytes of a third
+        let mut stream = bytes.clone();
+        stream.extend_from_slice(&bytes[..HEADER_SIZE + 1]);
+        assert_eq!(next_link_msg(&mut stream).unwrap().unwrap().header.msg_id, 1);
+        assert_eq!(next_link_msg(&mut stream).unwrap().unwrap().header.msg_id, 2);
+        assert!(next_link_msg(&mut stream).is_none());
+        assert_eq!(stream, bytes[..HEADER_SIZE + 1]);
+
+        // A frame split across reads, a byte at a time
+        let mut stream = vec![];
+        for byte in &bytes[..first.frame_len() - 1] {
+            stream.push(*byte);
+            assert!(next_link_msg(&mut stream).is_none());
+        }
+        stream.push(bytes[first.frame_len() - 1]);
+        assert_eq!(next_link_msg(&mut stream).unwrap().unwrap().msg_body, vec![1, 2, 3]);
+        assert!(stream.is_empty());
+    }
+
+    #[test]
+    fn test_next_link_msg_drops_stream_after_bad_frame() {
+        let msg = Msg::new(MsgType::Cmd, 1, 4, 7, 2, vec![1, 2, 3]).with_crc(CrcKind::Crc16);
+        let mut stream = [serialize_msg(&msg).unwrap(), serialize_msg(&msg).unwrap()].concat();
+        stream[HEADER_SIZE] ^= 0x01;
+        assert!(matches!(next_link_msg(&mut stream), Some(Err(DecodeError::CrcMismatch { .. }))));
+        assert!(stream.is_empty());
+
+        let mut stream = serialize_msg(&msg.with_crc(CrcKind::None)).unwrap();
+        assert_eq!(next_link_msg(&mut stream).unwrap().err(), Some(DecodeError::MissingCrc));
+        assert!(stream.is_empty());
+
+        let mut stream = vec![0xff; HEADER_SIZE];
+        assert_eq!(next_link_msg(&mut stream).unwrap().err(), Some(DecodeError::UnsupportedVersion(0xff)));
+        assert!(stream.is_empty());
+    }
+
+    mod crc_properties {
+        use super::*;
+        use proptest::prelude::*;
+
+        fn crc_kind() -> impl Strategy<Value = CrcKind> {
+            prop_oneof![Just(CrcKind::Crc16), Just(CrcKind::Crc32)]
+        }
+
+        proptest! {
+            /// Both CRCs have a hamming distance of at least 4 for frames this size, so any 1 to 3 bit
+            /// errors anywhere in the frame (header, body or trailer) must be caught.
+            #[test]
+            fn bit_flips_are_detected(
+                crc in crc_kind(),
+                msg_id in any::<u16>(),
+                op_code in any::<u8>(),
+                body in proptest::collection::vec(any::<u8>(), 0..1024),
+                flip_seeds in proptest::collection::vec(any::<usize>(), 1..=3),
+            ) {
+                let msg = Msg::new(MsgType::Cmd, msg_id, 4, 7, op_code, body).with_crc(crc);
+                let frame = serialize_msg(&msg).unwrap();
+                prop_assert!(deserialize_link_msg(&frame).is_ok());
+
+                let mut flips: Vec<usize> = flip_seeds.iter().map(|seed| seed % (frame.len() * 8)).collect();
+                flips.sort_unstable();
+                flips.dedup();
+
+                let mut corrupted = frame.clone();
+                for bit in flips {
+                    corrupted[bit / 8] ^= 1 << (bit % 8);
+                }
+                prop_assert!(deserialize_link_msg(&corrupted).is_err());
+            }
+
+            #[test]
+            fn decoder_never_panics(bytes in proptest::collection::vec(any::<u8>(), 0..64)) {
+                let _ = deserialize_msg(&bytes);
+                let _ = deserialize_link_msg(&bytes);
+            }
+        }
+    }
 }
@@ -0,0 +1,153 @@
+/*
+Allocation of msg ids.
+
+The msg_id in the header is what ties an Ack, Report, response or bulk transfer back to the msg that
+caused it, so ids have to be unique among the msgs in flight. The id space is split in two, so the
+GS and the spacecraft never hand out the same id:
+
+    0                 reserved, used to NACK frames whose header can't be trusted
+    0x0001 - 0x7FFF   GS originated
+    0x8000 - 0xFFFF   spacecraft originated, one block of SC_BLOCK_SIZE ids per component
+
+Each spacecraft component allocates from its own block, so processes on the OBC don't need to share
+any state to keep their ids apart. Anything sent in response to a msg reuses the id of that msg rather
+than allocating a new one.
+
+An allocator starts at a random point in its range and wraps around within it, so a restarted process
+is unlikely to reuse the ids of msgs that were still in flight before it went down.
+*/
+
+use crate::component_ids::ComponentIds;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use std::ops::RangeInclusive;
+
+/// Id carried by NACKs of frames whose header could not be trusted. Never allocated.
+pub const RESERVED_MSG_ID: u16 = 0;
+/// Ids handed out by the GS
+pub const GS_MSG_IDS: RangeInclusive<u16> = 0x0001..=0x7FFF;
+/// Ids handed out by components on the spacecraft
+pub const SC_MSG_IDS: RangeInclusive<u16> = 0x8000..=0xFFFF;
+/// Number of ids in each spacecraft component's block
+pub const SC_BLOCK_SIZE: u16 = 0x400;
+
+// Every component id has to get a whole block of the spacecraft range
+const _: () = assert!((ComponentIds::LAST as u32 + 1) * SC_BLOCK_SIZE as u32 <= 0x8000);
+
+/// Which side of the link a msg_id was allocated on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MsgOrigin {
+    Ground,
+    Spacecraft,
+}
+
+impl MsgOrigin {
+    /// Where `msg_id` was allocated, or None for the reserved id
+    pub fn of(msg_id: u16) -> Option<MsgOrigin> {
+        if GS_MSG_IDS.contains(&msg_id) {
+            Some(MsgOrigin::Ground)
+        } else if SC_MSG_IDS.contains(&msg_id) {
+            Some(MsgOrigin::Spacecraft)
+        } else {
+            None
+        }
+    }
+}
+
+/// Hands out msg ids from a fixed range, wrapping around when the end is reached
+#[derive(Debug, Clone)]
+pub struct MsgIdAllocator {
+    first: u16,
+    last: u16,
+    next: u16,
+}
+
+impl MsgIdAllocator {
+    /// Allocator for msgs sent by the GS
+    pub fn ground() -> Self {
+        Self::random_start(*GS_MSG_IDS.start(), *GS_MSG_IDS.end())
+    }
+
+    /// Allocator for msgs sent by `component` on the spacecraft
+    pub fn spacecraft(component: ComponentIds) -> Self {
+        let first = SC_MSG_IDS.start() + component as u16 * SC_BLOCK_SIZE;
+        Self::random_start(first, first + (SC_BLOCK_SIZE - 1))
+    }
+
+    fn random_start(first: u16, last: u16) -> Self {
+        let offset = OsRng.next_u32() % (last - first + 1) as u32;
+        MsgIdAllocator { first, last, next: first + offset as u16 }
+    }
+
+    /// Continue allocating from `msg_id`, e.g. to pick up where a previous run left off.
+    /// Ids outside of this allocator's range are ignored.
+    pub fn starting_at(mut self, msg_id: u16) -> Self {
+        if self.contains(msg_id) {
+            self.next = msg_id;
+        }
+        self
+    }
+
+    /// Whether `msg_id` is one this allocator hands out
+    pub fn contains(&self, msg_id: u16) -> bool {
+        (self.first..=self.last).contains(&msg_id)
+    }
+
+    /// Allocate the next id
+    pub fn next_id(&mut self) -> u16 {
+        let msg_id = self.next;
+        self.next = if msg_id == self.last { self.first } else { msg_id + 1 };
+        msg_id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ranges_do_not_overlap() {
+        let mut gs = MsgIdAllocator::ground();
+        for _ in 0..100 {
+            let id = gs.next_id();
+            assert_ne!(id, RESERVED_MSG_ID);
+            assert_eq!(MsgOrigin::of(id), Some(MsgOrigin::Ground));
+        }
+        assert_eq!(MsgOrigin::of(RESERVED_MSG_ID), None);
+
+        let blocks: Vec<MsgIdAllocator> = ComponentIds::iter().map(MsgIdAllocator::spacecraft).collect();
+        for (i, block) in blocks.iter().enumerate() {
+            assert!(SC_MSG_IDS.contains(&block.first) && SC_MSG_IDS.contains(&block.last));
+            for other in &blocks[i + 1..] {
+                assert!(!block.contains(other.first) && !block.contains(other.last));
+            }
+        }
+    }
+
+    #[test]
+    fn test_rollover_stays_in_range() {
+        let mut gs = MsgIdAllocator::ground().starting_at(0x7FFF);
+        assert_eq!(gs.next_id(), 0x7FFF);
+        assert_eq!(gs.next_id(), 1);
+
+        let mut iris = MsgIdAllocator::spacecraft(ComponentIds::IRIS);
+        let last = iris.last;
+        iris = iris.starting_at(last);
+        assert_eq!(iris.next_id(), last);
+        assert_eq!(iris.next_id(), last + 1 - SC_BLOCK_SIZE);
+
+        // An id from some other range doesn't move the allocator out of its own
+        let mut gs = MsgIdAllocator::ground().starting_at(0x8000);
+        assert_eq!(MsgOrigin::of(gs.next_id()), Some(MsgOrigin::Ground));
+    }
+
+    #[test]
+    fn test_ids_are_unique_until_wrap() {
+        let mut eps = MsgIdAllocator::spacecraft(ComponentIds::EPS);
+        let mut seen = std::collections::HashSet::new();
+        for _ in 0..SC_BLOCK_SIZE {
+            assert!(seen.insert(eps.next_id()));
+        }
+        assert!(!seen.insert(eps.next_id()));
+    }
+}
@@ -0,0 +1,477 @@
+/*
+Single registry of every component and the opcodes it accepts.
+
+The `registry!` invocation at the bottom of this file is the only place component ids and opcodes are
+defined. From it are generated:
+    - `component_ids::ComponentIds`, with name lookups both ways
+    - an enum per component in `opcodes`, with conversions from the raw opcode and from its name
+    - `COMPONENTS`, a static table describing all of the above, for tools like the CLI and dashboard
+
+To add an opcode, add a line to the component's block in the invocation below. An opcode is written as
+`Name = value`, optionally followed by `: "description"` and a list of argument schemas in brackets (see
+`cmd_args` for how they are encoded).
+*/
+
+use crate::key_store::KEY_SLOTS;
+use crate::link_crypto::KEY_SIZE;
+use crate::cmd_args::{arg, decode_args, encode_args, ArgError, ArgSpec, ArgType::*, ArgValue};
+use serde::Serialize;
+use std::fmt;
+
+/// Opcode reserved to mean 'not a valid opcode' - no component may define an opcode with this value
+pub const INVALID_OPCODE: u8 = 255;
+
+#[derive(Debug, Serialize)]
+pub struct OpcodeInfo {
+    pub name: &'static str,
+    pub value: u8,
+    pub description: &'static str,
+    pub args: &'static [ArgSpec],
+}
+
+impl OpcodeInfo {
+    /// Validate and pack the operator's argument tokens into a msg body
+    pub fn encode(&self, tokens: &[&str]) -> Result<Vec<u8>, ArgError> {
+        encode_args(self.args, tokens)
+    }
+
+    /// Unpack a msg body into this opcode's arguments
+    pub fn decode(&self, body: &[u8]) -> Result<Vec<ArgValue>, ArgError> {
+        decode_args(self.args, body)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ComponentInfo {
+    pub name: &'static str,
+    pub id: u8,
+    pub opcodes: &'static [OpcodeInfo],
+}
+
+impl ComponentInfo {
+    /// Look up an opcode by its name (case insensitive) or its number
+    pub fn opcode(&self, name_or_value: &str) -> Option<&'static OpcodeInfo> {
+        let opcodes: &'static [OpcodeInfo] = self.opcodes;
+        match name_or_value.parse::<u8>() {
+            Ok(value) => opcodes.iter().find(|op| op.value == value),
+            Err(_) => opcodes.iter().find(|op| op.name.eq_ignore_ascii_case(name_or_value)),
+        }
+    }
+
+    /// Usage string listing the opcodes, for help output
+    pub fn usage(&self) -> String {
+        let mut usage = format!("{} <opcode> <args>, where <opcode> is one of:", self.name);
+        for op in self.opcodes {
+            usage.push_str(&format!("\n  {:>3} {:<22} {}", op.value, op.name, op.description));
+            for arg in op.args {
+                usage.push_str(&format!(" {}", arg));
+            }
+        }
+        usage
+    }
+}
+
+/// Look up a component by its name (case insensitive)
+pub fn component(name: &str) -> Option<&'static ComponentInfo> {
+    COMPONENTS.iter().find(|c| c.name.eq_ignore_ascii_case(name))
+}
+
+pub fn component_by_id(id: u8) -> Option<&'static ComponentInfo> {
+    COMPONENTS.iter().find(|c| c.id == id)
+}
+
+/// A name or number that is not in the registry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegistryError {
+    UnknownComponentId(u8),
+    UnknownComponentName(String),
+    UnknownOpcode(String),
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegistryError::UnknownComponentId(id) => write!(f, "unknown component id {}", id),
+            RegistryError::UnknownComponentName(name) => write!(f, "unknown component '{}'", name),
+            RegistryError::UnknownOpcode(op) => write!(f, "unknown opcode '{}'", op),
+        }
+    }
+}
+
+impl std::error::Error for RegistryError {}
+
+macro_rules! opcode_description {
+    ($op:ident) => {
+        stringify!($op)
+    };
+    ($op:ident $desc:literal) => {
+        $desc
+    };
+}
+
+macro_rules! registry {
+    (
+        $(
+            $comp:ident = $cid:literal {
+                $( $op:ident = $opval:literal $(: $desc:literal)? $([ $($arg:expr),* $(,)? ])? ),* $(,)?
+            }
+        ),* $(,)?
+    ) => {
+        /// Each thing that can emit or receive a message has an associated ID. Each message header includes this id for source and destination.
+        pub mod component_ids {
+            use super::{ComponentInfo, RegistryError, COMPONENTS};
+            use std::fmt;
+            use std::str::FromStr;
+
+            #[allow(clippy::upper_case_acronyms)]
+            #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+            pub enum ComponentIds {
+                $( $comp = $cid, )*
+            }
+
+            impl ComponentIds {
+                /// One more than the highest component id, for sizing tables indexed by id
+                pub const LAST: u8 = {
+                    let mut last = 0;
+                    $( if $cid + 1 > last { last = $cid + 1; } )*
+                    last
+                };
+
+                pub const ALL: &'static [ComponentIds] = &[ $( ComponentIds::$comp, )* ];
+
+                pub fn iter() -> impl Iterator<Item = ComponentIds> {
+                    Self::ALL.iter().copied()
+                }
+
+                pub fn name(&self) -> &'static str {
+                    match self {
+                        $( ComponentIds::$comp => stringify!($comp), )*
+                    }
+                }
+
+                pub fn info(&self) -> &'static ComponentInfo {
+                    COMPONENTS.iter().find(|c| c.id == *self as u8).expect("every component is in the registry")
+                }
+            }
+
+            impl fmt::Display for ComponentIds {
+                fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                    write!(f, "{}", self.name())
+                }
+            }
+
+            impl FromStr for ComponentIds {
+                type Err = RegistryError;
+                fn from_str(s: &str) -> Result<Self, Self::Err> {
+                    Self::iter()
+                        .find(|c| c.name() == s)
+                        .ok_or_else(|| RegistryError::UnknownComponentName(s.to_string()))
+                }
+            }
+
+            impl TryFrom<u8> for ComponentIds {
+                type Error = RegistryError;
+                fn try_from(value: u8) -> Result<Self, Self::Error> {
+                    match value {
+                        $( $cid => Ok(ComponentIds::$comp), )*
+                        _ => Err(RegistryError::UnknownComponentId(value)),
+                    }
+                }
+            }
+        }
+
+        /// Here opcodes and their associated meaning are defined for each component.
+        /// Any value that isn't a known opcode converts to the `Error` variant.
+        pub mod opcodes {
+            use super::{ArgError, ArgValue, OpcodeInfo, RegistryError, INVALID_OPCODE};
+            use std::fmt;
+            use std::str::FromStr;
+
+            $(
+                #[allow(clippy::upper_case_acronyms)]
+                #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+                pub enum $comp {
+                    $( $op = $opval, )*
+                    Error = INVALID_OPCODE as isize,
+                }
+
+                impl $comp {
+                    /// The opcode with this value, if there is one
+                    pub fn lookup(value: u8) -> Option<Self> {
+                        match value {
+                            $( $opval => Some(Self::$op), )*
+                            _ => None,
+                        }
+                    }
+
+                    pub fn name(&self) -> &'static str {
+                        match self {
+                            $( Self::$op => stringify!($op), )*
+                            Self::Error => "Error",
+                        }
+                    }
+
+                    /// The registry entry for this opcode, None for `Error`
+                    pub fn info(&self) -> Option<&'static OpcodeInfo> {
+                        super::component(stringify!($comp))
+                            .and_then(|c| c.opcodes.iter().find(|op| op.value == *self as u8))
+                    }
+
+                    /// Unpack a msg body sent with this opcode. A body sent with an invalid opcode only decodes if it is empty
+                    pub fn decode(&self, body: &[u8]) -> Result<Vec<ArgValue>, ArgError> {
+                        match self.info() {
+                            Some(info) => info.decode(body),
+                            None => super::decode_args(&[], body),
+                        }
+                    }
+                }
+
+                impl From<u8> for $comp {
+                    fn from(value: u8) -> Self {
+                        Self::lookup(value).unwrap_or(Self::Error)
+                    }
+                }
+
+                impl FromStr for $comp {
+                    type Err = RegistryError;
+                    fn from_str(s: &str) -> Result<Self, Self::Err> {
+                        match s {
+                            $( _ if s.eq_ignore_ascii_case(stringify!($op)) => Ok(Self::$op), )*
+                            _ => Err(RegistryError::UnknownOpcode(s.to_string())),
+                        }
+                    }
+                }
+
+                impl fmt::Display for $comp {
+                    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                        match self {
+                            $( Self::$op => write!(f, "{}", opcode_description!($op $($desc)?)), )*
+                            Self::Error => write!(f, "INVALID OPCODE"),
+                        }
+                    }
+                }
+            )*
+        }
+
+        pub const COMPONENTS: &[ComponentInfo] = &[
+            $(
+                ComponentInfo {
+                    name: stringify!($comp),
+                    id: $cid,
+                    opcodes: &[
+                        $(
+                            OpcodeInfo {
+                                name: stringify!($op),
+                                value: $opval,
+                                description: opcode_description!($op $($desc)?),
+                                args: &[ $( $( $arg ),* )? ],
+                            },
+                        )*
+                    ],
+                },
+            )*
+        ];
+    };
+}
+
+registry! {
+    OBC = 0 {},
+    EPS = 1 {
+        On = 1,
+        Off = 2,
+        GetHK = 3: "Get Housekeeping",
+        Reset = 7,
+    },
+    ADCS = 2 {
+        Detumble = 0,
+        // Action 0 turns the ADCS off, 1 on and 2 gets its state
+        OnOff = 1: "On/Off" [arg("action", U8).range(0, 2)],
+        // For the following, action 0 gets the current value and 1 sets it to the values given
+        WheelSpeed = 2: "Wheel Speed" [
+            arg("action", U8).range(0, 1),
+            arg("x", I16).units("rpm").optional(),
+            arg("y", I16).units("rpm").optional(),
+            arg("z", I16).units("rpm").optional(),
+        ],
+        GetHk = 3: "Get Housekeeping",
+        MagnetorquerCurrent = 4: "Magnetorquer Current" [
+            arg("action", U8).range(0, 1),
+            arg("x", I16).units("mA").optional(),
+            arg("y", I16).units("mA").optional(),
+            arg("z", I16).units("mA").optional(),
+        ],
+        OnboardTime = 5: "Onboard Time" [
+            arg("action", U8).range(0, 1),
+            arg("time", U32).units("s").optional(),
+        ],
+        GetOrientation = 6: "Get Orientation",
+        Reset = 7,
+        OrientToSBand = 9: "Orient to S-Band",
+    },
+    DFGM = 3 {
+        ToggleDataCollection = 0: "Toggle Data Collection" [arg("state", Bool)],
+    },
+    IRIS = 4 {
+        CaptureImage = 0: "Capture Image",
+        ToggleSensor = 1: "Toggle Sensor" [arg("state", Bool)],
+        FetchImage = 2: "Fetch Image" [arg("count", U8).range(1, 255)],
+        GetHK = 3: "Get Housekeeping",
+        GetNImagesAvailable = 4: "Get Number of Images Available",
+        SetTime = 5: "Set Time" [arg("time", U32).units("s")],
+        GetTime = 6: "Get Time",
+        Reset = 7,
+        DelImage = 8: "Delete Image" [arg("index", U8)],
+        GetImageSize = 9: "Get Image Size" [arg("index", U8)],
+    },
+    GPS = 5 {
+        SetTime = 5: "Set Time" [arg("time", U32).units("s")],
+    },
+    DEPLOYABLES = 6 {},
+    GS = 7 {},
+    COMS = 8 {
+        GetHK = 3: "Get Housekeeping",
+        SetBeacon = 4: "Set Beacon" [arg("beacon", Str)],
+        GetBeacon = 5: "Get Beacon",
+        RotateKey = 7: "Rotate Key (master key only)" [
+            arg("slot", U8).range(0, KEY_SLOTS as i64 - 1),
+            arg("key", Bytes(KEY_SIZE)),
+        ],
+        SelectKey = 8: "Select Key (master key only)" [arg("slot", U8).range(0, KEY_SLOTS as i64 - 1)],
+    },
+    BulkMsgDispatcher = 9 {},
+    SHELL = 10 {},
+    UHF = 11 {
+        GetHK = 3: "Get Housekeeping",
+        SetBeacon = 4: "Set Beacon" [arg("beacon", Str)],
+        GetBeacon = 5: "Get Beacon",
+        SetMode = 6: "Set Mode" [arg("mode", U8)],
+        Reset = 7,
+        GetMode = 8: "Get Mode",
+    },
+    CmdDispatcher = 12 {
+        // Sent by components to tell the dispatcher which socket to deliver their msgs to. Components
+        // repeat it regularly, and the dispatcher forgets registrations that aren't renewed
+        Register = 0: "Register" [arg("socket", Str)],
+        // Reports the depth and counters of the dispatcher's queue for each destination
+        GetHK = 3: "Get Housekeeping",
+    },
+    Scheduler = 13 {
+        // The body is the 6 byte little endian time to run the cmd at, in ms since the Unix epoch,
+        // followed by the serialized cmd. The CLI builds it from a time and an ordinary cmd line
+        ScheduleCmd = 0: "Schedule Command",
+        // Reports a page of the schedule as a table (see common::schedule), starting from the task at `first`
+        ListTasks = 1: "List Tasks" [arg("first", U16).optional()],
+        CancelTask = 2: "Cancel Task" [arg("id", U16)],
+        ShiftTask = 3: "Shift Task" [arg("id", U16), arg("delay", I32).units("s")],
+        SetTaskTime = 4: "Set Task Time" [arg("id", U16), arg("time", U32).units("s")],
+        // Cancels every task
+        ClearTasks = 5: "Clear Tasks",
+        // The body is an event trigger (see common::schedule) followed by the serialized cmd, built by
+        // the CLI like that of a ScheduleCmd
+        ScheduleOnEvent = 6: "Schedule Command On Event",
+        // Sent by the components that raise events (see common::events), to trigger the tasks waiting on them
+        PublishEvent = 7: "Publish Event" [arg("event", U8), arg("value", I32).optional()],
+    },
+    Sequencer = 14 {
+        // Starts uploading a sequence (see common::sequence) of `size` bytes, dropping any upload in progress
+        BeginUpload = 0: "Begin Upload" [arg("size", U16), arg("name", Str)],
+        // The body is the offset of the chunk in the sequence (u16, little endian) followed by the chunk.
+        // The CLI uploads sequences from a file with SEQ UPLOAD
+        UploadChunk = 1: "Upload Chunk",
+        // Checks the upload is complete and matches `crc`, and stores it under its name
+        EndUpload = 2: "End Upload" [arg("crc", U32)],
+        StartSequence = 3: "Start Sequence" [arg("name", Str)],
+        // Stops the running sequence before its next step, until it is resumed
+        PauseSequence = 4: "Pause Sequence",
+        ResumeSequence = 5: "Resume Sequence",
+        AbortSequence = 6: "Abort Sequence",
+        ListSequences = 7: "List Sequences",
+        DeleteSequence = 8: "Delete Sequence" [arg("name", Str)],
+        // Reports the running sequence, its step and the result of its last cmd
+        GetStatus = 9: "Get Status",
+    },
+    Time = 15 {
+        // Sets the onboard UTC (see common::time) to `utc`, the GS's time when it sent the cmd, plus half of
+        // `rtt`, its estimate of the round trip delay to the satellite. The CLI syncs the time with TIME SYNC
+        SyncTime = 0: "Sync Time" [arg("utc", I64).units("ms"), arg("rtt", U32).units("ms")],
+        // Reports the onboard UTC and MET, the correction and when it was last synced
+        GetTime = 1: "Get Time",
+        // Mission elapsed time counts from `epoch`, a UTC in ms
+        SetMetEpoch = 2: "Set MET Epoch" [arg("epoch", I64).units("ms")],
+        // Sets the time of IRIS, the ADCS and the GPS to the onboard UTC
+        PropagateTime = 3: "Propagate Time",
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::component_ids::ComponentIds;
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_table_matches_enums() {
+        assert_eq!(COMPONENTS.len(), ComponentIds::ALL.len());
+        for c in ComponentIds::iter() {
+            assert_eq!(c.info().name, c.to_string());
+            assert_eq!(ComponentIds::try_from(c.info().id), Ok(c));
+        }
+        assert_eq!(ComponentIds::LAST, 16);
+        assert_eq!(ComponentIds::try_from(16), Err(RegistryError::UnknownComponentId(16)));
+        assert!(ComponentIds::from_str("LAST").is_err());
+    }
+
+    #[test]
+    fn test_opcode_conversions() {
+        assert_eq!(opcodes::COMS::from(4), opcodes::COMS::SetBeacon);
+        // 6 used to be the COMS 'Error' opcode, but was never converted back to it
+        assert_eq!(opcodes::COMS::from(6), opcodes::COMS::Error);
+        assert_eq!(opcodes::COMS::lookup(6), None);
+        assert_eq!(opcodes::EPS::from(INVALID_OPCODE), opcodes::EPS::Error);
+        assert_eq!(opcodes::IRIS::from_str("fetchimage"), Ok(opcodes::IRIS::FetchImage));
+        assert_eq!(opcodes::ADCS::OnOff.to_string(), "On/Off");
+        assert_eq!(opcodes::ADCS::Reset.to_string(), "Reset");
+        assert_eq!(opcodes::ADCS::from(8).to_string(), "INVALID OPCODE");
+    }
+
+    #[test]
+    fn test_info_lookups() {
+        let iris = component("iris").unwrap();
+        assert_eq!(iris.id, ComponentIds::IRIS as u8);
+        assert_eq!(iris.opcode("2").unwrap().name, "FetchImage");
+        assert_eq!(iris.opcode("FETCHIMAGE").unwrap().args[0].name, "count");
+        assert!(iris.opcode("42").is_none());
+        assert_eq!(component_by_id(8).unwrap().name, "COMS");
+        assert!(component("nope").is_none());
+    }
+
+    #[test]
+    fn test_opcode_args() {
+        let toggle = opcodes::DFGM::ToggleDataCollection;
+        let body = toggle.info().unwrap().encode(&["1"]).unwrap();
+        assert_eq!(body, [1]);
+        assert_eq!(toggle.decode(&body).unwrap()[0].as_bool(), Some(true));
+        // The ASCII encoding operators used to send no longer gets guessed at
+        assert!(toggle.decode(b"1").is_err());
+        assert_eq!(opcodes::DFGM::Error.decode(&[]), Ok(vec![]));
+        assert!(opcodes::DFGM::Error.info().is_none());
+
+        let wheels = component("adcs").unwrap().opcode("WheelSpeed").unwrap();
+        assert_eq!(wheels.encode(&["0"]).unwrap(), [0]);
+        assert_eq!(wheels.encode(&["1", "-2", "0", "300"]).unwrap(), [1, 0xfe, 0xff, 0, 0, 0x2c, 1]);
+        assert!(wheels.encode(&["2"]).is_err());
+        assert!(component("adcs").unwrap().usage().contains("[x: i16 rpm]"));
+    }
+
+    #[test]
+    fn test_optional_args_are_trailing() {
+        for c in COMPONENTS {
+            for op in c.opcodes {
+                let first_optional = op.args.iter().position(|a| a.optional).unwrap_or(op.args.len());
+                assert!(op.args[first_optional..].iter().all(|a| a.optional), "{} {}", c.name, op.name);
+                let str_pos = op.args.iter().position(|a| a.kind == Str);
+                assert!(str_pos.is_none() || str_pos == Some(op.args.len() - 1), "{} {}", c.name, op.name);
+            }
+        }
+    }
+}
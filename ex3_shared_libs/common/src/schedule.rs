@@ -0,0 +1,295 @@
+/*
+Formats shared by the scheduler and the GS: the time tags and event triggers cmds are scheduled with, the
+states of a task, and the table of tasks downlinked in the Report on a Scheduler ListTasks.
+
+A time tag is a 48 bit count of ms since the Unix epoch, little endian. An event trigger is
+
+    event (u8) | condition (u8) | threshold (i32) | delay (u32, s)
+
+where the condition is 0 for any value of the event, 1 for a value above the threshold and 2 for one below
+it. The table is a page of the schedule, soonest task first, that fits in one downlink frame:
+
+    total tasks (u16) | index of the first row (u16) | rows
+
+with each row
+
+    task id (u16) | time tag (6 bytes) | state (u8) | dest (u8) | opcode (u8) | msg_id (u16)
+    | event (u8) | condition (u8) | threshold (i32)
+
+The event is 0xff for a task with a time. For one waiting on an event the time tag holds the delay in ms
+instead. All integers are little endian.
+*/
+
+use crate::constants::DOWNLINK_MSG_BODY_SIZE;
+use crate::events::{Event, EventKind};
+use std::fmt;
+use std::io::{Error as IoError, ErrorKind};
+
+/// Size of a time tag
+pub const TIME_SIZE: usize = 6;
+const TABLE_HEADER_SIZE: usize = 4;
+pub const ROW_SIZE: usize = 19;
+/// Size of an event trigger
+pub const TRIGGER_SIZE: usize = 10;
+/// Event byte of a row for a task with a time
+const NO_EVENT: u8 = 0xff;
+/// Most rows a table can carry
+pub const ROWS_PER_PAGE: usize = (DOWNLINK_MSG_BODY_SIZE - TABLE_HEADER_SIZE) / ROW_SIZE;
+
+pub fn encode_time(time: u64) -> [u8; TIME_SIZE] {
+    let mut bytes = [0u8; TIME_SIZE];
+    bytes.copy_from_slice(&time.to_le_bytes()[..TIME_SIZE]);
+    bytes
+}
+
+fn invalid(reason: String) -> IoError {
+    IoError::new(ErrorKind::InvalidData, reason)
+}
+
+/// Which values of an event trigger a task
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    Any,
+    Above(i32),
+    Below(i32),
+}
+
+impl Condition {
+    pub fn is_met_by(&self, value: i32) -> bool {
+        match *self {
+            Condition::Any => true,
+            Condition::Above(threshold) => value > threshold,
+            Condition::Below(threshold) => value < threshold,
+        }
+    }
+
+    fn to_bytes(self) -> [u8; 5] {
+        let (code, threshold) = match self {
+            Condition::Any => (0, 0),
+            Condition::Above(threshold) => (1, threshold),
+            Condition::Below(threshold) => (2, threshold),
+        };
+        let mut bytes = [code, 0, 0, 0, 0];
+        bytes[1..].copy_from_slice(&threshold.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Condition, IoError> {
+        let threshold = i32::from_le_bytes([bytes[1], bytes[2], bytes[3], bytes[4]]);
+        match bytes[0] {
+            0 => Ok(Condition::Any),
+            1 => Ok(Condition::Above(threshold)),
+            2 => Ok(Condition::Below(threshold)),
+            code => Err(invalid(format!("invalid trigger condition {}", code))),
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Condition::Any => Ok(()),
+            Condition::Above(threshold) => write!(f, ">{}", threshold),
+            Condition::Below(threshold) => write!(f, "<{}", threshold),
+        }
+    }
+}
+
+/// Run a task `delay` s after the next `event` that meets `condition`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EventTrigger {
+    pub event: EventKind,
+    pub condition: Condition,
+    pub delay: u32,
+}
+
+impl EventTrigger {
+    pub fn is_triggered_by(&self, event: &Event) -> bool {
+        event.kind == self.event && self.condition.is_met_by(event.value)
+    }
+
+    pub fn to_bytes(&self) -> [u8; TRIGGER_SIZE] {
+        let mut bytes = [0u8; TRIGGER_SIZE];
+        bytes[0] = self.event as u8;
+        bytes[1..6].copy_from_slice(&self.condition.to_bytes());
+        bytes[6..].copy_from_slice(&self.delay.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<EventTrigger, IoError> {
+        let bytes = bytes.get(..TRIGGER_SIZE).ok_or_else(|| {
+            IoError::new(ErrorKind::InvalidInput, format!("a trigger is {} bytes, got {}", TRIGGER_SIZE, bytes.len()))
+        })?;
+        Ok(EventTrigger {
+            event: EventKind::try_from(bytes[0])?,
+            condition: Condition::from_bytes(&bytes[1..6])?,
+            delay: u32::from_le_bytes([bytes[6], bytes[7], bytes[8], bytes[9]]),
+        })
+    }
+}
+
+/// "BatteryVoltage>7400+60", as it is typed in the cli_ground_station
+impl fmt::Display for EventTrigger {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.event, self.condition)?;
+        if self.delay > 0 {
+            write!(f, "+{}", self.delay)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageState {
+    New,
+    Suspended,
+    Waiting,
+    Running,
+    Done,
+    Armed,
+}
+
+impl fmt::Display for MessageState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl TryFrom<u8> for MessageState {
+    type Error = IoError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(MessageState::New),
+            1 => Ok(MessageState::Suspended),
+            2 => Ok(MessageState::Waiting),
+            3 => Ok(MessageState::Running),
+            4 => Ok(MessageState::Done),
+            5 => Ok(MessageState::Armed),
+            _ => Err(invalid(format!("invalid task state {}", value))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskRow {
+    pub id: u16,
+    /// ms since the Unix epoch, 0 for a task waiting on an event
+    pub time: u64,
+    pub state: MessageState,
+    pub dest: u8,
+    pub opcode: u8,
+    pub msg_id: u16,
+    /// What the task is waiting on, if it is not a time
+    pub trigger: Option<EventTrigger>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskTable {
+    /// Number of tasks in the whole schedule
+    pub total: u16,
+    /// Index in the schedule of the first row
+    pub first: u16,
+    pub rows: Vec<TaskRow>,
+}
+
+impl TaskTable {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(TABLE_HEADER_SIZE + ROW_SIZE * self.rows.len());
+        bytes.extend(self.total.to_le_bytes());
+        bytes.extend(self.first.to_le_bytes());
+        for row in &self.rows {
+            bytes.extend(row.id.to_le_bytes());
+            match row.trigger {
+                Some(trigger) => bytes.extend(encode_time(trigger.delay as u64 * 1000)),
+                None => bytes.extend(encode_time(row.time)),
+            }
+            bytes.extend([row.state as u8, row.dest, row.opcode]);
+            bytes.extend(row.msg_id.to_le_bytes());
+            match row.trigger {
+                Some(trigger) => {
+                    bytes.push(trigger.event as u8);
+                    bytes.extend(trigger.condition.to_bytes());
+                }
+                None => bytes.extend([NO_EVENT, 0, 0, 0, 0, 0]),
+            }
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<TaskTable, IoError> {
+        if bytes.len() < TABLE_HEADER_SIZE || !(bytes.len() - TABLE_HEADER_SIZE).is_multiple_of(ROW_SIZE) {
+            return Err(invalid(format!("a task table can't be {} bytes", bytes.len())));
+        }
+        let u16_at = |b: &[u8], i: usize| u16::from_le_bytes([b[i], b[i + 1]]);
+        let rows = bytes[TABLE_HEADER_SIZE..]
+            .chunks(ROW_SIZE)
+            .map(|row| {
+                let mut time = [0u8; 8];
+                time[..TIME_SIZE].copy_from_slice(&row[2..2 + TIME_SIZE]);
+                let mut time = u64::from_le_bytes(time);
+                let trigger = match row[13] {
+                    NO_EVENT => None,
+                    event => {
+                        let delay = (time / 1000) as u32;
+                        time = 0;
+                        Some(EventTrigger { event: EventKind::try_from(event)?, condition: Condition::from_bytes(&row[14..])?, delay })
+                    }
+                };
+                Ok(TaskRow {
+                    id: u16_at(row, 0),
+                    time,
+                    state: MessageState::try_from(row[8])?,
+                    dest: row[9],
+                    opcode: row[10],
+                    msg_id: u16_at(row, 11),
+                    trigger,
+                })
+            })
+            .collect::<Result<Vec<_>, IoError>>()?;
+        Ok(TaskTable { total: u16_at(bytes, 0), first: u16_at(bytes, 2), rows })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table_round_trip() {
+        let row = |id, state| TaskRow { id, time: 1_717_243_200_000 + id as u64, state, dest: 4, opcode: 2, msg_id: 0x1234, trigger: None };
+        let mut rows: Vec<TaskRow> = (0..ROWS_PER_PAGE as u16).map(|id| row(id, MessageState::Waiting)).collect();
+        rows[1].trigger = Some(EventTrigger { event: EventKind::BatteryVoltage, condition: Condition::Below(-5), delay: 90 });
+        rows[1].time = 0;
+        rows[1].state = MessageState::Armed;
+        let table = TaskTable { total: 20, first: 8, rows };
+        let bytes = table.to_bytes();
+        assert!(bytes.len() <= DOWNLINK_MSG_BODY_SIZE);
+        assert_eq!(TaskTable::from_bytes(&bytes).unwrap(), table);
+
+        let empty = TaskTable { total: 0, first: 0, rows: vec![] };
+        assert_eq!(TaskTable::from_bytes(&empty.to_bytes()).unwrap(), empty);
+        assert!(TaskTable::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        let mut bad_state = TaskTable { total: 1, first: 0, rows: vec![row(1, MessageState::Done)] }.to_bytes();
+        bad_state[TABLE_HEADER_SIZE + 8] = 9;
+        assert!(TaskTable::from_bytes(&bad_state).is_err());
+    }
+
+    #[test]
+    fn test_triggers() {
+        let trigger = EventTrigger { event: EventKind::BatteryVoltage, condition: Condition::Above(7400), delay: 60 };
+        assert_eq!(EventTrigger::from_bytes(&trigger.to_bytes()).unwrap(), trigger);
+        assert_eq!(trigger.to_string(), "BatteryVoltage>7400+60");
+        let reading = |value| Event { kind: EventKind::BatteryVoltage, value };
+        assert!(trigger.is_triggered_by(&reading(7401)));
+        assert!(!trigger.is_triggered_by(&reading(7400)));
+        assert!(!trigger.is_triggered_by(&Event { kind: EventKind::GsPassStart, value: 7401 }));
+
+        let pass = EventTrigger { event: EventKind::GsPassStart, condition: Condition::Any, delay: 0 };
+        assert_eq!(pass.to_string(), "GsPassStart");
+        assert!(EventTrigger::from_bytes(&pass.to_bytes()[..TRIGGER_SIZE - 1]).is_err());
+        let mut bad_condition = pass.to_bytes();
+        bad_condition[1] = 3;
+        assert!(EventTrigger::from_bytes(&bad_condition).is_err());
+    }
+}
@@ -0,0 +1,355 @@
+/*
+Stored cmd sequences, run onboard by the sequencer.
+
+A sequence is written on the ground as text, one step per line:
+
+    IRIS ToggleSensor on        send a cmd, typed as in the cli_ground_station, and wait for its Report
+    wait 5                      wait 5 s
+    timeout 30                  wait up to 30 s for the Reports on the cmds after this, rather than 10
+    abort if Failed NoReply     abort the sequence if the outcome of the last cmd was one of these
+    abort unless Success        or if it was anything but these
+    loop 3                      run the steps up to the matching `end` 3 times
+    end
+
+The outcome of a cmd is the result code of its Report, Nacked if the cmd_dispatcher couldn't deliver it,
+or NoReply if its Report didn't come within the timeout. Blank lines and anything after a # are ignored.
+Cmds are typed with the args in the registry, so those whose body the CLI builds (ScheduleCmd, SHELL, ...)
+can't be part of a sequence.
+
+The text is compiled into steps, which are uploaded and stored as
+
+    number of steps (u16) | steps
+
+with each step starting with its kind:
+
+    0 cmd       timeout (u16, s) | length of the cmd (u16) | serialized cmd
+    1 wait      delay (u32, s)
+    2 abort if  outcome mask (u8), with bit n set to abort on outcome n
+    3 repeat    index of the first step of the loop (u16) | times to go back to it (u16)
+
+A loop is compiled into a repeat step where it ends. All integers are little endian.
+*/
+
+use crate::component_ids::ComponentIds;
+use crate::message_structure::{deserialize_msg, serialize_msg, Msg, MsgType, ResultCode};
+use crate::registry;
+use std::fmt;
+use std::io::{Error as IoError, ErrorKind};
+
+/// Largest sequence that can be stored
+pub const MAX_SEQUENCE_SIZE: usize = 4096;
+pub const MAX_NAME_LEN: usize = 32;
+/// How long to wait for the Report on a cmd, unless the sequence sets another timeout
+pub const DEFAULT_TIMEOUT: u16 = 10;
+
+const CMD: u8 = 0;
+const WAIT: u8 = 1;
+const ABORT_IF: u8 = 2;
+const REPEAT: u8 = 3;
+
+fn invalid(reason: String) -> IoError {
+    IoError::new(ErrorKind::InvalidData, reason)
+}
+
+/// Sequences are stored under their name, so it has to make a good file name
+pub fn check_name(name: &str) -> Result<(), IoError> {
+    if name.is_empty() || name.len() > MAX_NAME_LEN
+        || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        return Err(IoError::new(ErrorKind::InvalidInput, format!(
+            "bad sequence name '{}', expected up to {} letters, digits, _ or -", name, MAX_NAME_LEN)));
+    }
+    Ok(())
+}
+
+/// What came of a cmd a sequence sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Result(ResultCode),
+    Nacked,
+    NoReply,
+}
+
+const OUTCOMES: [Outcome; 7] = [
+    Outcome::Result(ResultCode::Success),
+    Outcome::Result(ResultCode::Failed),
+    Outcome::Result(ResultCode::InvalidArgs),
+    Outcome::Result(ResultCode::NotImplemented),
+    Outcome::Result(ResultCode::Unavailable),
+    Outcome::Nacked,
+    Outcome::NoReply,
+];
+
+impl Outcome {
+    /// Bit of the outcome in an abort mask
+    pub fn bit(&self) -> u8 {
+        let code = match *self {
+            Outcome::Result(code) => code as u8,
+            Outcome::Nacked => 5,
+            Outcome::NoReply => 6,
+        };
+        1 << code
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Result(code) => write!(f, "{}", code),
+            Outcome::Nacked => write!(f, "Nacked"),
+            Outcome::NoReply => write!(f, "NoReply"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Step {
+    /// Send `cmd` and wait up to `timeout` s for its Report
+    Cmd { timeout: u16, cmd: Msg },
+    /// Wait this many s
+    Wait(u32),
+    /// Abort if the outcome of the last cmd is in the mask
+    AbortIf(u8),
+    /// Go back to step `to` another `times` times
+    Repeat { to: u16, times: u16 },
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Step::Cmd { timeout, cmd } => {
+                let dest = registry::component_by_id(cmd.header.dest_id);
+                let op = dest.and_then(|c| c.opcode(&cmd.header.op_code.to_string()));
+                match (dest, op) {
+                    (Some(dest), Some(op)) => write!(f, "{} {}", dest.name, op.name)?,
+                    _ => write!(f, "{} {}", cmd.header.dest_id, cmd.header.op_code)?,
+                }
+                write!(f, " ({} byte body, {} s timeout)", cmd.msg_body.len(), timeout)
+            }
+            Step::Wait(secs) => write!(f, "wait {} s", secs),
+            Step::AbortIf(mask) => {
+                let outcomes: Vec<String> = OUTCOMES.iter().filter(|o| mask & o.bit() != 0).map(|o| o.to_string()).collect();
+                write!(f, "abort if {}", outcomes.join(" "))
+            }
+            Step::Repeat { to, times } => write!(f, "back to step {} {} more times", to, times),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Sequence {
+    pub steps: Vec<Step>,
+}
+
+impl Sequence {
+    pub fn to_bytes(&self) -> Result<Vec<u8>, IoError> {
+        let mut bytes = (self.steps.len() as u16).to_le_bytes().to_vec();
+        for step in &self.steps {
+            match step {
+                Step::Cmd { timeout, cmd } => {
+                    let cmd = serialize_msg(cmd)?;
+                    bytes.push(CMD);
+                    bytes.extend(timeout.to_le_bytes());
+                    bytes.extend((cmd.len() as u16).to_le_bytes());
+                    bytes.extend(cmd);
+                }
+                Step::Wait(secs) => {
+                    bytes.push(WAIT);
+                    bytes.extend(secs.to_le_bytes());
+                }
+                Step::AbortIf(mask) => bytes.extend([ABORT_IF, *mask]),
+                Step::Repeat { to, times } => {
+                    bytes.push(REPEAT);
+                    bytes.extend(to.to_le_bytes());
+                    bytes.extend(times.to_le_bytes());
+                }
+            }
+        }
+        if bytes.len() > MAX_SEQUENCE_SIZE {
+            return Err(invalid(format!("the sequence is {} bytes, at most {} can be stored", bytes.len(), MAX_SEQUENCE_SIZE)));
+        }
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Sequence, IoError> {
+        let mut rest = bytes;
+        let mut take = |n: usize| -> Result<&[u8], IoError> {
+            if rest.len() < n {
+                return Err(invalid(format!("sequence ends part way through, {} bytes in", bytes.len() - rest.len())));
+            }
+            let (taken, left) = rest.split_at(n);
+            rest = left;
+            Ok(taken)
+        };
+        let u16_from = |b: &[u8]| u16::from_le_bytes([b[0], b[1]]);
+        let count = u16_from(take(2)?);
+        let mut steps = Vec::with_capacity(count as usize);
+        for i in 0..count {
+            let step = match take(1)?[0] {
+                CMD => {
+                    let timeout = u16_from(take(2)?);
+                    let len = u16_from(take(2)?) as usize;
+                    let cmd = deserialize_msg(take(len)?).map_err(|e| invalid(format!("step {}: {}", i, e)))?;
+                    if cmd.header.msg_type != MsgType::Cmd {
+                        return Err(invalid(format!("step {} is a {:?}, not a cmd", i, cmd.header.msg_type)));
+                    }
+                    Step::Cmd { timeout, cmd }
+                }
+                WAIT => {
+                    let b = take(4)?;
+                    Step::Wait(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                }
+                ABORT_IF => Step::AbortIf(take(1)?[0]),
+                REPEAT => {
+                    let to = u16_from(take(2)?);
+                    if to > i {
+                        return Err(invalid(format!("step {} repeats from step {}, after it", i, to)));
+                    }
+                    Step::Repeat { to, times: u16_from(take(2)?) }
+                }
+                kind => return Err(invalid(format!("step {} is of unknown kind {}", i, kind))),
+            };
+            steps.push(step);
+        }
+        if !rest.is_empty() {
+            return Err(invalid(format!("{} bytes after the last step", rest.len())));
+        }
+        Ok(Sequence { steps })
+    }
+
+    /// Compile the text of a sequence. Errors give the line they are on
+    pub fn parse(text: &str) -> Result<Sequence, String> {
+        let mut steps = vec![];
+        let mut timeout = DEFAULT_TIMEOUT;
+        // Index of the first step, and the count, of each loop that hasn't ended yet
+        let mut loops: Vec<(usize, u16, usize)> = vec![];
+        for (i, line) in text.lines().enumerate() {
+            let line_no = i + 1;
+            let at_line = |e: String| format!("line {}: {}", line_no, e);
+            let tokens: Vec<&str> = line.split('#').next().unwrap_or("").split_whitespace().collect();
+            let Some(first) = tokens.first() else {
+                continue;
+            };
+            let number = |token: Option<&&str>, what: &str| -> Result<u32, String> {
+                token.and_then(|t| t.parse::<u32>().ok()).ok_or_else(|| at_line(format!("expected {} in whole seconds", what)))
+            };
+            match first.to_lowercase().as_str() {
+                "wait" => steps.push(Step::Wait(number(tokens.get(1), "a delay")?)),
+                "timeout" => {
+                    timeout = number(tokens.get(1), "a timeout")?.try_into().map_err(|_| at_line("timeout too long".to_string()))?;
+                }
+                "abort" => steps.push(Step::AbortIf(parse_abort(&tokens[1..]).map_err(at_line)?)),
+                "loop" => {
+                    let count = tokens.get(1).and_then(|t| t.parse::<u16>().ok()).filter(|&n| n > 0)
+                        .ok_or_else(|| at_line("expected how many times to loop".to_string()))?;
+                    loops.push((steps.len(), count, line_no));
+                }
+                "end" => {
+                    let (start, count, _) = loops.pop().ok_or_else(|| at_line("end without a loop".to_string()))?;
+                    steps.push(Step::Repeat { to: start as u16, times: count - 1 });
+                }
+                _ => steps.push(Step::Cmd { timeout, cmd: parse_cmd(&tokens).map_err(at_line)? }),
+            }
+        }
+        if let Some((_, _, line_no)) = loops.pop() {
+            return Err(format!("line {}: loop without an end", line_no));
+        }
+        Ok(Sequence { steps })
+    }
+}
+
+/// The mask of "abort if <outcomes>" or "abort unless <outcomes>"
+fn parse_abort(tokens: &[&str]) -> Result<u8, String> {
+    let (unless, names) = match tokens.split_first() {
+        Some((word, names)) if word.eq_ignore_ascii_case("if") => (false, names),
+        Some((word, names)) if word.eq_ignore_ascii_case("unless") => (true, names),
+        _ => return Err("expected abort if <outcomes> or abort unless <outcomes>".to_string()),
+    };
+    if names.is_empty() {
+        return Err("expected the outcomes to abort on".to_string());
+    }
+    let mut mask = 0;
+    for name in names {
+        let outcome = OUTCOMES.iter().find(|o| o.to_string().eq_ignore_ascii_case(name)).ok_or_else(|| {
+            let all: Vec<String> = OUTCOMES.iter().map(|o| o.to_string()).collect();
+            format!("unknown outcome {}, expected one of {}", name, all.join(", "))
+        })?;
+        mask |= outcome.bit();
+    }
+    let all = OUTCOMES.iter().fold(0, |mask, o| mask | o.bit());
+    Ok(if unless { all & !mask } else { mask })
+}
+
+fn parse_cmd(tokens: &[&str]) -> Result<Msg, String> {
+    let component = registry::component(tokens[0]).ok_or_else(|| format!("unknown payload or step {}", tokens[0]))?;
+    let op_name = tokens.get(1).ok_or_else(|| format!("expected an opcode for {}", component.name))?;
+    let op = component.opcode(op_name).ok_or_else(|| format!("unknown opcode for {}: {}", component.name, op_name))?;
+    let body = op.encode(&tokens[2..]).map_err(|e| format!("bad arguments for {} {}: {}", component.name, op.name, e))?;
+    Ok(Msg::new(MsgType::Cmd, 0, component.id, ComponentIds::Sequencer as u8, op.value, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CAPTURE: &str = "
+        # Capture an image and fetch it, trying up to 3 times
+        timeout 30
+        loop 3
+            IRIS ToggleSensor on
+            wait 5
+            IRIS CaptureImage       # takes a while
+            abort unless Success Unavailable
+        end
+        IRIS FetchImage 1
+        abort if NoReply nacked
+    ";
+
+    #[test]
+    fn test_parse() {
+        let sequence = Sequence::parse(CAPTURE).unwrap();
+        assert_eq!(sequence.steps.len(), 7);
+        let Step::Cmd { timeout, ref cmd } = sequence.steps[0] else { panic!("{}", sequence.steps[0]) };
+        assert_eq!((timeout, cmd.header.dest_id, cmd.header.op_code), (30, ComponentIds::IRIS as u8, 1));
+        assert_eq!(cmd.msg_body, [1]);
+        assert!(matches!(sequence.steps[1], Step::Wait(5)));
+        let unavailable = Outcome::Result(ResultCode::Unavailable).bit();
+        let Step::AbortIf(mask) = sequence.steps[3] else { panic!("{}", sequence.steps[3]) };
+        assert_eq!(mask & (Outcome::Result(ResultCode::Success).bit() | unavailable), 0);
+        assert_ne!(mask & Outcome::NoReply.bit(), 0);
+        assert!(matches!(sequence.steps[4], Step::Repeat { to: 0, times: 2 }));
+        assert!(matches!(sequence.steps[6], Step::AbortIf(mask) if mask == Outcome::NoReply.bit() | Outcome::Nacked.bit()));
+        assert_eq!(sequence.steps[6].to_string(), "abort if Nacked NoReply");
+
+        for (text, line) in [("wait", 1), ("\nloop 2\nwait 1", 2), ("end", 1), ("EPS Explode", 1), ("IRIS FetchImage 0", 1),
+                             ("wait 1\nabort when Failed", 2), ("abort if Late", 1), ("Moon On", 1)] {
+            let e = Sequence::parse(text).unwrap_err();
+            assert!(e.starts_with(&format!("line {}:", line)), "{}: {}", text, e);
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let sequence = Sequence::parse(CAPTURE).unwrap();
+        let bytes = sequence.to_bytes().unwrap();
+        let decoded = Sequence::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.to_bytes().unwrap(), bytes);
+        assert_eq!(decoded.steps.iter().map(|s| s.to_string()).collect::<Vec<_>>(),
+                   sequence.steps.iter().map(|s| s.to_string()).collect::<Vec<_>>());
+
+        assert!(Sequence::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Sequence::from_bytes(&[bytes.as_slice(), &[0]].concat()).is_err());
+        // Loops can only go back
+        let forward = Sequence { steps: vec![Step::Repeat { to: 1, times: 1 }, Step::Wait(1)] };
+        assert!(Sequence::from_bytes(&forward.to_bytes().unwrap()).is_err());
+        let too_long = Sequence { steps: vec![Step::Wait(1); MAX_SEQUENCE_SIZE] };
+        assert!(too_long.to_bytes().is_err());
+    }
+
+    #[test]
+    fn test_names() {
+        assert!(check_name("capture_image-2").is_ok());
+        for bad in ["", "../keys", "a b", &"x".repeat(MAX_NAME_LEN + 1)] {
+            assert_eq!(check_name(bad).unwrap_err().kind(), ErrorKind::InvalidInput);
+        }
+    }
+}
@@ -0,0 +1,228 @@
+/*
+Onboard time.
+
+Everything on the OBC that needs the time - the scheduler, logs, housekeeping - takes it from here rather
+than from the system clock. The onboard UTC is the system clock plus a correction, which the time service
+works out whenever the GS syncs the time, and mission elapsed time (MET) is the time since the mission
+epoch, which the time service sets to the UTC it first starts at unless the GS sets another.
+
+The correction and the epoch are kept in the clock file, clock.json in the time service's data dir, so
+every process sees the same time and it survives the OBC rebooting. Processes re-read the file every
+RELOAD_INTERVAL, so a sync reaches them within that. Until the time service has run, there is no
+correction and MET counts from when the process first asked for the time.
+*/
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{Error as IoError, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub const CLOCK_FILE: &str = "clock.json";
+/// How often processes check the clock file for a new correction
+pub const RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What is kept in the clock file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClockState {
+    /// Added to the system clock to get UTC, in ms
+    pub offset_ms: i64,
+    /// UTC MET counts from, in ms since the Unix epoch
+    pub met_epoch_ms: Option<i64>,
+    /// Onboard UTC of the last sync, in ms
+    pub synced_ms: Option<i64>,
+    /// Round trip delay the GS gave with the last sync, in ms
+    pub rtt_ms: Option<u32>,
+}
+
+fn system_millis() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |t| t.as_millis() as i64)
+}
+
+pub struct Clock {
+    path: PathBuf,
+    state: ClockState,
+    loaded_at: Instant,
+    /// MET epoch to use while the clock file doesn't give one
+    default_epoch_ms: i64,
+}
+
+impl Clock {
+    /// The clock kept in the file at `path`. A missing file is a clock that was never set
+    pub fn open(path: &Path) -> Result<Clock, IoError> {
+        let mut clock = Clock { path: path.to_path_buf(), state: ClockState::default(), loaded_at: Instant::now(),
+                                default_epoch_ms: 0 };
+        clock.reload()?;
+        clock.default_epoch_ms = clock.utc_millis();
+        Ok(clock)
+    }
+
+    /// Read the clock file again
+    pub fn reload(&mut self) -> Result<(), IoError> {
+        self.loaded_at = Instant::now();
+        self.state = match fs::read_to_string(&self.path) {
+            Ok(text) => serde_json::from_str(&text)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => ClockState::default(),
+            Err(e) => return Err(e),
+        };
+        Ok(())
+    }
+
+    fn reload_if_stale(&mut self) {
+        if self.loaded_at.elapsed() >= RELOAD_INTERVAL {
+            // A clock file that can't be read leaves the time as it was rather than jumping
+            let state = self.state;
+            if self.reload().is_err() {
+                self.state = state;
+            }
+        }
+    }
+
+    pub fn state(&mut self) -> ClockState {
+        self.reload_if_stale();
+        self.state
+    }
+
+    /// Onboard UTC, in ms since the Unix epoch
+    pub fn utc_millis(&mut self) -> i64 {
+        self.reload_if_stale();
+        system_millis() + self.state.offset_ms
+    }
+
+    /// Mission elapsed time, in ms
+    pub fn met_millis(&mut self) -> i64 {
+        let utc = self.utc_millis();
+        utc - self.state.met_epoch_ms.unwrap_or(self.default_epoch_ms)
+    }
+
+    /// Correct the clock so that it read `utc_ms` half of `rtt_ms` ago, i.e. when the GS sent the sync.
+    /// Returns how far the clock moved, in ms
+    pub fn sync(&mut self, utc_ms: i64, rtt_ms: u32) -> Result<i64, IoError> {
+        let before = self.utc_millis();
+        let mut state = self.state;
+        state.offset_ms = utc_ms + rtt_ms as i64 / 2 - system_millis();
+        state.synced_ms = Some(utc_ms + rtt_ms as i64 / 2);
+        state.rtt_ms = Some(rtt_ms);
+        self.save(state)?;
+        Ok(self.utc_millis() - before)
+    }
+
+    pub fn set_met_epoch(&mut self, epoch_ms: i64) -> Result<(), IoError> {
+        let mut state = self.state;
+        state.met_epoch_ms = Some(epoch_ms);
+        self.save(state)
+    }
+
+    /// Start MET now, unless it has been started already
+    pub fn start_met(&mut self) -> Result<(), IoError> {
+        if self.state().met_epoch_ms.is_some() {
+            return Ok(());
+        }
+        let now = self.utc_millis();
+        self.set_met_epoch(now)
+    }
+
+    /// Write the clock file, through a temporary file so a reset part way through leaves the old one
+    fn save(&mut self, state: ClockState) -> Result<(), IoError> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp_path = self.path.with_extension("tmp");
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(serde_json::to_string(&state)?.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &self.path)?;
+        self.state = state;
+        self.loaded_at = Instant::now();
+        Ok(())
+    }
+}
+
+/// Path of the clock file from the config
+pub fn clock_path() -> PathBuf {
+    crate::config::get().data_dir("time").join(CLOCK_FILE)
+}
+
+static CLOCK: Mutex<Option<Clock>> = Mutex::new(None);
+
+/// Run `f` on this process's clock, opening it the first time
+fn with_clock<T>(f: impl FnOnce(&mut Clock) -> T) -> T {
+    let mut clock = CLOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let clock = clock.get_or_insert_with(|| {
+        let path = clock_path();
+        // Nothing is logged here, as the logger takes its timestamps from this clock
+        Clock::open(&path).unwrap_or_else(|e| {
+            eprintln!("Cannot read the clock file {:?}, using the system clock: {}", path, e);
+            Clock { path, state: ClockState::default(), loaded_at: Instant::now(), default_epoch_ms: system_millis() }
+        })
+    });
+    f(clock)
+}
+
+/// Onboard UTC, in ms since the Unix epoch
+pub fn utc_millis() -> i64 {
+    with_clock(|clock| clock.utc_millis())
+}
+
+/// Onboard UTC
+pub fn utc_now() -> DateTime<Utc> {
+    DateTime::from_timestamp_millis(utc_millis()).unwrap_or_default()
+}
+
+/// Mission elapsed time, in ms
+pub fn met_millis() -> i64 {
+    with_clock(|clock| clock.met_millis())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_sync() {
+        let dir = TempDir::new("time").unwrap();
+        let path = dir.path().join(CLOCK_FILE);
+        let mut clock = Clock::open(&path).unwrap();
+        assert_eq!(clock.state(), ClockState::default());
+        assert!((clock.utc_millis() - system_millis()).abs() < 1000);
+
+        // The GS is an hour ahead, and the sync took 2 s to get up
+        let gs_time = system_millis() + 3_600_000 - 2000;
+        let moved = clock.sync(gs_time, 4000).unwrap();
+        assert!((moved - 3_600_000).abs() < 1000, "{}", moved);
+        assert!((clock.utc_millis() - (system_millis() + 3_600_000)).abs() < 1000);
+        let state = clock.state();
+        assert_eq!((state.synced_ms, state.rtt_ms), (Some(gs_time + 2000), Some(4000)));
+
+        // Other processes see the correction
+        let mut other = Clock::open(&path).unwrap();
+        assert_eq!(other.state(), state);
+        clock.sync(system_millis(), 0).unwrap();
+        other.reload().unwrap();
+        assert!(other.state().offset_ms.abs() < 1000);
+    }
+
+    #[test]
+    fn test_met() {
+        let dir = TempDir::new("time").unwrap();
+        let path = dir.path().join(CLOCK_FILE);
+        let mut clock = Clock::open(&path).unwrap();
+        // Counts from when the clock was opened until MET is started
+        assert!((0..1000).contains(&clock.met_millis()));
+
+        let a_day_ago = clock.utc_millis() - 86_400_000;
+        clock.set_met_epoch(a_day_ago).unwrap();
+        clock.start_met().unwrap();
+        assert!((clock.met_millis() - 86_400_000).abs() < 1000);
+        // Moving the clock moves MET with it
+        clock.sync(system_millis() + 60_000, 0).unwrap();
+        assert!((clock.met_millis() - 86_460_000).abs() < 1000);
+
+        let mut fresh = Clock::open(&dir.path().join("other.json")).unwrap();
+        fresh.start_met().unwrap();
+        assert!((0..1000).contains(&fresh.met_millis()));
+    }
+}
@@ -0,0 +1,74 @@
+/*
+Publishing on the event bus (see common::events).
+
+An event is a Scheduler PublishEvent cmd sent through the cmd_dispatcher, so it reaches the scheduler
+wherever it is registered. Events published while the cmd_dispatcher can't be reached are queued and
+sent once it is back, keeping only the latest MAX_QUEUED.
+*/
+
+use common::component_ids::ComponentIds;
+use common::events::EventKind;
+use common::message_structure::{serialize_msg, Msg, MsgType};
+use common::msg_id::MsgIdAllocator;
+use common::opcodes;
+use interface::ipc::CMD_DISPATCHER_SOCKET;
+use interface::reconnect::{Reconnecting, WhileDown};
+use interface::Interface;
+use log::{debug, warn};
+
+/// Most events kept while the cmd_dispatcher is down
+const MAX_QUEUED: usize = 16;
+
+pub struct EventPublisher {
+    component: ComponentIds,
+    bus: Box<dyn Interface>,
+    msg_ids: MsgIdAllocator,
+}
+
+impl EventPublisher {
+    /// Publish events raised by `component` through the cmd_dispatcher
+    pub fn new(component: ComponentIds) -> EventPublisher {
+        let bus = Reconnecting::ipc_client(CMD_DISPATCHER_SOCKET).while_down(WhileDown::Queue(MAX_QUEUED));
+        EventPublisher::with_bus(component, Box::new(bus))
+    }
+
+    /// Publish events by sending them on `bus`
+    pub fn with_bus(component: ComponentIds, bus: Box<dyn Interface>) -> EventPublisher {
+        EventPublisher { component, bus, msg_ids: MsgIdAllocator::spacecraft(component) }
+    }
+
+    /// Publish an event. Failing to is only logged, as nothing the publisher does depends on it
+    pub fn publish(&mut self, kind: EventKind, value: i32) {
+        let mut body = vec![kind as u8];
+        body.extend(value.to_le_bytes());
+        let msg = Msg::new(MsgType::Cmd, self.msg_ids.next_id(), ComponentIds::Scheduler as u8, self.component as u8,
+                           opcodes::Scheduler::PublishEvent as u8, body);
+        match serialize_msg(&msg).and_then(|bytes| self.bus.send(&bytes)) {
+            Ok(_) => debug!("Published {} {}", kind, value),
+            Err(e) => warn!("Cannot publish {} {}: {}", kind, value, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::message_structure::deserialize_msg;
+    use interface::mock::pipe;
+
+    #[test]
+    fn test_publishes_to_the_scheduler() {
+        let (bus, mut scheduler) = pipe();
+        let mut events = EventPublisher::with_bus(ComponentIds::EPS, Box::new(bus));
+        events.publish(EventKind::BatteryVoltage, 7400);
+
+        let mut buf = [0u8; 64];
+        let len = scheduler.read(&mut buf).unwrap();
+        let msg = deserialize_msg(&buf[..len]).unwrap();
+        assert_eq!(msg.header.dest_id, ComponentIds::Scheduler as u8);
+        assert_eq!(msg.header.source_id, ComponentIds::EPS as u8);
+        let args = opcodes::Scheduler::PublishEvent.decode(&msg.msg_body).unwrap();
+        assert_eq!(args[0].int::<u8>(), Some(EventKind::BatteryVoltage as u8));
+        assert_eq!(args[1].int::<i32>(), Some(7400));
+    }
+}
@@ -0,0 +1,55 @@
+/*
+Shared main loop for the subsystem handlers.
+
+A handler implements `Handler` with what is particular to its subsystem - executing cmds, and anything
+it does periodically or when the subsystem sends it something - and `HandlerRuntime` does the rest:
+receiving cmds from the cmd_dispatcher, sending their Reports to whoever is waiting on them, registering with the
+cmd_dispatcher, connecting and reconnecting to the subsystem, and writing housekeeping. Handlers that
+raise events for the scheduler publish them with an `EventPublisher`, and those with a main loop of their
+own send their Reports with a `Reporter`.
+*/
+
+pub mod events;
+pub mod report;
+pub mod runtime;
+
+pub use events::EventPublisher;
+pub use report::Reporter;
+pub use runtime::HandlerRuntime;
+
+use common::component_ids::ComponentIds;
+use common::house_keeping::HKData;
+use common::message_structure::Msg;
+use interface::Interface;
+use std::io::Error as IoError;
+
+/// Data a cmd produced, which is sent back in its Report
+pub type Response = Vec<u8>;
+
+pub trait Handler {
+    /// Component the handler looks after. Its socket is named after it
+    const COMPONENT: ComponentIds;
+
+    /// Execute a cmd from the cmd_dispatcher. `peripheral` is None while the subsystem isn't connected
+    fn on_command(&mut self, cmd: &Msg, peripheral: Option<&mut dyn Interface>) -> Result<Response, IoError>;
+
+    /// A Report or Ack sent to the handler, e.g. on a cmd it sent onboard
+    fn on_report(&mut self, _msg: &Msg) -> Result<(), IoError> {
+        Ok(())
+    }
+
+    /// Run every tick, if the runtime was given a tick period
+    fn on_tick(&mut self, _peripheral: Option<&mut dyn Interface>) -> Result<(), IoError> {
+        Ok(())
+    }
+
+    /// Data the subsystem sent that wasn't read as the response to a cmd
+    fn on_peripheral_data(&mut self, _data: &[u8]) -> Result<(), IoError> {
+        Ok(())
+    }
+
+    /// Add the subsystem's housekeeping to `hk`, if the runtime was given somewhere to write it
+    fn collect_hk(&mut self, _hk: &mut HKData, _peripheral: Option<&mut dyn Interface>) -> Result<(), IoError> {
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+/*
+Sending the Reports on cmds back to whoever is waiting on them.
+
+The Report on a cmd the GS sent goes to the GS through the coms_handler, and the Report on a cmd from
+a component in REPORTED_COMPONENTS goes back to it through the cmd_dispatcher. Reports for those are
+queued while the cmd_dispatcher can't be reached, keeping the latest MAX_QUEUED_REPORTS. Nobody else
+waits on Reports, so cmds from other components aren't reported.
+*/
+
+use common::component_ids::ComponentIds;
+use common::message_structure::{serialize_msg, Msg};
+use interface::ipc::{IpcClient, CMD_DISPATCHER_SOCKET};
+use interface::reconnect::{Reconnecting, WhileDown};
+use interface::Interface;
+use log::{debug, warn};
+use std::io::Error as IoError;
+
+/// Socket of the coms_handler that downlinks Reports to the GS
+pub const GS_SOCKET: &str = "gs_non_bulk";
+/// Components onboard that want the Reports on the cmds they send
+pub const REPORTED_COMPONENTS: [ComponentIds; 2] = [ComponentIds::Sequencer, ComponentIds::Time];
+/// Most Reports kept for those components while the cmd_dispatcher is down
+const MAX_QUEUED_REPORTS: usize = 16;
+
+pub struct Reporter {
+    component: ComponentIds,
+    gs_interface: Reconnecting<IpcClient>,
+    onboard_interface: Reconnecting<IpcClient>,
+}
+
+impl Reporter {
+    /// Report on the cmds `component` executes
+    pub fn new(component: ComponentIds) -> Reporter {
+        Reporter::with_sockets(component, GS_SOCKET, CMD_DISPATCHER_SOCKET)
+    }
+
+    /// Send the GS's Reports to `gs_socket`, and the others to `onboard_socket`
+    pub fn with_sockets(component: ComponentIds, gs_socket: &str, onboard_socket: &str) -> Reporter {
+        Reporter {
+            component,
+            gs_interface: Reconnecting::ipc_client(gs_socket),
+            onboard_interface: Reconnecting::ipc_client(onboard_socket)
+                .while_down(WhileDown::Queue(MAX_QUEUED_REPORTS)),
+        }
+    }
+
+    /// Whether the source of `cmd` is waiting on its Report
+    fn is_reported(cmd: &Msg) -> bool {
+        let source = cmd.header.source_id;
+        source == ComponentIds::GS as u8 || REPORTED_COMPONENTS.iter().any(|c| *c as u8 == source)
+    }
+
+    /// Send the Report on `cmd`, with the response if it succeeded, if its source is waiting on it
+    pub fn report(&mut self, cmd: &Msg, result: Result<Vec<u8>, IoError>) {
+        if let Err(ref e) = result {
+            warn!("{} cmd {} failed: {}", self.component, cmd.header.msg_id, e);
+        }
+        let source = cmd.header.source_id;
+        let interface = if source == ComponentIds::GS as u8 {
+            &mut self.gs_interface
+        } else if Reporter::is_reported(cmd) {
+            &mut self.onboard_interface
+        } else {
+            debug!("No Report on {} cmd {} from {}", self.component, cmd.header.msg_id, source);
+            return;
+        };
+        if let Err(e) = serialize_msg(&cmd.report(result)).and_then(|bytes| interface.send(&bytes)) {
+            warn!("Error sending report to {}: {}", source, e);
+        }
+    }
+
+    /// Reconnect whichever link is down if it is time to, sending the Reports queued for it
+    pub fn maintain(&mut self) {
+        self.gs_interface.maintain();
+        self.onboard_interface.maintain();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::message_structure::{deserialize_msg, MsgType, ResultCode};
+    use interface::ipc::{poll_ipc_server_sockets, IpcServer};
+    use std::io::ErrorKind;
+
+    fn recv(server: &mut Option<IpcServer>) -> Option<Msg> {
+        let _ = poll_ipc_server_sockets(&mut vec![server]);
+        server.as_mut().unwrap().recv().map(|frame| deserialize_msg(&frame.data).unwrap())
+    }
+
+    #[test]
+    fn test_routes_reports_by_source() {
+        let mut gs = Some(IpcServer::new("report_test_gs".to_string()).unwrap());
+        let mut onboard = Some(IpcServer::new("report_test_dispatcher".to_string()).unwrap());
+        let mut reporter = Reporter::with_sockets(ComponentIds::IRIS, "report_test_gs", "report_test_dispatcher");
+        let iris = ComponentIds::IRIS as u8;
+
+        reporter.report(&Msg::new(MsgType::Cmd, 1, iris, ComponentIds::GS as u8, 0, vec![]), Ok(vec![1]));
+        reporter.report(&Msg::new(MsgType::Cmd, 2, iris, ComponentIds::Time as u8, 0, vec![]),
+                        Err(IoError::new(ErrorKind::Unsupported, "no")));
+        reporter.report(&Msg::new(MsgType::Cmd, 3, iris, ComponentIds::DFGM as u8, 0, vec![]), Ok(vec![]));
+
+        let report = recv(&mut gs).unwrap();
+        assert_eq!((report.header.msg_type, report.header.msg_id, report.msg_body), (MsgType::Report, 1, vec![1]));
+        let report = recv(&mut onboard).unwrap();
+        assert_eq!((report.header.msg_id, report.header.dest_id), (2, ComponentIds::Time as u8));
+        assert_eq!(report.result_code(), Some(Ok(ResultCode::NotImplemented)));
+        // The DFGM isn't waiting on its Report
+        assert!(recv(&mut onboard).is_none());
+    }
+}
@@ -0,0 +1,350 @@
+/*
+Runs a Handler.
+
+Cmds are received on the handler's socket, named after its component, and executed with `on_command`.
+Their Reports are sent back with a Reporter, to the GS or to the component onboard that sent them. Reports and
+Acks the handler receives go to `on_report` rather than being executed. The subsystem is
+connected to with the function given to `peripheral`, and reconnected to with backoff whenever the link
+is lost - while it is down the handler's reads and sends to it fail with an "unavailable" error.
+Anything the subsystem sends without being asked goes to `on_peripheral_data`. Everything runs on one
+reactor, so the runtime only wakes up when there is something to do.
+*/
+
+use crate::report::{Reporter, GS_SOCKET};
+use crate::Handler;
+use common::house_keeping::HKData;
+use common::logging::init_logger;
+use common::message_structure::*;
+use interface::ipc::{IpcServer, Registration};
+use interface::factory::{InterfaceSpec, Peripheral};
+use interface::reactor::{Pollable, Reactor};
+use interface::ipc::CMD_DISPATCHER_SOCKET;
+use interface::reconnect::{Link, Reconnecting};
+use interface::tcp::{TcpInterface, BUFFER_SIZE};
+use interface::Interface;
+use log::{debug, trace, warn};
+use std::io::Error as IoError;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How often to check whether it is time to try reconnecting the subsystem or the GS
+const RECONNECT_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// How long the runtime waits when nothing happens, before going round the loop again
+const IDLE_TIMEOUT: Duration = Duration::from_secs(1);
+
+type Connect<P> = Box<dyn FnMut() -> Result<P, IoError>>;
+
+/// What the reactor's callbacks work on
+struct State<H, P> {
+    handler: H,
+    dispatcher_interface: Option<IpcServer>,
+    reporter: Option<Reporter>,
+    peripheral: Option<Reconnecting<P>>,
+    registration: Option<Registration>,
+    hk_path: Option<String>,
+}
+
+pub struct HandlerRuntime<H, P = TcpInterface> {
+    state: State<H, P>,
+    reactor: Reactor<State<H, P>>,
+    socket_name: String,
+    gs_socket: String,
+    report_socket: String,
+    connect: Option<Connect<P>>,
+    tick: Option<Duration>,
+    hk_period: Option<Duration>,
+    log_path: Option<PathBuf>,
+    started: bool,
+}
+
+impl<H, P> HandlerRuntime<H, P>
+where
+    H: Handler + 'static,
+    P: Link + Pollable + 'static,
+{
+    pub fn new(handler: H) -> HandlerRuntime<H, P> {
+        HandlerRuntime {
+            state: State {
+                handler,
+                dispatcher_interface: None,
+                reporter: None,
+                peripheral: None,
+                registration: None,
+                hk_path: None,
+            },
+            reactor: Reactor::new(),
+            socket_name: H::COMPONENT.to_string(),
+            gs_socket: GS_SOCKET.to_string(),
+            report_socket: CMD_DISPATCHER_SOCKET.to_string(),
+            connect: None,
+            tick: None,
+            hk_period: None,
+            log_path: None,
+            started: false,
+        }
+    }
+
+    /// Receive cmds on a socket other than the one named after the handler's component
+    pub fn socket_name(mut self, name: &str) -> Self {
+        self.socket_name = name.to_string();
+        self
+    }
+
+    /// Send Reports to a socket other than GS_SOCKET
+    pub fn gs_socket(mut self, name: &str) -> Self {
+        self.gs_socket = name.to_string();
+        self
+    }
+
+    /// Send the Reports for components onboard to a socket other than the cmd_dispatcher's
+    pub fn report_socket(mut self, name: &str) -> Self {
+        self.report_socket = name.to_string();
+        self
+    }
+
+    /// Connect to the subsystem with `connect`
+    pub fn peripheral<F>(mut self, connect: F) -> Self
+    where
+        F: FnMut() -> Result<P, IoError> + 'static,
+    {
+        self.connect = Some(Box::new(connect));
+        self
+    }
+
+    /// Run the handler's `on_tick` every `period`
+    pub fn tick(mut self, period: Duration) -> Self {
+        self.tick = Some(period);
+        self
+    }
+
+    /// Write the housekeeping the handler collects to `path` every `period`
+    pub fn hk(mut self, period: Duration, path: &str) -> Self {
+        self.hk_period = Some(period);
+        self.state.hk_path = Some(path.to_string());
+        self
+    }
+
+    /// Log to `path`, usually the handler's log dir from the config (see common::config)
+    pub fn logs(mut self, path: impl AsRef<Path>) -> Self {
+        self.log_path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn handler(&self) -> &H {
+        &self.state.handler
+    }
+
+    pub fn handler_mut(&mut self) -> &mut H {
+        &mut self.state.handler
+    }
+
+    /// Run the handler for good. Errors are logged, not returned
+    pub fn run(&mut self) -> ! {
+        loop {
+            self.run_once(IDLE_TIMEOUT);
+        }
+    }
+
+    /// Wait for at most `timeout` for something to do, and do it
+    pub fn run_once(&mut self, timeout: Duration) {
+        if !self.started {
+            self.start();
+        }
+        match self.reactor.run_once(&mut self.state, timeout) {
+            Ok(errors) => {
+                for (source, e) in errors {
+                    warn!("{} error handling {:?}: {}", H::COMPONENT, source, e);
+                }
+            }
+            Err(e) => warn!("{} error waiting for input: {}", H::COMPONENT, e),
+        }
+    }
+
+    fn start(&mut self) {
+        self.started = true;
+        if let Some(ref log_path) = self.log_path {
+            init_logger(&log_path.to_string_lossy());
+        }
+        trace!("Starting {} Handler...", H::COMPONENT);
+
+        let state = &mut self.state;
+        state.dispatcher_interface = IpcServer::new(self.socket_name.clone())
+            .inspect_err(|e| warn!("Error creating dispatcher interface: {:?}", e))
+            .ok();
+        state.reporter = Some(Reporter::with_sockets(H::COMPONENT, &self.gs_socket, &self.report_socket));
+        if let Some(connect) = self.connect.take() {
+            state.peripheral = Some(Reconnecting::new(&H::COMPONENT.to_string(), connect));
+        }
+        state.registration = Some(Registration::new(H::COMPONENT, &self.socket_name));
+
+        self.reactor.add_source(|s: &mut State<H, P>| s.dispatcher_interface.as_mut(), State::handle_cmds);
+        self.reactor.add_source(|s: &mut State<H, P>| s.peripheral.as_mut(), State::handle_peripheral_data);
+        // Renews the registration when it is due
+        self.reactor.every(Duration::from_secs(1), |s: &mut State<H, P>| {
+            if let Some(ref mut registration) = s.registration {
+                registration.maintain();
+            }
+            Ok(())
+        });
+        self.reactor.every(RECONNECT_CHECK_INTERVAL, |s: &mut State<H, P>| {
+            if let Some(ref mut reporter) = s.reporter {
+                reporter.maintain();
+            }
+            if let Some(ref mut peripheral) = s.peripheral {
+                peripheral.maintain();
+            }
+            Ok(())
+        });
+        if let Some(period) = self.tick {
+            self.reactor.every(period, |s: &mut State<H, P>| {
+                s.handler.on_tick(s.peripheral.as_mut().map(|p| p as &mut dyn Interface))
+            });
+        }
+        if let Some(period) = self.hk_period {
+            self.reactor.every(period, State::write_hk);
+        }
+    }
+}
+
+impl<H: Handler + 'static> HandlerRuntime<H, Box<dyn Peripheral>> {
+    /// Connect to the subsystem with the interface `spec` describes
+    pub fn peripheral_spec(self, spec: InterfaceSpec) -> Self {
+        self.peripheral(move || spec.open())
+    }
+}
+
+impl<H: Handler, P: Link> State<H, P> {
+    /// Cmds from the cmd_dispatcher
+    fn handle_cmds(&mut self) -> Result<(), IoError> {
+        while let Some(frame) = self.dispatcher_interface.as_mut().and_then(|s| s.recv()) {
+            let cmd = match deserialize_msg(&frame.data) {
+                Ok(cmd) => cmd,
+                Err(e) => {
+                    warn!("Error deserializing {} cmd: {:?}", H::COMPONENT, e);
+                    continue;
+                }
+            };
+            if matches!(cmd.header.msg_type, MsgType::Report | MsgType::Ack) {
+                debug!("{} received {:?} on msg {}", H::COMPONENT, cmd.header.msg_type, cmd.header.msg_id);
+                if let Err(e) = self.handler.on_report(&cmd) {
+                    warn!("{} error handling {:?} on msg {}: {}", H::COMPONENT, cmd.header.msg_type, cmd.header.msg_id, e);
+                }
+                continue;
+            }
+            debug!("{} received cmd {} with opcode {}", H::COMPONENT, cmd.header.msg_id, cmd.header.op_code);
+            let res = self.handler.on_command(&cmd, self.peripheral.as_mut().map(|p| p as &mut dyn Interface));
+            if let Some(ref mut reporter) = self.reporter {
+                reporter.report(&cmd, res);
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_peripheral_data(&mut self) -> Result<(), IoError> {
+        let Some(ref mut peripheral) = self.peripheral else {
+            return Ok(());
+        };
+        let mut buf = [0u8; BUFFER_SIZE];
+        // Once the link is lost it isn't polled until it is back
+        let n = peripheral.read(&mut buf)?;
+        trace!("From {} got {} bytes", H::COMPONENT, n);
+        self.handler.on_peripheral_data(&buf[..n])
+    }
+
+    fn write_hk(&mut self) -> Result<(), IoError> {
+        let Some(path) = self.hk_path.clone() else {
+            return Ok(());
+        };
+        let mut hk = HKData::new(H::COMPONENT);
+        self.handler.collect_hk(&mut hk, self.peripheral.as_mut().map(|p| p as &mut dyn Interface))?;
+        hk.write_to_file(&path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::component_ids::ComponentIds;
+    use interface::ipc::{poll_ipc_server_sockets, IpcClient};
+    use std::io::ErrorKind;
+
+    #[derive(Default)]
+    struct Echo {
+        ticks: usize,
+        reports: Vec<u16>,
+    }
+
+    impl Handler for Echo {
+        const COMPONENT: ComponentIds = ComponentIds::GPS;
+
+        fn on_command(&mut self, cmd: &Msg, peripheral: Option<&mut dyn Interface>) -> Result<Vec<u8>, IoError> {
+            assert!(peripheral.is_none());
+            match cmd.header.op_code {
+                0 => Ok(cmd.msg_body.clone()),
+                _ => Err(IoError::new(ErrorKind::Unsupported, "not implemented")),
+            }
+        }
+
+        fn on_report(&mut self, msg: &Msg) -> Result<(), IoError> {
+            self.reports.push(msg.header.msg_id);
+            Ok(())
+        }
+
+        fn on_tick(&mut self, _peripheral: Option<&mut dyn Interface>) -> Result<(), IoError> {
+            self.ticks += 1;
+            Ok(())
+        }
+    }
+
+    fn recv_report(gs: &mut Option<IpcServer>) -> Msg {
+        let _ = poll_ipc_server_sockets(&mut vec![gs]);
+        deserialize_msg(&gs.as_mut().unwrap().recv().unwrap().data).unwrap()
+    }
+
+    #[test]
+    fn test_reports_cmds_from_gs() {
+        let mut gs = Some(IpcServer::new("runtime_test_gs".to_string()).unwrap());
+        let mut onboard = Some(IpcServer::new("runtime_test_dispatcher".to_string()).unwrap());
+        let mut runtime: HandlerRuntime<Echo> = HandlerRuntime::new(Echo::default())
+            .socket_name("runtime_test_handler")
+            .gs_socket("runtime_test_gs")
+            .report_socket("runtime_test_dispatcher")
+            .tick(Duration::from_millis(10));
+        runtime.run_once(Duration::ZERO);
+
+        let mut dispatcher = IpcClient::new("runtime_test_handler".to_string()).unwrap();
+        let gps = ComponentIds::GPS as u8;
+        let gs_id = ComponentIds::GS as u8;
+        let cmds = [
+            Msg::new(MsgType::Cmd, 1, gps, gs_id, 0, vec![1, 2, 3]),
+            // Not from the GS, so there is no Report
+            Msg::new(MsgType::Cmd, 2, gps, ComponentIds::DFGM as u8, 0, vec![]),
+            Msg::new(MsgType::Cmd, 3, gps, gs_id, 7, vec![]),
+            // Reported back through the cmd_dispatcher
+            Msg::new(MsgType::Cmd, 4, gps, ComponentIds::Sequencer as u8, 0, vec![4]),
+            // Handed to on_report rather than executed
+            Msg::new(MsgType::Report, 5, gps, ComponentIds::IRIS as u8, ResultCode::Success as u8, vec![]),
+            Msg::new_ack(6, gps, ComponentIds::CmdDispatcher as u8, AckCode::Failed, vec![]),
+        ];
+        for cmd in &cmds {
+            dispatcher.send(&serialize_msg(cmd).unwrap()).unwrap();
+        }
+        runtime.run_once(Duration::from_secs(1));
+
+        let report = recv_report(&mut gs);
+        assert_eq!((report.header.msg_id, report.header.dest_id), (1, gs_id));
+        assert_eq!(report.result_code(), Some(Ok(ResultCode::Success)));
+        assert_eq!(report.msg_body, vec![1, 2, 3]);
+        let report = recv_report(&mut gs);
+        assert_eq!(report.header.msg_id, 3);
+        assert_eq!(report.result_code(), Some(Ok(ResultCode::NotImplemented)));
+        let report = recv_report(&mut onboard);
+        assert_eq!((report.header.msg_id, report.header.dest_id), (4, ComponentIds::Sequencer as u8));
+        assert_eq!(report.msg_body, vec![4]);
+        assert_eq!(runtime.handler().reports, [5, 6]);
+
+        while runtime.handler().ticks < 2 {
+            runtime.run_once(Duration::from_secs(1));
+        }
+    }
+}
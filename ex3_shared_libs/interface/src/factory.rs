@@ -0,0 +1,243 @@
+/*
+Opens an interface from a URI-style spec, so a handler's peripheral can be chosen at runtime - a TCP
+connection to a simulated subsystem during development, and the UART, I2C or SPI device of the real one
+in flight:
+
+    tcp://127.0.0.1:1803                TCP client. ?read_timeout_ms=N stops reads blocking for longer
+    uart:///dev/ttyS1?baud=115200       serial port, 9600 baud unless given
+    i2c:///dev/i2c-1@0x40               I2C device at an address on a bus
+    spi:///dev/spidev0.0?speed_hz=5000  SPI device, 5000 Hz unless given
+    ipc://gps_device                    IPC client of the named server
+
+The interface is returned as a `Peripheral`, an Interface that can be waited on by a `Reactor` and kept
+connected by `Reconnecting`.
+*/
+
+use super::i2c::I2cDeviceInterface;
+use super::ipc::IpcClient;
+use super::reactor::Pollable;
+use super::reconnect::{Link, Reconnecting};
+use super::spi::SpiInterface;
+use super::tcp::TcpInterface;
+use super::uart::{SerialPortSettings, UartInterface};
+use nix::sys::termios::BaudRate;
+use spidev::{SpiModeFlags, SpidevOptions};
+use std::fmt;
+use std::io::{Error as IoError, ErrorKind};
+use std::str::FromStr;
+use std::time::Duration;
+
+pub const DEFAULT_BAUD: u32 = 9600;
+pub const DEFAULT_SPI_SPEED_HZ: u32 = 5000;
+
+/// An interface opened from a spec
+pub trait Peripheral: Link + Pollable {}
+
+impl<T: Link + Pollable + ?Sized> Peripheral for T {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InterfaceSpec {
+    Tcp { host: String, port: u16, read_timeout: Option<Duration> },
+    Uart { path: String, baud: u32 },
+    I2c { path: String, address: u16 },
+    Spi { path: String, speed_hz: u32 },
+    Ipc { server: String },
+}
+
+fn invalid(spec: &str, reason: &str) -> IoError {
+    IoError::new(ErrorKind::InvalidInput, format!("invalid interface spec '{}': {}", spec, reason))
+}
+
+/// Parse a number, in hex if it starts with 0x
+fn parse_number<T: TryFrom<u64>>(s: &str) -> Option<T> {
+    let n = match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()?,
+        None => s.parse::<u64>().ok()?,
+    };
+    T::try_from(n).ok()
+}
+
+fn baud_rate(baud: u32) -> Option<BaudRate> {
+    let rate = match baud {
+        1200 => BaudRate::B1200,
+        2400 => BaudRate::B2400,
+        4800 => BaudRate::B4800,
+        9600 => BaudRate::B9600,
+        19200 => BaudRate::B19200,
+        38400 => BaudRate::B38400,
+        57600 => BaudRate::B57600,
+        115200 => BaudRate::B115200,
+        230400 => BaudRate::B230400,
+        460800 => BaudRate::B460800,
+        921600 => BaudRate::B921600,
+        _ => return None,
+    };
+    Some(rate)
+}
+
+impl FromStr for InterfaceSpec {
+    type Err = IoError;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let (scheme, rest) = spec.split_once("://").ok_or_else(|| invalid(spec, "expected <scheme>://..."))?;
+        let (target, query) = rest.split_once('?').unwrap_or((rest, ""));
+        if target.is_empty() {
+            return Err(invalid(spec, "nothing to connect to"));
+        }
+        // Only one option is taken by any interface, so any other is a mistake
+        let option = |name: &str| -> Result<Option<u64>, IoError> {
+            match query.split_once('=') {
+                None if query.is_empty() => Ok(None),
+                Some((key, value)) if key == name => parse_number(value)
+                    .map(Some)
+                    .ok_or_else(|| invalid(spec, &format!("{} must be a number", name))),
+                _ => Err(invalid(spec, &format!("unknown option '{}'", query))),
+            }
+        };
+        let no_options = || match query {
+            "" => Ok(()),
+            _ => Err(invalid(spec, &format!("unknown option '{}'", query))),
+        };
+        match scheme {
+            "tcp" => {
+                let (host, port) = target.rsplit_once(':').ok_or_else(|| invalid(spec, "expected <host>:<port>"))?;
+                let port = parse_number(port).ok_or_else(|| invalid(spec, "invalid port"))?;
+                let read_timeout = option("read_timeout_ms")?.map(Duration::from_millis);
+                Ok(InterfaceSpec::Tcp { host: host.to_string(), port, read_timeout })
+            }
+            "uart" => {
+                let baud = option("baud")?.map_or(DEFAULT_BAUD, |b| b as u32);
+                if baud_rate(baud).is_none() {
+                    return Err(invalid(spec, &format!("unsupported baud rate {}", baud)));
+                }
+                Ok(InterfaceSpec::Uart { path: target.to_string(), baud })
+            }
+            "i2c" => {
+                no_options()?;
+                let (path, address) = target.rsplit_once('@').ok_or_else(|| invalid(spec, "expected <bus>@<address>"))?;
+                let address = parse_number(address).ok_or_else(|| invalid(spec, "invalid address"))?;
+                Ok(InterfaceSpec::I2c { path: path.to_string(), address })
+            }
+            "spi" => {
+                let speed_hz = option("speed_hz")?.map_or(Ok(DEFAULT_SPI_SPEED_HZ), u32::try_from)
+                    .map_err(|_| invalid(spec, "speed_hz is too large"))?;
+                Ok(InterfaceSpec::Spi { path: target.to_string(), speed_hz })
+            }
+            "ipc" => {
+                no_options()?;
+                Ok(InterfaceSpec::Ipc { server: target.to_string() })
+            }
+            _ => Err(invalid(spec, &format!("unknown scheme {}", scheme))),
+        }
+    }
+}
+
+impl fmt::Display for InterfaceSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InterfaceSpec::Tcp { host, port, read_timeout } => {
+                write!(f, "tcp://{}:{}", host, port)?;
+                match read_timeout {
+                    Some(timeout) => write!(f, "?read_timeout_ms={}", timeout.as_millis()),
+                    None => Ok(()),
+                }
+            }
+            InterfaceSpec::Uart { path, baud } => write!(f, "uart://{}?baud={}", path, baud),
+            InterfaceSpec::I2c { path, address } => write!(f, "i2c://{}@{:#04x}", path, address),
+            InterfaceSpec::Spi { path, speed_hz } => write!(f, "spi://{}?speed_hz={}", path, speed_hz),
+            InterfaceSpec::Ipc { server } => write!(f, "ipc://{}", server),
+        }
+    }
+}
+
+impl InterfaceSpec {
+    pub fn open(&self) -> Result<Box<dyn Peripheral>, IoError> {
+        let peripheral: Box<dyn Peripheral> = match self {
+            InterfaceSpec::Tcp { host, port, read_timeout } => {
+                let tcp = TcpInterface::new_client(host.clone(), *port)?;
+                tcp.stream.set_read_timeout(*read_timeout)?;
+                Box::new(tcp)
+            }
+            InterfaceSpec::Uart { path, baud } => {
+                let settings = SerialPortSettings {
+                    baud_rate: baud_rate(*baud).ok_or_else(|| invalid(&self.to_string(), "unsupported baud rate"))?,
+                    ..SerialPortSettings::new()
+                };
+                Box::new(UartInterface::open(path, Some(&settings))?)
+            }
+            InterfaceSpec::I2c { path, address } => Box::new(I2cDeviceInterface::new(path, *address)?),
+            InterfaceSpec::Spi { path, speed_hz } => {
+                let options = SpidevOptions::new()
+                    .bits_per_word(8)
+                    .max_speed_hz(*speed_hz)
+                    .lsb_first(false)
+                    .mode(SpiModeFlags::SPI_MODE_0)
+                    .build();
+                Box::new(SpiInterface::new(path, Some(options))?)
+            }
+            InterfaceSpec::Ipc { server } => Box::new(IpcClient::new(server.clone())?),
+        };
+        Ok(peripheral)
+    }
+}
+
+/// Open the interface `spec` describes
+pub fn open(spec: &str) -> Result<Box<dyn Peripheral>, IoError> {
+    spec.parse::<InterfaceSpec>()?.open()
+}
+
+impl Reconnecting<Box<dyn Peripheral>> {
+    /// Keep the interface `spec` describes connected
+    pub fn open(spec: InterfaceSpec) -> Reconnecting<Box<dyn Peripheral>> {
+        Reconnecting::new(&spec.to_string(), move || spec.open())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Interface;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_specs() {
+        let specs = [
+            ("tcp://127.0.0.1:1803", InterfaceSpec::Tcp { host: "127.0.0.1".to_string(), port: 1803, read_timeout: None }),
+            ("uart:///dev/ttyS1?baud=115200", InterfaceSpec::Uart { path: "/dev/ttyS1".to_string(), baud: 115200 }),
+            ("i2c:///dev/i2c-1@0x40", InterfaceSpec::I2c { path: "/dev/i2c-1".to_string(), address: 0x40 }),
+            ("spi:///dev/spidev0.0?speed_hz=5000", InterfaceSpec::Spi { path: "/dev/spidev0.0".to_string(), speed_hz: 5000 }),
+            ("ipc://gps_device", InterfaceSpec::Ipc { server: "gps_device".to_string() }),
+        ];
+        for (spec, expected) in specs {
+            let parsed: InterfaceSpec = spec.parse().unwrap();
+            assert_eq!(parsed, expected);
+            assert_eq!(parsed.to_string(), spec);
+        }
+        assert_eq!("uart:///dev/ttyS1".parse::<InterfaceSpec>().unwrap(),
+                   InterfaceSpec::Uart { path: "/dev/ttyS1".to_string(), baud: DEFAULT_BAUD });
+        assert_eq!("tcp://localhost:50000?read_timeout_ms=500".parse::<InterfaceSpec>().unwrap(),
+                   InterfaceSpec::Tcp { host: "localhost".to_string(), port: 50000, read_timeout: Some(Duration::from_millis(500)) });
+
+        for bad in ["127.0.0.1:1803", "tcp://127.0.0.1", "tcp://:99999", "uart:///dev/ttyS1?baud=12",
+                    "uart:///dev/ttyS1?speed=9600", "i2c:///dev/i2c-1", "can://bus", "ipc://"] {
+            let e = bad.parse::<InterfaceSpec>().unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::InvalidInput, "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_opens_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut peripheral = open(&format!("tcp://127.0.0.1:{}?read_timeout_ms=10", port)).unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+        peripheral.send(b"ping").unwrap();
+        let mut buf = [0u8; 4];
+        peer.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+        // Nothing to read, so the read times out rather than blocking
+        assert!(peripheral.read(&mut buf).is_err());
+        assert!(peripheral.zero_read_is_eof());
+    }
+}
@@ -4,23 +4,53 @@ Summer 2024
 
 */
 use nix::libc;
-use nix::sys::socket::{self, bind, socket, AddressFamily, SockFlag, SockType, UnixAddr};
+use nix::sys::socket::{self, bind, socket, AddressFamily, MsgFlags, SockFlag, SockType, UnixAddr};
 use nix::unistd::write;
+use std::collections::{HashMap, VecDeque};
 use std::ffi::CString;
 use nix::unistd::unlink;
 use std::process::exit;
-use std::io::Error as IoError;
-use std::os::fd::{AsFd, AsRawFd, OwnedFd};
-use std::path::Path;
+use std::io::{Error as IoError, ErrorKind};
+use std::os::fd::{AsFd, AsRawFd, OwnedFd, RawFd};
+use std::path::{Path, PathBuf};
 use std::{fs, io};
+use std::time::{Duration, Instant};
+use common::component_ids::ComponentIds;
+use common::message_structure::{serialize_msg, Msg, MsgType};
+use common::msg_id::MsgIdAllocator;
+use common::opcodes;
 use super::Interface;
 
 // TODO: Implement drop trait so that IpcClients socket paths are deleted when they go out of scope
 
-const SOCKET_PATH_PREPEND: &str = "/tmp/fifo_socket_";
 const CLIENT_PARTIAL_POSTFIX: &str = "_client_";
-pub const IPC_BUFFER_SIZE: usize = 4096;
+/// Max size of one datagram on an IPC socket. Frames that don't fit in one are split over several
+pub const IPC_DATAGRAM_SIZE: usize = 4096;
+/// Every datagram starts with the sender's sequence number for the frame, the index of the fragment
+/// and the number of fragments in the frame, each a little endian u16
+const FRAGMENT_HEADER_SIZE: usize = 6;
+const FRAGMENT_PAYLOAD_SIZE: usize = IPC_DATAGRAM_SIZE - FRAGMENT_HEADER_SIZE;
+/// Largest frame that can be sent over IPC
+pub const IPC_MAX_FRAME_SIZE: usize = 1 << 20;
+const MAX_FRAGMENTS: usize = IPC_MAX_FRAME_SIZE.div_ceil(FRAGMENT_PAYLOAD_SIZE);
+/// Number of received frames a socket holds until they are taken with `recv`. When it is full the
+/// oldest frame is dropped
+pub const IPC_RECV_QUEUE_SIZE: usize = 64;
+/// How long the rest of a partly received frame is waited for before it is discarded
+const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(1);
 const POLL_TIMEOUT_MS: i32 = 100;
+/// Name of the socket the cmd_dispatcher receives msgs on
+pub const CMD_DISPATCHER_SOCKET: &str = "cmd_dispatcher";
+/// How often components renew their registration with the cmd_dispatcher
+pub const REGISTRATION_INTERVAL: Duration = Duration::from_secs(5);
+/// How long the cmd_dispatcher keeps a registration that hasn't been renewed
+pub const REGISTRATION_TTL: Duration = Duration::from_secs(15);
+
+/// Sockets are created at this prefix followed by their name, e.g. /tmp/fifo_socket_DFGM. It is set in
+/// the config (see common::config)
+pub fn socket_prefix() -> &'static str {
+    &common::config::get().ipc.socket_prefix
+}
 
 /// Create a unix domain socket with a type of SOCK_DGRAM.
 /// Because both server and client need to create a socket, this is a helper function outside of the structs
@@ -29,41 +59,202 @@ fn create_socket() -> Result<OwnedFd, IoError> {
     Ok(sock_fd)
 }
 
-/// Client struct using a unix domain socket of type SOCKSEQ packet, that connects to a server socket
+/// One msg received on an IPC socket, however many datagrams it was sent in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IpcFrame {
+    pub data: Vec<u8>,
+    /// Address of the socket that sent the frame
+    pub sender: Option<UnixAddr>,
+}
+
+impl IpcFrame {
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Path of the socket that sent the frame, e.g. /tmp/fifo_socket_gs_bulk_client_1
+    pub fn sender_path(&self) -> Option<&Path> {
+        self.sender.as_ref().and_then(|addr| addr.path())
+    }
+}
+
+/// Fragments of a frame that hasn't been completely received yet
+#[derive(Debug)]
+struct PartialFrame {
+    fragments: Vec<Option<Vec<u8>>>,
+    missing: usize,
+    started: Instant,
+}
+
+/// Reassembles frames from the datagrams received on a socket, and holds them until they are taken
+#[derive(Debug, Default)]
+struct RecvQueue {
+    frames: VecDeque<IpcFrame>,
+    /// By sender and sequence number
+    partial: HashMap<(Option<PathBuf>, u16), PartialFrame>,
+}
+
+impl RecvQueue {
+    fn push(&mut self, frame: IpcFrame) {
+        if self.frames.len() >= IPC_RECV_QUEUE_SIZE {
+            eprintln!("IPC receive queue full, dropping the oldest frame");
+            self.frames.pop_front();
+        }
+        self.frames.push_back(frame);
+    }
+
+    /// Add a received datagram. Once all the fragments of a frame are in, the frame is queued
+    fn add_datagram(&mut self, datagram: &[u8], sender: Option<UnixAddr>) -> Result<(), IoError> {
+        if datagram.len() < FRAGMENT_HEADER_SIZE {
+            return Err(IoError::new(ErrorKind::InvalidData, format!("datagram of {} B has no fragment header", datagram.len())));
+        }
+        let field = |i: usize| u16::from_le_bytes([datagram[i], datagram[i + 1]]);
+        let (seq, index, count) = (field(0), field(2) as usize, field(4) as usize);
+        if index >= count || count > MAX_FRAGMENTS {
+            return Err(IoError::new(ErrorKind::InvalidData, format!("invalid fragment {} of {}", index, count)));
+        }
+        let payload = datagram[FRAGMENT_HEADER_SIZE..].to_vec();
+        if count == 1 {
+            self.push(IpcFrame { data: payload, sender });
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        self.partial.retain(|_, p| now.duration_since(p.started) < REASSEMBLY_TIMEOUT);
+        let key = (sender.as_ref().and_then(|a| a.path()).map(Path::to_path_buf), seq);
+        let partial = self.partial.entry(key.clone()).or_insert_with(|| PartialFrame {
+            fragments: vec![None; count],
+            missing: count,
+            started: now,
+        });
+        if partial.fragments.len() != count {
+            self.partial.remove(&key);
+            return Err(IoError::new(ErrorKind::InvalidData, format!("fragment count of frame {} changed", seq)));
+        }
+        if partial.fragments[index].replace(payload).is_none() {
+            partial.missing -= 1;
+        }
+        if partial.missing == 0 {
+            let fragments = self.partial.remove(&key).unwrap().fragments;
+            self.push(IpcFrame { data: fragments.into_iter().flatten().flatten().collect(), sender });
+        }
+        Ok(())
+    }
+
+    /// Receive whatever is waiting on `fd` if there are no frames queued, without blocking
+    fn receive_if_empty(&mut self, fd: RawFd) {
+        if self.frames.is_empty() {
+            if let Err(e) = receive_pending(fd, self) {
+                eprintln!("Error receiving on IPC socket: {}", e);
+            }
+        }
+    }
+
+    /// Block until a frame has been received on `fd`, then copy it into `data`.
+    /// A frame that doesn't fit in `data` stays queued
+    fn read_into(&mut self, fd: RawFd, data: &mut [u8]) -> Result<(usize, Option<UnixAddr>), IoError> {
+        while self.frames.is_empty() {
+            poll_fds(&[fd], -1)?;
+            receive_pending(fd, self)?;
+        }
+        let len = self.frames[0].len();
+        if len > data.len() {
+            return Err(IoError::new(ErrorKind::InvalidInput, format!("frame of {} B doesn't fit in {} B", len, data.len())));
+        }
+        let frame = self.frames.pop_front().unwrap();
+        data[..len].copy_from_slice(&frame.data);
+        Ok((len, frame.sender))
+    }
+}
+
+/// Wait up to `timeout_ms` (forever if negative) for any of `fds` to be readable, and return which are
+fn poll_fds(fds: &[RawFd], timeout_ms: i32) -> Result<Vec<bool>, IoError> {
+    let mut poll_fds: Vec<libc::pollfd> = fds.iter()
+        .map(|&fd| libc::pollfd { fd, events: libc::POLLIN, revents: 0 })
+        .collect();
+    let poll_result = unsafe {
+        libc::poll(poll_fds.as_mut_ptr(), poll_fds.len() as libc::nfds_t, timeout_ms)
+    };
+    if poll_result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(poll_fds.iter().map(|p| p.revents & libc::POLLIN != 0).collect())
+}
+
+/// Receive every datagram waiting on `fd` into `queue`, without blocking
+fn receive_pending(fd: RawFd, queue: &mut RecvQueue) -> Result<(), IoError> {
+    let mut datagram = [0u8; IPC_DATAGRAM_SIZE];
+    while poll_fds(&[fd], 0)?[0] {
+        let (len, sender) = socket::recvfrom::<UnixAddr>(fd, &mut datagram)?;
+        if let Err(e) = queue.add_datagram(&datagram[..len], sender) {
+            eprintln!("Dropped datagram: {}", e);
+        }
+    }
+    Ok(())
+}
+
+/// Send `data` to `addr` as one frame, split over as many datagrams as it takes
+fn send_frame(fd: RawFd, addr: &UnixAddr, data: &[u8], seq: u16, flags: MsgFlags) -> Result<usize, IoError> {
+    if data.len() > IPC_MAX_FRAME_SIZE {
+        return Err(IoError::new(ErrorKind::InvalidInput, format!("frame of {} B is larger than {} B", data.len(), IPC_MAX_FRAME_SIZE)));
+    }
+    // An empty frame is still sent, as one empty fragment
+    let count = data.len().div_ceil(FRAGMENT_PAYLOAD_SIZE).max(1);
+    let mut datagram = Vec::with_capacity(IPC_DATAGRAM_SIZE);
+    for index in 0..count {
+        let payload = &data[index * FRAGMENT_PAYLOAD_SIZE..data.len().min((index + 1) * FRAGMENT_PAYLOAD_SIZE)];
+        datagram.clear();
+        datagram.extend_from_slice(&seq.to_le_bytes());
+        datagram.extend_from_slice(&(index as u16).to_le_bytes());
+        datagram.extend_from_slice(&(count as u16).to_le_bytes());
+        datagram.extend_from_slice(payload);
+        socket::sendto(fd, &datagram, addr, flags)?;
+    }
+    Ok(data.len())
+}
+
+/// Poll sockets for incoming datagrams and queue the frames they complete. If frames are already
+/// queued this doesn't wait. Returns the number of frames queued on all the sockets
+fn poll_queues(mut sockets: Vec<(RawFd, &mut RecvQueue)>) -> Result<usize, IoError> {
+    let queued = |sockets: &Vec<(RawFd, &mut RecvQueue)>| sockets.iter().map(|(_, q)| q.frames.len()).sum::<usize>();
+    let timeout_ms = if queued(&sockets) > 0 { 0 } else { POLL_TIMEOUT_MS };
+    let fds: Vec<RawFd> = sockets.iter().map(|(fd, _)| *fd).collect();
+    let readable = poll_fds(&fds, timeout_ms)?;
+    for ((fd, queue), readable) in sockets.iter_mut().zip(readable) {
+        if readable {
+            receive_pending(*fd, queue)?;
+        }
+    }
+    Ok(queued(&sockets))
+}
+
+/// Client struct using a unix domain socket of type SOCK_DGRAM, that connects to a server socket
 #[derive(Debug)]
 pub struct IpcClient {
     pub socket_path: String,
     pub fd: OwnedFd,
     pub server_addr: Option<UnixAddr>,
-    pub buffer: [u8; IPC_BUFFER_SIZE],
+    next_seq: u16,
+    recv_queue: RecvQueue,
 }
 
 impl Interface for IpcClient {
-    /// reads data from the given server, if client_addr_op is not None then we assign this
-    /// client's unix address to the client_addr field in the IpcServer Struct.
-    /// *** This function does not read into IpcServer's buffer field ***
+    /// Blocks until a frame is received and copies it into `data`. If the frame came from a socket
+    /// other than the server, that becomes the server the client sends to.
     fn read(&mut self, data: &mut [u8]) -> Result<usize, IoError> {
-        let (bytes_read, server_addr_op) = socket::recvfrom::<UnixAddr>(self.fd.as_raw_fd(), data)?;
+        let (bytes_read, server_addr_op) = self.recv_queue.read_into(self.fd.as_raw_fd(), data)?;
         if server_addr_op.is_some() {
-            // In this conditional we can check if we accidentally recv data from socket that is
-            // not our server
             self.server_addr = server_addr_op;
-        } else {
-            return Ok(0)
         }
         Ok(bytes_read)
     }
 
-    /// Sends the data to the client via IpcServer's client_addr field
-    /// if client_addr is none, then we return NotFound error
     fn send(&mut self, data: &[u8]) -> Result<usize, IoError> {
-        if self.server_addr.is_none() {
-            // Return not found error
-            return Err(io::Error::new(io::ErrorKind::NotFound, format!("No client address for server {}", self.socket_path)));
-        }
-        // Server Address should never unwrap here
-        let bytes_sent = socket::sendto(self.fd.as_raw_fd(), data, &self.server_addr.unwrap(), socket::MsgFlags::empty())?;
-        Ok(bytes_sent)
+        IpcClient::send(self, data)
     }
 }
 
@@ -105,7 +296,7 @@ impl IpcClient {
             }
         }
         // Initialize server addr to be /tmp/fifo_socket_<server_name>
-        let server_name = format!("{}{}", SOCKET_PATH_PREPEND, server_name);
+        let server_name = format!("{}{}", socket_prefix(), server_name);
         let server_address_c_str = CString::new(server_name.clone()).unwrap();
         let server_addr = UnixAddr::new(server_address_c_str.as_bytes()).unwrap_or_else(
             |err| {
@@ -118,90 +309,57 @@ impl IpcClient {
             socket_path: socket_path.clone(),
             fd: socket_fd,
             server_addr: Some(server_addr),
-            buffer: [0u8; IPC_BUFFER_SIZE],
+            next_seq: 0,
+            recv_queue: RecvQueue::default(),
         };
         Ok(client)
     }
 
+    /// Take the oldest frame received, if any. Frames are received by `poll_ipc_clients`, or here
+    /// without waiting if none are queued
+    pub fn recv(&mut self) -> Option<IpcFrame> {
+        self.recv_queue.receive_if_empty(self.fd.as_raw_fd());
+        self.recv_queue.frames.pop_front()
+    }
+
+    /// Number of received frames waiting to be taken with `recv`
+    pub fn queued(&self) -> usize {
+        self.recv_queue.frames.len()
+    }
 
-    /// Users of this lib can call this to clear the buffer - otherwise the preivous read data will remain
-    /// the IPC client has no way of knowing when the user is done with the data in its buffer, so it is the responsibility of the user to clear it
-    pub fn clear_buffer(&mut self) {
-        self.buffer = [0u8; IPC_BUFFER_SIZE];
-        println!("Buffer cleared");
+    /// Send `data` to the server as one frame, waiting if the server's socket is full
+    pub fn send(&mut self, data: &[u8]) -> Result<usize, IoError> {
+        self.send_with_flags(data, MsgFlags::empty())
     }
 
-    /// Returns the buffer in its current state for directly reading values in real time.
-    /// **This function also clears the buffer after the read!**
-    pub fn read_buffer(&mut self) -> Vec<u8> {
-        let tmp = self.buffer.to_vec();
-        self.clear_buffer();
-        tmp
+    /// Like `send`, but fails with ErrorKind::WouldBlock instead of waiting when the server's socket
+    /// is full because it isn't keeping up. If that happens part way through a frame that took more
+    /// than one datagram, the server discards the part it got
+    pub fn try_send(&mut self, data: &[u8]) -> Result<usize, IoError> {
+        self.send_with_flags(data, MsgFlags::MSG_DONTWAIT)
     }
-    pub fn send(&mut self, data: &[u8]) -> Result<usize, IoError>{
-        if self.server_addr.is_none() {
+
+    fn send_with_flags(&mut self, data: &[u8], flags: MsgFlags) -> Result<usize, IoError> {
+        let Some(ref server_addr) = self.server_addr else {
             eprintln!("No server found for client.");
             // return no such device or address error (ENXIO)
             return Err(io::Error::from_raw_os_error(6));
-        }
-        // Server Address should never unwrap here
-        let ret = socket::sendto(self.fd.as_raw_fd(), data, &self.server_addr.unwrap(), socket::MsgFlags::empty())?;
-        Ok(ret)
+        };
+        let seq = self.next_seq;
+        self.next_seq = seq.wrapping_add(1);
+        send_frame(self.fd.as_raw_fd(), server_addr, data, seq, flags)
     }
 }
 
-/// This function polls each ipc client in the provided vector of optional clients.
-/// Returns the number of bytes read and the optional address which it was sent from if successful,
-/// if it fails it returns an IO Error.
-pub fn poll_ipc_clients(clients: &mut Vec<&mut Option<IpcClient>>) -> Result<(usize, Option<UnixAddr>), std::io::Error> {
-    //Create poll fd instances for each client
-    let mut poll_fds: Vec<libc::pollfd> = Vec::new();
-    for client in &mut *clients {
-        // Poll fd for incoming data
-        if client.is_none() {
-            return Ok((0, None));
-        }
-        poll_fds.push(libc::pollfd {
-            fd: client.as_ref().unwrap().fd.as_raw_fd(),
-            events: libc::POLLIN,
-            revents: 0,
-        });
-    }
-
-    let poll_result = unsafe {
-        libc::poll(
-            poll_fds.as_mut_ptr(),
-            poll_fds.len() as libc::nfds_t,
-            POLL_TIMEOUT_MS,
-        )
-    };
-
-    if poll_result < 0 {
-        eprintln!(
-            "Error polling for client data: {}",
-            io::Error::last_os_error()
-        );
-        exit(1);
-    }
-
-    //Poll each client for incoming data
-    for poll_fd in poll_fds.iter() {
-        if poll_fd.revents & libc::POLLIN != 0 {
-            let client = clients.iter_mut().find(|s| s.as_ref().unwrap().fd.as_raw_fd() == poll_fd.fd);
-            if let Some(client) = client {
-                // Handle incoming data from a client
-                let (bytes_read, recv_addr) = socket::recvfrom::<UnixAddr> (client.as_ref().unwrap().fd.as_raw_fd(), &mut client.as_mut().unwrap().buffer)?;
-                if bytes_read > 0 {
-                    println!(
-                        "Received {} bytes on socket {} from {:?}",
-                        bytes_read, client.as_ref().unwrap().socket_path, &recv_addr.unwrap().path().unwrap().to_str()
-                    );
-                    return Ok((bytes_read, recv_addr));
-                }
-            }
-        }
-    }
-    Ok((0, None))
+/// Polls each of the provided clients for incoming data, waiting up to POLL_TIMEOUT_MS for some if
+/// none has frames queued already. Received frames are queued on their client, to be taken with
+/// `recv`. Returns the number of frames queued on all the clients.
+pub fn poll_ipc_clients(clients: &mut Vec<&mut Option<IpcClient>>) -> Result<usize, IoError> {
+    let sockets = clients.iter_mut()
+        .filter_map(|c| c.as_mut())
+        .map(|c| (c.fd.as_raw_fd(), &mut c.recv_queue))
+        .collect();
+    poll_queues(sockets)
 }
 
 pub struct IpcServer {
@@ -209,19 +367,17 @@ pub struct IpcServer {
     pub fd: OwnedFd,
     // client addr is the unix addr of the client most recently talked to by the server
     pub client_addr: Option<UnixAddr>,
-    pub buffer: [u8; IPC_BUFFER_SIZE],
+    next_seq: u16,
+    recv_queue: RecvQueue,
 }
 
 impl Interface for IpcServer {
-    /// reads data from the given server, if client_addr_op is not None then we assign this
-    /// client's unix address to the client_addr field in the IpcServer Struct.
-    /// *** This function does not read into IpcServer's buffer field ***
+    /// Blocks until a frame is received and copies it into `data`. The client that sent it becomes
+    /// the one the server sends to.
     fn read(&mut self, data: &mut [u8]) -> Result<usize, IoError> {
-        let (bytes_read, client_addr_op) = socket::recvfrom::<UnixAddr>(self.fd.as_raw_fd(), data)?;
+        let (bytes_read, client_addr_op) = self.recv_queue.read_into(self.fd.as_raw_fd(), data)?;
         if client_addr_op.is_some() {
             self.client_addr = client_addr_op;
-        } else {
-            return Ok(0)
         }
         Ok(bytes_read)
     }
@@ -229,19 +385,19 @@ impl Interface for IpcServer {
     /// Sends the data to the client via IpcServer's client_addr field
     /// if client_addr is none, then we return NotFound error
     fn send(&mut self, data: &[u8]) -> Result<usize, IoError> {
-        if self.client_addr.is_none() {
+        let Some(ref client_addr) = self.client_addr else {
             // Return not found error
             return Err(io::Error::new(io::ErrorKind::NotFound, format!("No client address for server {}", self.socket_path)));
-        }
-        // Server Address should never unwrap here
-        let bytes_sent = socket::sendto(self.fd.as_raw_fd(), data, &self.client_addr.unwrap(), socket::MsgFlags::empty())?;
-        Ok(bytes_sent)
+        };
+        let seq = self.next_seq;
+        self.next_seq = seq.wrapping_add(1);
+        send_frame(self.fd.as_raw_fd(), client_addr, data, seq, MsgFlags::empty())
     }
 }
 
 impl IpcServer {
     pub fn new(socket_name: String) -> Result<IpcServer, IoError> {
-        let socket_path = format!("{}{}", SOCKET_PATH_PREPEND, socket_name);
+        let socket_path = format!("{}{}", socket_prefix(), socket_name);
         // This is a measure for when servers need to initiate communication with the client
         // socket, this should not really happen. If needed we can manually set the unix address
         // in the server struct.
@@ -254,7 +410,8 @@ impl IpcServer {
             fd: socket_conn_fd,
             // Client socket is none to start
             client_addr: Some(client_unix_addr),
-            buffer: [0u8; IPC_BUFFER_SIZE],
+            next_seq: 0,
+            recv_queue: RecvQueue::default(),
         };
         server.bind_socket()?;
         // Normally a server would accept conn here - but instead we do this in the polling loop
@@ -284,79 +441,80 @@ impl IpcServer {
         Ok(())
     }
 
-    /// Users of this lib can call this to clear the buffer - otherwise the preivous read data will remain
-    ///  the IPC server has no way of knowing when the user is done with the data in its buffer, so it is the responsibility of the user to clear it
-    pub fn clear_buffer(&mut self) {
-        self.buffer = [0u8; IPC_BUFFER_SIZE];
-        println!("Buffer cleared");
+    /// Take the oldest frame received, if any. The client that sent it becomes the one the server
+    /// sends to. Frames are received by `poll_ipc_server_sockets`, or here without waiting if none
+    /// are queued
+    pub fn recv(&mut self) -> Option<IpcFrame> {
+        self.recv_queue.receive_if_empty(self.fd.as_raw_fd());
+        let frame = self.recv_queue.frames.pop_front()?;
+        if frame.sender.is_some() {
+            self.client_addr = frame.sender;
+        }
+        Some(frame)
     }
 
-    /// Returns the buffer in its current state for directly reading values in real time.
-    /// **This function also clears the buffer after the read!**
-    pub fn read_buffer(&mut self) -> Vec<u8> {
-        let tmp = self.buffer.to_vec();
-        self.clear_buffer();
-        tmp
+    /// Number of received frames waiting to be taken with `recv`
+    pub fn queued(&self) -> usize {
+        self.recv_queue.frames.len()
     }
 }
 
 
-/// Takes a vector of mutable referenced IpcServers and polls them for incoming data
-/// The IpcServers must be mutable because the connected state and data_fd are mutated in the polling loop
-///
-/// This function no longer needs to poll for connections. Only input.
-pub fn poll_ipc_server_sockets(servers: &mut Vec<&mut Option<IpcServer>>) -> Result<(usize, Option<UnixAddr>), IoError> {
-    //Create poll fd instances for each client
-    let mut poll_fds: Vec<libc::pollfd> = Vec::new();
-    for server in &mut *servers {
-        // Poll fd for incoming data
-        if server.is_none() {
-            return Ok((0, None));
-        }
-        poll_fds.push(libc::pollfd {
-            fd: server.as_ref().unwrap().fd.as_raw_fd(),
-            events: libc::POLLIN,
-            revents: 0,
-        });
-    }
+/// Takes a vector of mutable referenced IpcServers and polls them for incoming data, waiting up to
+/// POLL_TIMEOUT_MS for some if none has frames queued already. Received frames are queued on their
+/// server, to be taken with `recv`. Returns the number of frames queued on all the servers.
+pub fn poll_ipc_server_sockets(servers: &mut Vec<&mut Option<IpcServer>>) -> Result<usize, IoError> {
+    let sockets = servers.iter_mut()
+        .filter_map(|s| s.as_mut())
+        .map(|s| (s.fd.as_raw_fd(), &mut s.recv_queue))
+        .collect();
+    poll_queues(sockets)
+}
 
-    let poll_result = unsafe {
-        libc::poll(
-            poll_fds.as_mut_ptr(),
-            poll_fds.len() as libc::nfds_t,
-            POLL_TIMEOUT_MS,
-        )
-    };
+/// Keeps a component registered with the cmd_dispatcher, so msgs for it are delivered to `socket_name`.
+/// The registration is sent when this is created and renewed every REGISTRATION_INTERVAL by
+/// `maintain`, which also re-registers the component if the cmd_dispatcher is restarted.
+pub struct Registration {
+    component: ComponentIds,
+    socket_name: String,
+    client: Option<IpcClient>,
+    msg_ids: MsgIdAllocator,
+    last_sent: Option<Instant>,
+}
 
-    if poll_result < 0 {
-        eprintln!(
-            "Error polling for client data: {}",
-            io::Error::last_os_error()
-        );
-        exit(1);
-    }
-
-    //Poll each client for incoming data
-    for poll_fd in poll_fds.iter() {
-        if poll_fd.revents & libc::POLLIN != 0 {
-            let mut server = servers.iter_mut().find(|s| s.as_ref().unwrap().fd.as_raw_fd() == poll_fd.fd);
-            if let Some(ref mut server) = server {
-                // Handle incoming data from a client
-                let (bytes_read, recv_addr) = socket::recvfrom::<UnixAddr>(server.as_ref().unwrap().fd.as_raw_fd(), &mut server.as_mut().unwrap().buffer)?;
-                if bytes_read > 0 {
-                    println!(
-                        "Received {} bytes on socket {} from {:?}",
-                        bytes_read, server.as_ref().unwrap().socket_path,
-                        &recv_addr.unwrap().path().unwrap().to_str().unwrap()
-                    );
-                    // Set the servers client address so we know which client to respond to.
-                    server.as_mut().unwrap().client_addr = recv_addr;
-                    return Ok((bytes_read, recv_addr));
-                }
-            }
+impl Registration {
+    pub fn new(component: ComponentIds, socket_name: &str) -> Registration {
+        let mut registration = Registration {
+            component,
+            socket_name: socket_name.to_string(),
+            client: None,
+            msg_ids: MsgIdAllocator::spacecraft(component),
+            last_sent: None,
+        };
+        registration.maintain();
+        registration
+    }
+
+    /// Renew the registration if it is due. Should be called every time round the component's loop
+    pub fn maintain(&mut self) {
+        if self.last_sent.is_some_and(|t| t.elapsed() < REGISTRATION_INTERVAL) {
+            return;
+        }
+        self.last_sent = Some(Instant::now());
+        if self.client.is_none() {
+            self.client = IpcClient::new(CMD_DISPATCHER_SOCKET.to_string()).ok();
+        }
+        let Some(ref mut client) = self.client else {
+            return;
+        };
+        let register = opcodes::CmdDispatcher::Register;
+        let msg = Msg::new(MsgType::Cmd, self.msg_ids.next_id(), ComponentIds::CmdDispatcher as u8,
+                           self.component as u8, register as u8, self.socket_name.as_bytes().to_vec());
+        // Not an error while the cmd_dispatcher isn't running, it gets the next one
+        if let Ok(bytes) = serialize_msg(&msg) {
+            let _ = client.send(&bytes);
         }
     }
-    Ok((0, None))
 }
 
 /// Wrapper for the unistd lib write fxn
@@ -377,7 +535,8 @@ pub fn ipc_write(fd: &OwnedFd, data: &[u8]) -> Result<usize, std::io::Error> {
 /// Function to generate unique client address, currently the maximum number of
 /// clients per server is bottlenecked to 255 (unique identifier is u8)
 fn gen_client_socket_path(server_name: &String) -> Result<String, IoError> {
-    let paths = fs::read_dir("/tmp/")?;
+    let socket_dir = Path::new(socket_prefix()).parent().unwrap_or(Path::new("/"));
+    let paths = fs::read_dir(socket_dir)?;
     let mut max: u8 = 1;
     for path in paths {
         let curr_path = path.unwrap().path().to_str().unwrap().to_string();
@@ -408,7 +567,7 @@ fn gen_client_socket_path(server_name: &String) -> Result<String, IoError> {
     // This formatted string gives "/tmp/fifo_socket_<server_name>_client_#"
     // where # is the unique id for the client socket.
     let new_socket_path = format!("{}{}{}{}",
-        SOCKET_PATH_PREPEND,
+        socket_prefix(),
         server_name,
         CLIENT_PARTIAL_POSTFIX,
         max
@@ -472,4 +631,60 @@ mod tests {
         );
         assert_eq!(server.client_addr.unwrap().path().unwrap().to_str().unwrap(), client_2.socket_path);
     }
+
+
+    /// Wait for the next frame on `server`
+    fn recv_frame(server: &mut Option<IpcServer>) -> IpcFrame {
+        while poll_ipc_server_sockets(&mut vec![&mut *server]).unwrap() == 0 {}
+        server.as_mut().unwrap().recv().unwrap()
+    }
+
+    #[test]
+    fn test_frames_larger_than_a_datagram() {
+        let mut server = Some(IpcServer::new("ipc_test_large".to_string()).unwrap());
+        let mut client = IpcClient::new("ipc_test_large".to_string()).unwrap();
+        let big: Vec<u8> = (0..3 * IPC_DATAGRAM_SIZE + 17).map(|i| i as u8).collect();
+        assert_eq!(client.send(&big).unwrap(), big.len());
+        client.send(&[1, 2, 3]).unwrap();
+
+        let frame = recv_frame(&mut server);
+        assert_eq!(frame.data, big);
+        assert_eq!(frame.sender_path().unwrap().to_str().unwrap(), client.socket_path);
+        assert_eq!(recv_frame(&mut server).data, [1, 2, 3]);
+        assert!(client.send(&vec![0; IPC_MAX_FRAME_SIZE + 1]).is_err());
+    }
+
+    #[test]
+    fn test_zeroed_and_empty_frames_are_received() {
+        let mut server = Some(IpcServer::new("ipc_test_zeroes".to_string()).unwrap());
+        let mut client = IpcClient::new("ipc_test_zeroes".to_string()).unwrap();
+        client.send(&[0; 10]).unwrap();
+        client.send(&[]).unwrap();
+        assert_eq!(recv_frame(&mut server).data, [0; 10]);
+        assert!(recv_frame(&mut server).is_empty());
+        // Nothing is left over once the frames have been taken
+        assert_eq!(poll_ipc_server_sockets(&mut vec![&mut server]).unwrap(), 0);
+        assert!(server.as_mut().unwrap().recv().is_none());
+    }
+
+    #[test]
+    fn test_reassembles_interleaved_fragments() {
+        let mut queue = RecvQueue::default();
+        let fragment = |seq: u16, index: u16, count: u16, payload: &[u8]| {
+            [&seq.to_le_bytes()[..], &index.to_le_bytes(), &count.to_le_bytes(), payload].concat()
+        };
+        let a = UnixAddr::new("/tmp/fifo_socket_a").unwrap();
+        let b = UnixAddr::new("/tmp/fifo_socket_b").unwrap();
+        queue.add_datagram(&fragment(7, 1, 2, b"world"), Some(a)).unwrap();
+        queue.add_datagram(&fragment(7, 0, 2, b"other "), Some(b)).unwrap();
+        queue.add_datagram(&fragment(7, 0, 2, b"hello "), Some(a)).unwrap();
+        queue.add_datagram(&fragment(7, 1, 2, b"sender"), Some(b)).unwrap();
+        let frames: Vec<IpcFrame> = queue.frames.drain(..).collect();
+        assert_eq!(frames[0], IpcFrame { data: b"hello world".to_vec(), sender: Some(a) });
+        assert_eq!(frames[1], IpcFrame { data: b"other sender".to_vec(), sender: Some(b) });
+
+        assert!(queue.add_datagram(&[1, 2], Some(a)).is_err());
+        assert!(queue.add_datagram(&fragment(8, 2, 2, b""), Some(a)).is_err());
+        assert!(queue.frames.is_empty());
+    }
 }
@@ -5,6 +5,10 @@ pub mod i2c;
 pub mod uart;
 pub mod tcp;
 pub mod spi;
+pub mod reactor;
+pub mod reconnect;
+pub mod factory;
+pub mod mock;
 
 /// Interface trait to be implemented by all external interfaces
 pub trait Interface {
@@ -13,3 +17,13 @@ pub trait Interface {
     /// Read byte data from the interface into a byte slice buffer. Return number of bytes read
     fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Error>;
 }
+
+impl<T: Interface + ?Sized> Interface for Box<T> {
+    fn send(&mut self, data: &[u8]) -> Result<usize, Error> {
+        (**self).send(data)
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+        (**self).read(buffer)
+    }
+}
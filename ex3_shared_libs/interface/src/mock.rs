@@ -0,0 +1,362 @@
+/*
+In memory interfaces, so handlers can be tested without a simulated subsystem to talk to:
+
+    pipe()                  two connected ends. What is sent on one is read from the other
+    ScriptedPeripheral      replies to the requests it expects, in order, and fails on anything else
+    FaultInjector           wraps an interface to delay, drop, split or fail its sends and reads
+
+Nothing blocks. A read with nothing to read fails with WouldBlock, as a read of a socket with a read
+timeout does, so a handler reading more than it is sent gets an error rather than hanging the test.
+*/
+
+use super::reactor::Pollable;
+use super::reconnect::Link;
+use super::Interface;
+use std::collections::VecDeque;
+use std::io::{Error as IoError, ErrorKind};
+use std::os::fd::RawFd;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+fn nothing_to_read() -> IoError {
+    IoError::new(ErrorKind::WouldBlock, "nothing to read")
+}
+
+/// Take as much of `data` as fits in `buffer`
+fn read_from(data: &mut VecDeque<u8>, buffer: &mut [u8]) -> usize {
+    let n = data.len().min(buffer.len());
+    for (byte, b) in buffer.iter_mut().zip(data.drain(..n)) {
+        *byte = b;
+    }
+    n
+}
+
+/// Bytes going one way through a pipe
+#[derive(Default)]
+struct Channel {
+    data: VecDeque<u8>,
+    /// Set when either end is dropped
+    closed: bool,
+}
+
+/// One end of a pipe. Once the other end is dropped reads return 0 after what was sent has been read,
+/// like a closed TCP connection, and sends fail with BrokenPipe
+pub struct PipeEnd {
+    rx: Arc<Mutex<Channel>>,
+    tx: Arc<Mutex<Channel>>,
+}
+
+/// Create two connected ends
+pub fn pipe() -> (PipeEnd, PipeEnd) {
+    let a = Arc::new(Mutex::new(Channel::default()));
+    let b = Arc::new(Mutex::new(Channel::default()));
+    (PipeEnd { rx: a.clone(), tx: b.clone() }, PipeEnd { rx: b, tx: a })
+}
+
+impl PipeEnd {
+    /// Number of bytes waiting to be read
+    pub fn available(&self) -> usize {
+        self.rx.lock().unwrap().data.len()
+    }
+}
+
+impl Interface for PipeEnd {
+    fn send(&mut self, data: &[u8]) -> Result<usize, IoError> {
+        let mut tx = self.tx.lock().unwrap();
+        if tx.closed {
+            return Err(IoError::new(ErrorKind::BrokenPipe, "other end of the pipe is closed"));
+        }
+        tx.data.extend(data);
+        Ok(data.len())
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, IoError> {
+        let mut rx = self.rx.lock().unwrap();
+        match read_from(&mut rx.data, buffer) {
+            0 if !rx.closed && !buffer.is_empty() => Err(nothing_to_read()),
+            n => Ok(n),
+        }
+    }
+}
+
+impl Drop for PipeEnd {
+    fn drop(&mut self) {
+        self.rx.lock().unwrap().closed = true;
+        self.tx.lock().unwrap().closed = true;
+    }
+}
+
+impl Link for PipeEnd {
+    fn zero_read_is_eof(&self) -> bool {
+        true
+    }
+}
+
+/// Never polled, it is only ready when it has something to read
+impl Pollable for PipeEnd {
+    fn poll_fd(&self) -> RawFd {
+        -1
+    }
+
+    fn has_pending(&self) -> bool {
+        let rx = self.rx.lock().unwrap();
+        !rx.data.is_empty() || rx.closed
+    }
+}
+
+/// A subsystem that expects to be sent certain requests in order, replying to each with a canned
+/// response. Sending anything else fails with InvalidData, so a test finds out what was sent instead
+#[derive(Default)]
+pub struct ScriptedPeripheral {
+    script: VecDeque<(Vec<u8>, Vec<u8>)>,
+    replies: VecDeque<u8>,
+    sent: Vec<Vec<u8>>,
+}
+
+impl ScriptedPeripheral {
+    pub fn new() -> ScriptedPeripheral {
+        ScriptedPeripheral::default()
+    }
+
+    /// Expect `request` next, and reply with `reply`, which may be empty
+    pub fn expect(mut self, request: &[u8], reply: &[u8]) -> Self {
+        self.script.push_back((request.to_vec(), reply.to_vec()));
+        self
+    }
+
+    /// Have `data` to read without it being asked for, like a subsystem that sends data on its own
+    pub fn unprompted(mut self, data: &[u8]) -> Self {
+        self.replies.extend(data);
+        self
+    }
+
+    /// Whether every expected request has been sent and every reply read
+    pub fn is_done(&self) -> bool {
+        self.script.is_empty() && self.replies.is_empty()
+    }
+
+    /// Everything sent to it so far, expected or not
+    pub fn sent(&self) -> &[Vec<u8>] {
+        &self.sent
+    }
+}
+
+impl Interface for ScriptedPeripheral {
+    fn send(&mut self, data: &[u8]) -> Result<usize, IoError> {
+        self.sent.push(data.to_vec());
+        match self.script.front() {
+            Some((request, _)) if request == data => {
+                let (_, reply) = self.script.pop_front().unwrap();
+                self.replies.extend(reply);
+                Ok(data.len())
+            }
+            Some((request, _)) => Err(IoError::new(ErrorKind::InvalidData, format!(
+                "expected {:?}, got {:?}", String::from_utf8_lossy(request), String::from_utf8_lossy(data)))),
+            None => Err(IoError::new(ErrorKind::InvalidData, format!(
+                "nothing more expected, got {:?}", String::from_utf8_lossy(data)))),
+        }
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, IoError> {
+        match read_from(&mut self.replies, buffer) {
+            0 if !buffer.is_empty() => Err(nothing_to_read()),
+            n => Ok(n),
+        }
+    }
+}
+
+impl Link for ScriptedPeripheral {}
+
+impl Pollable for ScriptedPeripheral {
+    fn poll_fd(&self) -> RawFd {
+        -1
+    }
+
+    fn has_pending(&self) -> bool {
+        !self.replies.is_empty()
+    }
+}
+
+/// Something to go wrong with the next send or read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fault {
+    /// Wait this long before going ahead
+    Delay(Duration),
+    /// Report a send as successful without sending it, or discard what a read got
+    Drop,
+    /// Fail with this kind of error
+    Error(ErrorKind),
+}
+
+/// Wraps an interface, applying faults to its sends and reads. Faults queued for the next sends or
+/// reads are applied once each, in order, and reads can be limited to a few bytes at a time
+pub struct FaultInjector<I> {
+    inner: I,
+    send_faults: VecDeque<Fault>,
+    read_faults: VecDeque<Fault>,
+    max_read: Option<usize>,
+}
+
+impl<I: Interface> FaultInjector<I> {
+    pub fn new(inner: I) -> FaultInjector<I> {
+        FaultInjector { inner, send_faults: VecDeque::new(), read_faults: VecDeque::new(), max_read: None }
+    }
+
+    /// Apply `fault` to the next send that doesn't already have one
+    pub fn on_send(mut self, fault: Fault) -> Self {
+        self.send_faults.push_back(fault);
+        self
+    }
+
+    /// Apply `fault` to the next read that doesn't already have one
+    pub fn on_read(mut self, fault: Fault) -> Self {
+        self.read_faults.push_back(fault);
+        self
+    }
+
+    /// Read at most `n` bytes at a time, as a stream split over several packets is
+    pub fn max_read(mut self, n: usize) -> Self {
+        self.max_read = Some(n);
+        self
+    }
+
+    pub fn get_ref(&self) -> &I {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut I {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> I {
+        self.inner
+    }
+}
+
+impl<I: Interface> Interface for FaultInjector<I> {
+    fn send(&mut self, data: &[u8]) -> Result<usize, IoError> {
+        match self.send_faults.pop_front() {
+            Some(Fault::Delay(delay)) => thread::sleep(delay),
+            Some(Fault::Drop) => return Ok(data.len()),
+            Some(Fault::Error(kind)) => return Err(IoError::new(kind, "injected send fault")),
+            None => (),
+        }
+        self.inner.send(data)
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, IoError> {
+        let limit = self.max_read.unwrap_or(buffer.len()).min(buffer.len());
+        match self.read_faults.pop_front() {
+            Some(Fault::Delay(delay)) => thread::sleep(delay),
+            Some(Fault::Drop) => {
+                self.inner.read(&mut buffer[..limit])?;
+                buffer[..limit].fill(0);
+                return Err(nothing_to_read());
+            }
+            Some(Fault::Error(kind)) => return Err(IoError::new(kind, "injected read fault")),
+            None => (),
+        }
+        self.inner.read(&mut buffer[..limit])
+    }
+}
+
+impl<I: Link> Link for FaultInjector<I> {
+    fn zero_read_is_eof(&self) -> bool {
+        self.inner.zero_read_is_eof()
+    }
+}
+
+impl<I: Pollable> Pollable for FaultInjector<I> {
+    fn poll_fd(&self) -> RawFd {
+        self.inner.poll_fd()
+    }
+
+    fn has_pending(&self) -> bool {
+        self.inner.has_pending()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reconnect::{LinkState, Reconnecting};
+    use std::time::Instant;
+
+    #[test]
+    fn test_pipe() {
+        let (mut a, mut b) = pipe();
+        a.send(b"ping").unwrap();
+        assert!(b.has_pending());
+        let mut buf = [0u8; 8];
+        assert_eq!(b.read(&mut buf).unwrap(), 4);
+        assert_eq!(&buf[..4], b"ping");
+        assert_eq!(b.read(&mut buf).unwrap_err().kind(), ErrorKind::WouldBlock);
+
+        // What was sent before the other end closed can still be read, then it reads as EOF
+        b.send(b"pong").unwrap();
+        drop(b);
+        assert_eq!(a.read(&mut buf[..2]).unwrap(), 2);
+        assert_eq!(a.read(&mut buf).unwrap(), 2);
+        assert_eq!(a.read(&mut buf).unwrap(), 0);
+        assert_eq!(a.send(b"ping").unwrap_err().kind(), ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn test_reconnecting_pipe() {
+        let (a, b) = pipe();
+        let mut ends = vec![a];
+        let mut link = Reconnecting::new("pipe", move || ends.pop().ok_or(ErrorKind::ConnectionRefused.into()));
+        drop(b);
+        let mut buf = [0u8; 4];
+        assert_eq!(link.read(&mut buf).unwrap_err().kind(), ErrorKind::NotConnected);
+        assert_eq!(link.state(), LinkState::Down);
+    }
+
+    #[test]
+    fn test_scripted_peripheral() {
+        let mut peripheral = ScriptedPeripheral::new()
+            .expect(b"request:Temperature", b"Temperature: 20")
+            .expect(b"execute:ResetDevice", b"")
+            .unprompted(b"hello");
+        let mut buf = [0u8; 32];
+        assert_eq!(peripheral.read(&mut buf).unwrap(), 5);
+        peripheral.send(b"request:Temperature").unwrap();
+        let n = peripheral.read(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"Temperature: 20");
+
+        let e = peripheral.send(b"execute:Explode").unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        assert!(!peripheral.is_done());
+        peripheral.send(b"execute:ResetDevice").unwrap();
+        assert_eq!(peripheral.read(&mut buf).unwrap_err().kind(), ErrorKind::WouldBlock);
+        assert!(peripheral.is_done());
+        assert_eq!(peripheral.sent().len(), 3);
+    }
+
+    #[test]
+    fn test_fault_injector() {
+        let (a, mut b) = pipe();
+        let mut faulty = FaultInjector::new(a)
+            .on_send(Fault::Drop)
+            .on_send(Fault::Error(ErrorKind::TimedOut))
+            .on_send(Fault::Delay(Duration::from_millis(20)))
+            .on_read(Fault::Drop)
+            .max_read(3);
+
+        assert_eq!(faulty.send(b"lost").unwrap(), 4);
+        assert_eq!(faulty.send(b"fails").unwrap_err().kind(), ErrorKind::TimedOut);
+        let start = Instant::now();
+        faulty.send(b"late").unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        let mut buf = [0u8; 8];
+        assert_eq!(b.read(&mut buf).unwrap(), 4);
+        assert_eq!(&buf[..4], b"late");
+
+        b.send(b"abcdefgh").unwrap();
+        assert_eq!(faulty.read(&mut buf).unwrap_err().kind(), ErrorKind::WouldBlock);
+        assert_eq!(faulty.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"def");
+        assert_eq!(faulty.get_ref().available(), 2);
+    }
+}
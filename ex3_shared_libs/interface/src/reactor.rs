@@ -0,0 +1,337 @@
+/*
+Event loop for components that wait on several interfaces and timers at once.
+
+Interfaces stay owned by the component (its context, C). Each source is registered with a function that
+picks the interface out of the context - a source whose interface is None is skipped, not an error -
+and a callback run with the context when the interface has data to read. Timers run a callback once
+after a delay, or repeatedly. One call to `run_once` waits on everything in a single poll, then runs the
+callback of every source that is ready and every timer that is due.
+
+I2C and SPI devices can't tell when they have data, so they should be read from a timer instead.
+*/
+
+use super::i2c::I2cDeviceInterface;
+use super::ipc::{IpcClient, IpcServer};
+use super::spi::SpiInterface;
+use super::tcp::TcpInterface;
+use super::uart::UartInterface;
+use nix::libc;
+use std::io::{Error as IoError, ErrorKind};
+use std::os::fd::{AsRawFd, RawFd};
+use std::time::{Duration, Instant};
+
+/// An interface the reactor can wait on
+pub trait Pollable {
+    /// File descriptor that becomes readable when the interface has data
+    fn poll_fd(&self) -> RawFd;
+
+    /// Whether data has already been received and is waiting to be taken, so there is no need to wait
+    fn has_pending(&self) -> bool {
+        false
+    }
+}
+
+impl Pollable for IpcServer {
+    fn poll_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+
+    fn has_pending(&self) -> bool {
+        self.queued() > 0
+    }
+}
+
+impl Pollable for IpcClient {
+    fn poll_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+
+    fn has_pending(&self) -> bool {
+        self.queued() > 0
+    }
+}
+
+impl Pollable for TcpInterface {
+    fn poll_fd(&self) -> RawFd {
+        self.stream.as_raw_fd()
+    }
+}
+
+impl Pollable for UartInterface {
+    fn poll_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+// I2C and SPI devices are never ready, they have to be read from a timer
+impl Pollable for I2cDeviceInterface {
+    fn poll_fd(&self) -> RawFd {
+        -1
+    }
+}
+
+impl Pollable for SpiInterface {
+    fn poll_fd(&self) -> RawFd {
+        -1
+    }
+}
+
+impl<T: Pollable + ?Sized> Pollable for Box<T> {
+    fn poll_fd(&self) -> RawFd {
+        (**self).poll_fd()
+    }
+
+    fn has_pending(&self) -> bool {
+        (**self).has_pending()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SourceId(usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TimerId(usize);
+
+/// Something that is ready to be handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ready {
+    Source(SourceId),
+    Timer(TimerId),
+}
+
+type Callback<C> = Box<dyn FnMut(&mut C) -> Result<(), IoError>>;
+/// The source's fd and whether it has data pending, if its interface is there
+type SourceState<C> = Box<dyn Fn(&mut C) -> Option<(RawFd, bool)>>;
+
+struct Source<C> {
+    id: SourceId,
+    state: SourceState<C>,
+    on_ready: Callback<C>,
+}
+
+struct Timer<C> {
+    id: TimerId,
+    due: Instant,
+    period: Option<Duration>,
+    on_fire: Callback<C>,
+}
+
+pub struct Reactor<C> {
+    sources: Vec<Source<C>>,
+    timers: Vec<Timer<C>>,
+    next_id: usize,
+}
+
+impl<C> Default for Reactor<C> {
+    fn default() -> Self {
+        Reactor { sources: Vec::new(), timers: Vec::new(), next_id: 0 }
+    }
+}
+
+impl<C> Reactor<C> {
+    pub fn new() -> Reactor<C> {
+        Reactor::default()
+    }
+
+    fn next_id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    /// Run `on_ready` whenever the interface `select` picks out of the context has data to read
+    pub fn add_source<I, S, F>(&mut self, select: S, on_ready: F) -> SourceId
+    where
+        I: Pollable,
+        S: Fn(&mut C) -> Option<&mut I> + 'static,
+        F: FnMut(&mut C) -> Result<(), IoError> + 'static,
+    {
+        let id = SourceId(self.next_id());
+        let state = Box::new(move |ctx: &mut C| select(ctx).map(|i| (i.poll_fd(), i.has_pending())));
+        self.sources.push(Source { id, state, on_ready: Box::new(on_ready) });
+        id
+    }
+
+    pub fn remove_source(&mut self, id: SourceId) {
+        self.sources.retain(|s| s.id != id);
+    }
+
+    /// Run `on_fire` every `period`, starting one period from now
+    pub fn every<F>(&mut self, period: Duration, on_fire: F) -> TimerId
+    where
+        F: FnMut(&mut C) -> Result<(), IoError> + 'static,
+    {
+        self.add_timer(period, Some(period), Box::new(on_fire))
+    }
+
+    /// Run `on_fire` once, after `delay`
+    pub fn after<F>(&mut self, delay: Duration, on_fire: F) -> TimerId
+    where
+        F: FnMut(&mut C) -> Result<(), IoError> + 'static,
+    {
+        self.add_timer(delay, None, Box::new(on_fire))
+    }
+
+    fn add_timer(&mut self, delay: Duration, period: Option<Duration>, on_fire: Callback<C>) -> TimerId {
+        let id = TimerId(self.next_id());
+        self.timers.push(Timer { id, due: Instant::now() + delay, period, on_fire });
+        id
+    }
+
+    pub fn cancel_timer(&mut self, id: TimerId) {
+        self.timers.retain(|t| t.id != id);
+    }
+
+    /// Wait until a source is ready or a timer is due, for at most `timeout`. Returns every source that
+    /// is ready and every timer that is due, which is empty if the timeout ran out or the wait was
+    /// interrupted by a signal.
+    pub fn wait(&mut self, ctx: &mut C, timeout: Duration) -> Result<Vec<Ready>, IoError> {
+        let mut ready = Vec::new();
+        let mut poll_fds = Vec::new();
+        let mut polled = Vec::new();
+        for source in &self.sources {
+            match (source.state)(ctx) {
+                Some((_, true)) => ready.push(Ready::Source(source.id)),
+                Some((fd, false)) => {
+                    poll_fds.push(libc::pollfd { fd, events: libc::POLLIN, revents: 0 });
+                    polled.push(source.id);
+                }
+                None => (),
+            }
+        }
+
+        // Don't wait past the next timer, or at all if something is ready already
+        let now = Instant::now();
+        let wait = match self.timers.iter().map(|t| t.due).min() {
+            _ if !ready.is_empty() => Duration::ZERO,
+            Some(due) => timeout.min(due.saturating_duration_since(now)),
+            None => timeout,
+        };
+        // Rounded up so a timer isn't woken for just before it is due
+        let timeout_ms = wait.as_nanos().div_ceil(1_000_000).min(i32::MAX as u128) as i32;
+        let poll_result = unsafe {
+            libc::poll(poll_fds.as_mut_ptr(), poll_fds.len() as libc::nfds_t, timeout_ms)
+        };
+        if poll_result < 0 {
+            let e = IoError::last_os_error();
+            return match e.kind() {
+                ErrorKind::Interrupted => Ok(ready),
+                _ => Err(e),
+            };
+        }
+
+        // A hung up or failed fd is ready too, so its callback finds out when it reads
+        let events = libc::POLLIN | libc::POLLHUP | libc::POLLERR;
+        ready.extend(poll_fds.iter().zip(polled)
+            .filter(|(p, _)| p.revents & events != 0)
+            .map(|(_, id)| Ready::Source(id)));
+        let now = Instant::now();
+        ready.extend(self.timers.iter().filter(|t| t.due <= now).map(|t| Ready::Timer(t.id)));
+        Ok(ready)
+    }
+
+    /// Run the callbacks of everything in `ready`. Returns the errors the callbacks returned, with what
+    /// they were for. A timer that isn't repeating is removed once it has run.
+    pub fn dispatch(&mut self, ctx: &mut C, ready: &[Ready]) -> Vec<(Ready, IoError)> {
+        let mut errors = Vec::new();
+        for &r in ready {
+            let res = match r {
+                Ready::Source(id) => match self.sources.iter_mut().find(|s| s.id == id) {
+                    Some(source) => (source.on_ready)(ctx),
+                    None => continue,
+                },
+                Ready::Timer(id) => match self.timers.iter().position(|t| t.id == id) {
+                    Some(i) => {
+                        let timer = &mut self.timers[i];
+                        let res = (timer.on_fire)(ctx);
+                        match timer.period {
+                            // Kept to its schedule, unless it has fallen a whole period behind
+                            Some(period) => timer.due = (timer.due + period).max(Instant::now()),
+                            None => {
+                                self.timers.remove(i);
+                            }
+                        }
+                        res
+                    }
+                    None => continue,
+                },
+            };
+            if let Err(e) = res {
+                errors.push((r, e));
+            }
+        }
+        errors
+    }
+
+    /// Wait for at most `timeout`, then run the callbacks of whatever is ready. Returns the errors the
+    /// callbacks returned; an error is only returned if waiting failed.
+    pub fn run_once(&mut self, ctx: &mut C, timeout: Duration) -> Result<Vec<(Ready, IoError)>, IoError> {
+        let ready = self.wait(ctx, timeout)?;
+        Ok(self.dispatch(ctx, &ready))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Component {
+        server: Option<IpcServer>,
+        missing: Option<IpcServer>,
+        received: Vec<Vec<u8>>,
+        ticks: usize,
+        fired: usize,
+    }
+
+    fn reactor() -> Reactor<Component> {
+        let mut reactor = Reactor::new();
+        reactor.add_source(|c: &mut Component| c.server.as_mut(), |c| {
+            while let Some(frame) = c.server.as_mut().unwrap().recv() {
+                c.received.push(frame.data);
+            }
+            Ok(())
+        });
+        reactor.add_source(|c: &mut Component| c.missing.as_mut(), |_| unreachable!());
+        reactor
+    }
+
+    #[test]
+    fn test_dispatches_ready_sources() {
+        let mut component = Component {
+            server: Some(IpcServer::new("reactor_test_sources".to_string()).unwrap()),
+            ..Default::default()
+        };
+        let mut reactor = reactor();
+        assert!(reactor.wait(&mut component, Duration::from_millis(10)).unwrap().is_empty());
+
+        let mut client = IpcClient::new("reactor_test_sources".to_string()).unwrap();
+        client.send(&[0]).unwrap();
+        client.send(&[]).unwrap();
+        let errors = reactor.run_once(&mut component, Duration::from_secs(1)).unwrap();
+        assert!(errors.is_empty());
+        assert_eq!(component.received, [vec![0], vec![]]);
+    }
+
+    #[test]
+    fn test_timers() {
+        let mut component = Component::default();
+        let mut reactor = reactor();
+        reactor.every(Duration::from_millis(20), |c| {
+            c.ticks += 1;
+            Ok(())
+        });
+        reactor.after(Duration::from_millis(30), |c| {
+            c.fired += 1;
+            Err(IoError::other("failed"))
+        });
+
+        let start = Instant::now();
+        let mut errors = 0;
+        while component.ticks < 5 {
+            errors += reactor.run_once(&mut component, Duration::from_secs(1)).unwrap().len();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert_eq!(component.fired, 1);
+        assert_eq!(errors, 1);
+    }
+}
@@ -0,0 +1,340 @@
+/*
+Wrapper that keeps an interface connected.
+
+When a read or send finds the link is gone - the other end closed it, reset it, or isn't there - the
+interface is dropped and connected again with the function it was created with. Attempts back off
+exponentially, from INITIAL_BACKOFF up to MAX_BACKOFF, and are made when the interface is next used
+or `maintain` is called. While the link is down reads fail with a NotConnected "unavailable" error,
+and sends either fail the same way or are queued and sent once the link is back.
+*/
+
+use super::i2c::I2cDeviceInterface;
+use super::ipc::IpcClient;
+use super::reactor::Pollable;
+use super::spi::SpiInterface;
+use super::tcp::TcpInterface;
+use super::uart::{SerialPortSettings, UartInterface};
+use super::Interface;
+use std::collections::VecDeque;
+use std::io::{Error as IoError, ErrorKind};
+use std::os::fd::RawFd;
+use std::time::{Duration, Instant};
+
+pub const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+pub const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// An interface that can lose its link
+pub trait Link: Interface {
+    /// Whether a read of 0 bytes means the other end closed the link, rather than there being no data
+    fn zero_read_is_eof(&self) -> bool {
+        false
+    }
+}
+
+impl Link for TcpInterface {
+    fn zero_read_is_eof(&self) -> bool {
+        true
+    }
+}
+
+impl Link for UartInterface {}
+
+impl Link for IpcClient {}
+
+impl Link for I2cDeviceInterface {}
+
+impl Link for SpiInterface {}
+
+impl<T: Link + ?Sized> Link for Box<T> {
+    fn zero_read_is_eof(&self) -> bool {
+        (**self).zero_read_is_eof()
+    }
+}
+
+/// Whether an error means the link is gone, rather than the one read or send failing
+pub fn is_link_error(e: &IoError) -> bool {
+    matches!(e.kind(), ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::ConnectionRefused
+                       | ErrorKind::BrokenPipe | ErrorKind::UnexpectedEof | ErrorKind::NotConnected
+                       | ErrorKind::NotFound)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkState {
+    Up,
+    Down,
+}
+
+/// What to do with data sent while the link is down
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WhileDown {
+    /// Fail the send
+    Reject,
+    /// Send it once the link is back, keeping at most this many sends. The oldest are dropped first
+    Queue(usize),
+}
+
+type Connect<I> = Box<dyn FnMut() -> Result<I, IoError>>;
+
+pub struct Reconnecting<I> {
+    name: String,
+    connect: Connect<I>,
+    inner: Option<I>,
+    while_down: WhileDown,
+    queue: VecDeque<Vec<u8>>,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    backoff: Duration,
+    next_attempt: Instant,
+}
+
+impl<I: Link> Reconnecting<I> {
+    /// Connect with `connect`, now and whenever the link is lost. `name` is what the link is called in
+    /// errors and logs
+    pub fn new<F>(name: &str, connect: F) -> Reconnecting<I>
+    where
+        F: FnMut() -> Result<I, IoError> + 'static,
+    {
+        let mut link = Reconnecting {
+            name: name.to_string(),
+            connect: Box::new(connect),
+            inner: None,
+            while_down: WhileDown::Reject,
+            queue: VecDeque::new(),
+            initial_backoff: INITIAL_BACKOFF,
+            max_backoff: MAX_BACKOFF,
+            backoff: INITIAL_BACKOFF,
+            next_attempt: Instant::now(),
+        };
+        link.maintain();
+        link
+    }
+
+    pub fn while_down(mut self, while_down: WhileDown) -> Self {
+        self.while_down = while_down;
+        self
+    }
+
+    /// Wait `initial` after the first failed attempt to connect, doubling each time up to `max`
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self.backoff = initial;
+        // The attempt made by `new` failed, so the next one is due after the new backoff
+        if self.inner.is_none() {
+            self.next_attempt = Instant::now() + initial;
+            self.backoff = (initial * 2).min(max);
+        }
+        self
+    }
+
+    pub fn state(&self) -> LinkState {
+        match self.inner {
+            Some(_) => LinkState::Up,
+            None => LinkState::Down,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The connected interface, if the link is up
+    pub fn get_mut(&mut self) -> Option<&mut I> {
+        self.inner.as_mut()
+    }
+
+    /// Number of sends waiting for the link to come back
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Try to connect if the link is down and it is time for another attempt. Returns the state of the link
+    pub fn maintain(&mut self) -> LinkState {
+        if self.inner.is_some() || Instant::now() < self.next_attempt {
+            return self.state();
+        }
+        match (self.connect)() {
+            Ok(inner) => {
+                println!("{} link is up", self.name);
+                self.inner = Some(inner);
+                self.backoff = self.initial_backoff;
+                self.flush_queue();
+            }
+            Err(e) => {
+                eprintln!("Cannot connect {}, trying again in {:?}: {}", self.name, self.backoff, e);
+                self.next_attempt = Instant::now() + self.backoff;
+                self.backoff = (self.backoff * 2).min(self.max_backoff);
+            }
+        }
+        self.state()
+    }
+
+    /// Error for using the link while it is down
+    pub fn unavailable(&self) -> IoError {
+        IoError::new(ErrorKind::NotConnected, format!("{} unavailable", self.name))
+    }
+
+    fn link_lost(&mut self, e: &IoError) {
+        eprintln!("{} link is down: {}", self.name, e);
+        self.inner = None;
+        // The first attempt is made straight away, in case the other end is already back
+        self.next_attempt = Instant::now();
+    }
+
+    /// Send what was queued while the link was down, until it is all sent or the link goes down again
+    fn flush_queue(&mut self) {
+        while let Some(data) = self.queue.pop_front() {
+            let Some(ref mut inner) = self.inner else {
+                self.queue.push_front(data);
+                return;
+            };
+            match inner.send(&data) {
+                Err(e) if is_link_error(&e) => {
+                    self.queue.push_front(data);
+                    self.link_lost(&e);
+                    return;
+                }
+                Err(e) => eprintln!("Error sending queued data to {}: {}", self.name, e),
+                Ok(_) => (),
+            }
+        }
+    }
+}
+
+impl Reconnecting<TcpInterface> {
+    pub fn tcp(ip: &str, port: u16) -> Reconnecting<TcpInterface> {
+        let ip = ip.to_string();
+        Reconnecting::new(&format!("{}:{}", ip, port), move || TcpInterface::new_client(ip.clone(), port))
+    }
+}
+
+impl Reconnecting<UartInterface> {
+    pub fn uart(file_path: &str, settings: SerialPortSettings) -> Reconnecting<UartInterface> {
+        let file_path = file_path.to_string();
+        Reconnecting::new(&file_path.clone(), move || UartInterface::open(&file_path, Some(&settings)))
+    }
+}
+
+impl Reconnecting<IpcClient> {
+    pub fn ipc_client(server_name: &str) -> Reconnecting<IpcClient> {
+        let server_name = server_name.to_string();
+        Reconnecting::new(&server_name.clone(), move || IpcClient::new(server_name.clone()))
+    }
+}
+
+impl<I: Link> Interface for Reconnecting<I> {
+    fn send(&mut self, data: &[u8]) -> Result<usize, IoError> {
+        self.maintain();
+        if let Some(ref mut inner) = self.inner {
+            match inner.send(data) {
+                Err(e) if is_link_error(&e) => self.link_lost(&e),
+                res => return res,
+            }
+        }
+        match self.while_down {
+            WhileDown::Reject => Err(self.unavailable()),
+            WhileDown::Queue(max) => {
+                if self.queue.len() >= max {
+                    self.queue.pop_front();
+                }
+                self.queue.push_back(data.to_vec());
+                Ok(data.len())
+            }
+        }
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, IoError> {
+        self.maintain();
+        let Some(ref mut inner) = self.inner else {
+            return Err(self.unavailable());
+        };
+        match inner.read(buffer) {
+            Ok(0) if !buffer.is_empty() && inner.zero_read_is_eof() => {
+                self.link_lost(&IoError::new(ErrorKind::UnexpectedEof, "closed by the other end"));
+                Err(self.unavailable())
+            }
+            Err(e) if is_link_error(&e) => {
+                self.link_lost(&e);
+                Err(self.unavailable())
+            }
+            res => res,
+        }
+    }
+}
+
+impl<I: Pollable> Pollable for Reconnecting<I> {
+    /// While the link is down this is -1, which poll ignores
+    fn poll_fd(&self) -> RawFd {
+        self.inner.as_ref().map_or(-1, |i| i.poll_fd())
+    }
+
+    fn has_pending(&self) -> bool {
+        self.inner.as_ref().is_some_and(|i| i.has_pending())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_reconnects_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut link = Reconnecting::tcp("127.0.0.1", port)
+            .while_down(WhileDown::Queue(2))
+            .backoff(Duration::ZERO, Duration::ZERO);
+        assert_eq!(link.state(), LinkState::Up);
+
+        // The other end going away is noticed on the next read
+        let (peer, _) = listener.accept().unwrap();
+        drop(peer);
+        drop(listener);
+        let mut buf = [0u8; 8];
+        let e = link.read(&mut buf).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::NotConnected);
+        assert_eq!(e.to_string(), format!("127.0.0.1:{} unavailable", port));
+        assert_eq!(link.state(), LinkState::Down);
+
+        // Sends made while down are sent once it is back, keeping the latest
+        for data in [b"a", b"b", b"c"] {
+            assert_eq!(link.send(data).unwrap(), 1);
+        }
+        assert_eq!(link.queued(), 2);
+        let listener = TcpListener::bind(("127.0.0.1", port)).unwrap();
+        assert_eq!(link.maintain(), LinkState::Up);
+        let (mut peer, _) = listener.accept().unwrap();
+        let mut received = Vec::new();
+        while received.len() < 2 {
+            let n = std::io::Read::read(&mut peer, &mut buf).unwrap();
+            received.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(received, b"bc");
+    }
+
+    #[test]
+    fn test_backs_off() {
+        let mut attempts = 0;
+        let mut link: Reconnecting<TcpInterface> = Reconnecting::new("nothing", move || {
+            attempts += 1;
+            Err(IoError::new(ErrorKind::ConnectionRefused, format!("attempt {}", attempts)))
+        }).backoff(Duration::from_millis(20), Duration::from_millis(40));
+        assert_eq!(link.state(), LinkState::Down);
+        assert_eq!(link.send(&[1]).unwrap_err().kind(), ErrorKind::NotConnected);
+        assert_eq!(link.poll_fd(), -1);
+
+        // Attempts aren't made until the backoff has passed, and it doubles up to the max
+        let start = Instant::now();
+        let mut attempt_times = Vec::new();
+        while start.elapsed() < Duration::from_millis(150) {
+            let before = link.next_attempt;
+            link.maintain();
+            if link.next_attempt != before {
+                attempt_times.push(start.elapsed());
+            }
+        }
+        assert!((3..=5).contains(&attempt_times.len()), "{:?}", attempt_times);
+        assert!(attempt_times.windows(2).all(|w| w[1] - w[0] >= Duration::from_millis(20)));
+    }
+}
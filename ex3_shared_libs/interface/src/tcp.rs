@@ -3,7 +3,6 @@ Written by Devin Headrick and Rowan Rasmusson
 Summer 2024
 */
 use super::Interface;
-use std::io;
 use std::io::{Error, Read, Write};
 use std::net::{TcpListener, TcpStream, Shutdown};
 
@@ -45,7 +44,7 @@ impl TcpInterface {
                 }
             }
         }
-        Err(Error::new(io::ErrorKind::Other, "No incoming connections"))
+        Err(Error::other("No incoming connections"))
     }
 
     pub fn close(&mut self) {
@@ -96,10 +96,14 @@ impl Interface for UartInterface {
 
 impl UartInterface {
     pub fn new(file_path: &str, settings_option: Option<&SerialPortSettings>) -> Self {
-        let serial_port_fd_raw = fcntl::open(file_path, fcntl::OFlag::O_RDWR, Mode::S_IWUSR)
-                                .expect("Could not open serial port.");
+        UartInterface::open(file_path, settings_option).expect("Could not open serial port.")
+    }
+
+    /// Like `new`, but returns an error if the serial port cannot be opened or set up
+    pub fn open(file_path: &str, settings_option: Option<&SerialPortSettings>) -> Result<Self, std::io::Error> {
+        let serial_port_fd_raw = fcntl::open(file_path, fcntl::OFlag::O_RDWR, Mode::S_IWUSR)?;
         let serial_port_fd = unsafe { File::from_raw_fd(serial_port_fd_raw) };
-        let mut tty = termios::tcgetattr(&serial_port_fd).expect("Error getting serial port settings");
+        let mut tty = termios::tcgetattr(&serial_port_fd)?;
 
         // Create new instance of default settings if user did not provide any
         let settings = match settings_option {
@@ -117,11 +121,11 @@ impl UartInterface {
         tty.control_chars[SpecialCharacterIndices::VMIN as usize] = 0;
 
         // Set baud rate
-        termios::cfsetspeed(&mut tty, settings.baud_rate).unwrap();
+        termios::cfsetspeed(&mut tty, settings.baud_rate)?;
         // Apply settings immediately
-        termios::tcsetattr(&serial_port_fd, termios::SetArg::TCSANOW, &tty).unwrap();
+        termios::tcsetattr(&serial_port_fd, termios::SetArg::TCSANOW, &tty)?;
 
-        UartInterface {fd: serial_port_fd.into(), file_path: file_path.to_string()}
+        Ok(UartInterface {fd: serial_port_fd.into(), file_path: file_path.to_string()})
     }
 
     // Flushes bytes in output bufffer that have not been transmitted yet
@@ -0,0 +1,113 @@
+/*
+Simulated ADCS, which takes ':' delimited text cmds (see adcs_handler's sim_adcs). Cmds that set values
+reply "OK", those that get them reply with the values ':' delimited, and errors reply "ERROR: ...". Only
+ON, GS and RESET work while the ADCS is off.
+*/
+
+use crate::{int_params, split_cmd, Subsystem};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Default)]
+pub struct Adcs {
+    on: bool,
+    wheel_speeds: [i64; 3],
+    magnetorquer_currents: [i64; 3],
+    orientation: [i64; 3],
+    /// Difference between the ADCS's clock and the system's, in s
+    clock_offset: i64,
+}
+
+fn unix_time() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |t| t.as_secs() as i64)
+}
+
+fn join(values: &[i64]) -> String {
+    values.iter().map(i64::to_string).collect::<Vec<_>>().join(":")
+}
+
+impl Adcs {
+    fn reply(&mut self, cmd: &str, params: &[String]) -> Result<String, String> {
+        if !self.on && !matches!(cmd, "ON" | "GS" | "RESET") {
+            return Err("ERROR: ADCS is off".to_string());
+        }
+        let ok = || Ok("OK".to_string());
+        match cmd {
+            "ON" => {
+                self.on = true;
+                ok()
+            }
+            "OFF" => {
+                self.on = false;
+                ok()
+            }
+            "GS" => Ok(if self.on { "ON" } else { "OFF" }.to_string()),
+            "GWS" => Ok(join(&self.wheel_speeds)),
+            "SWS" => {
+                self.wheel_speeds = int_params(params)?;
+                ok()
+            }
+            "SC" => ok(),
+            "GMC" => Ok(join(&self.magnetorquer_currents)),
+            "SMC" => {
+                self.magnetorquer_currents = int_params(params)?;
+                ok()
+            }
+            "GTM" => Ok((unix_time() + self.clock_offset).to_string()),
+            "STM" => {
+                let [time] = int_params(params)?;
+                self.clock_offset = time - unix_time();
+                ok()
+            }
+            "GOR" => Ok(join(&self.orientation)),
+            "RESET" => {
+                *self = Adcs::default();
+                ok()
+            }
+            _ => Err(format!("ERROR: unknown cmd {}", cmd)),
+        }
+    }
+}
+
+impl Subsystem for Adcs {
+    fn name(&self) -> &'static str {
+        "ADCS"
+    }
+
+    fn handle(&mut self, request: &[u8]) -> Option<Vec<u8>> {
+        let (cmd, params) = split_cmd(request);
+        let reply = self.reply(&cmd, &params).unwrap_or_else(|e| e);
+        Some(reply.into_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reply(adcs: &mut Adcs, request: &str) -> String {
+        String::from_utf8(adcs.handle(request.as_bytes()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_cmds() {
+        let mut adcs = Adcs::default();
+        assert_eq!(reply(&mut adcs, "GS"), "OFF");
+        assert_eq!(reply(&mut adcs, "GWS"), "ERROR: ADCS is off");
+        assert_eq!(reply(&mut adcs, "ON"), "OK");
+        assert_eq!(reply(&mut adcs, "GS"), "ON");
+        assert_eq!(reply(&mut adcs, "SWS:100:-5:0"), "OK");
+        assert_eq!(reply(&mut adcs, "GWS"), "100:-5:0");
+        assert_eq!(reply(&mut adcs, "SMC:10:20:-30"), "OK");
+        assert_eq!(reply(&mut adcs, "GMC"), "10:20:-30");
+        assert_eq!(reply(&mut adcs, "STM:1700000000"), "OK");
+        let time: i64 = reply(&mut adcs, "GTM").parse().unwrap();
+        assert!((1700000000..1700000010).contains(&time));
+        assert_eq!(reply(&mut adcs, "GOR"), "0:0:0");
+
+        for bad in ["SWS:1:2", "SMC:a:b:c", "STM", "SPIN"] {
+            assert!(reply(&mut adcs, bad).starts_with("ERROR: "), "{}", bad);
+        }
+        assert_eq!(reply(&mut adcs, "RESET"), "OK");
+        assert_eq!(reply(&mut adcs, "GS"), "OFF");
+    }
+}
@@ -0,0 +1,92 @@
+/*
+Simulated DFGM, which sends a packet of magnetic field samples every second and takes no cmds. Packets
+are big endian, laid out as:
+
+    "DFGM"                  sync
+    u32                     sequence number
+    u32                     seconds since the DFGM was powered on
+    20 x u16                HK channels
+    100 x (i32, i32, i32)   x, y and z field samples in nT
+*/
+
+use crate::Subsystem;
+use std::time::{Duration, Instant};
+
+pub const DFGM_PACKET_SIZE: usize = 1252;
+pub const SAMPLES_PER_PACKET: usize = 100;
+const HK_CHANNELS: usize = 20;
+const SYNC: &[u8; 4] = b"DFGM";
+
+pub struct Dfgm {
+    sequence: u32,
+    powered_on: Instant,
+}
+
+impl Default for Dfgm {
+    fn default() -> Dfgm {
+        Dfgm { sequence: 0, powered_on: Instant::now() }
+    }
+}
+
+impl Dfgm {
+    /// The field at `t` seconds into an orbit, which turns through the three axes once every 90 mins
+    fn field(t: f64) -> [i32; 3] {
+        let phase = t * std::f64::consts::TAU / 5400.0;
+        [(30000.0 * phase.cos()) as i32, (30000.0 * phase.sin()) as i32, (-40000.0 * (phase / 2.0).cos()) as i32]
+    }
+
+    pub fn packet(&mut self) -> Vec<u8> {
+        let seconds = self.powered_on.elapsed().as_secs() as u32;
+        let mut packet = Vec::with_capacity(DFGM_PACKET_SIZE);
+        packet.extend_from_slice(SYNC);
+        packet.extend_from_slice(&self.sequence.to_be_bytes());
+        packet.extend_from_slice(&seconds.to_be_bytes());
+        for channel in 0..HK_CHANNELS as u16 {
+            packet.extend_from_slice(&(1000 + channel).to_be_bytes());
+        }
+        for sample in 0..SAMPLES_PER_PACKET {
+            let t = seconds as f64 + sample as f64 / SAMPLES_PER_PACKET as f64;
+            for axis in Dfgm::field(t) {
+                packet.extend_from_slice(&axis.to_be_bytes());
+            }
+        }
+        self.sequence = self.sequence.wrapping_add(1);
+        packet
+    }
+}
+
+impl Subsystem for Dfgm {
+    fn name(&self) -> &'static str {
+        "DFGM"
+    }
+
+    fn handle(&mut self, _request: &[u8]) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn period(&self) -> Option<Duration> {
+        Some(Duration::from_secs(1))
+    }
+
+    fn unprompted(&mut self) -> Vec<u8> {
+        self.packet()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packets() {
+        let mut dfgm = Dfgm::default();
+        assert_eq!(dfgm.handle(b"anything"), None);
+        let first = dfgm.unprompted();
+        let second = dfgm.unprompted();
+        for (packet, sequence) in [(first, 0u32), (second, 1)] {
+            assert_eq!(packet.len(), DFGM_PACKET_SIZE);
+            assert_eq!(&packet[..4], SYNC);
+            assert_eq!(packet[4..8], sequence.to_be_bytes());
+        }
+    }
+}
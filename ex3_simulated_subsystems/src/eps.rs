@@ -0,0 +1,97 @@
+/*
+Simulated EPS, which takes text cmds of three kinds:
+
+    request:<field>             replies "<field>: <value>"
+    command:<field>:<value>     sets a field, replying "OK"
+    execute:ResetDevice         resets every field, replying "Resetting"
+
+Errors are replied with "ERROR: ..."
+*/
+
+use crate::{int_params, split_cmd, Subsystem};
+use std::time::Instant;
+
+/// Fields and their values after a reset
+const DEFAULT_FIELDS: [(&str, i64); 5] = [
+    ("Temperature", 21),
+    ("BatteryVoltage", 7400),
+    ("BatteryCurrent", 250),
+    ("SolarCurrent", 600),
+    ("OutputsEnabled", 1),
+];
+
+pub struct Eps {
+    fields: Vec<(String, i64)>,
+    reset_at: Instant,
+}
+
+impl Default for Eps {
+    fn default() -> Eps {
+        let fields = DEFAULT_FIELDS.iter().map(|(name, value)| (name.to_string(), *value)).collect();
+        Eps { fields, reset_at: Instant::now() }
+    }
+}
+
+impl Eps {
+    fn field(&mut self, name: &str) -> Option<&mut i64> {
+        self.fields.iter_mut().find(|(n, _)| n == name).map(|(_, value)| value)
+    }
+
+    fn reply(&mut self, kind: &str, params: &[String]) -> Result<String, String> {
+        let name = params.first().ok_or("ERROR: no field given")?.clone();
+        match kind {
+            "request" if name == "Uptime" => Ok(format!("Uptime: {}", self.reset_at.elapsed().as_secs())),
+            "request" => {
+                let value = self.field(&name).ok_or(format!("ERROR: no field {}", name))?;
+                Ok(format!("{}: {}", name, value))
+            }
+            "command" => {
+                let [new_value] = int_params(&params[1..])?;
+                *self.field(&name).ok_or(format!("ERROR: no field {}", name))? = new_value;
+                Ok("OK".to_string())
+            }
+            "execute" if name == "ResetDevice" => {
+                *self = Eps::default();
+                Ok("Resetting".to_string())
+            }
+            "execute" => Err(format!("ERROR: no action {}", name)),
+            _ => Err(format!("ERROR: unknown request {}", kind)),
+        }
+    }
+}
+
+impl Subsystem for Eps {
+    fn name(&self) -> &'static str {
+        "EPS"
+    }
+
+    fn handle(&mut self, request: &[u8]) -> Option<Vec<u8>> {
+        let (kind, params) = split_cmd(request);
+        let reply = self.reply(&kind, &params).unwrap_or_else(|e| e);
+        Some(reply.into_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reply(eps: &mut Eps, request: &str) -> String {
+        String::from_utf8(eps.handle(request.as_bytes()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_requests() {
+        let mut eps = Eps::default();
+        assert_eq!(reply(&mut eps, "request:Temperature"), "Temperature: 21");
+        assert_eq!(reply(&mut eps, "command:Temperature:-5"), "OK");
+        assert_eq!(reply(&mut eps, "request:Temperature"), "Temperature: -5");
+        assert_eq!(reply(&mut eps, "request:Uptime"), "Uptime: 0");
+        assert_eq!(reply(&mut eps, "execute:ResetDevice"), "Resetting");
+        assert_eq!(reply(&mut eps, "request:Temperature"), "Temperature: 21");
+
+        for bad in ["request:Pressure", "command:Temperature", "command:Temperature:hot", "execute:Explode", "request", "dummy"] {
+            assert!(reply(&mut eps, bad).starts_with("ERROR: "), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,161 @@
+/*
+Simulated IRIS camera, which takes text cmds and replies to each with FLAG:<len>:<data>|END| packets:
+
+    RST         reset, deleting every image
+    ON / OFF    power the sensors
+    TKI         take an image, which needs the sensors on
+    FTI:<n>     fetch the first n images: an IMAGES:<count> packet, then a name and a data packet for each
+    FSI:<n>     sizes of the first n images, a "<name>: <size>" line each
+    FNI         number of images
+    DTI:<n>     delete the first n images
+    FTT / STT:<unix time>   get / set the time, which is fetched as HH:MM:SS
+    FTH         HK, a "<name>: <value>" line each
+
+Cmds that do not fetch anything reply "OK", and errors reply "ERROR: ..."
+*/
+
+use crate::{int_params, split_cmd, Subsystem};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Size of simulated images, which is more than the handler reads in one packet
+pub const IMAGE_SIZE: usize = 3000;
+
+#[derive(Default)]
+pub struct Iris {
+    on: bool,
+    images: Vec<(String, Vec<u8>)>,
+    images_taken: u32,
+    /// Difference between the camera's clock and the system's, in s
+    clock_offset: i64,
+}
+
+/// Frame `data` as a packet
+pub fn packet(data: &[u8]) -> Vec<u8> {
+    let mut packet = format!("FLAG:{}:", data.len()).into_bytes();
+    packet.extend_from_slice(data);
+    packet.extend_from_slice(b"|END|");
+    packet
+}
+
+fn unix_time() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |t| t.as_secs() as i64)
+}
+
+impl Iris {
+    fn count(&self, params: &[String]) -> Result<usize, String> {
+        let [n] = int_params(params)?;
+        Ok((n.max(0) as usize).min(self.images.len()))
+    }
+
+    fn reply(&mut self, cmd: &str, params: &[String]) -> Result<Vec<u8>, String> {
+        let ok = || Ok(packet(b"OK"));
+        match cmd {
+            "RST" => {
+                *self = Iris::default();
+                ok()
+            }
+            "ON" => {
+                self.on = true;
+                ok()
+            }
+            "OFF" => {
+                self.on = false;
+                ok()
+            }
+            "TKI" if !self.on => Err("ERROR: IRIS sensors are off".to_string()),
+            "TKI" => {
+                let name = format!("image_{}.png", self.images_taken);
+                // Every image has different content, so a mixed up download shows
+                let data = (0..IMAGE_SIZE).map(|i| (i as u32 * 7 + self.images_taken) as u8).collect();
+                self.images.push((name, data));
+                self.images_taken += 1;
+                ok()
+            }
+            "FTI" => {
+                let n = self.count(params)?;
+                let mut reply = packet(format!("IMAGES:{}", n).as_bytes());
+                for (name, data) in &self.images[..n] {
+                    reply.extend(packet(name.as_bytes()));
+                    reply.extend(packet(data));
+                }
+                Ok(reply)
+            }
+            "FSI" => {
+                let n = self.count(params)?;
+                let sizes: String = self.images[..n].iter().map(|(name, data)| format!("{}: {}\n", name, data.len())).collect();
+                Ok(packet(sizes.as_bytes()))
+            }
+            "FNI" => Ok(packet(self.images.len().to_string().as_bytes())),
+            "DTI" => {
+                let n = self.count(params)?;
+                self.images.drain(..n);
+                ok()
+            }
+            "FTT" => {
+                let seconds = (unix_time() + self.clock_offset).rem_euclid(86400);
+                Ok(packet(format!("{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60).as_bytes()))
+            }
+            "STT" => {
+                let [time] = int_params(params)?;
+                self.clock_offset = time - unix_time();
+                ok()
+            }
+            "FTH" => {
+                let hk = format!(
+                    "Temperature: 25\nVoltage: 5000\nSensors: {}\nImages: {}\n",
+                    if self.on { "ON" } else { "OFF" },
+                    self.images.len()
+                );
+                Ok(packet(hk.as_bytes()))
+            }
+            _ => Err(format!("ERROR: unknown cmd {}", cmd)),
+        }
+    }
+}
+
+impl Subsystem for Iris {
+    fn name(&self) -> &'static str {
+        "IRIS"
+    }
+
+    fn handle(&mut self, request: &[u8]) -> Option<Vec<u8>> {
+        let (cmd, params) = split_cmd(request);
+        Some(self.reply(&cmd, &params).unwrap_or_else(|e| packet(e.as_bytes())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reply(iris: &mut Iris, request: &str) -> Vec<u8> {
+        iris.handle(request.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_cmds() {
+        let mut iris = Iris::default();
+        assert_eq!(reply(&mut iris, "TKI"), packet(b"ERROR: IRIS sensors are off"));
+        assert_eq!(reply(&mut iris, "ON"), b"FLAG:2:OK|END|");
+        for _ in 0..3 {
+            assert_eq!(reply(&mut iris, "TKI"), packet(b"OK"));
+        }
+        assert_eq!(reply(&mut iris, "FNI"), packet(b"3"));
+        assert_eq!(reply(&mut iris, "FSI:9"), packet(b"image_0.png: 3000\nimage_1.png: 3000\nimage_2.png: 3000\n"));
+        assert_eq!(reply(&mut iris, "DTI:1"), packet(b"OK"));
+
+        let fetched = reply(&mut iris, "FTI:1");
+        let image: Vec<u8> = (0..IMAGE_SIZE).map(|i| (i as u32 * 7 + 1) as u8).collect();
+        assert_eq!(fetched, [packet(b"IMAGES:1"), packet(b"image_1.png"), packet(&image)].concat());
+        assert_eq!(reply(&mut iris, "FTI:0"), packet(b"IMAGES:0"));
+
+        assert_eq!(reply(&mut iris, "STT:43200"), packet(b"OK"));
+        assert!(String::from_utf8(reply(&mut iris, "FTT")).unwrap().starts_with("FLAG:8:12:00:0"));
+        assert!(String::from_utf8(reply(&mut iris, "FTH")).unwrap().contains("Images: 2\n"));
+
+        assert_eq!(reply(&mut iris, "RST"), packet(b"OK"));
+        assert_eq!(reply(&mut iris, "FNI"), packet(b"0"));
+        assert_eq!(reply(&mut iris, "FTI"), packet(b"ERROR: expected 1 values, got 0"));
+        assert_eq!(reply(&mut iris, "SNAP"), packet(b"ERROR: unknown cmd SNAP"));
+    }
+}
@@ -0,0 +1,76 @@
+/*
+Simulated subsystems for running the OBC FSW and GS without any hardware. Each one is a TCP server on its
+port from `common::ports`, speaking the protocol its handler expects:
+
+    DFGM    sends a 1252B packet every second, and takes no cmds
+    EPS     replies to request:<field>, command:<field>:<value> and execute:<action>
+    ADCS    replies to ':' delimited cmds like SWS:100:-5:0
+    IRIS    replies to cmds like FTI:2 with FLAG:<len>:<data>|END| packets
+    UHF     replies to UHF: cmds on the UART port, passes everything else between the OBC and GS, and
+            broadcasts its beacon
+
+What a subsystem sends can be scripted, and faults injected into it (see script).
+*/
+
+pub mod adcs;
+pub mod dfgm;
+pub mod eps;
+pub mod iris;
+pub mod script;
+pub mod server;
+pub mod uhf;
+
+use std::time::Duration;
+
+/// The behaviour of a simulated subsystem, independent of the link to its handler
+pub trait Subsystem {
+    /// Name of the subsystem in logs
+    fn name(&self) -> &'static str;
+
+    /// The reply to a request from the handler, if there is one
+    fn handle(&mut self, request: &[u8]) -> Option<Vec<u8>>;
+
+    /// How often the subsystem sends data unprompted, if it does
+    fn period(&self) -> Option<Duration> {
+        None
+    }
+
+    /// The data the subsystem sends unprompted
+    fn unprompted(&mut self) -> Vec<u8> {
+        vec![]
+    }
+}
+
+/// Split a text cmd like SWS:100:-5:0 into its name and params
+fn split_cmd(request: &[u8]) -> (String, Vec<String>) {
+    let text = String::from_utf8_lossy(request);
+    let mut parts = text.trim_end_matches(['\0', '\n', '\r']).split(':').map(str::to_string);
+    let name = parts.next().unwrap_or_default();
+    (name, parts.collect())
+}
+
+/// Parse the integer params of a cmd, of which there must be N
+fn int_params<const N: usize>(params: &[String]) -> Result<[i64; N], String> {
+    if params.len() != N {
+        return Err(format!("ERROR: expected {} values, got {}", N, params.len()));
+    }
+    let mut values = [0; N];
+    for (value, param) in values.iter_mut().zip(params) {
+        *value = param.parse().map_err(|_| format!("ERROR: {} is not an integer", param))?;
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_splits_cmds() {
+        assert_eq!(split_cmd(b"SWS:100:-5:0\n"), ("SWS".to_string(), vec!["100".to_string(), "-5".to_string(), "0".to_string()]));
+        assert_eq!(split_cmd(b"ON"), ("ON".to_string(), vec![]));
+        assert_eq!(int_params::<3>(&["1".into(), "-2".into(), "3".into()]), Ok([1, -2, 3]));
+        assert!(int_params::<3>(&["1".into()]).is_err());
+        assert!(int_params::<1>(&["one".into()]).is_err());
+    }
+}
@@ -0,0 +1,95 @@
+/*
+Runs the simulated subsystems named as args, or all of them by default, on their ports from common::ports:
+
+    cargo run --bin sim -- [--script <file>] [dfgm] [eps] [adcs] [iris] [uhf]
+
+A script changes how the subsystems behave and injects faults into what they send (see sim::script).
+*/
+
+use common::logging::init_program_logger;
+use common::ports;
+use log::error;
+use sim::script::Script;
+use sim::{adcs, dfgm, eps, iris, server, uhf};
+use std::io::{Error, ErrorKind};
+use std::net::TcpListener;
+use std::path::Path;
+use std::thread::JoinHandle;
+
+const SUBSYSTEMS: [&str; 5] = ["dfgm", "eps", "adcs", "iris", "uhf"];
+const HOST: &str = "127.0.0.1";
+
+fn listen(name: &str, port: u16) -> Result<TcpListener, Error> {
+    let listener = TcpListener::bind((HOST, port))
+        .map_err(|e| Error::new(e.kind(), format!("cannot listen on port {} for the {} sim: {}", port, name, e)))?;
+    println!("Simulating {} on {}:{}", name, HOST, port);
+    Ok(listener)
+}
+
+/// Start simulating the subsystem called `name`. Its ports are bound before returning, so the caller
+/// knows they are in use
+fn start(name: &str, script: &Script) -> Result<JoinHandle<std::io::Result<()>>, Error> {
+    let handle = match name {
+        "dfgm" => {
+            let (listener, behaviour) = (listen("DFGM", ports::SIM_DFGM_PORT)?, script.dfgm.clone());
+            std::thread::spawn(move || server::serve(listener, dfgm::Dfgm::default(), behaviour))
+        }
+        "eps" => {
+            let (listener, behaviour) = (listen("EPS", ports::SIM_EPS_PORT)?, script.eps.clone());
+            std::thread::spawn(move || server::serve(listener, eps::Eps::default(), behaviour))
+        }
+        "adcs" => {
+            let (listener, behaviour) = (listen("ADCS", ports::SIM_ADCS_PORT)?, script.adcs.clone());
+            std::thread::spawn(move || server::serve(listener, adcs::Adcs::default(), behaviour))
+        }
+        "iris" => {
+            let (listener, behaviour) = (listen("IRIS", ports::SIM_IRIS_PORT)?, script.iris.clone());
+            std::thread::spawn(move || server::serve(listener, iris::Iris::default(), behaviour))
+        }
+        "uhf" => {
+            let uart = listen("UHF UART", ports::SIM_ESAT_UART_PORT)?;
+            let radio = listen("UHF radio", ports::SIM_ESAT_UHF_PORT)?;
+            let beacon = listen("UHF beacon", ports::SIM_ESAT_BEACON_PORT)?;
+            let behaviour = script.uhf.clone();
+            std::thread::spawn(move || uhf::serve(uart, radio, beacon, uhf::Uhf::default(), behaviour))
+        }
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("unknown subsystem {}, expected one of {}", name, SUBSYSTEMS.join(", ")),
+            ))
+        }
+    };
+    Ok(handle)
+}
+
+fn main() -> Result<(), Error> {
+    init_program_logger("sim");
+
+    let mut args = std::env::args().skip(1);
+    let mut script = Script::default();
+    let mut names = vec![];
+    while let Some(arg) = args.next() {
+        if arg == "--script" {
+            let path = args
+                .next()
+                .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "--script needs a file"))?;
+            script = Script::load(Path::new(&path))?;
+        } else {
+            names.push(arg.to_lowercase());
+        }
+    }
+    if names.is_empty() {
+        names = SUBSYSTEMS.iter().map(|s| s.to_string()).collect();
+    }
+
+    let handles = names.iter().map(|name| start(name, &script)).collect::<Result<Vec<_>, _>>()?;
+    for handle in handles {
+        // Sims only stop if their listener fails
+        if let Ok(Err(e)) = handle.join() {
+            error!("Sim failed: {}", e);
+            return Err(e);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,184 @@
+/*
+Scripted behaviours of the simulated subsystems, read from a TOML file with a table for each subsystem
+(dfgm, eps, adcs, iris and uhf) that is scripted:
+
+    [eps]
+    # Replies given instead of the simulated ones, to requests that match them exactly
+    replies = [{ request = "request:Temperature", reply = "Temperature: 95" }]
+
+    # Faults in what the subsystem sends its handler. The messages sent are counted, and after the
+    # first `after` are sent normally, every `every`th one has the fault, `times` times (forever by default)
+    [[eps.faults]]
+    fault = "drop"          # drop, delay, corrupt or disconnect
+    after = 2
+    every = 3
+
+    [[dfgm.faults]]
+    fault = "delay"
+    delay_ms = 1500
+    times = 1
+*/
+
+use serde::Deserialize;
+use std::io::{Error as IoError, ErrorKind};
+use std::path::Path;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Script {
+    pub dfgm: Behaviour,
+    pub eps: Behaviour,
+    pub adcs: Behaviour,
+    pub iris: Behaviour,
+    pub uhf: Behaviour,
+}
+
+/// How one subsystem differs from its simulation
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Behaviour {
+    pub replies: Vec<Reply>,
+    pub faults: Vec<FaultRule>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Reply {
+    pub request: String,
+    pub reply: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Fault {
+    /// The message is never sent
+    Drop,
+    /// The message is sent late, by delay_ms
+    Delay,
+    /// A byte of the message is flipped
+    Corrupt,
+    /// The connection is closed instead of sending the message
+    Disconnect,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FaultRule {
+    pub fault: Fault,
+    #[serde(default)]
+    pub after: u32,
+    #[serde(default = "every_msg")]
+    pub every: u32,
+    #[serde(default)]
+    pub times: Option<u32>,
+    #[serde(default)]
+    pub delay_ms: u64,
+}
+
+fn every_msg() -> u32 {
+    1
+}
+
+impl FaultRule {
+    pub fn delay(&self) -> Duration {
+        Duration::from_millis(self.delay_ms)
+    }
+
+    /// Whether the rule applies to the `n`th msg sent (from 1), having been applied `applied` times
+    pub fn applies(&self, n: u32, applied: u32) -> bool {
+        n > self.after && (n - self.after - 1).is_multiple_of(self.every) && self.times.is_none_or(|times| applied < times)
+    }
+}
+
+impl Behaviour {
+    /// The scripted reply to a request, if there is one
+    pub fn reply_to(&self, request: &[u8]) -> Option<Vec<u8>> {
+        self.replies
+            .iter()
+            .find(|r| r.request.as_bytes() == request)
+            .map(|r| r.reply.clone().into_bytes())
+    }
+}
+
+fn invalid(reason: impl std::fmt::Display) -> IoError {
+    IoError::new(ErrorKind::InvalidInput, format!("invalid sim script: {}", reason))
+}
+
+impl Script {
+    pub fn load(path: &Path) -> Result<Script, IoError> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| IoError::new(e.kind(), format!("cannot read sim script {:?}: {}", path, e)))?;
+        Script::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Script, IoError> {
+        let script: Script = toml::from_str(text).map_err(|e| invalid(e.message()))?;
+        for rule in [&script.dfgm, &script.eps, &script.adcs, &script.iris, &script.uhf]
+            .iter()
+            .flat_map(|b| &b.faults)
+        {
+            if rule.every == 0 {
+                return Err(invalid("every must be at least 1"));
+            }
+            if rule.fault == Fault::Delay && rule.delay_ms == 0 {
+                return Err(invalid("a delay needs delay_ms"));
+            }
+        }
+        Ok(script)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_scripts() {
+        let script = Script::parse(
+            r#"
+            [eps]
+            replies = [{ request = "request:Temperature", reply = "Temperature: 95" }]
+            [[eps.faults]]
+            fault = "drop"
+            after = 2
+            every = 3
+            [[dfgm.faults]]
+            fault = "delay"
+            delay_ms = 1500
+            times = 1
+            "#,
+        )
+        .unwrap();
+        assert_eq!(script.eps.reply_to(b"request:Temperature"), Some(b"Temperature: 95".to_vec()));
+        assert_eq!(script.eps.reply_to(b"request:Voltage"), None);
+        assert_eq!(script.dfgm.faults[0].delay(), Duration::from_millis(1500));
+        assert_eq!(script.adcs, Behaviour::default());
+        assert_eq!(Script::parse("").unwrap(), Script::default());
+
+        for bad in ["[gps]", "[eps]\nfaults = [{ fault = \"explode\" }]", "[eps]\nfaults = [{ fault = \"drop\", every = 0 }]",
+                    "[eps]\nfaults = [{ fault = \"delay\" }]", "[eps]\nreplies = [{ request = \"x\" }]"] {
+            assert_eq!(Script::parse(bad).unwrap_err().kind(), ErrorKind::InvalidInput, "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_example_script() {
+        let script = Script::parse(include_str!("../flaky_radio.toml")).unwrap();
+        assert_eq!(script.uhf.faults[0].fault, Fault::Corrupt);
+    }
+
+    #[test]
+    fn test_fault_rules() {
+        let rule = FaultRule { fault: Fault::Drop, after: 2, every: 3, times: Some(2), delay_ms: 0 };
+        let mut applied = 0;
+        let hits: Vec<u32> = (1..=12)
+            .filter(|&n| {
+                let hit = rule.applies(n, applied);
+                applied += hit as u32;
+                hit
+            })
+            .collect();
+        assert_eq!(hits, vec![3, 6]);
+    }
+}
@@ -0,0 +1,229 @@
+/*
+Serves a simulated subsystem over TCP to one handler at a time, injecting the faults of its scripted
+behaviour into everything it sends.
+*/
+
+use crate::script::{Behaviour, Fault, FaultRule};
+use crate::Subsystem;
+use log::{debug, info, warn};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+
+/// How long to wait for a request before checking whether anything is due to be sent
+pub const POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// Largest request read at once
+const REQUEST_BUFFER_SIZE: usize = 4096;
+
+/// The faults injected into what a subsystem sends, and how many msgs it has sent
+pub struct Faults {
+    rules: Vec<FaultRule>,
+    applied: Vec<u32>,
+    sent: u32,
+}
+
+impl Faults {
+    pub fn new(rules: Vec<FaultRule>) -> Faults {
+        let applied = vec![0; rules.len()];
+        Faults { rules, applied, sent: 0 }
+    }
+
+    /// The fault to inject into the next msg sent, if any
+    fn next(&mut self) -> Option<FaultRule> {
+        self.sent += 1;
+        let i = (0..self.rules.len()).find(|&i| self.rules[i].applies(self.sent, self.applied[i]))?;
+        self.applied[i] += 1;
+        Some(self.rules[i].clone())
+    }
+
+    /// Send `msg` with the next fault injected. Returns false if the connection should be closed instead
+    pub fn send(&mut self, stream: &mut impl Write, msg: &[u8]) -> std::io::Result<bool> {
+        let Some(rule) = self.next() else {
+            stream.write_all(msg)?;
+            return Ok(true);
+        };
+        debug!("Injecting {:?} into msg {}", rule.fault, self.sent);
+        match rule.fault {
+            Fault::Drop => {}
+            Fault::Delay => {
+                std::thread::sleep(rule.delay());
+                stream.write_all(msg)?;
+            }
+            Fault::Corrupt => {
+                let mut corrupted = msg.to_vec();
+                if let Some(byte) = corrupted.get_mut(msg.len() / 2) {
+                    *byte ^= 0xFF;
+                }
+                stream.write_all(&corrupted)?;
+            }
+            Fault::Disconnect => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+/// Accept a connection if one is waiting on the non-blocking `listener`
+pub fn accept_ready(listener: &TcpListener) -> std::io::Result<Option<TcpStream>> {
+    match listener.accept() {
+        Ok((stream, addr)) => {
+            debug!("Accepted connection from {}", addr);
+            stream.set_nonblocking(false)?;
+            stream.set_read_timeout(Some(POLL_INTERVAL))?;
+            Ok(Some(stream))
+        }
+        Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Read what has arrived on `stream` within the POLL_INTERVAL. A closed connection is an UnexpectedEof error
+pub fn read_ready(stream: &mut TcpStream) -> std::io::Result<Option<Vec<u8>>> {
+    let mut buf = vec![0u8; REQUEST_BUFFER_SIZE];
+    match stream.read(&mut buf) {
+        Ok(0) => Err(ErrorKind::UnexpectedEof.into()),
+        Ok(n) => {
+            buf.truncate(n);
+            Ok(Some(buf))
+        }
+        Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Serve `subsystem` on `listener` forever, to one handler at a time
+pub fn serve<S: Subsystem>(listener: TcpListener, mut subsystem: S, behaviour: Behaviour) -> std::io::Result<()> {
+    let mut faults = Faults::new(behaviour.faults.clone());
+    loop {
+        let (stream, addr) = listener.accept()?;
+        stream.set_read_timeout(Some(POLL_INTERVAL))?;
+        info!("{} handler connected from {}", subsystem.name(), addr);
+        match session(&mut subsystem, &behaviour, &mut faults, stream) {
+            Ok(()) => info!("{} handler disconnected", subsystem.name()),
+            Err(e) => warn!("{} handler connection failed: {}", subsystem.name(), e),
+        }
+    }
+}
+
+/// Reply to the requests of the handler on `stream` and send it any unprompted data, until it disconnects
+fn session<S: Subsystem>(subsystem: &mut S, behaviour: &Behaviour, faults: &mut Faults, mut stream: TcpStream) -> std::io::Result<()> {
+    let mut next_unprompted = subsystem.period().map(|period| Instant::now() + period);
+    loop {
+        match read_ready(&mut stream) {
+            Ok(Some(request)) => {
+                debug!("{} got {:?}", subsystem.name(), String::from_utf8_lossy(&request));
+                let reply = behaviour.reply_to(&request).or_else(|| subsystem.handle(&request));
+                if let Some(reply) = reply {
+                    if !faults.send(&mut stream, &reply)? {
+                        return Ok(());
+                    }
+                }
+            }
+            Ok(None) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        if let (Some(due), Some(period)) = (next_unprompted, subsystem.period()) {
+            if Instant::now() >= due {
+                let data = subsystem.unprompted();
+                if !faults.send(&mut stream, &data)? {
+                    return Ok(());
+                }
+                next_unprompted = Some(due + period);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Echoes requests back, and sends "tick" unprompted every period
+    struct Echo {
+        period: Option<Duration>,
+    }
+
+    impl Subsystem for Echo {
+        fn name(&self) -> &'static str {
+            "Echo"
+        }
+
+        fn handle(&mut self, request: &[u8]) -> Option<Vec<u8>> {
+            (request != b"quiet").then(|| request.to_vec())
+        }
+
+        fn period(&self) -> Option<Duration> {
+            self.period
+        }
+
+        fn unprompted(&mut self) -> Vec<u8> {
+            b"tick".to_vec()
+        }
+    }
+
+    fn start(echo: Echo, behaviour: Behaviour) -> std::net::SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || serve(listener, echo, behaviour));
+        addr
+    }
+
+    fn connect(addr: std::net::SocketAddr) -> TcpStream {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        stream
+    }
+
+    fn read(stream: &mut TcpStream, n: usize) -> Vec<u8> {
+        let mut buf = vec![0u8; n];
+        stream.read_exact(&mut buf).unwrap();
+        buf
+    }
+
+    fn rule(fault: Fault, after: u32) -> FaultRule {
+        FaultRule { fault, after, every: 1, times: Some(1), delay_ms: 0 }
+    }
+
+    #[test]
+    fn test_serves_requests() {
+        let behaviour = Behaviour {
+            replies: vec![crate::script::Reply { request: "ping".to_string(), reply: "pong".to_string() }],
+            faults: vec![],
+        };
+        let mut stream = connect(start(Echo { period: None }, behaviour));
+        stream.write_all(b"hello").unwrap();
+        assert_eq!(read(&mut stream, 5), b"hello");
+        stream.write_all(b"ping").unwrap();
+        assert_eq!(read(&mut stream, 4), b"pong");
+        // Nothing is sent back for a request without a reply
+        stream.write_all(b"quiet").unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        stream.write_all(b"again").unwrap();
+        assert_eq!(read(&mut stream, 5), b"again");
+
+        let mut stream = connect(start(Echo { period: Some(Duration::from_millis(20)) }, Behaviour::default()));
+        assert_eq!(read(&mut stream, 8), b"ticktick");
+    }
+
+    #[test]
+    fn test_injects_faults() {
+        let mut faults = Faults::new(vec![rule(Fault::Drop, 0), rule(Fault::Corrupt, 1), rule(Fault::Disconnect, 3)]);
+        let mut sent = vec![];
+        assert!(faults.send(&mut sent, b"one").unwrap());
+        assert!(faults.send(&mut sent, b"two").unwrap());
+        assert!(faults.send(&mut sent, b"three").unwrap());
+        assert!(!faults.send(&mut sent, b"four").unwrap());
+        assert!(faults.send(&mut sent, b"five").unwrap());
+        assert_eq!(sent, [&b"t\x88o"[..], b"three", b"five"].concat());
+
+        // After a disconnect, the next handler to connect is served
+        let behaviour = Behaviour { replies: vec![], faults: vec![rule(Fault::Disconnect, 0)] };
+        let addr = start(Echo { period: None }, behaviour);
+        let mut stream = connect(addr);
+        stream.write_all(b"hello").unwrap();
+        assert_eq!(stream.read(&mut [0u8; 8]).unwrap(), 0);
+        let mut stream = connect(addr);
+        stream.write_all(b"hello").unwrap();
+        assert_eq!(read(&mut stream, 5), b"hello");
+    }
+}
@@ -0,0 +1,248 @@
+/*
+Simulated UHF transceiver. The coms_handler connects to its UART port, the GS to its radio port, and any
+number of listeners to its beacon port:
+
+    UHF:SET_BEACON:<beacon>     from the OBC, replies "OK"
+    UHF:GET_BEACON:             replies with the beacon
+    UHF:SET_MODE:<mode>         replies "OK"
+    UHF:GET_MODE:               replies with the mode
+
+Anything else from the OBC is downlinked to the GS, and everything from the GS is uplinked to the OBC. The
+beacon is broadcast with the call sign in front of it every beacon period. Faults are injected into
+everything sent to the OBC, so uplinked frames can be dropped or corrupted as they would be over the air.
+*/
+
+use crate::script::Behaviour;
+use crate::server::{accept_ready, read_ready, Faults, POLL_INTERVAL};
+use crate::{split_cmd, Subsystem};
+use log::{debug, info, warn};
+use std::io::Write;
+use std::net::{TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+
+/// 7 characters, as the GS expects
+pub const CALL_SIGN: &str = "VA6EXA3";
+pub const DEFAULT_BEACON: &str = "Ex3 Beacon";
+const DEFAULT_BEACON_PERIOD: Duration = Duration::from_secs(10);
+const CMD_PREFIX: &[u8] = b"UHF:";
+
+pub struct Uhf {
+    beacon: String,
+    mode: u8,
+    beacon_period: Duration,
+}
+
+impl Default for Uhf {
+    fn default() -> Uhf {
+        Uhf { beacon: DEFAULT_BEACON.to_string(), mode: 0, beacon_period: DEFAULT_BEACON_PERIOD }
+    }
+}
+
+impl Uhf {
+    pub fn beacon_period(mut self, period: Duration) -> Uhf {
+        self.beacon_period = period;
+        self
+    }
+
+    fn reply(&mut self, cmd: &str, params: &[String]) -> Result<String, String> {
+        let value = params.join(":");
+        match cmd {
+            "SET_BEACON" => {
+                self.beacon = value;
+                Ok("OK".to_string())
+            }
+            "GET_BEACON" => Ok(self.beacon.clone()),
+            "SET_MODE" => {
+                self.mode = value.parse().map_err(|_| format!("ERROR: invalid mode {}", value))?;
+                Ok("OK".to_string())
+            }
+            "GET_MODE" => Ok(self.mode.to_string()),
+            _ => Err(format!("ERROR: unknown cmd {}", cmd)),
+        }
+    }
+}
+
+impl Subsystem for Uhf {
+    fn name(&self) -> &'static str {
+        "UHF"
+    }
+
+    /// Replies to cmds for the UHF, while everything else is passed through
+    fn handle(&mut self, request: &[u8]) -> Option<Vec<u8>> {
+        let cmd = request.strip_prefix(CMD_PREFIX)?;
+        let (name, mut params) = split_cmd(cmd);
+        // Cmds end with a ':', even when they take no value
+        if params.last().is_some_and(String::is_empty) {
+            params.pop();
+        }
+        Some(self.reply(&name, &params).unwrap_or_else(|e| e).into_bytes())
+    }
+
+    fn period(&self) -> Option<Duration> {
+        Some(self.beacon_period)
+    }
+
+    fn unprompted(&mut self) -> Vec<u8> {
+        format!("{}{}", CALL_SIGN, self.beacon).into_bytes()
+    }
+}
+
+/// Read from the connection in `stream`, if there is one, dropping it once it is closed
+fn read_connection(stream: &mut Option<TcpStream>, name: &str) -> Option<Vec<u8>> {
+    match read_ready(stream.as_mut()?) {
+        Ok(data) => data,
+        Err(e) => {
+            info!("{} disconnected from the UHF: {}", name, e);
+            *stream = None;
+            None
+        }
+    }
+}
+
+/// Serve the UHF forever, to the coms_handler on `uart`, the GS on `radio` and beacon listeners on `beacon`
+pub fn serve(uart: TcpListener, radio: TcpListener, beacon: TcpListener, mut uhf: Uhf, behaviour: Behaviour) -> std::io::Result<()> {
+    for listener in [&uart, &radio, &beacon] {
+        listener.set_nonblocking(true)?;
+    }
+    let mut faults = Faults::new(behaviour.faults.clone());
+    let mut obc: Option<TcpStream> = None;
+    let mut gs: Option<TcpStream> = None;
+    let mut listeners: Vec<TcpStream> = vec![];
+    let mut next_beacon = Instant::now() + uhf.beacon_period;
+    loop {
+        // A new connection replaces the old one, as the other end has reconnected
+        if let Some(stream) = accept_ready(&uart)? {
+            info!("OBC connected to the UHF");
+            obc = Some(stream);
+        }
+        if let Some(stream) = accept_ready(&radio)? {
+            info!("GS connected to the UHF");
+            gs = Some(stream);
+        }
+        if let Some(stream) = accept_ready(&beacon)? {
+            listeners.push(stream);
+        }
+
+        if let Some(data) = read_connection(&mut obc, "OBC") {
+            match behaviour.reply_to(&data).or_else(|| uhf.handle(&data)) {
+                Some(reply) => send_to_obc(&mut obc, &mut faults, &reply),
+                None => match gs {
+                    Some(ref mut stream) => {
+                        debug!("Downlinking {}B", data.len());
+                        if let Err(e) = stream.write_all(&data) {
+                            warn!("Error downlinking to the GS: {}", e);
+                            gs = None;
+                        }
+                    }
+                    None => warn!("No GS in range, dropping {}B downlink", data.len()),
+                },
+            }
+        }
+        if let Some(data) = read_connection(&mut gs, "GS") {
+            debug!("Uplinking {}B", data.len());
+            send_to_obc(&mut obc, &mut faults, &data);
+        }
+
+        if Instant::now() >= next_beacon {
+            let beacon = uhf.unprompted();
+            listeners.retain_mut(|listener| listener.write_all(&beacon).is_ok());
+            next_beacon += uhf.beacon_period;
+        }
+        if obc.is_none() && gs.is_none() {
+            // Nothing to read, which would have waited the poll interval
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+fn send_to_obc(obc: &mut Option<TcpStream>, faults: &mut Faults, data: &[u8]) {
+    let Some(stream) = obc else {
+        warn!("No OBC connected to the UHF, dropping {}B", data.len());
+        return;
+    };
+    match faults.send(stream, data) {
+        Ok(true) => {}
+        Ok(false) => {
+            info!("Disconnecting the OBC from the UHF");
+            *obc = None;
+        }
+        Err(e) => {
+            warn!("Error sending to the OBC: {}", e);
+            *obc = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::script::{Fault, FaultRule};
+    use std::io::Read;
+
+    fn reply(uhf: &mut Uhf, request: &str) -> Option<String> {
+        uhf.handle(request.as_bytes()).map(|r| String::from_utf8(r).unwrap())
+    }
+
+    #[test]
+    fn test_cmds() {
+        let mut uhf = Uhf::default();
+        assert_eq!(reply(&mut uhf, "UHF:GET_BEACON:").unwrap(), DEFAULT_BEACON);
+        assert_eq!(reply(&mut uhf, "UHF:SET_BEACON:Hello: world").unwrap(), "OK");
+        assert_eq!(reply(&mut uhf, "UHF:GET_BEACON:").unwrap(), "Hello: world");
+        assert_eq!(uhf.unprompted(), b"VA6EXA3Hello: world");
+        assert_eq!(reply(&mut uhf, "UHF:SET_MODE:3").unwrap(), "OK");
+        assert_eq!(reply(&mut uhf, "UHF:GET_MODE:").unwrap(), "3");
+        assert!(reply(&mut uhf, "UHF:SET_MODE:fast").unwrap().starts_with("ERROR: "));
+        assert!(reply(&mut uhf, "UHF:SELF_DESTRUCT:").unwrap().starts_with("ERROR: "));
+        // Anything else is for the GS
+        assert_eq!(reply(&mut uhf, "frame"), None);
+    }
+
+    fn listener() -> (TcpListener, std::net::SocketAddr) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        (listener, addr)
+    }
+
+    fn connect(addr: std::net::SocketAddr) -> TcpStream {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        stream
+    }
+
+    fn read(stream: &mut TcpStream, n: usize) -> Vec<u8> {
+        let mut buf = vec![0u8; n];
+        stream.read_exact(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_passes_through() {
+        let (uart, uart_addr) = listener();
+        let (radio, radio_addr) = listener();
+        let (beacon, beacon_addr) = listener();
+        // The third msg to the OBC, the second frame uplinked, is corrupted over the air
+        let behaviour = Behaviour {
+            replies: vec![],
+            faults: vec![FaultRule { fault: Fault::Corrupt, after: 2, every: 1, times: Some(1), delay_ms: 0 }],
+        };
+        let uhf = Uhf::default().beacon_period(Duration::from_millis(50));
+        std::thread::spawn(move || serve(uart, radio, beacon, uhf, behaviour));
+
+        let mut beacon_listener = connect(beacon_addr);
+        assert_eq!(read(&mut beacon_listener, 17), b"VA6EXA3Ex3 Beacon");
+
+        let mut obc = connect(uart_addr);
+        obc.write_all(b"UHF:GET_MODE:").unwrap();
+        assert_eq!(read(&mut obc, 1), b"0");
+        let mut gs = connect(radio_addr);
+        // Wait for the UHF to accept the GS, so the downlink is not dropped
+        std::thread::sleep(Duration::from_millis(100));
+        obc.write_all(b"down").unwrap();
+        assert_eq!(read(&mut gs, 4), b"down");
+        gs.write_all(b"up").unwrap();
+        assert_eq!(read(&mut obc, 2), b"up");
+        gs.write_all(b"up").unwrap();
+        assert_eq!(read(&mut obc, 2), [b'u', b'p' ^ 0xFF]);
+    }
+}
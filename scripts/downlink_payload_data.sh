@@ -2,21 +2,15 @@
 # Written by Rowan Rasmusson
 # Summer 2024
 
-# User must provide the path to the simulated subsystem directory on their machine as the first arg
-PATH_TO_SIM_SUBS=$1
-
-if [ "$#" -lt 1 ]; then
-    echo "ERROR=> Requires argument: <path to sim subsystem dir>\n"
-    exit 0
-fi;
-echo "Path being used to sim subs: $PATH_TO_SIM_SUBS"
+# Any args (e.g. --script <file relative to the repo root>) are passed on to the simulated subsystems,
+# see ex3_simulated_subsystems
+SIM_ARGS="$*"
 
 # Create a detached session using our config file to hold our windows
 tmux -f .tmux.conf new-session -d -s "downlink_payload_data"
 
 ## Create the simulated subystem components (DFGM, UHF) - because they are tcp servers  
-tmux new-window -n "SIM_UHF_SUBSYSTEM" -- "trap : SIGINT; cd $PATH_TO_SIM_SUBS/UHF && python3 ./simulated_uhf.py ; exec bash"
-tmux new-window -n "SIM_DFGM_SUBSYSTEM" -- "trap : SIGINT; cd $PATH_TO_SIM_SUBS/DFGM && python3 ./dfgm_subsystem.py ; exec bash"
+tmux new-window -n "SIM_SUBSYSTEMS" -- "trap : SIGINT; cd ../ && cargo run --bin sim -- $SIM_ARGS uhf dfgm; exec bash"
 #                                             ^ to continue after CTRL+C
 sleep 0.25
 # Create bulk msg dispatcher 
@@ -21,7 +21,7 @@ tmux new-window -n "SIM_GPS" -- "trap : SIGINT; cd $PATH_TO_SIM_SUBS/GPS && pyth
 tmux new-window -n "BULK_MSG_DISPATCHER" -- "trap : SIGINT; cd ../ex3_obc_fsw/bulk_msg_dispatcher && cargo run; exec bash"
 sleep 0.25
 
-tmux new-window -n "SIM_UHF_SUBSYSTEM" -- "trap : SIGINT; cd $PATH_TO_SIM_SUBS/UHF && python3 ./simulated_uhf.py ; exec bash;"
+tmux new-window -n "SIM_SUBSYSTEMS" -- "trap : SIGINT; cd ../ && cargo run --bin sim -- uhf; exec bash"
 sleep 0.25
 
 tmux new-window -n "COMS_HANDLER" -- "trap : SIGINT; cd ../ && cargo run --bin coms_handler; exec bash"
@@ -31,7 +31,7 @@ sleep 0.25
 tmux new-window -n "GPS_HANDLER" -- "trap : SIGINT; cd ../ && cargo run --bin gps_handler; exec bash"
 sleep 0.25
 
-## Create the msg dispatcher (last component of the obc fsw because it creates ipc clients
+## Create the msg dispatcher (handlers can be started before or after it, they register with it)
 tmux new-window -n "CMD_DISPATCHER" -- "trap : SIGINT; cd ../ && cargo run --bin cmd_dispatcher; exec bash"
 sleep 0.25
 
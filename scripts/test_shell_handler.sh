@@ -2,14 +2,9 @@
 # Written by Kaaden RumanCam
 # Summer 2024
 
-# User must provide the path to the simulated subsystem directory on their machine as the first arg
-PATH_TO_SIM_SUBS=$1
-
-if [ "$#" -lt 1 ]; then
-    echo "ERROR=> Requires argument: <path to sim subsystem dir>\n"
-    exit 0
-fi;
-echo "Path being used to sim subs: $PATH_TO_SIM_SUBS"
+# Any args (e.g. --script <file relative to the repo root>) are passed on to the simulated subsystems,
+# see ex3_simulated_subsystems
+SIM_ARGS="$*"
 
 # Create a detached session using our config file to hold our windows
 tmux -f .tmux.conf new-session -d -s "test_shell_handler"
@@ -18,7 +13,7 @@ tmux -f .tmux.conf new-session -d -s "test_shell_handler"
 tmux new-window -n "BULK_MSG_DISPATCHER" -- "trap : SIGINT; cd ../ex3_obc_fsw/bulk_msg_dispatcher && cargo run; exec bash"
 sleep 0.25
 
-tmux new-window -n "SIM_UHF_SUBSYSTEM" -- "trap : SIGINT; cd $PATH_TO_SIM_SUBS/UHF && python3 ./simulated_uhf.py ; exec bash;"
+tmux new-window -n "SIM_SUBSYSTEMS" -- "trap : SIGINT; cd ../ && cargo run --bin sim -- $SIM_ARGS uhf; exec bash"
 
 tmux new-window -n "COMS_HANDLER" -- "trap : SIGINT; cd ../ && cargo run --bin coms_handler; exec bash"
 sleep 0.25
@@ -27,8 +22,8 @@ sleep 0.25
 tmux new-window -n "SHELL_HANDLER" -- "trap : SIGINT; cd ../ && cargo run --bin shell_handler; exec bash"
 sleep 0.25
 
-## Create the msg dispatcher (last component of the obc fsw because it creates ipc clients
-tmux new-window -n "CMD_DISPATCHER" -- "trap : SIGINT; cd ../ && cargo run --bin cmd_dispatcher; exec bash"
+## Create the msg dispatcher (handlers can be started before or after it, they register with it)
+tmux new-window -n "CMD_DISPATCHER" -- "trap : SIGINT; cd ../ && cargo run --bin cmd_dispatcher -- --dev-mode; exec bash"
 sleep 0.25
 
 ## Launch the GS simulation (this can just be a tcp server for now )
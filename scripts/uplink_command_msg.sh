@@ -2,23 +2,16 @@
 # Written by Devin Headrick 
 # Summer 2024
 
-# User must provide the path to the simulated subsystem directory on their machine as the first arg
-PATH_TO_SIM_SUBS=$1
-
-if [ "$#" -lt 1 ]; then
-    echo "ERROR=> Requires argument: <path to sim subsystem dir>\n"
-    exit 0
-fi;
-echo "Path being used to sim subs: $PATH_TO_SIM_SUBS"
+# Any args (e.g. --script <file relative to the repo root>) are passed on to the simulated subsystems,
+# see ex3_simulated_subsystems
+SIM_ARGS="$*"
 
 # Create a detached session using our config file to hold our windows
 # IRIS commented out for now while work gets done on the handler
 tmux -f .tmux.conf new-session -d -s "uplink_command_msg"
 
 # Create the simulated subystem components (dfgm and uhf transciever) - because they are tcp servers  
-tmux new-window -n "SIM_DFGM_SUBSYSTEM" -- "trap : SIGINT; cd $PATH_TO_SIM_SUBS/DFGM && python3 ./dfgm_subsystem.py; exec bash"
-tmux new-window -n "SIM_UHF_SUBSYSTEM" -- "trap : SIGINT; cd $PATH_TO_SIM_SUBS/UHF && python3 ./simulated_uhf.py; exec bash"
-# tmux new-window -n "SIM_IRIS_SUBSYSTEM" -- "trap : SIGINT; cd $PATH_TO_SIM_SUBS/IRIS && python3 ./iris_simulated_server.py; exec bash"
+tmux new-window -n "SIM_SUBSYSTEMS" -- "trap : SIGINT; cd ../ && cargo run --bin sim -- $SIM_ARGS dfgm uhf; exec bash"
 # For now the UHF transceiver is bypassed and the GS sends msgs directly to the coms handler 
 sleep 0.25
 